@@ -0,0 +1,979 @@
+// The append only file. Every write command is logged as a RESP array once it
+// succeeded and the log is replayed at startup. Files follow redis 7's
+// multi-part layout, a directory with a base snapshot, the incremental logs
+// written since and a manifest naming them:
+//
+//   appendonlydir/appendonly.aof.1.base.rdb
+//   appendonlydir/appendonly.aof.1.incr.aof
+//   appendonlydir/appendonly.aof.manifest
+//
+// BGREWRITEAOF switches to a fresh incremental file and writes a new base
+// from the dataset as of that moment, so nothing is buffered meanwhile.
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+
+use crate::config::{AppendFsync, Config};
+use crate::outbox;
+use crate::persistence;
+use crate::rdb;
+use crate::resp_frame::RespFrame;
+use crate::scripting::Scripting;
+use crate::session::Session;
+use crate::storage::Storage;
+use crate::{ServerState, commands};
+
+pub struct Aof {
+    // checked before taking the lock, most servers run without an AOF
+    enabled: AtomicBool,
+    // shared with the thread writing a new base
+    log: Arc<Mutex<Log>>,
+}
+
+struct Log {
+    dir: PathBuf,
+    filename: String,
+    fsync: AppendFsync,
+    load_truncated: bool,
+    manifest: Manifest,
+    // the newest incremental file, which commands are appended to
+    file: Option<File>,
+    // commands of the running command, written out by `flush`
+    buffer: Vec<u8>,
+    // the database the log last switched to with SELECT
+    selected_db: Option<usize>,
+    // EXEC and scripts wrap what they write in MULTI/EXEC, written lazily
+    // so read only transactions leave no trace
+    atomic_depth: usize,
+    multi_written: bool,
+    // written but not yet synced, for everysec
+    unsynced: bool,
+    rewrite_in_progress: bool,
+    last_rewrite_ok: bool,
+    last_write_ok: bool,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Manifest {
+    base: Option<ManifestEntry>,
+    incrs: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ManifestEntry {
+    name: String,
+    seq: u64,
+}
+
+/// How BGREWRITEAOF went, for its reply.
+#[derive(Debug, PartialEq)]
+pub enum Rewrite {
+    Started,
+    AlreadyRunning,
+    Failed,
+}
+
+impl Aof {
+    /// Starts out disabled, `load` or `start` turn it on.
+    pub fn new(config: &Config) -> Self {
+        Aof {
+            enabled: AtomicBool::new(false),
+            log: Arc::new(Mutex::new(Log {
+                dir: config.aof_dir(),
+                filename: config.appendfilename.clone(),
+                fsync: config.appendfsync,
+                load_truncated: config.aof_load_truncated,
+                manifest: Manifest::default(),
+                file: None,
+                buffer: Vec::new(),
+                selected_db: None,
+                atomic_depth: 0,
+                multi_written: false,
+                unsynced: false,
+                rewrite_in_progress: false,
+                last_rewrite_ok: true,
+                last_write_ok: true,
+            })),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn fsync_policy(&self) -> AppendFsync {
+        self.log.lock().unwrap().fsync
+    }
+
+    pub fn set_fsync_policy(&self, fsync: AppendFsync) {
+        self.log.lock().unwrap().fsync = fsync;
+    }
+
+    pub fn rewrite_in_progress(&self) -> bool {
+        self.log.lock().unwrap().rewrite_in_progress
+    }
+
+    pub fn last_rewrite_ok(&self) -> bool {
+        self.log.lock().unwrap().last_rewrite_ok
+    }
+
+    pub fn last_write_ok(&self) -> bool {
+        self.log.lock().unwrap().last_write_ok
+    }
+
+    /// Logs a command that changed the dataset, as `translate` put it.
+    /// Called under the storage lock, so commands are logged in the order
+    /// they ran.
+    pub fn feed(&self, db_index: usize, args: &[Bytes]) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut log = self.log.lock().unwrap();
+        if log.atomic_depth > 0 && !log.multi_written {
+            log.multi_written = true;
+            encode(&mut log.buffer, &[Bytes::from("MULTI")]);
+        }
+        if log.selected_db != Some(db_index) {
+            log.selected_db = Some(db_index);
+            encode(
+                &mut log.buffer,
+                &[Bytes::from("SELECT"), Bytes::from(db_index.to_string())],
+            );
+        }
+        encode(&mut log.buffer, args);
+    }
+
+    /// Everything fed until the matching `end_atomic` is replayed as one
+    /// transaction.
+    pub fn begin_atomic(&self) {
+        if self.is_enabled() {
+            self.log.lock().unwrap().atomic_depth += 1;
+        }
+    }
+
+    pub fn end_atomic(&self) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut log = self.log.lock().unwrap();
+        log.atomic_depth = log.atomic_depth.saturating_sub(1);
+        if log.atomic_depth == 0 && log.multi_written {
+            log.multi_written = false;
+            encode(&mut log.buffer, &[Bytes::from("EXEC")]);
+        }
+    }
+
+    /// Writes out what the last command logged, before its reply is sent.
+    /// With `appendfsync always` it is on disk by then too.
+    pub fn flush(&self) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.log.lock().unwrap().flush();
+    }
+
+    /// Run once a second: syncs what was written since, for everysec.
+    pub fn fsync_if_needed(&self) {
+        if !self.is_enabled() {
+            return;
+        }
+        let file = {
+            let mut log = self.log.lock().unwrap();
+            if log.fsync != AppendFsync::EverySec || !log.unsynced {
+                return;
+            }
+            log.unsynced = false;
+            log.file.as_ref().and_then(|file| file.try_clone().ok())
+        };
+        // a slow disk should hold up this task, not the clients
+        if let Some(file) = file
+            && let Err(e) = file.sync_data()
+        {
+            println!("Error syncing the append only file: {}", e);
+        }
+    }
+
+    /// Flushes and syncs everything, on shutdown.
+    pub fn sync(&self) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut log = self.log.lock().unwrap();
+        log.flush();
+        if let Some(file) = &log.file
+            && let Err(e) = file.sync_data()
+        {
+            println!("Error syncing the append only file: {}", e);
+        }
+    }
+
+    /// Turns the AOF on for a dataset that has none yet by writing its first
+    /// base. At startup this happens before clients are served; CONFIG SET
+    /// appendonly does it in the background.
+    pub fn start(&self, storage: &Storage, scripting: &Scripting, background: bool) -> Rewrite {
+        if self.is_enabled() {
+            return Rewrite::Started;
+        }
+        self.enabled.store(true, Ordering::Relaxed);
+        let mut rewrite = self.begin_rewrite(storage, scripting, background, true);
+        if rewrite == Rewrite::Started && !background && !self.last_rewrite_ok() {
+            rewrite = Rewrite::Failed;
+        }
+        if rewrite != Rewrite::Started {
+            self.stop();
+        }
+        rewrite
+    }
+
+    /// Stops logging, for CONFIG SET appendonly no. The files stay on disk.
+    pub fn stop(&self) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut log = self.log.lock().unwrap();
+        log.flush();
+        log.file = None;
+        log.selected_db = None;
+        self.enabled.store(false, Ordering::Relaxed);
+    }
+
+    /// Compacts the log: commands go to a new incremental file from now on
+    /// and a new base is written in the background from the dataset as of
+    /// this call. Once the base is on disk the manifest drops the files it
+    /// replaces.
+    pub fn rewrite(&self, storage: &Storage, scripting: &Scripting) -> Rewrite {
+        self.begin_rewrite(storage, scripting, true, false)
+    }
+
+    // when `starting`, the files in the manifest are from before the AOF was
+    // turned on and must not be loaded together with the new one
+    fn begin_rewrite(
+        &self,
+        storage: &Storage,
+        scripting: &Scripting,
+        background: bool,
+        starting: bool,
+    ) -> Rewrite {
+        let mut log = self.log.lock().unwrap();
+        if log.rewrite_in_progress {
+            return Rewrite::AlreadyRunning;
+        }
+        log.rewrite_in_progress = true;
+        log.flush();
+
+        let incr_seq = log.manifest.incrs.last().map_or(1, |incr| incr.seq + 1);
+        let base_seq = log.manifest.base.as_ref().map_or(1, |base| base.seq + 1);
+        let incr = ManifestEntry {
+            name: format!("{}.{}.incr.aof", log.filename, incr_seq),
+            seq: incr_seq,
+        };
+        let opened = log.create_incr(&incr).and_then(|()| {
+            log.manifest.incrs.push(incr.clone());
+            // until the new base is in, the old files are needed as well
+            if log.manifest.base.is_some() && !starting {
+                log.write_manifest()?;
+            }
+            Ok(())
+        });
+        if let Err(e) = opened {
+            println!("Error opening a new append only file: {}", e);
+            log.rewrite_in_progress = false;
+            log.last_rewrite_ok = false;
+            return Rewrite::Failed;
+        }
+        let base = ManifestEntry {
+            name: format!("{}.{}.base.rdb", log.filename, base_seq),
+            seq: base_seq,
+        };
+        let dir = log.dir.clone();
+        drop(log);
+
+        let snapshot = persistence::snapshot(storage, scripting);
+        let shared = self.log.clone();
+        let write_base = move || {
+            let written = persistence::write_snapshot(&dir.join(&base.name), &snapshot);
+            let mut log = shared.lock().unwrap();
+            log.rewrite_in_progress = false;
+            let result = written.and_then(|()| {
+                let previous = log.manifest.clone();
+                log.manifest.base = Some(base);
+                log.manifest.incrs.retain(|entry| entry.seq >= incr.seq);
+                log.write_manifest()?;
+                for entry in previous.base.iter().chain(&previous.incrs) {
+                    if !log.manifest.contains(&entry.name) {
+                        let _ = fs::remove_file(dir.join(&entry.name));
+                    }
+                }
+                Ok(())
+            });
+            if let Err(e) = &result {
+                println!("Error rewriting the append only file: {}", e);
+            }
+            log.last_rewrite_ok = result.is_ok();
+        };
+        if background {
+            std::thread::spawn(write_base);
+        } else {
+            write_base();
+        }
+        Rewrite::Started
+    }
+}
+
+impl Log {
+    fn manifest_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.manifest", self.filename))
+    }
+
+    // continues the newest file after loading it
+    fn open_incr(&mut self, incr: &ManifestEntry) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(&incr.name))?;
+        self.file = Some(file);
+        // the replayed client's database is not known, so start with a SELECT
+        self.selected_db = None;
+        Ok(())
+    }
+
+    // a leftover file of the same name, from before the AOF was turned off,
+    // is not part of the new log
+    fn create_incr(&mut self, incr: &ManifestEntry) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        self.file = Some(File::create(self.dir.join(&incr.name))?);
+        // every file has to make sense on its own once older ones are gone
+        self.selected_db = None;
+        Ok(())
+    }
+
+    fn write_manifest(&self) -> io::Result<()> {
+        persistence::write_file(&self.manifest_path(), self.manifest.to_string().as_bytes())
+    }
+
+    fn flush(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let Some(file) = &mut self.file else {
+            self.buffer.clear();
+            return;
+        };
+        let mut written = file.write_all(&self.buffer);
+        if written.is_ok() && self.fsync == AppendFsync::Always {
+            written = file.sync_data();
+        }
+        if let Err(e) = &written {
+            println!("Error writing to the append only file: {}", e);
+        }
+        self.last_write_ok = written.is_ok();
+        self.unsynced = self.fsync == AppendFsync::EverySec;
+        self.buffer.clear();
+    }
+}
+
+impl Manifest {
+    fn contains(&self, name: &str) -> bool {
+        self.base
+            .iter()
+            .chain(&self.incrs)
+            .any(|entry| entry.name == name)
+    }
+
+    // lines of `file <name> seq <n> type <b|h|i>`, in any key order
+    fn parse(text: &str) -> Result<Manifest, String> {
+        let mut manifest = Manifest::default();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let field = |name: &str| {
+                words
+                    .chunks_exact(2)
+                    .find(|pair| pair[0] == name)
+                    .map(|pair| pair[1])
+            };
+            let (Some(name), Some(seq), Some(kind)) = (field("file"), field("seq"), field("type"))
+            else {
+                return Err(format!("invalid manifest line '{}'", line));
+            };
+            let seq = seq
+                .parse()
+                .map_err(|_| format!("invalid manifest line '{}'", line))?;
+            let entry = ManifestEntry {
+                name: name.to_string(),
+                seq,
+            };
+            match kind {
+                "b" => manifest.base = Some(entry),
+                "i" => manifest.incrs.push(entry),
+                // history files are left over from a rewrite and never loaded
+                "h" => {}
+                _ => return Err(format!("invalid manifest line '{}'", line)),
+            }
+        }
+        manifest.incrs.sort_by_key(|entry| entry.seq);
+        Ok(manifest)
+    }
+}
+
+impl std::fmt::Display for Manifest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(base) = &self.base {
+            writeln!(f, "file {} seq {} type b", base.name, base.seq)?;
+        }
+        for incr in &self.incrs {
+            writeln!(f, "file {} seq {} type i", incr.name, incr.seq)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replays the AOF of `state`'s config into its keyspace and turns logging
+/// on. Returns false, leaving the AOF off, when there is no manifest yet.
+pub fn load(state: &ServerState) -> io::Result<bool> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let (dir, manifest, load_truncated) = {
+        let log = state.aof.log.lock().unwrap();
+        let text = match fs::read_to_string(log.manifest_path()) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let manifest = Manifest::parse(&text).map_err(invalid)?;
+        (log.dir.clone(), manifest, log.load_truncated)
+    };
+
+    let mut storage = state.db.lock().unwrap();
+    if let Some(base) = &manifest.base {
+        let path = dir.join(&base.name);
+        let data = fs::read(&path)?;
+        if data.starts_with(b"REDIS") {
+            let snapshot = rdb::read_snapshot(&data)
+                .map_err(|message| invalid(format!("{}: {}", path.display(), message)))?;
+            let report =
+                persistence::restore(&snapshot, &mut storage, &state.scripting).map_err(invalid)?;
+            persistence::warn_skipped(&report);
+        } else {
+            replay(&path, &data, false, &mut storage, state)?;
+        }
+    }
+    for (index, incr) in manifest.incrs.iter().enumerate() {
+        let path = dir.join(&incr.name);
+        let data = fs::read(&path)?;
+        // only the newest file can have been cut short by a crash
+        let is_last = index + 1 == manifest.incrs.len();
+        replay(&path, &data, is_last && load_truncated, &mut storage, state)?;
+    }
+    storage.take_events();
+
+    let mut log = state.aof.log.lock().unwrap();
+    log.manifest = manifest;
+    match log.manifest.incrs.last().cloned() {
+        Some(incr) => log.open_incr(&incr)?,
+        None => {
+            let incr = ManifestEntry {
+                name: format!("{}.1.incr.aof", log.filename),
+                seq: 1,
+            };
+            log.open_incr(&incr)?;
+            log.manifest.incrs.push(incr);
+            log.write_manifest()?;
+        }
+    }
+    state.aof.enabled.store(true, Ordering::Relaxed);
+    Ok(true)
+}
+
+// runs the commands of one file as a client of its own
+fn replay(
+    path: &Path,
+    data: &[u8],
+    allow_truncated: bool,
+    storage: &mut Storage,
+    state: &ServerState,
+) -> io::Result<()> {
+    let (outbox, _) = outbox::channel();
+    let mut session = Session::new(outbox);
+    // a replica replays its log like its primary's stream, past READONLY
+    session.is_master = true;
+    let mut offset = 0;
+    // where the MULTI of a transaction still waiting for its EXEC starts
+    let mut multi_start = None;
+
+    while offset < data.len() {
+        match parse_command(&data[offset..]) {
+            Parsed::Command(args, used) => {
+                let name = args.first().map(|name| name.to_ascii_uppercase());
+                match name.as_deref() {
+                    Some(b"MULTI") => multi_start = Some(offset),
+                    Some(b"EXEC" | b"DISCARD") => multi_start = None,
+                    _ => {}
+                }
+                let frame = RespFrame::Array(args.into_iter().map(RespFrame::BulkString).collect());
+                commands::handle_command(frame, storage, &mut session, state);
+                offset += used;
+            }
+            Parsed::Truncated => break,
+            Parsed::Invalid => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: bad file format at offset {}", path.display(), offset),
+                ));
+            }
+        }
+    }
+    if offset == data.len() && multi_start.is_none() {
+        return Ok(());
+    }
+    if !allow_truncated {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}: unexpected end of file, set aof-load-truncated to yes to load it anyway",
+                path.display()
+            ),
+        ));
+    }
+    // a transaction cut short is dropped whole, its queued commands never
+    // ran, and the file ends before its MULTI so that what is appended next
+    // is not taken for part of it
+    let valid = multi_start.unwrap_or(offset);
+    println!(
+        "!!! Warning: short read while loading the AOF file {}!!!\n!!! Truncating the AOF at offset {} !!!",
+        path.display(),
+        valid
+    );
+    OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_len(valid as u64)?;
+    Ok(())
+}
+
+pub enum Parsed {
+    // the arguments and how many bytes they took
+    Command(Vec<Bytes>, usize),
+    Truncated,
+    Invalid,
+}
+
+// the AOF, like a replication stream, only holds `*<n>` arrays of `$<len>`
+// bulk strings
+pub fn parse_command(data: &[u8]) -> Parsed {
+    fn line(data: &[u8], offset: usize, prefix: u8) -> Result<(usize, usize), Parsed> {
+        let Some(end) = data[offset..].windows(2).position(|pair| pair == b"\r\n") else {
+            return Err(Parsed::Truncated);
+        };
+        let line = &data[offset..offset + end];
+        let value = line
+            .split_first()
+            .filter(|(first, _)| **first == prefix)
+            .and_then(|(_, digits)| std::str::from_utf8(digits).ok())
+            .and_then(|digits| digits.parse().ok())
+            .ok_or(Parsed::Invalid)?;
+        Ok((value, offset + end + 2))
+    }
+
+    let parse = || -> Result<Parsed, Parsed> {
+        let (count, mut offset) = line(data, 0, b'*')?;
+        if count == 0 {
+            return Err(Parsed::Invalid);
+        }
+        // the counts are only believed as far as the data goes
+        let mut args = Vec::with_capacity(count.min(data.len()));
+        for _ in 0..count {
+            if offset >= data.len() {
+                return Err(Parsed::Truncated);
+            }
+            let (len, start) = line(data, offset, b'$')?;
+            let end = start.saturating_add(len);
+            if data.len().saturating_sub(2) < end {
+                return Err(Parsed::Truncated);
+            }
+            if &data[end..end + 2] != b"\r\n" {
+                return Err(Parsed::Invalid);
+            }
+            args.push(Bytes::copy_from_slice(&data[start..end]));
+            offset = end + 2;
+        }
+        Ok(Parsed::Command(args, offset))
+    };
+    parse().unwrap_or_else(|parsed| parsed)
+}
+
+pub fn encode(out: &mut Vec<u8>, args: &[Bytes]) {
+    out.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        out.extend_from_slice(arg);
+        out.extend_from_slice(b"\r\n");
+    }
+}
+
+/// A write the way the AOF logs it and replicas receive it. A relative ttl
+/// would start over whenever the command is replayed, so it is made absolute.
+pub fn translate(storage: &Storage, args: &[RespFrame]) -> Vec<Bytes> {
+    let args: Vec<Bytes> = args.iter().map(argument).collect();
+    let clock = storage.clock();
+    let unix_ms_in = |ms: i64| (clock.to_unix_ms(clock.now()) as i64).saturating_add(ms);
+    match args.as_slice() {
+        [name, key, seconds] if name.eq_ignore_ascii_case(b"EXPIRE") => {
+            let unix_ms = unix_ms_in(integer(seconds).saturating_mul(1000));
+            vec![
+                Bytes::from("PEXPIREAT"),
+                key.clone(),
+                Bytes::from(unix_ms.to_string()),
+            ]
+        }
+        [name, key, ttl, payload, options @ ..]
+            if name.eq_ignore_ascii_case(b"RESTORE")
+                && integer(ttl) != 0
+                && !options
+                    .iter()
+                    .any(|arg| arg.eq_ignore_ascii_case(b"ABSTTL")) =>
+        {
+            let unix_ms = unix_ms_in(integer(ttl));
+            let mut restore = vec![
+                name.clone(),
+                key.clone(),
+                Bytes::from(unix_ms.to_string()),
+                payload.clone(),
+            ];
+            restore.extend_from_slice(options);
+            restore.push(Bytes::from("ABSTTL"));
+            restore
+        }
+        _ => args,
+    }
+}
+
+// commands are only logged once they succeeded, so their numbers parse
+fn integer(arg: &[u8]) -> i64 {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0)
+}
+
+fn argument(frame: &RespFrame) -> Bytes {
+    match frame {
+        RespFrame::BulkString(bytes) => bytes.clone(),
+        RespFrame::SimpleString(s) => Bytes::copy_from_slice(s.as_bytes()),
+        RespFrame::Integer(n) => Bytes::from(n.to_string()),
+        other => Bytes::from(other.raw().to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+
+    use crate::aof::{Manifest, ManifestEntry, Parsed, encode, parse_command};
+    use crate::config::Config;
+    use crate::execute;
+    use crate::resp_frame::RespFrame;
+    use crate::test_util::{TempDir, bulk, ok, run};
+
+    #[test]
+    fn test_commands_round_trip() {
+        let mut data = Vec::new();
+        encode(
+            &mut data,
+            &[Bytes::from("SET"), Bytes::from("k"), Bytes::from("a\r\nb")],
+        );
+        encode(&mut data, &[Bytes::from("DEL"), Bytes::from("k")]);
+
+        let Parsed::Command(args, used) = parse_command(&data) else {
+            panic!("the first command should parse");
+        };
+        assert_eq!(args, vec!["SET", "k", "a\r\nb"]);
+        assert!(
+            matches!(parse_command(&data[used..]), Parsed::Command(args, _) if args == vec!["DEL", "k"])
+        );
+
+        // every prefix of a command is a truncated one
+        for end in 1..used {
+            assert!(
+                matches!(parse_command(&data[..end]), Parsed::Truncated),
+                "{}",
+                end
+            );
+        }
+        assert!(matches!(parse_command(b"GET k\r\n"), Parsed::Invalid));
+        assert!(matches!(
+            parse_command(b"*1\r\n$1\r\nkk\r\n"),
+            Parsed::Invalid
+        ));
+        // counts and lengths past the data are truncated, not allocated
+        assert!(matches!(
+            parse_command(b"*18446744073709551615\r\n$1\r\nk\r\n"),
+            Parsed::Truncated
+        ));
+        assert!(matches!(
+            parse_command(b"*1\r\n$18446744073709551615\r\nk\r\n"),
+            Parsed::Truncated
+        ));
+    }
+
+    #[test]
+    fn test_manifest_format() {
+        let text = "file appendonly.aof.2.incr.aof seq 2 type i\n\
+                    file appendonly.aof.1.base.rdb seq 1 type b\n\
+                    file appendonly.aof.1.incr.aof type i seq 1\n\
+                    file appendonly.aof.0.base.rdb seq 0 type h\n";
+        let manifest = Manifest::parse(text).unwrap();
+        let entry = |name: &str, seq| ManifestEntry {
+            name: name.to_string(),
+            seq,
+        };
+        assert_eq!(
+            manifest,
+            Manifest {
+                base: Some(entry("appendonly.aof.1.base.rdb", 1)),
+                incrs: vec![
+                    entry("appendonly.aof.1.incr.aof", 1),
+                    entry("appendonly.aof.2.incr.aof", 2)
+                ],
+            }
+        );
+        assert_eq!(
+            manifest.to_string(),
+            "file appendonly.aof.1.base.rdb seq 1 type b\n\
+             file appendonly.aof.1.incr.aof seq 1 type i\n\
+             file appendonly.aof.2.incr.aof seq 2 type i\n"
+        );
+        assert!(Manifest::parse("file x seq one type i").is_err());
+    }
+
+    #[test]
+    fn test_aof_replays_writes() {
+        let dir = TempDir::new("aof");
+        let mut db = dir.aof_server();
+        run(&mut db, &["SET", "k", "v"]);
+        run(&mut db, &["EXPIRE", "k", "100"]);
+        run(&mut db, &["INCR", "n"]);
+        run(&mut db, &["MULTI"]);
+        run(&mut db, &["INCR", "n"]);
+        run(&mut db, &["GET", "n"]);
+        run(&mut db, &["EXEC"]);
+        run(
+            &mut db,
+            &["EVAL", "return redis.call('SET', KEYS[1], 'lua')", "1", "s"],
+        );
+        run(&mut db, &["SELECT", "3"]);
+        run(&mut db, &["SET", "other", "x"]);
+        let library = "#!lua name=lib\nredis.register_function('one', function() return 1 end)";
+        run(&mut db, &["FUNCTION", "LOAD", library]);
+        let RespFrame::BulkString(payload) = run(&mut db, &["DUMP", "other"]) else {
+            panic!("DUMP did not return a bulk string");
+        };
+        let restore = RespFrame::Array(vec![
+            bulk("RESTORE"),
+            bulk("restored"),
+            bulk("100000"),
+            RespFrame::BulkString(payload),
+        ]);
+        assert_eq!(execute(restore, &db.state, &mut db.session), ok());
+        // reads and failed commands are not logged
+        run(&mut db, &["GET", "other"]);
+        run(&mut db, &["INCR", "other"]);
+
+        let log = std::fs::read(dir.aof_file("appendonly.aof.1.incr.aof")).unwrap();
+        let log = String::from_utf8_lossy(&log);
+        assert!(log.starts_with("*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n*3\r\n$3\r\nSET\r\n"));
+        assert!(log.contains("PEXPIREAT") && !log.contains("EXPIRE\r\n"));
+        assert_eq!(log.matches("MULTI").count(), 2);
+        assert!(!log.contains("EVAL") && !log.contains("GET"));
+        assert_eq!(log.matches("INCR").count(), 2);
+        assert!(log.contains("ABSTTL"));
+
+        let mut restarted = dir.aof_server();
+        assert_eq!(run(&mut restarted, &["GET", "k"]), bulk("v"));
+        assert!(matches!(
+            run(&mut restarted, &["TTL", "k"]),
+            RespFrame::Integer(99 | 100)
+        ));
+        assert_eq!(run(&mut restarted, &["GET", "n"]), bulk("2"));
+        assert_eq!(run(&mut restarted, &["GET", "s"]), bulk("lua"));
+        assert_eq!(
+            run(&mut restarted, &["FCALL", "one", "0"]),
+            RespFrame::Integer(1)
+        );
+        run(&mut restarted, &["SELECT", "3"]);
+        assert_eq!(run(&mut restarted, &["GET", "other"]), bulk("x"));
+        assert!(matches!(
+            run(&mut restarted, &["TTL", "restored"]),
+            RespFrame::Integer(99 | 100)
+        ));
+
+        // the snapshot is not loaded when there is an AOF
+        assert_eq!(run(&mut restarted, &["SAVE"]), ok());
+        assert_eq!(run(&mut restarted, &["SET", "later", "1"]), ok());
+        run(&mut restarted, &["SELECT", "0"]);
+        run(&mut restarted, &["DEL", "k"]);
+        let mut again = dir.aof_server();
+        assert_eq!(run(&mut again, &["GET", "k"]), RespFrame::Null);
+    }
+
+    #[test]
+    fn test_aof_logs_expired_keys_as_deleted() {
+        let dir = TempDir::new("aof-expired");
+        let mut db = dir.aof_server();
+        run(&mut db, &["SET", "k", "v"]);
+        run(&mut db, &["EXPIRE", "k", "10"]);
+        db.state
+            .db
+            .lock()
+            .unwrap()
+            .clock()
+            .advance(Duration::from_secs(11));
+        run(&mut db, &["SET", "k", "again"]);
+
+        let log = std::fs::read(dir.aof_file("appendonly.aof.1.incr.aof")).unwrap();
+        let log = String::from_utf8_lossy(&log);
+        // the DEL comes before the write that replaced the expired key
+        let deleted = log.find("*2\r\n$3\r\nDEL\r\n$1\r\nk\r\n").unwrap();
+        assert!(deleted < log.find("again").unwrap());
+
+        // the replay does not depend on the clock of the restart
+        let mut restarted = dir.aof_server();
+        assert_eq!(run(&mut restarted, &["GET", "k"]), bulk("again"));
+        assert_eq!(run(&mut restarted, &["TTL", "k"]), RespFrame::Integer(-1));
+    }
+
+    #[test]
+    fn test_aof_truncated_tail() {
+        let dir = TempDir::new("aof-truncated");
+        let mut db = dir.aof_server();
+        run(&mut db, &["SET", "k", "v"]);
+        let incr = dir.aof_file("appendonly.aof.1.incr.aof");
+        let complete = std::fs::metadata(&incr).unwrap().len();
+        let mut log = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&incr)
+            .unwrap();
+        std::io::Write::write_all(&mut log, b"*3\r\n$3\r\nSET\r\n$1\r\nx").unwrap();
+
+        let strict = Config {
+            appendonly: true,
+            aof_load_truncated: false,
+            ..Config::default()
+        };
+        assert!(dir.server_with(strict).is_err());
+
+        let mut restarted = dir.aof_server();
+        assert_eq!(run(&mut restarted, &["GET", "k"]), bulk("v"));
+        assert_eq!(std::fs::metadata(&incr).unwrap().len(), complete);
+    }
+
+    #[test]
+    fn test_aof_truncated_inside_a_transaction() {
+        let multi = "*1\r\n$5\r\nMULTI\r\n*3\r\n$3\r\nSET\r\n$1\r\nx\r\n$1\r\n1\r\n";
+        // cut in the middle of a queued command, and at the end of one
+        for tail in [format!("{multi}*3\r\n$3\r\nSET\r\n$1"), multi.to_string()] {
+            let dir = TempDir::new("aof-truncated-multi");
+            let mut db = dir.aof_server();
+            run(&mut db, &["SET", "a", "1"]);
+            let incr = dir.aof_file("appendonly.aof.1.incr.aof");
+            let complete = std::fs::metadata(&incr).unwrap().len();
+            let mut log = std::fs::OpenOptions::new()
+                .append(true)
+                .open(&incr)
+                .unwrap();
+            std::io::Write::write_all(&mut log, tail.as_bytes()).unwrap();
+
+            let strict = Config {
+                appendonly: true,
+                aof_load_truncated: false,
+                ..Config::default()
+            };
+            assert!(dir.server_with(strict).is_err());
+
+            let mut restarted = dir.aof_server();
+            assert_eq!(std::fs::metadata(&incr).unwrap().len(), complete);
+            assert_eq!(run(&mut restarted, &["GET", "x"]), RespFrame::Null);
+            assert_eq!(run(&mut restarted, &["SET", "b", "2"]), ok());
+
+            let mut again = dir.aof_server();
+            assert_eq!(run(&mut again, &["GET", "a"]), bulk("1"));
+            assert_eq!(run(&mut again, &["GET", "b"]), bulk("2"));
+            assert_eq!(run(&mut again, &["GET", "x"]), RespFrame::Null);
+        }
+    }
+
+    #[test]
+    fn test_bgrewriteaof_compacts_the_log() {
+        let dir = TempDir::new("aof-rewrite");
+        // an existing snapshot seeds the first base
+        let mut db = dir.server();
+        run(&mut db, &["SET", "seed", "1"]);
+        run(&mut db, &["SAVE"]);
+
+        let mut db = dir.aof_server();
+        for _ in 0..10 {
+            run(&mut db, &["INCR", "n"]);
+        }
+        assert_eq!(
+            run(&mut db, &["BGREWRITEAOF"]),
+            RespFrame::SimpleString("Background append only file rewriting started".to_string())
+        );
+        while db.state.aof.rewrite_in_progress() {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        run(&mut db, &["SET", "after", "1"]);
+
+        assert_eq!(
+            std::fs::read_to_string(dir.aof_file("appendonly.aof.manifest")).unwrap(),
+            "file appendonly.aof.2.base.rdb seq 2 type b\n\
+             file appendonly.aof.2.incr.aof seq 2 type i\n"
+        );
+        assert!(!dir.aof_file("appendonly.aof.1.incr.aof").exists());
+        assert!(!dir.aof_file("appendonly.aof.1.base.rdb").exists());
+
+        let mut restarted = dir.aof_server();
+        assert_eq!(run(&mut restarted, &["GET", "seed"]), bulk("1"));
+        assert_eq!(run(&mut restarted, &["GET", "n"]), bulk("10"));
+        assert_eq!(run(&mut restarted, &["GET", "after"]), bulk("1"));
+    }
+
+    #[test]
+    fn test_config_appendonly_at_runtime() {
+        let dir = TempDir::new("aof-config");
+        let mut db = dir.server();
+        run(&mut db, &["SET", "k", "v"]);
+        assert_eq!(run(&mut db, &["CONFIG", "SET", "appendonly", "yes"]), ok());
+        while db.state.aof.rewrite_in_progress() {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        run(&mut db, &["SET", "k2", "v2"]);
+        assert_eq!(
+            run(&mut db, &["CONFIG", "GET", "append*"]),
+            RespFrame::Array(vec![
+                bulk("appendonly"),
+                bulk("yes"),
+                bulk("appendfsync"),
+                bulk("everysec"),
+                bulk("appendfilename"),
+                bulk("appendonly.aof"),
+                bulk("appenddirname"),
+                bulk("appendonlydir"),
+            ])
+        );
+        assert_eq!(run(&mut db, &["CONFIG", "SET", "appendonly", "no"]), ok());
+        run(&mut db, &["SET", "k3", "v3"]);
+
+        let mut restarted = dir.aof_server();
+        assert_eq!(run(&mut restarted, &["GET", "k"]), bulk("v"));
+        assert_eq!(run(&mut restarted, &["GET", "k2"]), bulk("v2"));
+        assert_eq!(run(&mut restarted, &["GET", "k3"]), RespFrame::Null);
+    }
+}
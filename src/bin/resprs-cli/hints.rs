@@ -0,0 +1,266 @@
+// Command hints, taken from the server's COMMAND DOCS once at startup. Redis
+// documents every argument; resprs only has a summary, which is shown
+// instead until the first argument is typed.
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use resprs::RespFrame;
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+#[derive(Debug, Default, PartialEq)]
+pub struct CommandDoc {
+    pub summary: String,
+    pub since: String,
+    pub group: String,
+    // one entry per top level argument, like "key" or "[NX|XX]"
+    pub arguments: Vec<String>,
+}
+
+pub type Docs = HashMap<String, CommandDoc>;
+
+/// Reads a COMMAND DOCS reply, in RESP2 or RESP3 shape.
+pub fn parse_docs(reply: &RespFrame) -> Docs {
+    pairs(reply)
+        .into_iter()
+        .filter_map(|(name, doc)| {
+            let name = text(name)?.to_lowercase();
+            let mut command = CommandDoc::default();
+            for (field, value) in pairs(doc) {
+                match text(field).as_deref() {
+                    Some("summary") => command.summary = text(value).unwrap_or_default(),
+                    Some("since") => command.since = text(value).unwrap_or_default(),
+                    Some("group") => command.group = text(value).unwrap_or_default(),
+                    Some("arguments") => {
+                        command.arguments = items(value).iter().map(render_argument).collect();
+                    }
+                    _ => {}
+                }
+            }
+            Some((name, command))
+        })
+        .collect()
+}
+
+// renders an argument like the redis docs do: [optional], a|b choices,
+// "name [name ...]" for repeats, with the option token in front
+fn render_argument(argument: &RespFrame) -> String {
+    let mut name = String::new();
+    let mut kind = String::new();
+    let mut token = None;
+    let mut flags = Vec::new();
+    let mut nested = Vec::new();
+    for (field, value) in pairs(argument) {
+        match text(field).as_deref() {
+            Some("name") if name.is_empty() => name = text(value).unwrap_or_default(),
+            Some("display_text") => name = text(value).unwrap_or_default(),
+            Some("type") => kind = text(value).unwrap_or_default(),
+            Some("token") => token = text(value),
+            Some("flags") => flags = items(value).iter().filter_map(text).collect(),
+            Some("arguments") => nested = items(value).iter().map(render_argument).collect(),
+            _ => {}
+        }
+    }
+
+    let optional = flags.iter().any(|flag| flag == "optional");
+    let mut rendered = match kind.as_str() {
+        "oneof" if optional => nested.join("|"),
+        "oneof" => format!("({})", nested.join("|")),
+        "block" => nested.join(" "),
+        "pure-token" => String::new(),
+        _ => name,
+    };
+    if let Some(token) = token {
+        rendered = if rendered.is_empty() {
+            token
+        } else {
+            format!("{} {}", token, rendered)
+        };
+    }
+    if flags.iter().any(|flag| flag == "multiple") {
+        rendered = format!("{} [{} ...]", rendered, rendered);
+    }
+    if optional {
+        rendered = format!("[{}]", rendered);
+    }
+    rendered
+}
+
+fn pairs(frame: &RespFrame) -> Vec<(&RespFrame, &RespFrame)> {
+    match frame {
+        RespFrame::Map(entries) => entries.iter().map(|(key, value)| (key, value)).collect(),
+        RespFrame::Array(items) => items
+            .chunks_exact(2)
+            .map(|pair| (&pair[0], &pair[1]))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn items(frame: &RespFrame) -> &[RespFrame] {
+    match frame {
+        RespFrame::Array(items) => items,
+        _ => &[],
+    }
+}
+
+fn text(frame: &RespFrame) -> Option<String> {
+    match frame {
+        RespFrame::BulkString(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+        RespFrame::SimpleString(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// What to show after `line`: the arguments not typed yet, or the summary.
+pub fn hint_for(docs: &Docs, line: &str) -> Option<String> {
+    let mut words = line.split_whitespace();
+    let name = words.next()?.to_lowercase();
+    let doc = docs.get(&name)?;
+    let typed = words.count();
+    let padding = if line.ends_with(char::is_whitespace) {
+        ""
+    } else {
+        " "
+    };
+
+    if doc.arguments.is_empty() {
+        return (typed == 0 && !doc.summary.is_empty())
+            .then(|| format!("{}# {}", padding, doc.summary));
+    }
+    let remaining: Vec<&str> = doc
+        .arguments
+        .iter()
+        .skip(typed)
+        .map(String::as_str)
+        .collect();
+    (!remaining.is_empty()).then(|| format!("{}{}", padding, remaining.join(" ")))
+}
+
+pub struct CliHelper {
+    pub docs: Docs,
+}
+
+impl Hinter for CliHelper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _: &Context<'_>) -> Option<String> {
+        // only hint with the cursor at the end, like redis-cli
+        if pos < line.len() {
+            return None;
+        }
+        hint_for(&self.docs, line)
+    }
+}
+
+impl Highlighter for CliHelper {
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("\x1b[90m{}\x1b[0m", hint))
+    }
+}
+
+impl Completer for CliHelper {
+    type Candidate = String;
+}
+
+impl Validator for CliHelper {}
+
+impl Helper for CliHelper {}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use resprs::RespFrame;
+
+    use crate::hints::{hint_for, parse_docs};
+
+    fn bulk(s: &str) -> RespFrame {
+        RespFrame::BulkString(Bytes::copy_from_slice(s.as_bytes()))
+    }
+
+    fn fields(pairs: &[(&str, RespFrame)]) -> RespFrame {
+        RespFrame::Array(
+            pairs
+                .iter()
+                .flat_map(|(name, value)| [bulk(name), value.clone()])
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_redis_arguments_become_hints() {
+        let condition = fields(&[
+            ("name", bulk("condition")),
+            ("type", bulk("oneof")),
+            ("flags", RespFrame::Array(vec![bulk("optional")])),
+            (
+                "arguments",
+                RespFrame::Array(vec![
+                    fields(&[
+                        ("name", bulk("nx")),
+                        ("type", bulk("pure-token")),
+                        ("token", bulk("NX")),
+                    ]),
+                    fields(&[
+                        ("name", bulk("xx")),
+                        ("type", bulk("pure-token")),
+                        ("token", bulk("XX")),
+                    ]),
+                ]),
+            ),
+        ]);
+        let reply = RespFrame::Array(vec![
+            bulk("set"),
+            fields(&[
+                ("summary", bulk("Sets the string value of a key.")),
+                (
+                    "arguments",
+                    RespFrame::Array(vec![
+                        fields(&[("name", bulk("key")), ("type", bulk("key"))]),
+                        fields(&[("name", bulk("value")), ("type", bulk("string"))]),
+                        condition,
+                    ]),
+                ),
+            ]),
+            bulk("del"),
+            fields(&[(
+                "arguments",
+                RespFrame::Array(vec![fields(&[
+                    ("name", bulk("key")),
+                    ("type", bulk("key")),
+                    ("flags", RespFrame::Array(vec![bulk("multiple")])),
+                ])]),
+            )]),
+        ]);
+        let docs = parse_docs(&reply);
+
+        assert_eq!(
+            hint_for(&docs, "set").as_deref(),
+            Some(" key value [NX|XX]")
+        );
+        assert_eq!(hint_for(&docs, "SET k ").as_deref(), Some("value [NX|XX]"));
+        assert_eq!(hint_for(&docs, "del ").as_deref(), Some("key [key ...]"));
+        assert_eq!(hint_for(&docs, "nope"), None);
+    }
+
+    #[test]
+    fn test_summary_without_arguments() {
+        let reply = RespFrame::Map(vec![(
+            bulk("get"),
+            RespFrame::Map(vec![(
+                bulk("summary"),
+                bulk("Returns the string value of a key."),
+            )]),
+        )]);
+        let docs = parse_docs(&reply);
+
+        assert_eq!(
+            hint_for(&docs, "GET ").as_deref(),
+            Some("# Returns the string value of a key.")
+        );
+        assert_eq!(hint_for(&docs, "GET k"), None);
+    }
+}
@@ -0,0 +1,134 @@
+// Splits a command line into arguments the way redis-cli does: words are
+// separated by whitespace, "double quotes" understand \n \r \t \b \a \\ \"
+// and \xHH escapes, 'single quotes' only \'. A closing quote must end the
+// word.
+
+/// The arguments of `line`, or `None` for unbalanced quotes.
+pub fn split_args(line: &str) -> Option<Vec<Vec<u8>>> {
+    let mut args = Vec::new();
+    let mut chars = line.bytes().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Some(args);
+        };
+
+        let mut arg = Vec::new();
+        match first {
+            b'"' => {
+                chars.next();
+                loop {
+                    match chars.next()? {
+                        b'"' => break,
+                        b'\\' => match chars.next()? {
+                            b'n' => arg.push(b'\n'),
+                            b'r' => arg.push(b'\r'),
+                            b't' => arg.push(b'\t'),
+                            b'b' => arg.push(0x08),
+                            b'a' => arg.push(0x07),
+                            b'x' => {
+                                let high = chars.next_if(u8::is_ascii_hexdigit);
+                                let low = chars.next_if(u8::is_ascii_hexdigit);
+                                match (high, low) {
+                                    (Some(high), Some(low)) => {
+                                        arg.push(hex_value(high) << 4 | hex_value(low));
+                                    }
+                                    // not a valid escape, keep it as typed
+                                    (high, low) => {
+                                        arg.push(b'x');
+                                        arg.extend(high.into_iter().chain(low));
+                                    }
+                                }
+                            }
+                            other => arg.push(other),
+                        },
+                        other => arg.push(other),
+                    }
+                }
+                ends_word(chars.peek())?;
+            }
+            b'\'' => {
+                chars.next();
+                loop {
+                    match chars.next()? {
+                        b'\'' => break,
+                        b'\\' if chars.peek() == Some(&b'\'') => {
+                            chars.next();
+                            arg.push(b'\'');
+                        }
+                        other => arg.push(other),
+                    }
+                }
+                ends_word(chars.peek())?;
+            }
+            _ => {
+                while let Some(c) = chars.next_if(|c| !c.is_ascii_whitespace()) {
+                    arg.push(c);
+                }
+            }
+        }
+        args.push(arg);
+    }
+}
+
+fn ends_word(next: Option<&u8>) -> Option<()> {
+    match next {
+        None => Some(()),
+        Some(c) if c.is_ascii_whitespace() => Some(()),
+        Some(_) => None,
+    }
+}
+
+fn hex_value(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _ => digit - b'A' + 10,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::input::split_args;
+
+    fn split(line: &str) -> Option<Vec<String>> {
+        split_args(line).map(|args| {
+            args.into_iter()
+                .map(|arg| String::from_utf8_lossy(&arg).into_owned())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn test_plain_words() {
+        assert_eq!(
+            split("  SET key   value "),
+            Some(vec!["SET".into(), "key".into(), "value".into()])
+        );
+        assert_eq!(split(""), Some(vec![]));
+    }
+
+    #[test]
+    fn test_quotes_and_escapes() {
+        assert_eq!(
+            split(r#"SET "a key" "line\none\x41""#),
+            Some(vec!["SET".into(), "a key".into(), "line\noneA".into()])
+        );
+        assert_eq!(
+            split(r"SET k 'it\'s \n'"),
+            Some(vec!["SET".into(), "k".into(), "it's \\n".into()])
+        );
+        assert_eq!(
+            split(r#"SET "" ''"#),
+            Some(vec!["SET".into(), "".into(), "".into()])
+        );
+    }
+
+    #[test]
+    fn test_unbalanced_quotes_are_rejected() {
+        assert_eq!(split(r#"SET "key"#), None);
+        assert_eq!(split("SET 'key"), None);
+        assert_eq!(split(r#"SET "key"value"#), None);
+    }
+}
@@ -0,0 +1,395 @@
+// resprs-cli, a small redis-cli for resprs and Redis servers.
+//
+//   resprs-cli [-h host] [-p port] [-a password] [-n db] [--raw] [cmd [arg ...]]
+//   resprs-cli --pipe < commands.resp
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::PathBuf;
+
+use bytes::Bytes;
+use resprs::{RespFrame, parser, serializer};
+use rustyline::Editor;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::hints::{CliHelper, Docs, parse_docs};
+use crate::input::split_args;
+
+mod hints;
+mod input;
+
+const HISTORY_FILE: &str = ".resprs_cli_history";
+
+struct Options {
+    host: String,
+    port: u16,
+    user: String,
+    password: Option<String>,
+    db: i64,
+    raw: bool,
+    pipe: bool,
+    // a command given on the command line runs once instead of the prompt
+    command: Vec<String>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options {
+            host: "127.0.0.1".to_string(),
+            port: 6380,
+            user: "default".to_string(),
+            password: None,
+            db: 0,
+            // like redis-cli, output that is not a terminal is raw by default
+            raw: !io::stdout().is_terminal(),
+            pipe: false,
+            command: Vec::new(),
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or(format!("missing value for '{}'", name));
+            match arg.as_str() {
+                "-h" => options.host = value("-h")?,
+                "-p" => {
+                    let port = value("-p")?;
+                    options.port = port
+                        .parse()
+                        .map_err(|_| format!("invalid port '{}'", port))?;
+                }
+                "--user" => options.user = value("--user")?,
+                "-a" | "--pass" => options.password = Some(value("-a")?),
+                "-n" => {
+                    let db = value("-n")?;
+                    options.db = db.parse().map_err(|_| format!("invalid db '{}'", db))?;
+                }
+                "--raw" => options.raw = true,
+                "--no-raw" => options.raw = false,
+                "--pipe" => options.pipe = true,
+                "--help" => return Err(String::new()),
+                _ if arg.starts_with('-') && options.command.is_empty() => {
+                    return Err(format!("unknown option '{}'", arg));
+                }
+                _ => {
+                    options.command.push(arg);
+                    options.command.extend(args.by_ref());
+                }
+            }
+        }
+        Ok(options)
+    }
+
+    fn addr(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+struct Connection {
+    stream: BufReader<TcpStream>,
+}
+
+impl Connection {
+    async fn open(options: &Options) -> io::Result<Connection> {
+        let stream = TcpStream::connect(options.addr()).await?;
+        let mut connection = Connection {
+            stream: BufReader::new(stream),
+        };
+
+        // HELLO 2 authenticates without switching protocols, resprs accepts any
+        // credentials and redis checks them
+        if let Some(password) = &options.password {
+            let hello = ["HELLO", "2", "AUTH", &options.user, password];
+            if let RespFrame::Error(e) = connection.request(&words(&hello)).await? {
+                eprintln!("Warning: AUTH failed: {}", e);
+            }
+        }
+        if options.db != 0 {
+            let select = ["SELECT", &options.db.to_string()];
+            if let RespFrame::Error(e) = connection.request(&words(&select)).await? {
+                eprintln!("Warning: SELECT {} failed: {}", options.db, e);
+            }
+        }
+        Ok(connection)
+    }
+
+    async fn send(&mut self, args: &[Vec<u8>]) -> io::Result<()> {
+        let frame = RespFrame::Array(
+            args.iter()
+                .map(|arg| RespFrame::BulkString(Bytes::copy_from_slice(arg)))
+                .collect(),
+        );
+        serializer::serialize_frame(self.stream.get_mut(), frame).await?;
+        self.stream.get_mut().flush().await
+    }
+
+    async fn read(&mut self) -> io::Result<RespFrame> {
+        parser::parse_frame(&mut self.stream).await
+    }
+
+    async fn request(&mut self, args: &[Vec<u8>]) -> io::Result<RespFrame> {
+        self.send(args).await?;
+        self.read().await
+    }
+}
+
+// redis-cli layout on a terminal, bare values with --raw
+fn format_reply(frame: &RespFrame, raw: bool) -> String {
+    if raw {
+        frame.raw().to_string()
+    } else {
+        frame.to_string()
+    }
+}
+
+fn words(words: &[&str]) -> Vec<Vec<u8>> {
+    words.iter().map(|word| word.as_bytes().to_vec()).collect()
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("Invalid arguments: {}", e);
+            }
+            eprintln!(
+                "Usage: resprs-cli [-h host] [-p port] [--user user] [-a password] [-n db]\n                  [--raw | --no-raw] [--pipe] [cmd [arg ...]]"
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let result = if options.pipe {
+        pipe(&options).await
+    } else if !options.command.is_empty() {
+        run_once(&options).await
+    } else {
+        repl(&options).await
+    };
+    if let Err(e) = result {
+        eprintln!("Could not connect to {}: {}", options.addr(), e);
+        std::process::exit(1);
+    }
+}
+
+async fn run_once(options: &Options) -> io::Result<()> {
+    let mut connection = Connection::open(options).await?;
+    let args: Vec<Vec<u8>> = options
+        .command
+        .iter()
+        .map(|arg| arg.as_bytes().to_vec())
+        .collect();
+    let reply = connection.request(&args).await?;
+    println!("{}", format_reply(&reply, options.raw));
+    if is_subscribe(&args) {
+        read_messages(&mut connection, options.raw).await?;
+    }
+    Ok(())
+}
+
+async fn repl(options: &Options) -> io::Result<()> {
+    let mut connection = Some(Connection::open(options).await?);
+    let docs = match &mut connection {
+        Some(connection) => load_docs(connection).await,
+        None => Docs::new(),
+    };
+
+    let mut editor: Editor<CliHelper, DefaultHistory> =
+        Editor::new().map_err(|e| io::Error::other(e.to_string()))?;
+    editor.set_helper(Some(CliHelper { docs }));
+    let history = history_path();
+    if let Some(history) = &history {
+        let _ = editor.load_history(history);
+    }
+
+    let mut db = options.db;
+    loop {
+        let prompt = match (&connection, db) {
+            (None, _) => "not connected> ".to_string(),
+            (Some(_), 0) => format!("{}> ", options.addr()),
+            (Some(_), db) => format!("{}[{}]> ", options.addr(), db),
+        };
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("{}", e);
+                break;
+            }
+        };
+        let Some(args) = split_args(&line) else {
+            println!("Invalid argument(s)");
+            continue;
+        };
+        let Some(name) = args.first() else {
+            continue;
+        };
+        let _ = editor.add_history_entry(line.as_str());
+
+        match String::from_utf8_lossy(name).to_lowercase().as_str() {
+            "quit" | "exit" => break,
+            "help" => {
+                let helper = editor.helper().expect("helper is set above");
+                print_help(&helper.docs, args.get(1));
+                continue;
+            }
+            _ => {}
+        }
+
+        // a lost connection is reopened on the next command
+        if connection.is_none() {
+            connection = Connection::open(options).await.ok();
+            db = options.db;
+        }
+        let Some(open) = &mut connection else {
+            println!("Could not connect to {}", options.addr());
+            continue;
+        };
+
+        match open.request(&args).await {
+            Ok(reply) => {
+                println!("{}", format_reply(&reply, options.raw));
+                if let Some(selected) = selected_db(&args, &reply) {
+                    db = selected;
+                }
+                if is_subscribe(&args) && !matches!(reply, RespFrame::Error(_)) {
+                    read_messages(open, options.raw).await?;
+                }
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+                connection = None;
+            }
+        }
+    }
+
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
+    }
+    Ok(())
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+// servers without COMMAND DOCS just give no hints
+async fn load_docs(connection: &mut Connection) -> Docs {
+    match connection.request(&words(&["COMMAND", "DOCS"])).await {
+        Ok(reply) => parse_docs(&reply),
+        Err(_) => Docs::new(),
+    }
+}
+
+fn print_help(docs: &Docs, command: Option<&Vec<u8>>) {
+    let Some(command) = command else {
+        println!("Type: \"help <command>\" for help on <command>");
+        return;
+    };
+    let name = String::from_utf8_lossy(command).to_lowercase();
+    let Some(doc) = docs.get(&name) else {
+        println!(
+            "No help for {}",
+            RespFrame::BulkString(Bytes::copy_from_slice(command))
+        );
+        return;
+    };
+    println!();
+    let usage = format!("{} {}", name.to_uppercase(), doc.arguments.join(" "));
+    println!("  {}", usage.trim_end());
+    println!("  summary: {}", doc.summary);
+    println!("  since: {}", doc.since);
+    println!("  group: {}", doc.group);
+    println!();
+}
+
+fn is_subscribe(args: &[Vec<u8>]) -> bool {
+    args.first().is_some_and(|name| {
+        [&b"SUBSCRIBE"[..], b"PSUBSCRIBE", b"SSUBSCRIBE"]
+            .iter()
+            .any(|command| name.eq_ignore_ascii_case(command))
+    })
+}
+
+fn selected_db(args: &[Vec<u8>], reply: &RespFrame) -> Option<i64> {
+    let [name, index] = args else {
+        return None;
+    };
+    if !name.eq_ignore_ascii_case(b"SELECT") || matches!(reply, RespFrame::Error(_)) {
+        return None;
+    }
+    std::str::from_utf8(index).ok()?.parse().ok()
+}
+
+// a subscribed connection only carries messages, printed until Ctrl-C
+async fn read_messages(connection: &mut Connection, raw: bool) -> io::Result<()> {
+    println!("Reading messages... (press Ctrl-C to quit)");
+    loop {
+        let frame = connection.read().await?;
+        println!("{}", format_reply(&frame, raw));
+    }
+}
+
+// Mass insertion: stdin already holds RESP encoded commands. They are sent as
+// they are, followed by an ECHO of a random marker, and replies are counted
+// until the marker comes back.
+async fn pipe(options: &Options) -> io::Result<()> {
+    let mut input = Vec::new();
+    io::stdin().read_to_end(&mut input)?;
+
+    let mut connection = Connection::open(options).await?;
+    let (read_half, mut write_half) = connection.stream.get_mut().split();
+    let mut reader = BufReader::new(read_half);
+
+    let marker = echo_marker();
+    let sending = async {
+        write_half.write_all(&input).await?;
+        let echo = RespFrame::Array(vec![
+            RespFrame::BulkString(Bytes::from_static(b"ECHO")),
+            RespFrame::BulkString(marker.clone()),
+        ]);
+        serializer::serialize_frame(&mut write_half, echo).await?;
+        write_half.flush().await?;
+        println!("All data transferred. Waiting for the last reply...");
+        io::Result::Ok(())
+    };
+    // replies are read while sending, so neither side's buffers fill up
+    let receiving = async {
+        let (mut errors, mut replies) = (0u64, 0u64);
+        loop {
+            match parser::parse_frame(&mut reader).await? {
+                RespFrame::BulkString(echoed) if echoed == marker => break,
+                RespFrame::Error(e) => {
+                    eprintln!("{}", e);
+                    errors += 1;
+                }
+                _ => {}
+            }
+            replies += 1;
+        }
+        io::Result::Ok((errors, replies))
+    };
+    let (sent, received) = tokio::join!(sending, receiving);
+    sent?;
+    let (errors, replies) = received?;
+
+    println!("Last reply received from server.");
+    println!("errors: {}, replies: {}", errors, replies);
+    io::stdout().flush()?;
+    if errors > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn echo_marker() -> Bytes {
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut marker = String::new();
+    for _ in 0..2 {
+        let hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        marker.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    Bytes::from(marker)
+}
@@ -0,0 +1,426 @@
+// An async RESP client, usable against resprs or a real redis. A `Client` is
+// one connection shared by all its clones: requests from concurrent tasks are
+// written back to back without waiting for replies, and replies are matched
+// to requests by order, which RESP guarantees.
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter, ReadHalf, WriteHalf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::resp_frame::{FromResp, FromRespError, RespFrame};
+use crate::{parser, serializer};
+
+pub use self::pool::{Pool, PooledClient};
+pub use self::subscriber::{Message, Subscriber};
+
+mod pool;
+mod subscriber;
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    // the server answered with an error reply
+    Server(String),
+    // a reply whose type the typed helper did not expect
+    UnexpectedReply(RespFrame),
+    // the connection went away before the reply arrived
+    Closed,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "io error: {}", e),
+            ClientError::Server(message) => write!(f, "{}", message),
+            ClientError::UnexpectedReply(frame) => write!(f, "unexpected reply: {:?}", frame),
+            ClientError::Closed => write!(f, "connection closed"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+impl From<FromRespError> for ClientError {
+    fn from(e: FromRespError) -> Self {
+        ClientError::UnexpectedReply(e.into_frame())
+    }
+}
+
+struct Request {
+    frame: RespFrame,
+    reply: oneshot::Sender<RespFrame>,
+}
+
+/// A pipelined connection. Clones share the connection.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), resprs::client::ClientError> {
+/// use resprs::client::Client;
+///
+/// let client = Client::connect("127.0.0.1:6380").await?;
+/// client.set("greeting", "hello").await?;
+/// assert_eq!(client.get("greeting").await?.as_deref(), Some(&b"hello"[..]));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Client {
+    requests: mpsc::UnboundedSender<Request>,
+    connection: Arc<Connection>,
+}
+
+// what the clones of a client and its reader task know about the connection
+#[derive(Default)]
+struct Connection {
+    // set by the reader once the server hung up
+    closed: AtomicBool,
+    // a MULTI or WATCH not ended by EXEC, DISCARD or UNWATCH yet
+    in_multi: AtomicBool,
+    watching: AtomicBool,
+    // SELECT, a subscription, CLIENT settings and the like, which stay
+    changed: AtomicBool,
+}
+
+impl Client {
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Client, ClientError> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(Client::from_stream(stream))
+    }
+
+    /// A client over an already open stream, such as an in-memory pipe.
+    pub fn from_stream<S>(stream: S) -> Client
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (read_half, write_half) = tokio::io::split(stream);
+        let (requests, pending_requests) = mpsc::unbounded_channel();
+        let (pending, waiting) = mpsc::unbounded_channel();
+        tokio::spawn(write_requests(write_half, pending_requests, pending));
+        let connection = Arc::new(Connection::default());
+        tokio::spawn(read_replies(read_half, waiting, connection.clone()));
+        Client {
+            requests,
+            connection,
+        }
+    }
+
+    /// Whether the connection is gone, after which every request fails.
+    pub fn is_closed(&self) -> bool {
+        self.requests.is_closed() || self.connection.closed.load(Ordering::Relaxed)
+    }
+
+    /// Whether a command sent left the connection in another state than a
+    /// fresh one, such as another database or an open transaction.
+    pub fn session_changed(&self) -> bool {
+        let connection = &self.connection;
+        connection.in_multi.load(Ordering::Relaxed)
+            || connection.watching.load(Ordering::Relaxed)
+            || connection.changed.load(Ordering::Relaxed)
+    }
+
+    /// Sends a frame and returns the reply as is, error replies included.
+    pub async fn send(&self, frame: RespFrame) -> Result<RespFrame, ClientError> {
+        self.track_session(&frame);
+        let (reply, receiver) = oneshot::channel();
+        self.requests
+            .send(Request { frame, reply })
+            .map_err(|_| ClientError::Closed)?;
+        receiver.await.map_err(|_| ClientError::Closed)
+    }
+
+    // notes the commands that change the connection for the commands after
+    // them, whether or not they succeed
+    fn track_session(&self, frame: &RespFrame) {
+        let RespFrame::Array(items) = frame else {
+            return;
+        };
+        let Some(RespFrame::BulkString(name)) = items.first() else {
+            return;
+        };
+        let connection = &self.connection;
+        match name.to_ascii_uppercase().as_slice() {
+            b"MULTI" => connection.in_multi.store(true, Ordering::Relaxed),
+            b"WATCH" => connection.watching.store(true, Ordering::Relaxed),
+            b"EXEC" | b"DISCARD" => {
+                connection.in_multi.store(false, Ordering::Relaxed);
+                connection.watching.store(false, Ordering::Relaxed);
+            }
+            b"UNWATCH" => connection.watching.store(false, Ordering::Relaxed),
+            b"SELECT" | b"SUBSCRIBE" | b"PSUBSCRIBE" | b"SSUBSCRIBE" | b"CLIENT" | b"HELLO"
+            | b"AUTH" | b"READONLY" | b"MONITOR" | b"REPLICAOF" | b"SLAVEOF" | b"SYNC"
+            | b"PSYNC" => connection.changed.store(true, Ordering::Relaxed),
+            _ => {}
+        }
+    }
+
+    /// Runs a command given as its words, turning error replies into `Err`.
+    pub async fn command<A: AsRef<[u8]>>(&self, args: &[A]) -> Result<RespFrame, ClientError> {
+        match self.send(command(args)).await? {
+            RespFrame::Error(message) => Err(ClientError::Server(message)),
+            reply => Ok(reply),
+        }
+    }
+
+    /// Runs a command and reads the reply into `T`, see [`FromResp`].
+    pub async fn query<T: FromResp, A: AsRef<[u8]>>(&self, args: &[A]) -> Result<T, ClientError> {
+        Ok(T::from_resp(self.command(args).await?)?)
+    }
+
+    pub async fn ping(&self) -> Result<(), ClientError> {
+        match self.command(&["PING"]).await? {
+            RespFrame::SimpleString(_) => Ok(()),
+            reply => Err(ClientError::UnexpectedReply(reply)),
+        }
+    }
+
+    pub async fn get(&self, key: impl AsRef<[u8]>) -> Result<Option<Bytes>, ClientError> {
+        self.query(&[b"GET", key.as_ref()]).await
+    }
+
+    pub async fn set(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+    ) -> Result<(), ClientError> {
+        let reply = self
+            .command(&[b"SET", key.as_ref(), value.as_ref()])
+            .await?;
+        ok(reply)
+    }
+
+    pub async fn getset(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+    ) -> Result<Option<Bytes>, ClientError> {
+        self.query(&[b"GETSET", key.as_ref(), value.as_ref()]).await
+    }
+
+    pub async fn mget<K: AsRef<[u8]>>(
+        &self,
+        keys: &[K],
+    ) -> Result<Vec<Option<Bytes>>, ClientError> {
+        self.query(&with_keys(b"MGET", keys)).await
+    }
+
+    pub async fn incr(&self, key: impl AsRef<[u8]>) -> Result<i64, ClientError> {
+        self.query(&[b"INCR", key.as_ref()]).await
+    }
+
+    pub async fn decr(&self, key: impl AsRef<[u8]>) -> Result<i64, ClientError> {
+        self.query(&[b"DECR", key.as_ref()]).await
+    }
+
+    pub async fn incrby(&self, key: impl AsRef<[u8]>, amount: i64) -> Result<i64, ClientError> {
+        let amount = amount.to_string();
+        self.query(&[b"INCRBY", key.as_ref(), amount.as_bytes()])
+            .await
+    }
+
+    pub async fn append(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+    ) -> Result<i64, ClientError> {
+        self.query(&[b"APPEND", key.as_ref(), value.as_ref()]).await
+    }
+
+    pub async fn del<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<i64, ClientError> {
+        self.query(&with_keys(b"DEL", keys)).await
+    }
+
+    pub async fn exists<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<i64, ClientError> {
+        self.query(&with_keys(b"EXISTS", keys)).await
+    }
+
+    /// Sets a ttl in seconds, returning whether the key existed.
+    pub async fn expire(&self, key: impl AsRef<[u8]>, seconds: i64) -> Result<bool, ClientError> {
+        let seconds = seconds.to_string();
+        self.query(&[b"EXPIRE", key.as_ref(), seconds.as_bytes()])
+            .await
+    }
+
+    /// The ttl in seconds, -1 without a ttl and -2 for a missing key.
+    pub async fn ttl(&self, key: impl AsRef<[u8]>) -> Result<i64, ClientError> {
+        self.query(&[b"TTL", key.as_ref()]).await
+    }
+
+    /// Publishes a message, returning how many subscribers received it.
+    pub async fn publish(
+        &self,
+        channel: impl AsRef<[u8]>,
+        message: impl AsRef<[u8]>,
+    ) -> Result<i64, ClientError> {
+        self.query(&[b"PUBLISH", channel.as_ref(), message.as_ref()])
+            .await
+    }
+}
+
+// Requests are written as they come and only flushed once none are waiting,
+// so concurrent requests share writes. The reply slot is queued for the
+// reader before the request is written, so replies always find it.
+async fn write_requests<S>(
+    write_half: WriteHalf<S>,
+    mut requests: mpsc::UnboundedReceiver<Request>,
+    pending: mpsc::UnboundedSender<oneshot::Sender<RespFrame>>,
+) where
+    S: AsyncWrite,
+{
+    let mut writer = BufWriter::new(write_half);
+
+    while let Some(Request { frame, reply }) = requests.recv().await {
+        if pending.send(reply).is_err() {
+            break;
+        }
+        if serializer::serialize_frame(&mut writer, frame)
+            .await
+            .is_err()
+        {
+            break;
+        }
+        if requests.is_empty() && writer.flush().await.is_err() {
+            break;
+        }
+    }
+    // closing our side makes the server hang up, which stops the reader
+    let _ = writer.shutdown().await;
+}
+
+async fn read_replies<S>(
+    read_half: ReadHalf<S>,
+    mut waiting: mpsc::UnboundedReceiver<oneshot::Sender<RespFrame>>,
+    connection: Arc<Connection>,
+) where
+    S: AsyncRead,
+{
+    let mut reader = BufReader::new(read_half);
+
+    while let Ok(frame) = parser::parse_frame(&mut reader).await {
+        // out of band pushes, like tracking invalidations, answer no request
+        if matches!(frame, RespFrame::Push(_)) {
+            continue;
+        }
+        let Some(reply) = waiting.recv().await else {
+            break;
+        };
+        // the caller may have stopped waiting, its reply is dropped then
+        let _ = reply.send(frame);
+    }
+    connection.closed.store(true, Ordering::Relaxed);
+    // dropping the receiver fails every request still waiting with Closed
+}
+
+fn command<A: AsRef<[u8]>>(args: &[A]) -> RespFrame {
+    RespFrame::Array(
+        args.iter()
+            .map(|arg| RespFrame::BulkString(Bytes::copy_from_slice(arg.as_ref())))
+            .collect(),
+    )
+}
+
+fn with_keys<'a, K: AsRef<[u8]>>(name: &'a [u8], keys: &'a [K]) -> Vec<&'a [u8]> {
+    std::iter::once(name)
+        .chain(keys.iter().map(|key| key.as_ref()))
+        .collect()
+}
+
+fn ok(reply: RespFrame) -> Result<(), ClientError> {
+    match reply {
+        RespFrame::SimpleString(s) if s == "OK" => Ok(()),
+        reply => Err(ClientError::UnexpectedReply(reply)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::client::{Client, ClientError};
+    use crate::resp_frame::RespFrame;
+    use crate::testing::TestServer;
+
+    #[tokio::test]
+    async fn test_typed_helpers() {
+        let server = TestServer::start().await;
+        let client = Client::connect(server.addr()).await.unwrap();
+
+        client.ping().await.unwrap();
+        assert_eq!(client.get("k").await.unwrap(), None);
+        client.set("k", "v").await.unwrap();
+        assert_eq!(client.get("k").await.unwrap(), Some(Bytes::from("v")));
+        assert_eq!(client.append("k", "w").await.unwrap(), 2);
+        assert_eq!(client.incrby("n", 5).await.unwrap(), 5);
+        assert_eq!(client.decr("n").await.unwrap(), 4);
+        assert_eq!(
+            client.mget(&["k", "missing"]).await.unwrap(),
+            vec![Some(Bytes::from("vw")), None]
+        );
+        assert!(client.expire("k", 100).await.unwrap());
+        assert_eq!(client.ttl("k").await.unwrap(), 99);
+        assert_eq!(client.exists(&["k", "n", "missing"]).await.unwrap(), 2);
+        assert_eq!(client.del(&["k", "n"]).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_error_replies() {
+        let server = TestServer::start().await;
+        let client = Client::from_stream(server.connect_duplex());
+
+        client.set("k", "text").await.unwrap();
+        let Err(ClientError::Server(message)) = client.incr("k").await else {
+            panic!("INCR of a string did not fail");
+        };
+        assert_eq!(message, "ERR value is not an integer or out of range");
+        // raw sends hand error replies back untouched
+        assert_eq!(
+            client
+                .send(RespFrame::Array(vec![RespFrame::BulkString(Bytes::from(
+                    "NOPE"
+                ))]))
+                .await
+                .unwrap(),
+            RespFrame::Error("ERR unknown command 'NOPE', with args beginning with: ".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_share_one_connection() {
+        let server = TestServer::start().await;
+        let client = Client::connect(server.addr()).await.unwrap();
+
+        let tasks: Vec<_> = (0..100)
+            .map(|_| {
+                let client = client.clone();
+                tokio::spawn(async move { client.incr("counter").await.unwrap() })
+            })
+            .collect();
+        let mut seen = Vec::new();
+        for task in tasks {
+            seen.push(task.await.unwrap());
+        }
+        seen.sort();
+        assert_eq!(seen, (1..=100).collect::<Vec<i64>>());
+    }
+
+    #[tokio::test]
+    async fn test_requests_fail_once_the_server_is_gone() {
+        let server = TestServer::start().await;
+        let client = Client::connect(server.addr()).await.unwrap();
+        client.ping().await.unwrap();
+
+        server.shutdown().await;
+        assert!(matches!(client.ping().await, Err(ClientError::Closed)));
+    }
+}
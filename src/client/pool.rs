@@ -0,0 +1,176 @@
+// A bounded set of connections to one server. Pipelining already lets many
+// tasks share a `Client`; the pool is for commands that tie up a connection,
+// like blocking reads or MULTI, and for spreading load over several sockets.
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::client::{Client, ClientError};
+
+struct PoolInner {
+    addr: String,
+    idle: Mutex<Vec<Client>>,
+    permits: Arc<Semaphore>,
+}
+
+/// Hands out at most `max_size` connections at a time; `get` waits for one
+/// to come back when all are in use. Cheap to clone.
+#[derive(Clone)]
+pub struct Pool {
+    inner: Arc<PoolInner>,
+}
+
+impl Pool {
+    /// Connections are opened lazily, the first `get` does not wait for the
+    /// rest of the pool.
+    pub fn new(addr: impl Into<String>, max_size: usize) -> Pool {
+        Pool {
+            inner: Arc::new(PoolInner {
+                addr: addr.into(),
+                idle: Mutex::new(Vec::new()),
+                permits: Arc::new(Semaphore::new(max_size)),
+            }),
+        }
+    }
+
+    /// A connection for the caller alone until the returned guard drops.
+    pub async fn get(&self) -> Result<PooledClient, ClientError> {
+        let permit = self
+            .inner
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| ClientError::Closed)?;
+
+        // idle connections may have died while nobody was using them
+        let reused = {
+            let mut idle = self.inner.idle.lock().unwrap();
+            idle.retain(|client| !client.is_closed());
+            idle.pop()
+        };
+        let client = match reused {
+            Some(client) => client,
+            None => Client::connect(self.inner.addr.as_str()).await?,
+        };
+
+        Ok(PooledClient {
+            client: Some(client),
+            pool: self.inner.clone(),
+            _permit: permit,
+        })
+    }
+
+    /// Connections currently waiting in the pool.
+    pub fn idle_count(&self) -> usize {
+        self.inner.idle.lock().unwrap().len()
+    }
+}
+
+/// A connection borrowed from a `Pool`, returned to it on drop unless the
+/// borrower changed its session, see [`Client::session_changed`]; such a
+/// connection is closed instead, the next borrower gets a fresh one.
+pub struct PooledClient {
+    client: Option<Client>,
+    pool: Arc<PoolInner>,
+    // held until the connection is back in the idle list
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Deref for PooledClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.client.as_ref().expect("client is only taken on drop")
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take()
+            && !client.is_closed()
+            && !client.session_changed()
+        {
+            self.pool.idle.lock().unwrap().push(client);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::client::{ClientError, Pool};
+    use crate::testing::TestServer;
+
+    #[tokio::test]
+    async fn test_pool_is_bounded_and_reuses_connections() {
+        let server = TestServer::start().await;
+        let pool = Pool::new(server.addr().to_string(), 2);
+
+        let first = pool.get().await.unwrap();
+        let second = pool.get().await.unwrap();
+        first.set("k", "v").await.unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), pool.get())
+                .await
+                .is_err()
+        );
+
+        drop(first);
+        assert_eq!(pool.idle_count(), 1);
+        let third = pool.get().await.unwrap();
+        assert_eq!(pool.idle_count(), 0);
+        assert_eq!(third.get("k").await.unwrap().as_deref(), Some(&b"v"[..]));
+        drop(second);
+        drop(third);
+        assert_eq!(pool.idle_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_pool_drops_connections_the_server_closed() {
+        let server = TestServer::start().await;
+        let pool = Pool::new(server.addr().to_string(), 1);
+
+        let pooled = pool.get().await.unwrap();
+        let client = (*pooled).clone();
+        drop(pooled);
+        assert_eq!(pool.idle_count(), 1);
+
+        server.shutdown().await;
+        while !client.is_closed() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        // a new connection is tried, which fails with the server gone
+        assert!(matches!(pool.get().await, Err(ClientError::Io(_))));
+        assert_eq!(pool.idle_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_pool_drops_connections_with_a_changed_session() {
+        let server = TestServer::start().await;
+        let pool = Pool::new(server.addr().to_string(), 1);
+
+        let pooled = pool.get().await.unwrap();
+        pooled.command(&["MULTI"]).await.unwrap();
+        pooled.command(&["SET", "k", "v"]).await.unwrap();
+        pooled.command(&["EXEC"]).await.unwrap();
+        drop(pooled);
+        assert_eq!(pool.idle_count(), 1);
+
+        let pooled = pool.get().await.unwrap();
+        pooled.command(&["MULTI"]).await.unwrap();
+        drop(pooled);
+        assert_eq!(pool.idle_count(), 0);
+
+        let pooled = pool.get().await.unwrap();
+        pooled.command(&["SELECT", "1"]).await.unwrap();
+        drop(pooled);
+        assert_eq!(pool.idle_count(), 0);
+
+        // neither the transaction nor the database reach the next borrower
+        let pooled = pool.get().await.unwrap();
+        assert_eq!(pooled.get("k").await.unwrap().as_deref(), Some(&b"v"[..]));
+    }
+}
@@ -0,0 +1,277 @@
+// A connection in Pub/Sub mode. Once subscribed a connection only carries
+// messages and subscription changes, so it is not shared like a `Client`:
+// the subscriber reads its own socket and hands messages out one by one.
+use std::collections::{HashSet, VecDeque};
+
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+use crate::client::{ClientError, command};
+use crate::resp_frame::RespFrame;
+use crate::{parser, serializer};
+
+/// A message published to a channel the subscriber listens to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub channel: Bytes,
+    // the pattern that matched, for PSUBSCRIBE subscriptions
+    pub pattern: Option<Bytes>,
+    pub payload: Bytes,
+}
+
+/// ```no_run
+/// # async fn example() -> Result<(), resprs::client::ClientError> {
+/// use resprs::client::Subscriber;
+///
+/// let mut subscriber = Subscriber::connect("127.0.0.1:6380").await?;
+/// subscriber.subscribe(&["news"]).await?;
+/// while let Some(message) = subscriber.next_message().await? {
+///     println!("{:?}", message.payload);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Subscriber<S = TcpStream> {
+    stream: BufReader<S>,
+    // messages that arrived while waiting for a subscription change
+    buffered: VecDeque<Message>,
+    // what a bare UNSUBSCRIBE or PUNSUBSCRIBE will confirm one by one
+    channels: HashSet<Bytes>,
+    patterns: HashSet<Bytes>,
+}
+
+impl Subscriber<TcpStream> {
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Subscriber, ClientError> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(Subscriber::from_stream(stream))
+    }
+}
+
+impl<S> Subscriber<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn from_stream(stream: S) -> Subscriber<S> {
+        Subscriber {
+            stream: BufReader::new(stream),
+            buffered: VecDeque::new(),
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+        }
+    }
+
+    pub async fn subscribe<C: AsRef<[u8]>>(&mut self, channels: &[C]) -> Result<(), ClientError> {
+        self.change(b"SUBSCRIBE", channels).await
+    }
+
+    pub async fn psubscribe<C: AsRef<[u8]>>(&mut self, patterns: &[C]) -> Result<(), ClientError> {
+        self.change(b"PSUBSCRIBE", patterns).await
+    }
+
+    /// Without channels every channel subscription is dropped, as in redis.
+    pub async fn unsubscribe<C: AsRef<[u8]>>(&mut self, channels: &[C]) -> Result<(), ClientError> {
+        self.change(b"UNSUBSCRIBE", channels).await
+    }
+
+    /// Without patterns every pattern subscription is dropped.
+    pub async fn punsubscribe<C: AsRef<[u8]>>(
+        &mut self,
+        patterns: &[C],
+    ) -> Result<(), ClientError> {
+        self.change(b"PUNSUBSCRIBE", patterns).await
+    }
+
+    /// Waits for the next message. `None` means the server closed the
+    /// connection.
+    pub async fn next_message(&mut self) -> Result<Option<Message>, ClientError> {
+        if let Some(message) = self.buffered.pop_front() {
+            return Ok(Some(message));
+        }
+        loop {
+            match self.read().await? {
+                None => return Ok(None),
+                Some(Event::Message(message)) => return Ok(Some(message)),
+                // confirmations of changes nobody is waiting for
+                Some(Event::Confirmation(_)) => {}
+            }
+        }
+    }
+
+    // sends a (un)subscribe command and waits until every name is confirmed,
+    // one reply per name, keeping the messages that arrive in between. An
+    // unsubscribe without names is confirmed once per current subscription,
+    // or once with no name when there is none.
+    async fn change<C: AsRef<[u8]>>(
+        &mut self,
+        name: &[u8],
+        names: &[C],
+    ) -> Result<(), ClientError> {
+        let unsubscribe = name.ends_with(b"UNSUBSCRIBE");
+        let subscribed = if name.starts_with(b"P") {
+            &mut self.patterns
+        } else {
+            &mut self.channels
+        };
+        let replies = if !names.is_empty() {
+            names.len()
+        } else if unsubscribe {
+            subscribed.len().max(1)
+        } else {
+            return Ok(());
+        };
+        if !unsubscribe {
+            subscribed.extend(
+                names
+                    .iter()
+                    .map(|name| Bytes::copy_from_slice(name.as_ref())),
+            );
+        } else if names.is_empty() {
+            subscribed.clear();
+        } else {
+            for name in names {
+                subscribed.remove(name.as_ref());
+            }
+        }
+
+        let mut args: Vec<&[u8]> = vec![name];
+        args.extend(names.iter().map(|name| name.as_ref()));
+        serializer::serialize_frame(self.stream.get_mut(), command(&args)).await?;
+        self.stream.get_mut().flush().await?;
+
+        let kind = name.to_ascii_lowercase();
+        let mut confirmed = 0;
+        while confirmed < replies {
+            match self.read().await? {
+                None => return Err(ClientError::Closed),
+                Some(Event::Message(message)) => self.buffered.push_back(message),
+                Some(Event::Confirmation(confirmed_kind)) if confirmed_kind == kind => {
+                    confirmed += 1
+                }
+                Some(Event::Confirmation(_)) => {}
+            }
+        }
+        Ok(())
+    }
+
+    async fn read(&mut self) -> Result<Option<Event>, ClientError> {
+        let frame = match parser::parse_frame(&mut self.stream).await {
+            Ok(frame) => frame,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        // RESP2 delivers pushes as plain arrays
+        let (RespFrame::Array(items) | RespFrame::Push(items)) = frame else {
+            return match frame {
+                RespFrame::Error(message) => Err(ClientError::Server(message)),
+                other => Err(ClientError::UnexpectedReply(other)),
+            };
+        };
+        let mut words = items.into_iter().map(|item| match item {
+            RespFrame::BulkString(bytes) => Some(bytes),
+            _ => None,
+        });
+        let Some(Some(kind)) = words.next() else {
+            return Err(ClientError::UnexpectedReply(RespFrame::Null));
+        };
+
+        Ok(Some(match kind.as_ref() {
+            b"message" | b"smessage" => Event::Message(Message {
+                channel: words.next().flatten().unwrap_or_default(),
+                pattern: None,
+                payload: words.next().flatten().unwrap_or_default(),
+            }),
+            b"pmessage" => Event::Message(Message {
+                pattern: words.next().flatten(),
+                channel: words.next().flatten().unwrap_or_default(),
+                payload: words.next().flatten().unwrap_or_default(),
+            }),
+            _ => Event::Confirmation(kind.to_ascii_lowercase()),
+        }))
+    }
+}
+
+enum Event {
+    Message(Message),
+    // "subscribe", "unsubscribe" and so on
+    Confirmation(Vec<u8>),
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::client::{Client, Message, Subscriber};
+    use crate::testing::TestServer;
+
+    #[tokio::test]
+    async fn test_receives_channel_and_pattern_messages() {
+        let server = TestServer::start().await;
+        let publisher = Client::connect(server.addr()).await.unwrap();
+        let mut subscriber = Subscriber::connect(server.addr()).await.unwrap();
+
+        subscriber.subscribe(&["news", "sport"]).await.unwrap();
+        subscriber.psubscribe(&["weather.*"]).await.unwrap();
+        assert_eq!(publisher.publish("news", "hello").await.unwrap(), 1);
+        assert_eq!(publisher.publish("weather.rain", "wet").await.unwrap(), 1);
+
+        assert_eq!(
+            subscriber.next_message().await.unwrap(),
+            Some(Message {
+                channel: Bytes::from("news"),
+                pattern: None,
+                payload: Bytes::from("hello"),
+            })
+        );
+        assert_eq!(
+            subscriber.next_message().await.unwrap(),
+            Some(Message {
+                channel: Bytes::from("weather.rain"),
+                pattern: Some(Bytes::from("weather.*")),
+                payload: Bytes::from("wet"),
+            })
+        );
+
+        subscriber.unsubscribe(&["news"]).await.unwrap();
+        assert_eq!(publisher.publish("news", "again").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_unsubscribe_without_names_drops_every_subscription() {
+        let server = TestServer::start().await;
+        let publisher = Client::from_stream(server.connect_duplex());
+        let mut subscriber = Subscriber::from_stream(server.connect_duplex());
+
+        // nothing to drop yet, the server still confirms once
+        subscriber.unsubscribe(&[] as &[&str]).await.unwrap();
+        subscriber.subscribe(&["a", "b"]).await.unwrap();
+        subscriber.psubscribe(&["c.*"]).await.unwrap();
+        subscriber.unsubscribe(&[] as &[&str]).await.unwrap();
+        assert_eq!(publisher.publish("a", "x").await.unwrap(), 0);
+        assert_eq!(publisher.publish("b", "x").await.unwrap(), 0);
+        assert_eq!(publisher.publish("c.d", "first").await.unwrap(), 1);
+
+        subscriber.punsubscribe(&[] as &[&str]).await.unwrap();
+        assert_eq!(publisher.publish("c.d", "x").await.unwrap(), 0);
+        let message = subscriber.next_message().await.unwrap().unwrap();
+        assert_eq!(message.payload, Bytes::from("first"));
+    }
+
+    #[tokio::test]
+    async fn test_messages_during_a_change_are_kept() {
+        let server = TestServer::start().await;
+        let publisher = Client::from_stream(server.connect_duplex());
+        let mut subscriber = Subscriber::from_stream(server.connect_duplex());
+
+        subscriber.subscribe(&["a"]).await.unwrap();
+        publisher.publish("a", "first").await.unwrap();
+        subscriber.subscribe(&["b"]).await.unwrap();
+
+        let message = subscriber.next_message().await.unwrap().unwrap();
+        assert_eq!(message.payload, Bytes::from("first"));
+
+        server.shutdown().await;
+        assert_eq!(subscriber.next_message().await.unwrap(), None);
+    }
+}
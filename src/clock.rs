@@ -0,0 +1,78 @@
+// The server's notion of "now" for key expiry. It follows the real clock
+// plus an offset that only moves forward, so tests can make keys expire
+// without sleeping.
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Cheap to clone, clones share the same offset.
+#[derive(Debug, Clone, Default)]
+pub struct Clock {
+    offset_ms: Arc<AtomicU64>,
+}
+
+impl Clock {
+    pub fn now(&self) -> Instant {
+        Instant::now() + Duration::from_millis(self.offset_ms.load(Ordering::Relaxed))
+    }
+
+    /// Milliseconds since the unix epoch at `at`, for expiry times that leave
+    /// the process in snapshots and the append only file.
+    pub fn to_unix_ms(&self, at: Instant) -> u64 {
+        let unix_now = self.unix_time();
+        let now = self.now();
+        let unix_at = if at >= now {
+            unix_now + (at - now)
+        } else {
+            unix_now.saturating_sub(now - at)
+        };
+        unix_at.as_millis() as u64
+    }
+
+    /// The instant a unix timestamp in milliseconds corresponds to. Times in
+    /// the past come back as `None`.
+    pub fn from_unix_ms(&self, unix_ms: u64) -> Option<Instant> {
+        let remaining = Duration::from_millis(unix_ms).checked_sub(self.unix_time())?;
+        (!remaining.is_zero()).then(|| self.now() + remaining)
+    }
+
+    fn unix_time(&self) -> Duration {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        since_epoch + Duration::from_millis(self.offset_ms.load(Ordering::Relaxed))
+    }
+
+    /// Moves the clock forward by `by`, rounded down to whole milliseconds.
+    pub fn advance(&self, by: Duration) {
+        self.offset_ms
+            .fetch_add(by.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::clock::Clock;
+
+    #[test]
+    fn test_clones_share_the_offset() {
+        let clock = Clock::default();
+        let before = clock.now();
+        clock.clone().advance(Duration::from_secs(60));
+        assert!(clock.now() - before >= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_unix_timestamps() {
+        let clock = Clock::default();
+        let at = clock.now() + Duration::from_secs(10);
+        let unix_ms = clock.to_unix_ms(at);
+        let back = clock.from_unix_ms(unix_ms).unwrap();
+        assert!(back.max(at) - back.min(at) < Duration::from_millis(2));
+
+        clock.advance(Duration::from_secs(11));
+        assert_eq!(clock.from_unix_ms(unix_ms), None);
+    }
+}
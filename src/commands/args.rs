@@ -0,0 +1,167 @@
+// A cursor over the arguments of a command. Handlers take arguments in the
+// order they expect them, and whatever is missing or malformed comes back as
+// the error redis would reply with, so parsing never needs the keyspace.
+// Commands with many options parse into a type of their own with `Parse`,
+// which their tests and fuzzers can run without a server.
+use bytes::Bytes;
+
+use crate::resp_frame::RespFrame;
+
+pub struct Args<'a> {
+    frames: &'a [RespFrame],
+    position: usize,
+}
+
+pub fn syntax_error() -> RespFrame {
+    RespFrame::Error("ERR syntax error".to_string())
+}
+
+pub fn not_an_integer() -> RespFrame {
+    RespFrame::Error("ERR value is not an integer or out of range".to_string())
+}
+
+/// A command's arguments parsed into a value before its handler touches the
+/// keyspace. Parsing takes every argument, leftovers are a syntax error.
+pub trait Parse: Sized {
+    fn parse(args: &mut Args) -> Result<Self, RespFrame>;
+}
+
+impl<'a> Args<'a> {
+    /// A cursor over `frames`, which start after the command name.
+    pub fn new(frames: &'a [RespFrame]) -> Args<'a> {
+        Args {
+            frames,
+            position: 0,
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.frames.len() - self.position
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// The next argument as raw bytes. Arity is checked before a handler
+    /// runs, so running out here means an option is missing its value.
+    pub fn next_bytes(&mut self) -> Result<Bytes, RespFrame> {
+        let frame = self.frames.get(self.position).ok_or_else(syntax_error)?;
+        self.position += 1;
+        match frame {
+            RespFrame::BulkString(bytes) => Ok(bytes.clone()),
+            RespFrame::SimpleString(s) => Ok(Bytes::copy_from_slice(s.as_bytes())),
+            _ => Err(RespFrame::Error(
+                "ERR Protocol error: expected bulk string".to_string(),
+            )),
+        }
+    }
+
+    pub fn next_key(&mut self) -> Result<Bytes, RespFrame> {
+        self.next_bytes()
+    }
+
+    pub fn next_i64(&mut self) -> Result<i64, RespFrame> {
+        let bytes = self.next_bytes()?;
+        std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+            .ok_or_else(not_an_integer)
+    }
+
+    /// The next argument upper cased, for subcommands and option names.
+    pub fn next_keyword(&mut self) -> Result<String, RespFrame> {
+        Ok(String::from_utf8_lossy(&self.next_bytes()?).to_uppercase())
+    }
+
+    /// Takes the next argument only if it is `flag`, ignoring case.
+    pub fn next_flag(&mut self, flag: &str) -> bool {
+        let matches = match self.frames.get(self.position) {
+            Some(RespFrame::BulkString(bytes)) => bytes.eq_ignore_ascii_case(flag.as_bytes()),
+            Some(RespFrame::SimpleString(s)) => s.eq_ignore_ascii_case(flag),
+            _ => false,
+        };
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    /// Everything not taken yet.
+    pub fn rest(&mut self) -> Result<Vec<Bytes>, RespFrame> {
+        let mut rest = Vec::with_capacity(self.remaining());
+        while !self.is_empty() {
+            rest.push(self.next_bytes()?);
+        }
+        Ok(rest)
+    }
+
+    /// Fails with a syntax error if arguments are left over.
+    pub fn finish(&self) -> Result<(), RespFrame> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(syntax_error())
+        }
+    }
+}
+
+/// Parses `args`, which start after the command name, for the tests of a
+/// command's `Parse`.
+#[cfg(test)]
+pub fn parse<T: Parse>(args: &[&str]) -> Result<T, RespFrame> {
+    let frames: Vec<RespFrame> = args
+        .iter()
+        .map(|arg| RespFrame::BulkString(Bytes::copy_from_slice(arg.as_bytes())))
+        .collect();
+    T::parse(&mut Args::new(&frames))
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::commands::args::Args;
+    use crate::resp_frame::RespFrame;
+
+    fn frames(args: &[&str]) -> Vec<RespFrame> {
+        args.iter()
+            .map(|arg| RespFrame::BulkString(Bytes::copy_from_slice(arg.as_bytes())))
+            .collect()
+    }
+
+    #[test]
+    fn test_cursor_takes_arguments_in_order() {
+        let frames = frames(&["key", "-12", "nx", "rest", "of it"]);
+        let mut args = Args::new(&frames);
+
+        assert_eq!(args.next_key(), Ok(Bytes::from("key")));
+        assert_eq!(args.next_i64(), Ok(-12));
+        assert!(!args.next_flag("XX"));
+        assert!(args.next_flag("NX"));
+        assert_eq!(args.remaining(), 2);
+        assert!(args.finish().is_err());
+        assert_eq!(
+            args.rest(),
+            Ok(vec![Bytes::from("rest"), Bytes::from("of it")])
+        );
+        assert_eq!(args.finish(), Ok(()));
+    }
+
+    #[test]
+    fn test_parse_errors_match_redis() {
+        let frames = frames(&["ten"]);
+        let mut args = Args::new(&frames);
+
+        assert_eq!(
+            args.next_i64(),
+            Err(RespFrame::Error(
+                "ERR value is not an integer or out of range".to_string()
+            ))
+        );
+        assert_eq!(
+            args.next_bytes(),
+            Err(RespFrame::Error("ERR syntax error".to_string()))
+        );
+    }
+}
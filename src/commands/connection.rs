@@ -0,0 +1,409 @@
+use bytes::Bytes;
+
+use crate::commands::args::{Args, Parse, not_an_integer, syntax_error};
+use crate::commands::{
+    ALLOW_BUSY, CommandResult, CommandSpec, Context, FAST, LOADING, NO_AUTH, NOSCRIPT, STALE, bulk,
+    help_reply, map_reply, next_db_index, ok, unknown_subcommand,
+};
+use crate::resp_frame::RespFrame;
+use crate::sync_key_tracking;
+use crate::tracking::TrackingOptions;
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("ping", -1, FAST, ping_command)
+        .categories(&["@connection"])
+        .doc(
+            "connection",
+            "Returns the server's liveliness response.",
+            "1.0.0",
+        ),
+    CommandSpec::new("echo", 2, FAST, echo_command)
+        .categories(&["@connection"])
+        .doc("connection", "Returns the given string.", "1.0.0"),
+    CommandSpec::new("select", 2, LOADING | STALE | FAST, select_command)
+        .categories(&["@connection"])
+        .doc("connection", "Changes the selected database.", "1.0.0"),
+    CommandSpec::new(
+        "hello",
+        -1,
+        NOSCRIPT | LOADING | STALE | FAST | NO_AUTH | ALLOW_BUSY,
+        hello_command,
+    )
+    .categories(&["@connection"])
+    .doc("connection", "Handshakes with the Redis server.", "6.0.0"),
+    CommandSpec::new("client", -2, NOSCRIPT | LOADING | STALE, client_command)
+        .categories(&["@connection"])
+        .doc(
+            "connection",
+            "A container for client connection commands.",
+            "2.4.0",
+        ),
+];
+
+pub fn ping_command(mut ctx: Context) -> CommandResult {
+    if ctx.args.remaining() > 1 {
+        return Err(RespFrame::Error(
+            "ERR wrong number of arguments for 'ping' command".to_string(),
+        ));
+    }
+    let message = if ctx.args.is_empty() {
+        None
+    } else {
+        Some(ctx.args.next_bytes()?)
+    };
+
+    // subscribed RESP2 clients get the pong as a message shaped array
+    if ctx.session.in_subscriber_mode() {
+        return Ok(RespFrame::Array(vec![
+            RespFrame::BulkString(Bytes::from_static(b"pong")),
+            RespFrame::BulkString(message.unwrap_or_default()),
+        ]));
+    }
+
+    Ok(match message {
+        Some(message) => RespFrame::BulkString(message),
+        None => RespFrame::SimpleString("PONG".to_string()),
+    })
+}
+
+pub fn echo_command(mut ctx: Context) -> CommandResult {
+    ctx.args.next_bytes().map(RespFrame::BulkString)
+}
+
+pub fn select_command(mut ctx: Context) -> CommandResult {
+    ctx.session.db_index = next_db_index(&mut ctx.args, ctx.storage)?;
+    ok()
+}
+
+// what HELLO was given besides the protocol version
+struct Hello {
+    resp3: bool,
+    name: Option<Bytes>,
+}
+
+fn parse_hello(args: &mut Args) -> Result<Option<Hello>, RespFrame> {
+    if args.is_empty() {
+        return Ok(None);
+    }
+    let resp3 = match args.next_i64() {
+        Ok(2) => false,
+        Ok(3) => true,
+        Ok(_) => {
+            return Err(RespFrame::Error(
+                "NOPROTO unsupported protocol version".to_string(),
+            ));
+        }
+        Err(_) => {
+            return Err(RespFrame::Error(
+                "ERR Protocol version is not an integer or out of range".to_string(),
+            ));
+        }
+    };
+
+    let mut name = None;
+    while !args.is_empty() {
+        // there are no users or passwords, so any credentials are accepted
+        if args.next_flag("AUTH") {
+            args.next_bytes()?;
+            args.next_bytes()?;
+        } else if args.next_flag("SETNAME") {
+            name = Some(args.next_bytes()?);
+        } else {
+            return Err(syntax_error());
+        }
+    }
+    Ok(Some(Hello { resp3, name }))
+}
+
+pub fn hello_command(mut ctx: Context) -> CommandResult {
+    let session = &mut *ctx.session;
+
+    if let Some(hello) = parse_hello(&mut ctx.args)? {
+        if hello.name.is_some() {
+            session.name = hello.name;
+        }
+        if hello.resp3 != session.resp3 {
+            ctx.state.pubsub.set_protocol(session.id, hello.resp3);
+            ctx.state.tracking.set_protocol(session.id, hello.resp3);
+            session.resp3 = hello.resp3;
+        }
+    }
+
+    let fields = vec![
+        (bulk("server"), bulk("redis")),
+        (bulk("version"), bulk("7.0.0")),
+        (
+            bulk("proto"),
+            RespFrame::Integer(if session.resp3 { 3 } else { 2 }),
+        ),
+        (bulk("id"), RespFrame::Integer(session.id as i64)),
+        (bulk("mode"), bulk("standalone")),
+        (bulk("role"), bulk("master")),
+        (bulk("modules"), RespFrame::Array(vec![])),
+    ];
+
+    Ok(map_reply(fields, session.resp3))
+}
+
+// CLIENT TRACKING ON|OFF and its options, checked against each other but not
+// yet against the client's current mode
+#[derive(Debug, PartialEq)]
+pub enum TrackingCommand {
+    On(TrackingOptions),
+    Off,
+}
+
+impl Parse for TrackingCommand {
+    fn parse(args: &mut Args) -> Result<Self, RespFrame> {
+        let enable = if args.next_flag("ON") {
+            true
+        } else if args.next_flag("OFF") {
+            false
+        } else {
+            return Err(syntax_error());
+        };
+
+        let mut options = TrackingOptions::default();
+        while !args.is_empty() {
+            if args.next_flag("REDIRECT") {
+                let id = args.next_i64()?;
+                let id = u64::try_from(id).map_err(|_| not_an_integer())?;
+                // 0 means no redirection, like in redis
+                options.redirect = (id != 0).then_some(id);
+            } else if args.next_flag("PREFIX") {
+                options.prefixes.push(args.next_bytes()?);
+            } else if args.next_flag("BCAST") {
+                options.bcast = true;
+            } else if args.next_flag("OPTIN") {
+                options.optin = true;
+            } else if args.next_flag("OPTOUT") {
+                options.optout = true;
+            } else if args.next_flag("NOLOOP") {
+                options.noloop = true;
+            } else {
+                return Err(syntax_error());
+            }
+        }
+
+        if !enable {
+            return Ok(TrackingCommand::Off);
+        }
+        if !options.bcast && !options.prefixes.is_empty() {
+            return Err(RespFrame::Error(
+                "ERR PREFIX option requires BCAST mode to be enabled".to_string(),
+            ));
+        }
+        if options.optin && options.optout {
+            return Err(RespFrame::Error(
+                "ERR You can't use both OPTIN and OPTOUT".to_string(),
+            ));
+        }
+        if options.bcast && (options.optin || options.optout) {
+            return Err(RespFrame::Error(
+                "ERR OPTIN and OPTOUT are not compatible with BCAST".to_string(),
+            ));
+        }
+        Ok(TrackingCommand::On(options))
+    }
+}
+
+pub fn client_command(mut ctx: Context) -> CommandResult {
+    let subcommand = ctx.args.next_keyword()?;
+    let Context {
+        mut args,
+        storage,
+        session,
+        state,
+        ..
+    } = ctx;
+
+    match (subcommand.as_str(), args.remaining()) {
+        ("ID", 0) => Ok(RespFrame::Integer(session.id as i64)),
+        ("TRACKING", 1..) => {
+            let TrackingCommand::On(options) = TrackingCommand::parse(&mut args)? else {
+                session.tracking = None;
+                state.tracking.disable(session.id);
+                sync_key_tracking(storage, state);
+                return ok();
+            };
+
+            if let Some(redirect) = options.redirect
+                && !state.clients.contains(redirect)
+            {
+                return Err(RespFrame::Error(
+                    "ERR The client ID you want redirect to does not exist".to_string(),
+                ));
+            }
+            if let Some(current) = &session.tracking {
+                if current.bcast != options.bcast {
+                    return Err(RespFrame::Error("ERR You can't switch BCAST mode on/off before disabling tracking for this client, and then re-enabling it with a different mode.".to_string()));
+                }
+                if (current.optin, current.optout) != (options.optin, options.optout) {
+                    return Err(RespFrame::Error("ERR You can't switch OPTIN/OPTOUT mode before disabling tracking for this client, and then re-enabling it with a different mode.".to_string()));
+                }
+            }
+
+            state.tracking.enable(
+                session.id,
+                session.outbox.clone(),
+                session.resp3,
+                options.clone(),
+            );
+            session.tracking = Some(options);
+            sync_key_tracking(storage, state);
+            ok()
+        }
+        ("CACHING", 1) => {
+            let Some(options) = session
+                .tracking
+                .as_ref()
+                .filter(|options| options.optin || options.optout)
+            else {
+                return Err(RespFrame::Error("ERR CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled".to_string()));
+            };
+            let caching = if args.next_flag("YES") {
+                true
+            } else if args.next_flag("NO") {
+                false
+            } else {
+                return Err(syntax_error());
+            };
+            if caching && !options.optin {
+                return Err(RespFrame::Error(
+                    "ERR CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode."
+                        .to_string(),
+                ));
+            }
+            if !caching && !options.optout {
+                return Err(RespFrame::Error(
+                    "ERR CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode."
+                        .to_string(),
+                ));
+            }
+            session.caching = Some(caching);
+            ok()
+        }
+        ("GETREDIR", 0) => Ok(RespFrame::Integer(match &session.tracking {
+            None => -1,
+            Some(options) => options.redirect.map_or(0, |id| id as i64),
+        })),
+        ("TRACKINGINFO", 0) => {
+            let (flags, redirect, prefixes) = match &session.tracking {
+                None => (vec![bulk("off")], -1, vec![]),
+                Some(options) => {
+                    let mut flags = vec![bulk("on")];
+                    for (set, flag) in [
+                        (options.bcast, "bcast"),
+                        (options.optin, "optin"),
+                        (options.optout, "optout"),
+                        (options.noloop, "noloop"),
+                    ] {
+                        if set {
+                            flags.push(bulk(flag));
+                        }
+                    }
+                    let prefixes = options
+                        .prefixes
+                        .iter()
+                        .cloned()
+                        .map(RespFrame::BulkString)
+                        .collect();
+                    (flags, options.redirect.map_or(0, |id| id as i64), prefixes)
+                }
+            };
+
+            let fields = vec![
+                (bulk("flags"), RespFrame::Array(flags)),
+                (bulk("redirect"), RespFrame::Integer(redirect)),
+                (bulk("prefixes"), RespFrame::Array(prefixes)),
+            ];
+            Ok(map_reply(fields, session.resp3))
+        }
+        ("HELP", 0) => help_reply(&[
+            "CLIENT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "CACHING (YES|NO)",
+            "    Enable/disable tracking of the keys for next command in OPTIN/OPTOUT modes.",
+            "GETREDIR",
+            "    Return the client ID we are redirecting to when tracking is enabled.",
+            "ID",
+            "    Return the ID of the current connection.",
+            "TRACKING (ON|OFF) [REDIRECT <id>] [BCAST] [PREFIX <prefix> [...]]",
+            "         [OPTIN] [OPTOUT] [NOLOOP]",
+            "    Control server assisted client side caching.",
+            "TRACKINGINFO",
+            "    Report tracking status for the current connection.",
+        ]),
+        _ => Err(unknown_subcommand(&subcommand, "CLIENT")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::commands::args::{parse, syntax_error};
+    use crate::commands::connection::TrackingCommand;
+    use crate::resp_frame::RespFrame;
+    use crate::test_util::{bulk, ok, run, setup_db};
+    use crate::tracking::TrackingOptions;
+
+    #[test]
+    fn test_parse_client_tracking() {
+        assert_eq!(
+            parse(&[
+                "on", "BCAST", "PREFIX", "a:", "PREFIX", "b:", "NOLOOP", "REDIRECT", "7"
+            ]),
+            Ok(TrackingCommand::On(TrackingOptions {
+                redirect: Some(7),
+                bcast: true,
+                prefixes: vec![Bytes::from("a:"), Bytes::from("b:")],
+                noloop: true,
+                ..TrackingOptions::default()
+            }))
+        );
+        assert_eq!(
+            parse(&["ON", "REDIRECT", "0", "OPTIN"]),
+            Ok(TrackingCommand::On(TrackingOptions {
+                optin: true,
+                ..TrackingOptions::default()
+            }))
+        );
+        // the options are not checked against each other when turning it off
+        assert_eq!(parse(&["OFF", "OPTIN", "OPTOUT"]), Ok(TrackingCommand::Off));
+
+        let error = |message: &str| Err(RespFrame::Error(message.to_string()));
+        assert_eq!(parse::<TrackingCommand>(&["MAYBE"]), Err(syntax_error()));
+        assert_eq!(
+            parse::<TrackingCommand>(&["ON", "PREFIX", "a:"]),
+            error("ERR PREFIX option requires BCAST mode to be enabled")
+        );
+        assert_eq!(
+            parse::<TrackingCommand>(&["ON", "OPTIN", "OPTOUT"]),
+            error("ERR You can't use both OPTIN and OPTOUT")
+        );
+        assert_eq!(
+            parse::<TrackingCommand>(&["ON", "BCAST", "OPTOUT"]),
+            error("ERR OPTIN and OPTOUT are not compatible with BCAST")
+        );
+    }
+
+    #[test]
+    fn test_select_isolates_databases() {
+        let mut db = setup_db();
+
+        assert_eq!(run(&mut db, &["SELECT", "15"]), ok());
+        assert_eq!(run(&mut db, &["GET", "live"]), RespFrame::Null);
+        assert_eq!(run(&mut db, &["SET", "live", "15"]), ok());
+        assert_eq!(run(&mut db, &["SELECT", "0"]), ok());
+        assert_eq!(run(&mut db, &["GET", "live"]), bulk("1"));
+
+        assert_eq!(
+            run(&mut db, &["SELECT", "16"]),
+            RespFrame::Error("ERR DB index is out of range".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["SELECT", "x"]),
+            RespFrame::Error("ERR value is not an integer or out of range".to_string())
+        );
+    }
+}
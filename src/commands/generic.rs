@@ -0,0 +1,1442 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use crate::commands::args::{Args, Parse, syntax_error};
+use crate::commands::{
+    CommandResult, CommandSpec, Context, DENYOOM, FAST, KeySpec, NO_MULTI, NOSCRIPT, READONLY,
+    WRITE, bulk, database_index, help_reply, next_db_index, ok, unknown_subcommand,
+};
+use crate::migrate::Migration;
+use crate::notify;
+use crate::rdb::{self, Value};
+use crate::resp_frame::RespFrame;
+use crate::session::Session;
+use crate::storage::{RedisValue, Storage};
+use crate::{ServerState, propagate, random_index};
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("del", -2, WRITE, del_command)
+        .keys(KeySpec::range(1, -1, 1))
+        .categories(&["@keyspace"])
+        .doc("generic", "Deletes one or more keys.", "1.0.0"),
+    CommandSpec::new("unlink", -2, WRITE | FAST, del_command)
+        .keys(KeySpec::range(1, -1, 1))
+        .categories(&["@keyspace"])
+        .doc("generic", "Asynchronously deletes one or more keys.", "4.0.0"),
+    CommandSpec::new("exists", -2, READONLY | FAST, exists_command)
+        .keys(KeySpec::range(1, -1, 1))
+        .categories(&["@keyspace"])
+        .doc("generic", "Determines whether one or more keys exist.", "1.0.0"),
+    CommandSpec::new("expire", -3, WRITE | FAST, expire_command)
+        .keys(KeySpec::single(1))
+        .categories(&["@keyspace"])
+        .doc("generic", "Sets the expiration time of a key in seconds.", "1.0.0"),
+    CommandSpec::new("pexpireat", -3, WRITE | FAST, pexpireat_command)
+        .keys(KeySpec::single(1))
+        .categories(&["@keyspace"])
+        .doc(
+            "generic",
+            "Sets the expiration time of a key to a Unix milliseconds timestamp.",
+            "2.6.0",
+        ),
+    CommandSpec::new("ttl", 2, READONLY | FAST, ttl_command)
+        .keys(KeySpec::single(1))
+        .categories(&["@keyspace"])
+        .doc("generic", "Returns the expiration time in seconds of a key.", "1.0.0"),
+    CommandSpec::new("keys", 2, READONLY, keys_command)
+        .categories(&["@keyspace", "@dangerous"])
+        .doc("generic", "Returns all key names that match a pattern.", "1.0.0"),
+    CommandSpec::new("rename", 3, WRITE, rename_command)
+        .keys(KeySpec::range(1, 2, 1))
+        .categories(&["@keyspace"])
+        .doc("generic", "Renames a key and overwrites the destination.", "1.0.0"),
+    CommandSpec::new("renamenx", 3, WRITE | FAST, rename_command)
+        .keys(KeySpec::range(1, 2, 1))
+        .categories(&["@keyspace"])
+        .doc(
+            "generic",
+            "Renames a key only when the target key name doesn't exist.",
+            "1.0.0",
+        ),
+    CommandSpec::new("copy", -3, WRITE | DENYOOM, copy_command)
+        .keys(KeySpec::range(1, 2, 1))
+        .categories(&["@keyspace"])
+        .doc("generic", "Copies the value of a key to a new key.", "6.2.0"),
+    CommandSpec::new("move", 3, WRITE | FAST, move_command)
+        .keys(KeySpec::single(1))
+        .categories(&["@keyspace"])
+        .doc("generic", "Moves a key to another database.", "1.0.0"),
+    CommandSpec::new("type", 2, READONLY | FAST, type_command)
+        .keys(KeySpec::single(1))
+        .categories(&["@keyspace"])
+        .doc("generic", "Determines the type of value stored at a key.", "1.0.0"),
+    CommandSpec::new("touch", -2, READONLY | FAST, touch_command)
+        .keys(KeySpec::range(1, -1, 1))
+        .categories(&["@keyspace"])
+        .doc(
+            "generic",
+            "Returns the number of existing keys out of those specified after updating the time they were last accessed.",
+            "3.2.1",
+        ),
+    CommandSpec::new("randomkey", 1, READONLY, randomkey_command)
+        .categories(&["@keyspace"])
+        .doc("generic", "Returns a random key name from the database.", "1.0.0"),
+    CommandSpec::new("object", -2, READONLY, object_command)
+        .keys(KeySpec::single(2))
+        .categories(&["@keyspace"])
+        .doc(
+            "generic",
+            "A container for object introspection commands.",
+            "2.2.3",
+        ),
+    CommandSpec::new("dump", 2, READONLY, dump_command)
+        .keys(KeySpec::single(1))
+        .categories(&["@keyspace"])
+        .doc(
+            "generic",
+            "Returns a serialized representation of the value stored at a key.",
+            "2.6.0",
+        ),
+    CommandSpec::new("migrate", -6, WRITE | NOSCRIPT | NO_MULTI, migrate_command)
+        .keys(KeySpec::single(3))
+        .categories(&["@keyspace", "@dangerous"])
+        .doc(
+            "generic",
+            "Atomically transfers a key from one Redis instance to another.",
+            "2.6.0",
+        ),
+    CommandSpec::new("restore", -4, WRITE | DENYOOM, restore_command)
+        .keys(KeySpec::single(1))
+        .categories(&["@keyspace", "@dangerous"])
+        .doc(
+            "generic",
+            "Creates a key from the serialized representation of a value.",
+            "2.6.0",
+        ),
+];
+
+pub fn del_command(mut ctx: Context) -> CommandResult {
+    let keys = ctx.args.rest()?;
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+    let mut deleted_count = 0;
+
+    // keys that aren't in the db are ignored
+    for key in &keys {
+        if db_guard.remove(key).is_some() {
+            db_guard.notify(notify::GENERIC, "del", key);
+            deleted_count += 1;
+        }
+    }
+    Ok(RespFrame::Integer(deleted_count))
+}
+
+pub fn exists_command(mut ctx: Context) -> CommandResult {
+    let keys = ctx.args.rest()?;
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+
+    // a key given twice is counted twice, like in redis
+    let exists_count = keys.iter().filter(|key| db_guard.contains(key)).count();
+    Ok(RespFrame::Integer(exists_count as i64))
+}
+
+// EXPIRE key seconds and PEXPIREAT key unix-time-milliseconds
+#[derive(Debug, PartialEq)]
+pub struct ExpireCommand {
+    pub key: Bytes,
+    pub time: i64,
+}
+
+impl Parse for ExpireCommand {
+    fn parse(args: &mut Args) -> Result<Self, RespFrame> {
+        let key = args.next_key()?;
+        let time = args.next_i64()?;
+        // the NX, XX, GT and LT options are not supported yet
+        args.finish()?;
+        Ok(ExpireCommand { key, time })
+    }
+}
+
+pub fn expire_command(mut ctx: Context) -> CommandResult {
+    let ExpireCommand { key, time: seconds } = ExpireCommand::parse(&mut ctx.args)?;
+
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+    let now = db_guard.now();
+    // the expiry has to fit in milliseconds since the epoch, as it is logged
+    let unix_now_ms = db_guard.clock().to_unix_ms(now) as i64;
+    let Some(ms) = seconds
+        .checked_mul(1000)
+        .filter(|ms| ms.checked_add(unix_now_ms).is_some())
+    else {
+        return Err(RespFrame::Error(
+            "ERR invalid expire time in 'expire' command".to_string(),
+        ));
+    };
+
+    let Some(value) = db_guard.lookup_write(&key) else {
+        return Ok(RespFrame::Integer(0));
+    };
+    if ms <= 0 {
+        db_guard.remove(&key);
+        db_guard.notify(notify::GENERIC, "del", &key);
+    } else {
+        value.expires_at = Some(now + Duration::from_millis(ms as u64));
+        db_guard.mark_modified(&key);
+        db_guard.notify(notify::GENERIC, "expire", &key);
+    }
+    Ok(RespFrame::Integer(1))
+}
+
+// the append only file logs EXPIRE as this, so replaying it later does not
+// restart the ttl
+pub fn pexpireat_command(mut ctx: Context) -> CommandResult {
+    let ExpireCommand { key, time: unix_ms } = ExpireCommand::parse(&mut ctx.args)?;
+
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+    let at = u64::try_from(unix_ms)
+        .ok()
+        .and_then(|unix_ms| db_guard.clock().from_unix_ms(unix_ms));
+
+    let Some(value) = db_guard.lookup_write(&key) else {
+        return Ok(RespFrame::Integer(0));
+    };
+    match at {
+        Some(at) => {
+            value.expires_at = Some(at);
+            db_guard.mark_modified(&key);
+            db_guard.notify(notify::GENERIC, "expire", &key);
+        }
+        None => {
+            db_guard.remove(&key);
+            db_guard.notify(notify::GENERIC, "del", &key);
+        }
+    }
+    Ok(RespFrame::Integer(1))
+}
+
+// -2 => key doesnt exst
+// -1 => no expiry set
+// time => time to expiry
+pub fn ttl_command(mut ctx: Context) -> CommandResult {
+    let key = ctx.args.next_key()?;
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+    let now = db_guard.now();
+
+    Ok(match db_guard.peek(&key) {
+        Some(value) => match value.expires_at {
+            Some(instant) => {
+                let remaining = instant.saturating_duration_since(now);
+                RespFrame::Integer(remaining.as_secs() as i64)
+            }
+            None => RespFrame::Integer(-1),
+        },
+        None => RespFrame::Integer(-2),
+    })
+}
+
+pub fn keys_command(mut ctx: Context) -> CommandResult {
+    let pattern = ctx.args.next_bytes()?;
+    if &pattern[..] != b"*" {
+        return Err(RespFrame::Error(
+            "ERR only '*' pattern is supported".to_string(),
+        ));
+    }
+
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+
+    Ok(RespFrame::Array(
+        db_guard
+            .keys()
+            .into_iter()
+            .map(RespFrame::BulkString)
+            .collect(),
+    ))
+}
+
+pub fn rename_command(mut ctx: Context) -> CommandResult {
+    let key = ctx.args.next_key()?;
+    let new_key = ctx.args.next_key()?;
+
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+
+    if db_guard.lookup_write(&key).is_none() {
+        return Err(RespFrame::Error("ERR no such key".to_string()));
+    }
+
+    let only_if_missing = ctx.command_name == "RENAMENX";
+    if only_if_missing && db_guard.contains(&new_key) {
+        return Ok(RespFrame::Integer(0));
+    }
+
+    // the value is moved as is, so the ttl carries over to the new key
+    if let Some(value) = db_guard.remove(&key) {
+        db_guard.notify(notify::GENERIC, "rename_from", &key);
+        db_guard.insert(new_key.clone(), value);
+        db_guard.notify(notify::GENERIC, "rename_to", &new_key);
+    }
+
+    if only_if_missing {
+        Ok(RespFrame::Integer(1))
+    } else {
+        ok()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CopyCommand {
+    pub source: Bytes,
+    pub destination: Bytes,
+    // checked against the number of databases by the handler
+    pub db: Option<i64>,
+    pub replace: bool,
+}
+
+impl Parse for CopyCommand {
+    fn parse(args: &mut Args) -> Result<Self, RespFrame> {
+        let mut copy = CopyCommand {
+            source: args.next_key()?,
+            destination: args.next_key()?,
+            db: None,
+            replace: false,
+        };
+        while !args.is_empty() {
+            if args.next_flag("REPLACE") {
+                copy.replace = true;
+            } else if args.next_flag("DB") {
+                copy.db = Some(args.next_i64()?);
+            } else {
+                return Err(syntax_error());
+            }
+        }
+        Ok(copy)
+    }
+}
+
+pub fn copy_command(mut ctx: Context) -> CommandResult {
+    let CopyCommand {
+        source,
+        destination,
+        db,
+        replace,
+    } = CopyCommand::parse(&mut ctx.args)?;
+    let destination_db = match db {
+        Some(index) => database_index(ctx.storage, index)?,
+        None => ctx.session.db_index,
+    };
+
+    if source == destination && destination_db == ctx.session.db_index {
+        return Err(RespFrame::Error(
+            "ERR source and destination objects are the same".to_string(),
+        ));
+    }
+
+    let Some(value) = ctx.storage.db(ctx.session.db_index).lookup_read(&source) else {
+        return Ok(RespFrame::Integer(0));
+    };
+    let (data, expires_at) = (value.data.clone(), value.expires_at);
+
+    let destination_guard = ctx.storage.db(destination_db);
+    if !replace && destination_guard.contains(&destination) {
+        return Ok(RespFrame::Integer(0));
+    }
+
+    let mut copy = RedisValue::new(data);
+    copy.expires_at = expires_at;
+    destination_guard.insert(destination.clone(), copy);
+    destination_guard.notify(notify::GENERIC, "copy_to", &destination);
+
+    Ok(RespFrame::Integer(1))
+}
+
+pub fn move_command(mut ctx: Context) -> CommandResult {
+    let key = ctx.args.next_key()?;
+    let destination_db = next_db_index(&mut ctx.args, ctx.storage)?;
+    let source_db = ctx.session.db_index;
+
+    if destination_db == source_db {
+        return Err(RespFrame::Error(
+            "ERR source and destination objects are the same".to_string(),
+        ));
+    }
+
+    if !ctx.storage.move_key(&key, source_db, destination_db) {
+        return Ok(RespFrame::Integer(0));
+    }
+    ctx.storage
+        .db(source_db)
+        .notify(notify::GENERIC, "move_from", &key);
+    ctx.storage
+        .db(destination_db)
+        .notify(notify::GENERIC, "move_to", &key);
+    Ok(RespFrame::Integer(1))
+}
+
+pub fn type_command(mut ctx: Context) -> CommandResult {
+    let key = ctx.args.next_key()?;
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+
+    let kind = if db_guard.contains(&key) {
+        "string"
+    } else {
+        "none"
+    };
+    Ok(RespFrame::SimpleString(kind.to_string()))
+}
+
+pub fn touch_command(mut ctx: Context) -> CommandResult {
+    let keys = ctx.args.rest()?;
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+
+    let touched_count = keys
+        .iter()
+        .filter(|key| db_guard.lookup_read(key).is_some())
+        .count();
+    Ok(RespFrame::Integer(touched_count as i64))
+}
+
+pub fn randomkey_command(ctx: Context) -> CommandResult {
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+    let mut valid_keys = db_guard.keys();
+
+    if valid_keys.is_empty() {
+        Ok(RespFrame::Null)
+    } else {
+        let index = random_index(valid_keys.len());
+        Ok(RespFrame::BulkString(valid_keys.swap_remove(index)))
+    }
+}
+
+pub fn object_command(mut ctx: Context) -> CommandResult {
+    let subcommand = ctx.args.next_keyword()?;
+
+    match subcommand.as_str() {
+        "HELP" if ctx.args.is_empty() => {
+            return help_reply(&[
+                "OBJECT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                "ENCODING <key>",
+                "    Return the kind of internal representation used in order to store the value",
+                "    associated with a <key>.",
+                "FREQ <key>",
+                "    Return the access frequency index of the <key>.",
+                "IDLETIME <key>",
+                "    Return the idle time of the <key>, that is the approximated number of",
+                "    seconds elapsed since the last access to the key.",
+                "REFCOUNT <key>",
+                "    Return the number of references of the value associated with the specified",
+                "    <key>.",
+            ]);
+        }
+        "ENCODING" | "IDLETIME" | "FREQ" | "REFCOUNT" if ctx.args.remaining() == 1 => {}
+        _ => return Err(unknown_subcommand(&subcommand, "OBJECT")),
+    }
+    let key = ctx.args.next_key()?;
+
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+    let now = db_guard.now();
+
+    // OBJECT inspects the key without counting as an access
+    let Some(value) = db_guard.peek(&key) else {
+        return Ok(RespFrame::Null);
+    };
+
+    Ok(match subcommand.as_str() {
+        "ENCODING" => RespFrame::BulkString(Bytes::from_static(value.encoding().as_bytes())),
+        "IDLETIME" => {
+            RespFrame::Integer(now.saturating_duration_since(value.last_accessed).as_secs() as i64)
+        }
+        "FREQ" => RespFrame::Integer(value.frequency(now) as i64),
+        _ => RespFrame::Integer(1),
+    })
+}
+
+// the payload is what redis' DUMP returns, so keys move between the two
+pub fn dump_command(mut ctx: Context) -> CommandResult {
+    let key = ctx.args.next_key()?;
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+
+    Ok(match db_guard.lookup_read(&key) {
+        Some(value) => RespFrame::BulkString(Bytes::from(rdb::dump_string(&value.data))),
+        None => RespFrame::Null,
+    })
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RestoreCommand {
+    pub key: Bytes,
+    // milliseconds, or a unix time in milliseconds with ABSTTL, 0 for none
+    pub ttl: u64,
+    pub payload: Bytes,
+    pub replace: bool,
+    pub absolute_ttl: bool,
+    pub idle_seconds: Option<u64>,
+    pub frequency: Option<u8>,
+}
+
+impl Parse for RestoreCommand {
+    fn parse(args: &mut Args) -> Result<Self, RespFrame> {
+        let key = args.next_key()?;
+        let ttl = args.next_i64()?;
+        let mut restore = RestoreCommand {
+            key,
+            ttl: 0,
+            payload: args.next_bytes()?,
+            replace: false,
+            absolute_ttl: false,
+            idle_seconds: None,
+            frequency: None,
+        };
+        while !args.is_empty() {
+            if args.next_flag("REPLACE") {
+                restore.replace = true;
+            } else if args.next_flag("ABSTTL") {
+                restore.absolute_ttl = true;
+            } else if restore.frequency.is_none() && args.next_flag("IDLETIME") {
+                let Ok(seconds) = u64::try_from(args.next_i64()?) else {
+                    return Err(RespFrame::Error(
+                        "ERR Invalid IDLETIME value, must be >= 0".to_string(),
+                    ));
+                };
+                restore.idle_seconds = Some(seconds);
+            } else if restore.idle_seconds.is_none() && args.next_flag("FREQ") {
+                let Ok(count) = u8::try_from(args.next_i64()?) else {
+                    return Err(RespFrame::Error(
+                        "ERR Invalid FREQ value, must be >= 0 and <= 255".to_string(),
+                    ));
+                };
+                restore.frequency = Some(count);
+            } else {
+                // IDLETIME and FREQ are for different eviction policies, so only one is accepted
+                return Err(syntax_error());
+            }
+        }
+        let Ok(ttl) = u64::try_from(ttl) else {
+            return Err(RespFrame::Error(
+                "ERR Invalid TTL value, must be >= 0".to_string(),
+            ));
+        };
+        restore.ttl = ttl;
+        Ok(restore)
+    }
+}
+
+pub fn restore_command(mut ctx: Context) -> CommandResult {
+    let RestoreCommand {
+        key,
+        ttl,
+        payload,
+        replace,
+        absolute_ttl,
+        idle_seconds,
+        frequency,
+    } = RestoreCommand::parse(&mut ctx.args)?;
+
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+    if !replace && db_guard.contains(&key) {
+        return Err(RespFrame::Error(
+            "BUSYKEY Target key name already exists.".to_string(),
+        ));
+    }
+    let Some(body) = rdb::verify_footer(&payload) else {
+        return Err(RespFrame::Error(
+            "ERR DUMP payload version or checksum are wrong".to_string(),
+        ));
+    };
+    let data = match rdb::read_dumped_value(body) {
+        Some(Value::String(data)) => data,
+        Some(other) => {
+            return Err(RespFrame::Error(format!(
+                "ERR the payload holds a {}, only strings are supported",
+                other.type_name()
+            )));
+        }
+        None => return Err(RespFrame::Error("ERR Bad data format".to_string())),
+    };
+
+    let now = db_guard.now();
+    let expires_at = match ttl {
+        0 => None,
+        _ if absolute_ttl => match db_guard.clock().from_unix_ms(ttl) {
+            Some(at) => Some(at),
+            // already expired, the key is not created and REPLACE still
+            // removes the old one
+            None => {
+                if db_guard.remove(&key).is_some() {
+                    db_guard.notify(notify::GENERIC, "del", &key);
+                }
+                return ok();
+            }
+        },
+        _ => Some(now + Duration::from_millis(ttl)),
+    };
+
+    let mut value = RedisValue::new(data);
+    value.expires_at = expires_at;
+    value.last_accessed = idle_seconds
+        .and_then(|seconds| now.checked_sub(Duration::from_secs(seconds)))
+        .unwrap_or(now);
+    if let Some(frequency) = frequency {
+        value.lfu_counter = frequency;
+    }
+    db_guard.restore(key.clone(), value);
+    db_guard.notify(notify::GENERIC, "restore", &key);
+    ok()
+}
+
+/// A MIGRATE whose keys are serialized, waiting for the target's replies.
+#[derive(Debug)]
+pub struct PendingMigrate {
+    pub migration: Migration,
+    pub sent: SentKeys,
+}
+
+/// The keys a MIGRATE sent, watched so that only the ones nobody changed in
+/// the meantime are deleted once they arrived.
+#[derive(Debug)]
+pub struct SentKeys {
+    db_index: usize,
+    keys: Vec<(Bytes, u64)>,
+    copy: bool,
+}
+
+// DUMPs the keys and leaves the migration in the session. The caller sends
+// it through migrate.rs, without the storage lock where it can, then calls
+// `finish_migrate`, which deletes the keys that arrived.
+#[derive(Debug, PartialEq)]
+pub struct MigrateCommand {
+    // host:port, with the host in brackets when it is an IPv6 address
+    pub addr: String,
+    pub db: usize,
+    pub timeout: Duration,
+    pub copy: bool,
+    pub replace: bool,
+    // the arguments of AUTH on the target, empty for none
+    pub auth: Vec<Bytes>,
+    // the key argument, or those after KEYS
+    pub keys: Vec<Bytes>,
+}
+
+impl Parse for MigrateCommand {
+    fn parse(args: &mut Args) -> Result<Self, RespFrame> {
+        let host = args.next_bytes()?;
+        let port = args.next_i64()?;
+        let key = args.next_key()?;
+        let Ok(db) = usize::try_from(args.next_i64()?) else {
+            return Err(RespFrame::Error("ERR invalid DB index".to_string()));
+        };
+        let timeout = args.next_i64()?;
+
+        let host = String::from_utf8_lossy(&host);
+        let mut migrate = MigrateCommand {
+            addr: if host.contains(':') {
+                format!("[{}]:{}", host, port)
+            } else {
+                format!("{}:{}", host, port)
+            },
+            db,
+            // like redis, a timeout that is not positive means one second
+            timeout: Duration::from_millis(if timeout > 0 { timeout as u64 } else { 1000 }),
+            copy: false,
+            replace: false,
+            auth: Vec::new(),
+            keys: Vec::new(),
+        };
+        let mut keys = None;
+        while !args.is_empty() {
+            if args.next_flag("COPY") {
+                migrate.copy = true;
+            } else if args.next_flag("REPLACE") {
+                migrate.replace = true;
+            } else if args.next_flag("AUTH") {
+                migrate.auth = vec![args.next_bytes()?];
+            } else if args.next_flag("AUTH2") {
+                migrate.auth = vec![args.next_bytes()?, args.next_bytes()?];
+            } else if args.next_flag("KEYS") {
+                if !key.is_empty() {
+                    return Err(RespFrame::Error(
+                        "ERR When using MIGRATE KEYS option, the key argument must be set to the empty string"
+                            .to_string(),
+                    ));
+                }
+                keys = Some(args.rest()?);
+            } else {
+                return Err(syntax_error());
+            }
+        }
+        migrate.keys = keys.unwrap_or_else(|| vec![key]);
+        Ok(migrate)
+    }
+}
+
+pub fn migrate_command(mut ctx: Context) -> CommandResult {
+    let MigrateCommand {
+        addr,
+        db,
+        timeout,
+        copy,
+        replace,
+        auth,
+        keys,
+    } = MigrateCommand::parse(&mut ctx.args)?;
+
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+    let now = db_guard.now();
+    let mut found = Vec::new();
+    let mut restores = Vec::new();
+    for key in keys {
+        let Some(value) = db_guard.lookup_read(&key) else {
+            continue;
+        };
+        // a ttl of 0 would make the key persistent on the target
+        let ttl = value.expires_at.map_or(0, |at| {
+            at.saturating_duration_since(now).as_millis().max(1) as i64
+        });
+        let mut restore = vec![
+            bulk("RESTORE"),
+            RespFrame::BulkString(key.clone()),
+            bulk(&ttl.to_string()),
+            RespFrame::BulkString(Bytes::from(rdb::dump_string(&value.data))),
+        ];
+        if replace {
+            restore.push(bulk("REPLACE"));
+        }
+        restores.push(RespFrame::Array(restore));
+        let stamp = db_guard.watch(&key);
+        found.push((key, stamp));
+    }
+    if found.is_empty() {
+        return Ok(RespFrame::simple("NOKEY"));
+    }
+
+    ctx.session.migration = Some(PendingMigrate {
+        migration: Migration {
+            addr,
+            db,
+            timeout,
+            auth,
+            restores,
+        },
+        sent: SentKeys {
+            db_index: ctx.session.db_index,
+            keys: found,
+            copy,
+        },
+    });
+    ok()
+}
+
+/// Deletes the keys the target took, unless they were written since they
+/// were sent, and returns the reply of MIGRATE.
+pub fn finish_migrate(
+    sent: SentKeys,
+    replies: Result<Vec<RespFrame>, String>,
+    storage: &mut Storage,
+    session: &Session,
+    state: &ServerState,
+) -> RespFrame {
+    let db_guard = storage.db(sent.db_index);
+    let unchanged: Vec<bool> = sent
+        .keys
+        .iter()
+        .map(|(key, stamp)| {
+            let unchanged = db_guard.watch_stamp(key) == Some(*stamp);
+            db_guard.unwatch(key);
+            unchanged
+        })
+        .collect();
+    let replies = match replies {
+        Ok(replies) => replies,
+        Err(message) => return RespFrame::Error(message),
+    };
+
+    let mut error = None;
+    let mut deleted = vec![bulk("DEL")];
+    for (((key, _), unchanged), reply) in sent.keys.into_iter().zip(unchanged).zip(replies) {
+        if let RespFrame::Error(message) = reply {
+            error = Some(message);
+        } else if !sent.copy && unchanged && db_guard.remove(&key).is_some() {
+            db_guard.notify(notify::GENERIC, "del", &key);
+            deleted.push(RespFrame::BulkString(key));
+        }
+    }
+    // replaying MIGRATE would move the keys again, the log only needs the DEL
+    if deleted.len() > 1 {
+        propagate(state, storage, session, &deleted);
+    }
+
+    match error {
+        Some(message) => RespFrame::Error(format!(
+            "ERR Target instance replied with error: {}",
+            message
+        )),
+        None => RespFrame::simple("OK"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+
+    use crate::commands::args::{not_an_integer, parse, syntax_error};
+    use crate::commands::generic::{CopyCommand, ExpireCommand, MigrateCommand, RestoreCommand};
+    use crate::execute;
+    use crate::rdb;
+    use crate::resp_frame::RespFrame;
+    use crate::test_util::{TestDb, bulk, ok, run, setup_db};
+
+    #[test]
+    fn test_parse_expire() {
+        assert_eq!(
+            parse(&["k", "-5"]),
+            Ok(ExpireCommand {
+                key: Bytes::from("k"),
+                time: -5,
+            })
+        );
+        assert_eq!(
+            parse::<ExpireCommand>(&["k", "soon"]),
+            Err(not_an_integer())
+        );
+        assert_eq!(
+            parse::<ExpireCommand>(&["k", "5", "NX"]),
+            Err(syntax_error())
+        );
+    }
+
+    #[test]
+    fn test_parse_copy() {
+        assert_eq!(
+            parse(&["a", "b", "replace", "DB", "3"]),
+            Ok(CopyCommand {
+                source: Bytes::from("a"),
+                destination: Bytes::from("b"),
+                db: Some(3),
+                replace: true,
+            })
+        );
+        // the range of the database is up to the handler
+        assert!(matches!(
+            parse::<CopyCommand>(&["a", "b", "DB", "-1"]),
+            Ok(CopyCommand {
+                db: Some(-1),
+                replace: false,
+                ..
+            })
+        ));
+        assert_eq!(parse::<CopyCommand>(&["a", "b", "DB"]), Err(syntax_error()));
+        assert_eq!(parse::<CopyCommand>(&["a", "b", "NX"]), Err(syntax_error()));
+    }
+
+    #[test]
+    fn test_parse_restore() {
+        assert_eq!(
+            parse(&["k", "100", "payload", "ABSTTL", "IDLETIME", "30", "REPLACE"]),
+            Ok(RestoreCommand {
+                key: Bytes::from("k"),
+                ttl: 100,
+                payload: Bytes::from("payload"),
+                replace: true,
+                absolute_ttl: true,
+                idle_seconds: Some(30),
+                frequency: None,
+            })
+        );
+        assert!(matches!(
+            parse::<RestoreCommand>(&["k", "0", "payload", "FREQ", "255"]),
+            Ok(RestoreCommand {
+                frequency: Some(255),
+                idle_seconds: None,
+                ..
+            })
+        ));
+        let error = |message: &str| Err(RespFrame::Error(message.to_string()));
+        assert_eq!(
+            parse::<RestoreCommand>(&["k", "-1", "payload"]),
+            error("ERR Invalid TTL value, must be >= 0")
+        );
+        assert_eq!(
+            parse::<RestoreCommand>(&["k", "0", "payload", "IDLETIME", "-1"]),
+            error("ERR Invalid IDLETIME value, must be >= 0")
+        );
+        assert_eq!(
+            parse::<RestoreCommand>(&["k", "0", "payload", "FREQ", "256"]),
+            error("ERR Invalid FREQ value, must be >= 0 and <= 255")
+        );
+        assert_eq!(
+            parse::<RestoreCommand>(&["k", "0", "payload", "FREQ", "1", "IDLETIME", "1"]),
+            Err(syntax_error())
+        );
+    }
+
+    #[test]
+    fn test_parse_migrate() {
+        assert_eq!(
+            parse(&[
+                "::1", "7000", "", "2", "0", "COPY", "AUTH2", "u", "p", "KEYS", "a", "b"
+            ]),
+            Ok(MigrateCommand {
+                addr: "[::1]:7000".to_string(),
+                db: 2,
+                timeout: Duration::from_secs(1),
+                copy: true,
+                replace: false,
+                auth: vec![Bytes::from("u"), Bytes::from("p")],
+                keys: vec![Bytes::from("a"), Bytes::from("b")],
+            })
+        );
+        assert!(matches!(
+            parse::<MigrateCommand>(&["host", "1", "k", "0", "250", "REPLACE"]),
+            Ok(MigrateCommand { replace: true, timeout, ref addr, ref keys, .. })
+                if timeout == Duration::from_millis(250)
+                    && addr == "host:1"
+                    && keys == &[Bytes::from("k")]
+        ));
+        let error = |message: &str| Err(RespFrame::Error(message.to_string()));
+        assert_eq!(
+            parse::<MigrateCommand>(&["host", "1", "k", "-1", "0"]),
+            error("ERR invalid DB index")
+        );
+        assert_eq!(
+            parse::<MigrateCommand>(&["host", "1", "k", "0", "0", "KEYS", "a"]),
+            error(
+                "ERR When using MIGRATE KEYS option, the key argument must be set to the empty string"
+            )
+        );
+        assert_eq!(
+            parse::<MigrateCommand>(&["host", "1", "k", "0", "0", "AUTH"]),
+            Err(syntax_error())
+        );
+    }
+
+    #[test]
+    fn test_expired_key_is_invisible_to_reads() {
+        let mut db = setup_db();
+
+        assert_eq!(run(&mut db, &["GET", "gone"]), RespFrame::Null);
+        assert_eq!(
+            run(&mut db, &["MGET", "gone", "live"]),
+            RespFrame::Array(vec![RespFrame::Null, bulk("1")])
+        );
+        assert_eq!(run(&mut db, &["STRLEN", "gone"]), RespFrame::Integer(0));
+        assert_eq!(run(&mut db, &["TTL", "gone"]), RespFrame::Integer(-2));
+        assert_eq!(
+            run(&mut db, &["TYPE", "gone"]),
+            RespFrame::SimpleString("none".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["OBJECT", "ENCODING", "gone"]),
+            RespFrame::Null
+        );
+        assert_eq!(
+            run(&mut db, &["KEYS", "*"]),
+            RespFrame::Array(vec![bulk("live")])
+        );
+        assert_eq!(run(&mut db, &["RANDOMKEY"]), bulk("live"));
+    }
+
+    #[test]
+    fn test_exists_and_touch_skip_expired_keys() {
+        let mut db = setup_db();
+
+        assert_eq!(
+            run(&mut db, &["EXISTS", "gone", "live"]),
+            RespFrame::Integer(1)
+        );
+        assert_eq!(
+            run(&mut db, &["TOUCH", "gone", "live"]),
+            RespFrame::Integer(1)
+        );
+        assert_eq!(run(&mut db, &["DBSIZE"]), RespFrame::Integer(1));
+    }
+
+    #[test]
+    fn test_del_does_not_count_expired_keys() {
+        let mut db = setup_db();
+        assert_eq!(
+            run(&mut db, &["DEL", "gone", "live"]),
+            RespFrame::Integer(1)
+        );
+
+        let mut db = setup_db();
+        assert_eq!(run(&mut db, &["UNLINK", "gone"]), RespFrame::Integer(0));
+    }
+
+    #[test]
+    fn test_expire_rejects_times_past_the_millisecond_range() {
+        let mut db = setup_db();
+        let invalid = RespFrame::Error("ERR invalid expire time in 'expire' command".to_string());
+
+        for seconds in ["9223372036854775807", "-9223372036854775808"] {
+            assert_eq!(run(&mut db, &["EXPIRE", "live", seconds]), invalid);
+            assert_eq!(run(&mut db, &["EXPIRE", "missing", seconds]), invalid);
+        }
+        // close to the limit, but the epoch offset pushes it over
+        assert_eq!(
+            run(&mut db, &["EXPIRE", "live", "9223372036854775"]),
+            invalid
+        );
+        assert_eq!(run(&mut db, &["TTL", "live"]), RespFrame::Integer(-1));
+
+        assert_eq!(
+            run(&mut db, &["EXPIRE", "live", "100000000000"]),
+            RespFrame::Integer(1)
+        );
+        assert!(matches!(
+            run(&mut db, &["TTL", "live"]),
+            RespFrame::Integer(ttl) if ttl > 99999999000
+        ));
+    }
+
+    #[test]
+    fn test_key_commands_treat_expired_key_as_missing() {
+        let mut db = setup_db();
+
+        assert_eq!(
+            run(&mut db, &["EXPIRE", "gone", "100"]),
+            RespFrame::Integer(0)
+        );
+        assert_eq!(
+            run(&mut db, &["RENAME", "gone", "other"]),
+            RespFrame::Error("ERR no such key".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["COPY", "gone", "other"]),
+            RespFrame::Integer(0)
+        );
+
+        let mut db = setup_db();
+        assert_eq!(
+            run(&mut db, &["RENAMENX", "live", "gone"]),
+            RespFrame::Integer(1)
+        );
+        assert_eq!(run(&mut db, &["GET", "gone"]), bulk("1"));
+
+        let mut db = setup_db();
+        assert_eq!(
+            run(&mut db, &["COPY", "live", "gone"]),
+            RespFrame::Integer(1)
+        );
+    }
+
+    #[test]
+    fn test_move_and_copy_between_databases() {
+        let mut db = setup_db();
+
+        assert_eq!(
+            run(&mut db, &["COPY", "live", "live", "DB", "1"]),
+            RespFrame::Integer(1)
+        );
+        assert_eq!(run(&mut db, &["MOVE", "live", "1"]), RespFrame::Integer(0));
+        assert_eq!(run(&mut db, &["MOVE", "gone", "1"]), RespFrame::Integer(0));
+
+        assert_eq!(run(&mut db, &["SET", "other", "x"]), ok());
+        assert_eq!(run(&mut db, &["MOVE", "other", "1"]), RespFrame::Integer(1));
+        assert_eq!(run(&mut db, &["EXISTS", "other"]), RespFrame::Integer(0));
+
+        assert_eq!(run(&mut db, &["SELECT", "1"]), ok());
+        assert_eq!(
+            run(&mut db, &["MGET", "live", "other"]),
+            RespFrame::Array(vec![bulk("1"), bulk("x")])
+        );
+    }
+
+    #[test]
+    fn test_rename_and_renamenx() {
+        let mut db = setup_db();
+        run(&mut db, &["SET", "a", "1"]);
+        run(&mut db, &["EXPIRE", "a", "100"]);
+        run(&mut db, &["SET", "b", "2"]);
+
+        assert_eq!(
+            run(&mut db, &["RENAME", "missing", "c"]),
+            RespFrame::Error("ERR no such key".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["RENAMENX", "missing", "c"]),
+            RespFrame::Error("ERR no such key".to_string())
+        );
+
+        // the ttl moves with the value
+        assert_eq!(run(&mut db, &["RENAME", "a", "c"]), ok());
+        assert_eq!(run(&mut db, &["EXISTS", "a"]), RespFrame::Integer(0));
+        assert_eq!(run(&mut db, &["GET", "c"]), bulk("1"));
+        assert!(matches!(
+            run(&mut db, &["TTL", "c"]),
+            RespFrame::Integer(99 | 100)
+        ));
+
+        assert_eq!(run(&mut db, &["RENAMENX", "c", "b"]), RespFrame::Integer(0));
+        assert_eq!(run(&mut db, &["GET", "b"]), bulk("2"));
+        assert_eq!(run(&mut db, &["RENAME", "c", "b"]), ok());
+        assert_eq!(run(&mut db, &["GET", "b"]), bulk("1"));
+        assert_eq!(run(&mut db, &["RENAMENX", "b", "d"]), RespFrame::Integer(1));
+        assert_eq!(run(&mut db, &["RENAME", "d", "d"]), ok());
+        assert_eq!(run(&mut db, &["GET", "d"]), bulk("1"));
+    }
+
+    #[test]
+    fn test_copy() {
+        let mut db = setup_db();
+        run(&mut db, &["SET", "other", "x"]);
+
+        assert_eq!(
+            run(&mut db, &["COPY", "live", "live"]),
+            RespFrame::Error("ERR source and destination objects are the same".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["COPY", "live", "live", "DB", "0"]),
+            RespFrame::Error("ERR source and destination objects are the same".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["COPY", "missing", "copy"]),
+            RespFrame::Integer(0)
+        );
+        assert_eq!(
+            run(&mut db, &["COPY", "live", "other"]),
+            RespFrame::Integer(0)
+        );
+        assert_eq!(run(&mut db, &["GET", "other"]), bulk("x"));
+        assert_eq!(
+            run(&mut db, &["COPY", "live", "other", "REPLACE"]),
+            RespFrame::Integer(1)
+        );
+        assert_eq!(run(&mut db, &["GET", "other"]), bulk("1"));
+        assert_eq!(
+            run(&mut db, &["COPY", "live", "x", "DB", "16"]),
+            RespFrame::Error("ERR DB index is out of range".to_string())
+        );
+
+        // the copy is independent of the source
+        assert_eq!(run(&mut db, &["INCR", "other"]), RespFrame::Integer(2));
+        assert_eq!(run(&mut db, &["GET", "live"]), bulk("1"));
+    }
+
+    #[test]
+    fn test_move() {
+        let mut db = setup_db();
+
+        assert_eq!(
+            run(&mut db, &["MOVE", "live", "0"]),
+            RespFrame::Error("ERR source and destination objects are the same".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["MOVE", "live", "16"]),
+            RespFrame::Error("ERR DB index is out of range".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["MOVE", "missing", "1"]),
+            RespFrame::Integer(0)
+        );
+
+        // a key that already exists in the target stays where it is
+        run(&mut db, &["SELECT", "1"]);
+        run(&mut db, &["SET", "live", "in 1"]);
+        run(&mut db, &["SELECT", "0"]);
+        assert_eq!(run(&mut db, &["MOVE", "live", "1"]), RespFrame::Integer(0));
+        assert_eq!(run(&mut db, &["GET", "live"]), bulk("1"));
+
+        assert_eq!(run(&mut db, &["MOVE", "live", "2"]), RespFrame::Integer(1));
+        assert_eq!(run(&mut db, &["EXISTS", "live"]), RespFrame::Integer(0));
+        run(&mut db, &["SELECT", "2"]);
+        assert_eq!(run(&mut db, &["GET", "live"]), bulk("1"));
+    }
+
+    #[test]
+    fn test_type_touch_and_unlink() {
+        let mut db = setup_db();
+        assert_eq!(
+            run(&mut db, &["TYPE", "live"]),
+            RespFrame::SimpleString("string".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["TYPE", "missing"]),
+            RespFrame::SimpleString("none".to_string())
+        );
+
+        run(&mut db, &["SET", "other", "x"]);
+        assert_eq!(
+            run(&mut db, &["TOUCH", "live", "other", "missing", "live"]),
+            RespFrame::Integer(3)
+        );
+        assert_eq!(
+            run(&mut db, &["UNLINK", "live", "other", "missing"]),
+            RespFrame::Integer(2)
+        );
+        assert_eq!(
+            run(&mut db, &["EXISTS", "live", "other"]),
+            RespFrame::Integer(0)
+        );
+    }
+
+    #[test]
+    fn test_randomkey() {
+        let mut db = setup_db();
+        run(&mut db, &["SET", "other", "x"]);
+        for _ in 0..20 {
+            let key = run(&mut db, &["RANDOMKEY"]);
+            assert!(key == bulk("live") || key == bulk("other"), "{:?}", key);
+        }
+
+        run(&mut db, &["SELECT", "1"]);
+        assert_eq!(run(&mut db, &["RANDOMKEY"]), RespFrame::Null);
+    }
+
+    #[test]
+    fn test_object() {
+        let mut db = setup_db();
+        run(&mut db, &["SET", "int", "12345"]);
+        run(&mut db, &["SET", "short", "hello"]);
+        run(&mut db, &["SET", "long", &"x".repeat(45)]);
+
+        let encoding = |db: &mut TestDb, key| run(db, &["OBJECT", "ENCODING", key]);
+        assert_eq!(encoding(&mut db, "int"), bulk("int"));
+        assert_eq!(encoding(&mut db, "short"), bulk("embstr"));
+        assert_eq!(encoding(&mut db, "long"), bulk("raw"));
+        assert_eq!(encoding(&mut db, "missing"), RespFrame::Null);
+        assert_eq!(
+            run(&mut db, &["OBJECT", "REFCOUNT", "int"]),
+            RespFrame::Integer(1)
+        );
+
+        // new keys start at redis' initial LFU value, OBJECT itself does not
+        // count as an access
+        assert_eq!(
+            run(&mut db, &["OBJECT", "FREQ", "short"]),
+            RespFrame::Integer(5)
+        );
+        run(&mut db, &["GET", "short"]);
+        assert_eq!(
+            run(&mut db, &["OBJECT", "FREQ", "short"]),
+            RespFrame::Integer(6)
+        );
+        db.state
+            .db
+            .lock()
+            .unwrap()
+            .clock()
+            .advance(Duration::from_secs(120));
+        assert_eq!(
+            run(&mut db, &["OBJECT", "FREQ", "short"]),
+            RespFrame::Integer(4)
+        );
+        assert_eq!(
+            run(&mut db, &["OBJECT", "IDLETIME", "short"]),
+            RespFrame::Integer(120)
+        );
+
+        assert_eq!(
+            run(&mut db, &["OBJECT", "NOPE", "short"]),
+            RespFrame::Error(
+                "ERR unknown subcommand or wrong number of arguments for 'NOPE'. Try OBJECT HELP."
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            run(&mut db, &["OBJECT", "FREQ"]),
+            RespFrame::Error(
+                "ERR unknown subcommand or wrong number of arguments for 'FREQ'. Try OBJECT HELP."
+                    .to_string()
+            )
+        );
+        assert!(matches!(
+            run(&mut db, &["OBJECT", "HELP"]),
+            RespFrame::Array(lines) if lines.len() == 12
+        ));
+    }
+
+    // the payload is binary, so it can't go through `run`
+    fn restore(db: &mut TestDb, key: &str, payload: &Bytes, args: &[&str]) -> RespFrame {
+        let mut frame = vec![
+            bulk("RESTORE"),
+            bulk(key),
+            bulk(args[0]),
+            RespFrame::BulkString(payload.clone()),
+        ];
+        frame.extend(args[1..].iter().copied().map(bulk));
+        execute(RespFrame::Array(frame), &db.state, &mut db.session)
+    }
+
+    // a DUMP payload around `body`, with a valid checksum
+    fn payload(body: &[u8], version: u16) -> Bytes {
+        let mut payload = body.to_vec();
+        payload.extend_from_slice(&version.to_le_bytes());
+        let crc = rdb::crc64(&payload);
+        payload.extend_from_slice(&crc.to_le_bytes());
+        Bytes::from(payload)
+    }
+
+    #[test]
+    fn test_dump_and_restore() {
+        let mut db = setup_db();
+        let RespFrame::BulkString(payload) = run(&mut db, &["DUMP", "live"]) else {
+            panic!("DUMP did not return a bulk string");
+        };
+        assert_eq!(run(&mut db, &["DUMP", "gone"]), RespFrame::Null);
+
+        assert_eq!(
+            restore(&mut db, "live", &payload, &["0"]),
+            RespFrame::Error("BUSYKEY Target key name already exists.".to_string())
+        );
+        assert_eq!(restore(&mut db, "copy", &payload, &["5000"]), ok());
+        assert_eq!(run(&mut db, &["GET", "copy"]), bulk("1"));
+        assert!(matches!(
+            run(&mut db, &["TTL", "copy"]),
+            RespFrame::Integer(4 | 5)
+        ));
+
+        assert_eq!(
+            restore(
+                &mut db,
+                "live",
+                &payload,
+                &["0", "REPLACE", "IDLETIME", "100"]
+            ),
+            ok()
+        );
+        assert_eq!(
+            run(&mut db, &["OBJECT", "IDLETIME", "live"]),
+            RespFrame::Integer(100)
+        );
+        assert_eq!(
+            restore(&mut db, "live", &payload, &["0", "REPLACE", "FREQ", "7"]),
+            ok()
+        );
+        assert_eq!(
+            run(&mut db, &["OBJECT", "FREQ", "live"]),
+            RespFrame::Integer(7)
+        );
+        assert_eq!(
+            restore(
+                &mut db,
+                "live",
+                &payload,
+                &["0", "REPLACE", "FREQ", "7", "IDLETIME", "1"]
+            ),
+            RespFrame::Error("ERR syntax error".to_string())
+        );
+
+        // an absolute ttl in the past removes the key REPLACE would overwrite
+        assert_eq!(
+            restore(&mut db, "live", &payload, &["1000", "REPLACE", "ABSTTL"]),
+            ok()
+        );
+        assert_eq!(run(&mut db, &["EXISTS", "live"]), RespFrame::Integer(0));
+
+        // SET mykey 10 and DUMP mykey on redis 6.2
+        let from_redis = Bytes::from_static(b"\x00\xc0\n\t\x00\xbem\x06\x89Z(\x00\n");
+        assert_eq!(restore(&mut db, "mykey", &from_redis, &["0"]), ok());
+        assert_eq!(run(&mut db, &["GET", "mykey"]), bulk("10"));
+
+        let mut corrupt = payload.to_vec();
+        corrupt[2] ^= 1;
+        assert_eq!(
+            restore(&mut db, "bad", &Bytes::from(corrupt), &["0"]),
+            RespFrame::Error("ERR DUMP payload version or checksum are wrong".to_string())
+        );
+        assert_eq!(
+            restore(&mut db, "bad", &payload, &["-1"]),
+            RespFrame::Error("ERR Invalid TTL value, must be >= 0".to_string())
+        );
+    }
+
+    #[test]
+    fn test_restore_rejects_bad_payloads() {
+        let mut db = setup_db();
+        let mut string = vec![rdb::TYPE_STRING];
+        rdb::write_string(&mut string, b"value");
+        assert_eq!(restore(&mut db, "k", &payload(&string, 10), &["0"]), ok());
+        assert_eq!(run(&mut db, &["GET", "k"]), bulk("value"));
+
+        let wrong = RespFrame::Error("ERR DUMP payload version or checksum are wrong".to_string());
+        let mut bad_crc = payload(&string, 10).to_vec();
+        *bad_crc.last_mut().unwrap() ^= 1;
+        assert_eq!(restore(&mut db, "a", &Bytes::from(bad_crc), &["0"]), wrong);
+        assert_eq!(restore(&mut db, "a", &payload(&string, 13), &["0"]), wrong);
+        assert_eq!(restore(&mut db, "a", &Bytes::from("short"), &["0"]), wrong);
+        let whole = payload(&string, 10);
+        let cut = whole.slice(..whole.len() - 1);
+        assert_eq!(restore(&mut db, "a", &cut, &["0"]), wrong);
+
+        let bad_format = RespFrame::Error("ERR Bad data format".to_string());
+        // a valid checksum over a string that ends early
+        let truncated = &string[..string.len() - 2];
+        assert_eq!(
+            restore(&mut db, "a", &payload(truncated, 10), &["0"]),
+            bad_format
+        );
+        // an LZF string claiming to be 2^62 bytes long
+        let mut huge = vec![rdb::TYPE_STRING, 0xc3, 0x05];
+        rdb::write_length(&mut huge, 1 << 62);
+        huge.extend_from_slice(&[0x00, b'a', 0xe0, 0x00, 0x00]);
+        assert_eq!(
+            restore(&mut db, "a", &payload(&huge, 10), &["0"]),
+            bad_format
+        );
+        assert_eq!(
+            restore(&mut db, "a", &payload(&[0x42], 10), &["0"]),
+            bad_format
+        );
+        assert_eq!(run(&mut db, &["EXISTS", "a"]), RespFrame::Integer(0));
+    }
+
+    #[test]
+    fn test_restore_options() {
+        let mut db = setup_db();
+        let RespFrame::BulkString(dumped) = run(&mut db, &["DUMP", "live"]) else {
+            panic!("DUMP did not return a bulk string");
+        };
+
+        // nothing is checked before BUSYKEY, not even the payload
+        assert_eq!(
+            restore(&mut db, "live", &Bytes::from("junk"), &["0"]),
+            RespFrame::Error("BUSYKEY Target key name already exists.".to_string())
+        );
+        assert_eq!(run(&mut db, &["SET", "live", "changed"]), ok());
+        assert_eq!(restore(&mut db, "live", &dumped, &["0", "REPLACE"]), ok());
+        assert_eq!(run(&mut db, &["GET", "live"]), bulk("1"));
+
+        // ABSTTL takes a unix time in milliseconds
+        let in_a_minute = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis()
+            + 60_000;
+        assert_eq!(
+            restore(
+                &mut db,
+                "abs",
+                &dumped,
+                &[&in_a_minute.to_string(), "ABSTTL"]
+            ),
+            ok()
+        );
+        assert!(matches!(
+            run(&mut db, &["TTL", "abs"]),
+            RespFrame::Integer(59 | 60)
+        ));
+        assert_eq!(restore(&mut db, "forever", &dumped, &["0", "ABSTTL"]), ok());
+        assert_eq!(run(&mut db, &["TTL", "forever"]), RespFrame::Integer(-1));
+
+        assert_eq!(
+            restore(&mut db, "idle", &dumped, &["0", "IDLETIME", "-1"]),
+            RespFrame::Error("ERR Invalid IDLETIME value, must be >= 0".to_string())
+        );
+        assert_eq!(
+            restore(&mut db, "freq", &dumped, &["0", "FREQ", "256"]),
+            RespFrame::Error("ERR Invalid FREQ value, must be >= 0 and <= 255".to_string())
+        );
+        assert_eq!(
+            restore(&mut db, "freq", &dumped, &["0", "FREQ", "ten"]),
+            RespFrame::Error("ERR value is not an integer or out of range".to_string())
+        );
+        assert_eq!(
+            restore(&mut db, "freq", &dumped, &["0", "NOPE"]),
+            RespFrame::Error("ERR syntax error".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["EXISTS", "idle", "freq"]),
+            RespFrame::Integer(0)
+        );
+    }
+}
@@ -0,0 +1,496 @@
+// The command table. Every command is declared next to its handler, in the
+// module of its redis command group, together with the metadata COMMAND
+// reports: arity, flags, where its keys are, ACL categories and docs.
+use bytes::Bytes;
+
+use crate::resp_frame::RespFrame;
+use crate::session::Session;
+use crate::storage::Storage;
+use crate::{ServerState, propagate, propagate_expired};
+
+use self::args::Args;
+
+pub use self::generic::{PendingMigrate, finish_migrate};
+pub use self::transactions::unwatch_all;
+
+mod args;
+mod connection;
+mod generic;
+mod pubsub;
+mod replication;
+mod scripting;
+mod server;
+mod string;
+mod transactions;
+
+// Command flags, one bit each, named as in the COMMAND INFO reply.
+pub const WRITE: u32 = 1 << 0;
+pub const READONLY: u32 = 1 << 1;
+pub const DENYOOM: u32 = 1 << 2;
+pub const ADMIN: u32 = 1 << 3;
+pub const PUBSUB: u32 = 1 << 4;
+pub const NOSCRIPT: u32 = 1 << 5;
+pub const LOADING: u32 = 1 << 6;
+pub const STALE: u32 = 1 << 7;
+pub const FAST: u32 = 1 << 8;
+pub const NO_AUTH: u32 = 1 << 9;
+pub const ALLOW_BUSY: u32 = 1 << 10;
+pub const NO_MULTI: u32 = 1 << 11;
+
+const FLAG_NAMES: &[(u32, &str)] = &[
+    (WRITE, "write"),
+    (READONLY, "readonly"),
+    (DENYOOM, "denyoom"),
+    (ADMIN, "admin"),
+    (PUBSUB, "pubsub"),
+    (NOSCRIPT, "noscript"),
+    (LOADING, "loading"),
+    (STALE, "stale"),
+    (FAST, "fast"),
+    (NO_AUTH, "no_auth"),
+    (ALLOW_BUSY, "allow_busy"),
+    (NO_MULTI, "no_multi"),
+];
+
+const GROUPS: &[&[CommandSpec]] = &[
+    connection::COMMANDS,
+    generic::COMMANDS,
+    pubsub::COMMANDS,
+    replication::COMMANDS,
+    scripting::COMMANDS,
+    server::COMMANDS,
+    string::COMMANDS,
+    transactions::COMMANDS,
+];
+
+/// What a handler works on: a cursor over the arguments after the command
+/// name, plus the locked storage, the calling client and the server.
+pub struct Context<'a> {
+    // upper cased, for handlers shared by several commands
+    pub command_name: &'a str,
+    pub args: Args<'a>,
+    pub storage: &'a mut Storage,
+    pub session: &'a mut Session,
+    pub state: &'a ServerState,
+}
+
+/// Both sides are sent to the client, the error side lets handlers bail out
+/// with `?`.
+pub type CommandResult = Result<RespFrame, RespFrame>;
+
+pub type Handler = fn(Context) -> CommandResult;
+
+/// Where a command's keys are among its arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeySpec {
+    None,
+    // every `step` arguments from `first` to `last`, a negative `last`
+    // counts from the end like in redis
+    Range {
+        first: usize,
+        last: i64,
+        step: usize,
+    },
+    // a numkeys argument at `index` followed by that many keys, like EVAL
+    Counted {
+        index: usize,
+    },
+}
+
+impl KeySpec {
+    pub const fn single(index: usize) -> KeySpec {
+        KeySpec::Range {
+            first: index,
+            last: index as i64,
+            step: 1,
+        }
+    }
+
+    pub const fn range(first: usize, last: i64, step: usize) -> KeySpec {
+        KeySpec::Range { first, last, step }
+    }
+}
+
+pub struct CommandSpec {
+    pub name: &'static str,
+    // positive means exactly that many arguments including the command name,
+    // negative means at least that many
+    pub arity: i64,
+    pub flags: u32,
+    pub keys: KeySpec,
+    // the ACL categories not implied by the flags
+    pub categories: &'static [&'static str],
+    pub group: &'static str,
+    pub summary: &'static str,
+    pub since: &'static str,
+    pub handler: Handler,
+}
+
+impl CommandSpec {
+    pub const fn new(name: &'static str, arity: i64, flags: u32, handler: Handler) -> CommandSpec {
+        CommandSpec {
+            name,
+            arity,
+            flags,
+            keys: KeySpec::None,
+            categories: &[],
+            group: "",
+            summary: "",
+            since: "",
+            handler,
+        }
+    }
+
+    pub const fn keys(mut self, keys: KeySpec) -> CommandSpec {
+        self.keys = keys;
+        self
+    }
+
+    pub const fn categories(mut self, categories: &'static [&'static str]) -> CommandSpec {
+        self.categories = categories;
+        self
+    }
+
+    pub const fn doc(
+        mut self,
+        group: &'static str,
+        summary: &'static str,
+        since: &'static str,
+    ) -> CommandSpec {
+        self.group = group;
+        self.summary = summary;
+        self.since = since;
+        self
+    }
+
+    pub fn has_flag(&self, flag: u32) -> bool {
+        self.flags & flag != 0
+    }
+
+    pub fn accepts(&self, arg_count: usize) -> bool {
+        let arg_count = arg_count as i64;
+        if self.arity >= 0 {
+            arg_count == self.arity
+        } else {
+            arg_count >= -self.arity
+        }
+    }
+
+    pub fn flag_names(&self) -> Vec<&'static str> {
+        let mut names: Vec<&str> = FLAG_NAMES
+            .iter()
+            .filter(|(flag, _)| self.has_flag(*flag))
+            .map(|(_, name)| *name)
+            .collect();
+        if matches!(self.keys, KeySpec::Counted { .. }) {
+            names.push("movablekeys");
+        }
+        names
+    }
+
+    /// The ACL categories, with the ones redis derives from the flags added
+    /// to the declared ones.
+    pub fn acl_categories(&self) -> Vec<&'static str> {
+        let mut categories = Vec::new();
+        if self.has_flag(WRITE) {
+            categories.push("@write");
+        }
+        if self.has_flag(READONLY) && !self.categories.contains(&"@scripting") {
+            categories.push("@read");
+        }
+        if self.has_flag(ADMIN) {
+            categories.extend(["@admin", "@dangerous"]);
+        }
+        if self.has_flag(PUBSUB) {
+            categories.push("@pubsub");
+        }
+        categories.push(if self.has_flag(FAST) {
+            "@fast"
+        } else {
+            "@slow"
+        });
+        for category in self.categories {
+            if !categories.contains(category) {
+                categories.push(category);
+            }
+        }
+        categories
+    }
+
+    /// Positions of the keys in `args`, or `None` when a key count is not a
+    /// valid number.
+    pub fn key_positions(&self, args: &[Bytes]) -> Option<Vec<usize>> {
+        match self.keys {
+            KeySpec::None => Some(Vec::new()),
+            KeySpec::Range { first, last, step } => {
+                let last = if last < 0 {
+                    args.len() as i64 + last
+                } else {
+                    last.min(args.len() as i64 - 1)
+                };
+                if last < first as i64 {
+                    return Some(Vec::new());
+                }
+                Some((first..=last as usize).step_by(step).collect())
+            }
+            KeySpec::Counted { index } => {
+                let count = std::str::from_utf8(args.get(index)?)
+                    .ok()?
+                    .parse::<usize>()
+                    .ok()?;
+                if index + count >= args.len() {
+                    return None;
+                }
+                Some((index + 1..=index + count).collect())
+            }
+        }
+    }
+}
+
+/// Every command in the table, grouped like the modules declare them.
+pub fn all() -> impl Iterator<Item = &'static CommandSpec> {
+    GROUPS.iter().flat_map(|group| group.iter())
+}
+
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    all().find(|spec| spec.name.eq_ignore_ascii_case(name))
+}
+
+fn unknown_command(args: &[RespFrame]) -> RespFrame {
+    let text = |frame: &RespFrame| match frame {
+        RespFrame::BulkString(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+        RespFrame::SimpleString(s) => s.clone(),
+        _ => String::new(),
+    };
+    let beginning: String = args[1..]
+        .iter()
+        .map(|arg| format!("'{}' ", text(arg)))
+        .collect();
+    RespFrame::Error(format!(
+        "ERR unknown command '{}', with args beginning with: {}",
+        text(&args[0]),
+        beginning
+    ))
+}
+
+fn wrong_arity(command_name: &str) -> RespFrame {
+    RespFrame::Error(format!(
+        "ERR wrong number of arguments for '{}' command",
+        command_name.to_lowercase()
+    ))
+}
+
+pub fn handle_command(
+    frame: RespFrame,
+    storage: &mut Storage,
+    session: &mut Session,
+    state: &ServerState,
+) -> RespFrame {
+    let RespFrame::Array(args) = frame else {
+        return RespFrame::Error("ERR command must be an array".to_string());
+    };
+
+    if args.is_empty() {
+        return RespFrame::Error("ERR empty command".to_string());
+    }
+
+    // extract command name
+    let command_name = match &args[0] {
+        RespFrame::BulkString(bytes) => String::from_utf8_lossy(bytes).to_uppercase(),
+        RespFrame::SimpleString(s) => s.to_uppercase(),
+        _ => return RespFrame::Error("ERR invalid command format".to_string()),
+    };
+
+    // a command that can't even be looked up also dooms the transaction it was sent in
+    let spec = match lookup(&command_name) {
+        Some(spec) if spec.accepts(args.len()) => spec,
+        found => {
+            if let Some(transaction) = &mut session.transaction {
+                transaction.aborted = true;
+            }
+            return match found {
+                Some(_) => wrong_arity(&command_name),
+                None => unknown_command(&args),
+            };
+        }
+    };
+
+    if session.in_subscriber_mode()
+        && !matches!(
+            command_name.as_str(),
+            "SUBSCRIBE"
+                | "PSUBSCRIBE"
+                | "SSUBSCRIBE"
+                | "UNSUBSCRIBE"
+                | "PUNSUBSCRIBE"
+                | "SUNSUBSCRIBE"
+                | "PING"
+                | "QUIT"
+                | "RESET"
+        )
+    {
+        return RespFrame::Error(format!(
+            "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+            command_name.to_lowercase()
+        ));
+    }
+
+    // a replica only changes its data the way its primary tells it to
+    if (spec.has_flag(WRITE) || changes_dataset(spec, &args))
+        && !session.is_master
+        && state.replication.is_replica()
+    {
+        if let Some(transaction) = &mut session.transaction {
+            transaction.aborted = true;
+        }
+        return RespFrame::Error(
+            "READONLY You can't write against a read only replica.".to_string(),
+        );
+    }
+
+    // inside MULTI everything except the transaction commands is only queued
+    if let Some(transaction) = &mut session.transaction
+        && spec.has_flag(NO_MULTI)
+    {
+        transaction.aborted = true;
+        return RespFrame::Error("ERR Command not allowed inside a transaction".to_string());
+    }
+    if let Some(transaction) = &mut session.transaction
+        && !matches!(
+            command_name.as_str(),
+            "EXEC" | "DISCARD" | "MULTI" | "WATCH"
+        )
+    {
+        transaction.queued.push(RespFrame::Array(args));
+        return RespFrame::SimpleString("QUEUED".to_string());
+    }
+
+    let reply = (spec.handler)(Context {
+        command_name: &command_name,
+        args: Args::new(&args[1..]),
+        storage,
+        session,
+        state,
+    });
+    propagate_expired(storage, state);
+    if reply.is_ok() && changes_dataset(spec, &args) {
+        propagate(state, storage, session, &args);
+    }
+    reply.unwrap_or_else(|error| error)
+}
+
+// what the append only file replays: writes, and the FUNCTION subcommands
+// that change libraries, which redis flags as writes one by one. MIGRATE
+// logs the keys it deleted itself.
+fn changes_dataset(spec: &CommandSpec, args: &[RespFrame]) -> bool {
+    if spec.has_flag(WRITE) {
+        return spec.name != "migrate";
+    }
+    spec.name == "function"
+        && matches!(
+            args.get(1),
+            Some(RespFrame::BulkString(subcommand))
+                if [&b"LOAD"[..], b"DELETE", b"FLUSH", b"RESTORE"]
+                    .iter()
+                    .any(|name| subcommand.eq_ignore_ascii_case(name))
+        )
+}
+
+fn ok() -> CommandResult {
+    Ok(RespFrame::simple("OK"))
+}
+
+fn bulk(s: &str) -> RespFrame {
+    s.into()
+}
+
+// HELP replies are one status line per entry
+fn help_reply(lines: &[&str]) -> CommandResult {
+    Ok(RespFrame::Array(
+        lines
+            .iter()
+            .map(|line| RespFrame::SimpleString(line.to_string()))
+            .collect(),
+    ))
+}
+
+fn unknown_subcommand(subcommand: &str, command: &str) -> RespFrame {
+    RespFrame::Error(format!(
+        "ERR unknown subcommand or wrong number of arguments for '{}'. Try {} HELP.",
+        subcommand, command
+    ))
+}
+
+// RESP2 has no maps, so they go out as flat key value arrays
+fn map_reply(fields: Vec<(RespFrame, RespFrame)>, resp3: bool) -> RespFrame {
+    if resp3 {
+        RespFrame::Map(fields)
+    } else {
+        RespFrame::Array(
+            fields
+                .into_iter()
+                .flat_map(|(key, value)| [key, value])
+                .collect(),
+        )
+    }
+}
+
+fn next_db_index(args: &mut Args, storage: &Storage) -> Result<usize, RespFrame> {
+    database_index(storage, args.next_i64()?)
+}
+
+fn database_index(storage: &Storage, index: i64) -> Result<usize, RespFrame> {
+    storage
+        .database_index(index)
+        .ok_or_else(|| RespFrame::Error("ERR DB index is out of range".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::resp_frame::RespFrame;
+    use crate::test_util::{run, setup_db};
+
+    #[test]
+    fn test_arity_is_checked_from_the_command_table() {
+        let mut db = setup_db();
+        assert_eq!(
+            run(&mut db, &["GET"]),
+            RespFrame::Error("ERR wrong number of arguments for 'get' command".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["mget"]),
+            RespFrame::Error("ERR wrong number of arguments for 'mget' command".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["nope", "a", "b"]),
+            RespFrame::Error(
+                "ERR unknown command 'nope', with args beginning with: 'a' 'b' ".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_argument_errors_are_uniform() {
+        let mut db = setup_db();
+        let not_an_integer =
+            RespFrame::Error("ERR value is not an integer or out of range".to_string());
+        assert_eq!(run(&mut db, &["INCRBY", "k", "ten"]), not_an_integer);
+        assert_eq!(run(&mut db, &["SELECT", "one"]), not_an_integer);
+        assert_eq!(run(&mut db, &["EVAL", "return 1", "x"]), not_an_integer);
+        assert_eq!(
+            run(&mut db, &["SET", "k", "v", "EX", "10"]),
+            RespFrame::Error("ERR syntax error".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["COPY", "a", "b", "DB"]),
+            RespFrame::Error("ERR syntax error".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["CLIENT", "NOPE"]),
+            RespFrame::Error(
+                "ERR unknown subcommand or wrong number of arguments for 'NOPE'. Try CLIENT HELP."
+                    .to_string()
+            )
+        );
+    }
+}
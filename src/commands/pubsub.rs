@@ -0,0 +1,653 @@
+use bytes::Bytes;
+
+use crate::ServerState;
+use crate::commands::{
+    CommandResult, CommandSpec, Context, FAST, LOADING, NOSCRIPT, PUBSUB, STALE, help_reply,
+    unknown_subcommand,
+};
+use crate::pubsub::push_frame;
+use crate::resp_frame::RespFrame;
+use crate::session::Session;
+use crate::slot::{key_hash_slot, slot_node};
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec::new(
+        "subscribe",
+        -2,
+        PUBSUB | NOSCRIPT | LOADING | STALE,
+        subscribe_command,
+    )
+    .doc(
+        "pubsub",
+        "Listens for messages published to channels.",
+        "2.0.0",
+    ),
+    CommandSpec::new(
+        "psubscribe",
+        -2,
+        PUBSUB | NOSCRIPT | LOADING | STALE,
+        subscribe_command,
+    )
+    .doc(
+        "pubsub",
+        "Listens for messages published to channels that match one or more patterns.",
+        "2.0.0",
+    ),
+    CommandSpec::new(
+        "unsubscribe",
+        -1,
+        PUBSUB | NOSCRIPT | LOADING | STALE,
+        unsubscribe_command,
+    )
+    .doc(
+        "pubsub",
+        "Stops listening to messages posted to channels.",
+        "2.0.0",
+    ),
+    CommandSpec::new(
+        "punsubscribe",
+        -1,
+        PUBSUB | NOSCRIPT | LOADING | STALE,
+        unsubscribe_command,
+    )
+    .doc(
+        "pubsub",
+        "Stops listening to messages published to channels that match one or more patterns.",
+        "2.0.0",
+    ),
+    CommandSpec::new(
+        "ssubscribe",
+        -2,
+        PUBSUB | NOSCRIPT | LOADING | STALE,
+        ssubscribe_command,
+    )
+    .doc(
+        "pubsub",
+        "Listens for messages published to shard channels.",
+        "7.0.0",
+    ),
+    CommandSpec::new(
+        "sunsubscribe",
+        -1,
+        PUBSUB | NOSCRIPT | LOADING | STALE,
+        sunsubscribe_command,
+    )
+    .doc(
+        "pubsub",
+        "Stops listening to messages posted to shard channels.",
+        "7.0.0",
+    ),
+    CommandSpec::new(
+        "spublish",
+        3,
+        PUBSUB | LOADING | STALE | FAST,
+        spublish_command,
+    )
+    .doc("pubsub", "Post a message to a shard channel", "7.0.0"),
+    CommandSpec::new(
+        "publish",
+        3,
+        PUBSUB | LOADING | STALE | FAST,
+        publish_command,
+    )
+    .doc("pubsub", "Posts a message to a channel.", "2.0.0"),
+    CommandSpec::new("pubsub", -2, LOADING | STALE, pubsub_command)
+        .categories(&["@pubsub"])
+        .doc("pubsub", "A container for Pub/Sub commands.", "2.8.0"),
+];
+
+pub fn subscribe_command(ctx: Context) -> CommandResult {
+    let Context {
+        command_name,
+        mut args,
+        session,
+        state,
+        ..
+    } = ctx;
+    let names = args.rest()?;
+
+    let pattern = command_name == "PSUBSCRIBE";
+    let mut replies = Vec::with_capacity(names.len());
+
+    for name in names {
+        if pattern {
+            if session.patterns.insert(name.clone()) {
+                state.pubsub.psubscribe(
+                    session.id,
+                    name.clone(),
+                    session.outbox.clone(),
+                    session.resp3,
+                );
+            }
+            replies.push(subscription_reply(
+                session,
+                "psubscribe",
+                Some(name),
+                session.subscription_count(),
+            ));
+        } else {
+            if session.channels.insert(name.clone()) {
+                state.pubsub.subscribe(
+                    session.id,
+                    name.clone(),
+                    session.outbox.clone(),
+                    session.resp3,
+                );
+            }
+            replies.push(subscription_reply(
+                session,
+                "subscribe",
+                Some(name),
+                session.subscription_count(),
+            ));
+        }
+    }
+
+    Ok(reply_many(session, replies))
+}
+
+pub fn unsubscribe_command(ctx: Context) -> CommandResult {
+    let Context {
+        command_name,
+        mut args,
+        session,
+        state,
+        ..
+    } = ctx;
+    let pattern = command_name == "PUNSUBSCRIBE";
+    let kind = if pattern {
+        "punsubscribe"
+    } else {
+        "unsubscribe"
+    };
+
+    // without arguments every current subscription is dropped
+    let names = if !args.is_empty() {
+        args.rest()?
+    } else if pattern {
+        session.patterns.iter().cloned().collect()
+    } else {
+        session.channels.iter().cloned().collect()
+    };
+
+    if names.is_empty() {
+        return Ok(subscription_reply(
+            session,
+            kind,
+            None,
+            session.subscription_count(),
+        ));
+    }
+
+    let mut replies = Vec::with_capacity(names.len());
+    for name in names {
+        if pattern {
+            if session.patterns.remove(&name) {
+                state.pubsub.punsubscribe(session.id, &name);
+            }
+        } else if session.channels.remove(&name) {
+            state.pubsub.unsubscribe(session.id, &name);
+        }
+        replies.push(subscription_reply(
+            session,
+            kind,
+            Some(name),
+            session.subscription_count(),
+        ));
+    }
+
+    Ok(reply_many(session, replies))
+}
+
+// Shard channels are routed by hash slot like keys. With cluster-enabled the
+// channels of one command have to share a slot, and a slot another node
+// serves is answered with MOVED. Unsubscribing is never redirected.
+fn check_shard_slot(
+    state: &ServerState,
+    channels: &[Bytes],
+    redirect: bool,
+) -> Result<(), RespFrame> {
+    let config = &state.config;
+    let Some(first) = channels.first().filter(|_| config.cluster_enabled) else {
+        return Ok(());
+    };
+    let slot = key_hash_slot(first);
+    if channels
+        .iter()
+        .any(|channel| key_hash_slot(channel) != slot)
+    {
+        return Err(RespFrame::Error(
+            "CROSSSLOT Keys in request don't hash to the same slot".to_string(),
+        ));
+    }
+    match slot_node(&config.cluster_slots, slot) {
+        Some(node) if redirect => Err(RespFrame::Error(format!("MOVED {} {}", slot, node))),
+        _ => Ok(()),
+    }
+}
+
+pub fn ssubscribe_command(ctx: Context) -> CommandResult {
+    let Context {
+        mut args,
+        session,
+        state,
+        ..
+    } = ctx;
+    let names = args.rest()?;
+    check_shard_slot(state, &names, true)?;
+
+    let mut replies = Vec::with_capacity(names.len());
+    for name in names {
+        if session.shard_channels.insert(name.clone()) {
+            state.pubsub.ssubscribe(
+                session.id,
+                name.clone(),
+                session.outbox.clone(),
+                session.resp3,
+            );
+        }
+        replies.push(subscription_reply(
+            session,
+            "ssubscribe",
+            Some(name),
+            session.shard_channels.len(),
+        ));
+    }
+
+    Ok(reply_many(session, replies))
+}
+
+pub fn sunsubscribe_command(ctx: Context) -> CommandResult {
+    let Context {
+        mut args,
+        session,
+        state,
+        ..
+    } = ctx;
+    let names = if !args.is_empty() {
+        let names = args.rest()?;
+        check_shard_slot(state, &names, false)?;
+        names
+    } else {
+        session.shard_channels.iter().cloned().collect()
+    };
+
+    if names.is_empty() {
+        return Ok(subscription_reply(session, "sunsubscribe", None, 0));
+    }
+
+    let mut replies = Vec::with_capacity(names.len());
+    for name in names {
+        if session.shard_channels.remove(&name) {
+            state.pubsub.sunsubscribe(session.id, &name);
+        }
+        replies.push(subscription_reply(
+            session,
+            "sunsubscribe",
+            Some(name),
+            session.shard_channels.len(),
+        ));
+    }
+
+    Ok(reply_many(session, replies))
+}
+
+pub fn spublish_command(ctx: Context) -> CommandResult {
+    let Context {
+        mut args, state, ..
+    } = ctx;
+    let channel = args.next_bytes()?;
+    let message = args.next_bytes()?;
+    check_shard_slot(state, std::slice::from_ref(&channel), true)?;
+
+    Ok(RespFrame::Integer(
+        state.pubsub.spublish(&channel, &message) as i64,
+    ))
+}
+
+pub fn publish_command(ctx: Context) -> CommandResult {
+    let Context {
+        mut args, state, ..
+    } = ctx;
+    let channel = args.next_bytes()?;
+    let message = args.next_bytes()?;
+
+    Ok(RespFrame::Integer(
+        state.pubsub.publish(&channel, &message) as i64
+    ))
+}
+
+pub fn pubsub_command(ctx: Context) -> CommandResult {
+    let Context {
+        mut args, state, ..
+    } = ctx;
+    let subcommand = args.next_keyword()?;
+    let rest = args.rest()?;
+
+    Ok(match (subcommand.as_str(), rest.len()) {
+        ("CHANNELS", 0 | 1) => RespFrame::Array(
+            state
+                .pubsub
+                .channels(rest.first().map(|pattern| pattern.as_ref()))
+                .into_iter()
+                .map(RespFrame::BulkString)
+                .collect(),
+        ),
+        ("NUMSUB", _) => RespFrame::Array(
+            rest.into_iter()
+                .flat_map(|channel| {
+                    let count = state.pubsub.subscriber_count(&channel);
+                    [
+                        RespFrame::BulkString(channel),
+                        RespFrame::Integer(count as i64),
+                    ]
+                })
+                .collect(),
+        ),
+        ("NUMPAT", 0) => RespFrame::Integer(state.pubsub.pattern_count() as i64),
+        ("SHARDCHANNELS", 0 | 1) => RespFrame::Array(
+            state
+                .pubsub
+                .shard_channels(rest.first().map(|pattern| pattern.as_ref()))
+                .into_iter()
+                .map(RespFrame::BulkString)
+                .collect(),
+        ),
+        ("SHARDNUMSUB", _) => RespFrame::Array(
+            rest.into_iter()
+                .flat_map(|channel| {
+                    let count = state.pubsub.shard_subscriber_count(&channel);
+                    [
+                        RespFrame::BulkString(channel),
+                        RespFrame::Integer(count as i64),
+                    ]
+                })
+                .collect(),
+        ),
+        ("HELP", 0) => {
+            return help_reply(&[
+                "PUBSUB <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                "CHANNELS [<pattern>]",
+                "    Return the currently active channels matching a <pattern> (default: '*').",
+                "NUMPAT",
+                "    Return number of subscriptions to patterns.",
+                "NUMSUB [<channel> ...]",
+                "    Return the number of subscribers for the specified channels, excluding",
+                "    pattern subscriptions(default: no channels).",
+                "SHARDCHANNELS [<pattern>]",
+                "    Return the currently active shard level channels matching a <pattern> (default: '*').",
+                "SHARDNUMSUB [<shardchannel> ...]",
+                "    Return the number of subscribers for the specified shard level channel(s)",
+            ]);
+        }
+        _ => return Err(unknown_subcommand(&subcommand, "PUBSUB")),
+    })
+}
+
+// SUBSCRIBE and friends answer with one frame per channel: all but the last
+// go out through the outbox ahead of the regular reply
+fn reply_many(session: &Session, mut frames: Vec<RespFrame>) -> RespFrame {
+    let last = frames.pop().unwrap_or(RespFrame::Null);
+    for frame in frames {
+        let _ = session.outbox.send(frame);
+    }
+    last
+}
+
+fn subscription_reply(
+    session: &Session,
+    kind: &'static str,
+    name: Option<Bytes>,
+    count: usize,
+) -> RespFrame {
+    push_frame(
+        vec![
+            RespFrame::BulkString(Bytes::from_static(kind.as_bytes())),
+            name.map_or(RespFrame::Null, RespFrame::BulkString),
+            RespFrame::Integer(count as i64),
+        ],
+        session.resp3,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, SlotRange};
+    use crate::resp_frame::RespFrame;
+    use crate::test_util::{TempDir, bulk, push, run, setup_db};
+
+    #[test]
+    fn test_subscribe_and_publish() {
+        let mut subscriber = setup_db();
+        let mut publisher = subscriber.connect();
+
+        assert_eq!(
+            run(&mut subscriber, &["SUBSCRIBE", "a", "b"]),
+            RespFrame::Array(vec![bulk("subscribe"), bulk("b"), RespFrame::Integer(2)])
+        );
+        assert_eq!(
+            subscriber.pushed(),
+            vec![RespFrame::Array(vec![
+                bulk("subscribe"),
+                bulk("a"),
+                RespFrame::Integer(1)
+            ])]
+        );
+
+        assert_eq!(
+            run(&mut publisher, &["PUBLISH", "a", "hello"]),
+            RespFrame::Integer(1)
+        );
+        assert_eq!(
+            subscriber.pushed(),
+            vec![RespFrame::Array(vec![
+                bulk("message"),
+                bulk("a"),
+                bulk("hello")
+            ])]
+        );
+        assert_eq!(
+            run(&mut publisher, &["PUBLISH", "nobody", "hello"]),
+            RespFrame::Integer(0)
+        );
+    }
+
+    #[test]
+    fn test_subscriber_mode_restricts_commands() {
+        let mut db = setup_db();
+        run(&mut db, &["SUBSCRIBE", "a"]);
+
+        assert_eq!(
+            run(&mut db, &["GET", "live"]),
+            RespFrame::Error("ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["PING"]),
+            RespFrame::Array(vec![bulk("pong"), bulk("")])
+        );
+
+        assert_eq!(
+            run(&mut db, &["UNSUBSCRIBE"]),
+            RespFrame::Array(vec![bulk("unsubscribe"), bulk("a"), RespFrame::Integer(0)])
+        );
+        assert_eq!(run(&mut db, &["GET", "live"]), bulk("1"));
+    }
+
+    #[test]
+    fn test_psubscribe_uses_glob_patterns() {
+        let mut subscriber = setup_db();
+        let mut publisher = subscriber.connect();
+
+        run(&mut subscriber, &["PSUBSCRIBE", "news.[sw]*"]);
+        run(&mut publisher, &["PUBLISH", "news.sport", "1"]);
+        run(&mut publisher, &["PUBLISH", "news.tech", "2"]);
+
+        assert_eq!(
+            subscriber.pushed(),
+            vec![RespFrame::Array(vec![
+                bulk("pmessage"),
+                bulk("news.[sw]*"),
+                bulk("news.sport"),
+                bulk("1")
+            ])]
+        );
+        assert_eq!(
+            run(&mut publisher, &["PUBSUB", "NUMPAT"]),
+            RespFrame::Integer(1)
+        );
+    }
+
+    #[test]
+    fn test_shard_channels_have_their_own_subscriptions() {
+        let mut subscriber = setup_db();
+        let mut publisher = subscriber.connect();
+
+        run(&mut subscriber, &["SUBSCRIBE", "orders"]);
+        assert_eq!(
+            run(&mut subscriber, &["SSUBSCRIBE", "{user1}.orders"]),
+            RespFrame::Array(vec![
+                bulk("ssubscribe"),
+                bulk("{user1}.orders"),
+                RespFrame::Integer(1)
+            ])
+        );
+
+        assert_eq!(
+            run(&mut publisher, &["SPUBLISH", "{user1}.orders", "42"]),
+            RespFrame::Integer(1)
+        );
+        assert_eq!(
+            run(&mut publisher, &["SPUBLISH", "orders", "42"]),
+            RespFrame::Integer(0)
+        );
+        assert_eq!(
+            subscriber.pushed(),
+            vec![RespFrame::Array(vec![
+                bulk("smessage"),
+                bulk("{user1}.orders"),
+                bulk("42")
+            ])]
+        );
+        assert_eq!(
+            run(&mut publisher, &["PUBSUB", "SHARDNUMSUB", "{user1}.orders"]),
+            RespFrame::Array(vec![bulk("{user1}.orders"), RespFrame::Integer(1)])
+        );
+
+        assert_eq!(
+            run(&mut subscriber, &["SUNSUBSCRIBE"]),
+            RespFrame::Array(vec![
+                bulk("sunsubscribe"),
+                bulk("{user1}.orders"),
+                RespFrame::Integer(0)
+            ])
+        );
+        assert_eq!(
+            run(&mut publisher, &["PUBSUB", "SHARDCHANNELS"]),
+            RespFrame::Array(vec![])
+        );
+    }
+
+    #[test]
+    fn test_shard_channels_follow_cluster_slots() {
+        let dir = TempDir::new("cluster-shard-channels");
+        let mut db = dir
+            .server_with(Config {
+                cluster_enabled: true,
+                cluster_slots: SlotRange::parse_list("8192-16383 10.0.0.2:7001").unwrap(),
+                ..Config::default()
+            })
+            .unwrap();
+        let error = |message: &str| RespFrame::Error(message.to_string());
+
+        // "foo" hashes to slot 12182 and "bar" to 5061
+        assert_eq!(
+            run(&mut db, &["SSUBSCRIBE", "bar", "foo"]),
+            error("CROSSSLOT Keys in request don't hash to the same slot")
+        );
+        assert_eq!(
+            run(&mut db, &["SSUBSCRIBE", "foo"]),
+            error("MOVED 12182 10.0.0.2:7001")
+        );
+        assert_eq!(
+            run(&mut db, &["SPUBLISH", "foo", "hi"]),
+            error("MOVED 12182 10.0.0.2:7001")
+        );
+        assert_eq!(
+            run(&mut db, &["SSUBSCRIBE", "bar", "{bar}.news"]),
+            RespFrame::Array(vec![
+                bulk("ssubscribe"),
+                bulk("{bar}.news"),
+                RespFrame::Integer(2)
+            ])
+        );
+        assert_eq!(
+            run(&mut db, &["SUNSUBSCRIBE", "bar", "foo"]),
+            error("CROSSSLOT Keys in request don't hash to the same slot")
+        );
+        assert_eq!(
+            run(&mut db.connect(), &["SPUBLISH", "bar", "hi"]),
+            RespFrame::Integer(1)
+        );
+
+        // without cluster-enabled every slot is served here
+        let mut standalone = setup_db();
+        assert_eq!(
+            run(&mut standalone, &["SSUBSCRIBE", "bar", "foo"]),
+            RespFrame::Array(vec![bulk("ssubscribe"), bulk("foo"), RespFrame::Integer(2)])
+        );
+    }
+
+    #[test]
+    fn test_resp3_subscribers_get_push_frames() {
+        let mut subscriber = setup_db();
+        let mut publisher = subscriber.connect();
+
+        let RespFrame::Map(_) = run(&mut subscriber, &["HELLO", "3"]) else {
+            panic!("HELLO 3 should reply with a map");
+        };
+        assert_eq!(
+            run(&mut subscriber, &["SUBSCRIBE", "a"]),
+            push(vec![bulk("subscribe"), bulk("a"), RespFrame::Integer(1)])
+        );
+        // RESP3 connections keep full access while subscribed
+        assert_eq!(run(&mut subscriber, &["GET", "live"]), bulk("1"));
+
+        run(&mut publisher, &["PUBLISH", "a", "hi"]);
+        assert_eq!(
+            subscriber.pushed(),
+            vec![push(vec![bulk("message"), bulk("a"), bulk("hi")])]
+        );
+    }
+
+    #[test]
+    fn test_pubsub_introspection() {
+        let mut first = setup_db();
+        let mut second = first.connect();
+
+        run(&mut first, &["SUBSCRIBE", "news", "chat"]);
+        run(&mut second, &["SUBSCRIBE", "news"]);
+        let mut observer = first.connect();
+
+        let RespFrame::Array(mut channels) = run(&mut observer, &["PUBSUB", "CHANNELS"]) else {
+            panic!("PUBSUB CHANNELS should reply with an array");
+        };
+        channels.sort_by_key(|frame| format!("{:?}", frame));
+        assert_eq!(channels, vec![bulk("chat"), bulk("news")]);
+
+        assert_eq!(
+            run(&mut observer, &["PUBSUB", "CHANNELS", "n*"]),
+            RespFrame::Array(vec![bulk("news")])
+        );
+        assert_eq!(
+            run(&mut observer, &["PUBSUB", "NUMSUB", "news", "chat", "none"]),
+            RespFrame::Array(vec![
+                bulk("news"),
+                RespFrame::Integer(2),
+                bulk("chat"),
+                RespFrame::Integer(1),
+                bulk("none"),
+                RespFrame::Integer(0),
+            ])
+        );
+    }
+}
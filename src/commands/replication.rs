@@ -0,0 +1,131 @@
+use crate::commands::args::syntax_error;
+use crate::commands::{
+    ADMIN, ALLOW_BUSY, CommandResult, CommandSpec, Context, FAST, LOADING, NOSCRIPT, STALE, ok,
+};
+use crate::config::MasterAddr;
+use crate::resp_frame::RespFrame;
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("replicaof", 3, ADMIN | NOSCRIPT | STALE, replicaof_command)
+        .categories(&["@dangerous"])
+        .doc(
+            "server",
+            "Configures a server as replica of another, or promotes it to a master.",
+            "5.0.0",
+        ),
+    CommandSpec::new("slaveof", 3, ADMIN | NOSCRIPT | STALE, replicaof_command)
+        .categories(&["@dangerous"])
+        .doc(
+            "server",
+            "Sets a Redis server as a replica of another, or promotes it to being a master.",
+            "1.0.0",
+        ),
+    CommandSpec::new("psync", -3, ADMIN | NOSCRIPT, psync_command)
+        .categories(&["@dangerous"])
+        .doc(
+            "server",
+            "An internal command used in replication.",
+            "2.8.0",
+        ),
+    CommandSpec::new(
+        "replconf",
+        -1,
+        ADMIN | NOSCRIPT | LOADING | STALE | ALLOW_BUSY,
+        replconf_command,
+    )
+    .categories(&["@dangerous"])
+    .doc(
+        "server",
+        "An internal command for configuring the replication stream.",
+        "3.0.0",
+    ),
+    CommandSpec::new("role", 1, NOSCRIPT | LOADING | STALE | FAST, role_command)
+        .categories(&["@admin", "@dangerous"])
+        .doc("server", "Returns the replication role.", "2.8.12"),
+];
+
+pub fn replicaof_command(mut ctx: Context) -> CommandResult {
+    let host = ctx.args.next_bytes()?;
+    let port = ctx.args.next_bytes()?;
+    let replication = &ctx.state.replication;
+
+    if host.eq_ignore_ascii_case(b"NO") && port.eq_ignore_ascii_case(b"ONE") {
+        if replication.set_master(None) {
+            ctx.storage.set_keep_expired(false);
+            println!("MASTER MODE enabled (user request)");
+        }
+        return ok();
+    }
+    let port = std::str::from_utf8(&port)
+        .ok()
+        .and_then(|port| port.parse().ok())
+        .ok_or_else(|| RespFrame::Error("ERR Invalid master port".to_string()))?;
+    let master = MasterAddr {
+        host: String::from_utf8_lossy(&host).into_owned(),
+        port,
+    };
+    if !replication.set_master(Some(master.clone())) {
+        return Ok(RespFrame::simple(
+            "OK Already connected to specified master",
+        ));
+    }
+    ctx.storage.set_keep_expired(true);
+    println!(
+        "REPLICAOF {}:{} enabled (user request)",
+        master.host, master.port
+    );
+    ok()
+}
+
+// the connection turns into a replication stream once the reply is out
+pub fn psync_command(mut ctx: Context) -> CommandResult {
+    let replid = ctx.args.next_bytes()?;
+    let offset = ctx.args.next_i64()?;
+    let (reply, stream) = ctx.state.replication.psync(
+        &replid,
+        offset,
+        ctx.storage,
+        &ctx.state.scripting,
+        ctx.session,
+    )?;
+    ctx.session.replica_stream = Some(stream);
+    Ok(reply)
+}
+
+// option value pairs a replica sends while connecting, and its ACKs once it
+// streams, whose reply the connection drops
+pub fn replconf_command(mut ctx: Context) -> CommandResult {
+    if !ctx.args.remaining().is_multiple_of(2) {
+        return Err(syntax_error());
+    }
+    while !ctx.args.is_empty() {
+        let option = ctx.args.next_bytes()?;
+        match option.to_ascii_uppercase().as_slice() {
+            b"LISTENING-PORT" => {
+                let port = ctx.args.next_i64()?;
+                ctx.session.replica_port = u16::try_from(port).ok();
+            }
+            b"ACK" => {
+                let offset = ctx.args.next_i64()?;
+                ctx.state
+                    .replication
+                    .ack(ctx.session.id, offset.max(0) as u64);
+            }
+            // only a replica answers GETACK, by itself
+            b"CAPA" | b"IP-ADDRESS" | b"FACK" | b"GETACK" => {
+                ctx.args.next_bytes()?;
+            }
+            _ => {
+                return Err(RespFrame::Error(format!(
+                    "ERR Unrecognized REPLCONF option: {}",
+                    String::from_utf8_lossy(&option)
+                )));
+            }
+        }
+    }
+    ok()
+}
+
+pub fn role_command(ctx: Context) -> CommandResult {
+    Ok(ctx.state.replication.role())
+}
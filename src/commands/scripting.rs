@@ -0,0 +1,569 @@
+use bytes::Bytes;
+
+use crate::commands::args::Args;
+use crate::commands::{
+    self, CommandResult, CommandSpec, Context, KeySpec, NOSCRIPT, READONLY, STALE, WRITE, bulk,
+    handle_command, help_reply, map_reply, ok, unknown_subcommand,
+};
+use crate::glob;
+use crate::outbox;
+use crate::resp_frame::RespFrame;
+use crate::scripting::RestorePolicy;
+use crate::session::Session;
+use crate::storage::Storage;
+use crate::{ServerState, begin_atomic, end_atomic};
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("eval", -3, NOSCRIPT | STALE, eval_command)
+        .keys(KeySpec::Counted { index: 2 })
+        .categories(&["@scripting"])
+        .doc("scripting", "Executes a server-side Lua script.", "2.6.0"),
+    CommandSpec::new("evalsha", -3, NOSCRIPT | STALE, eval_command)
+        .keys(KeySpec::Counted { index: 2 })
+        .categories(&["@scripting"])
+        .doc(
+            "scripting",
+            "Executes a server-side Lua script by SHA1 digest.",
+            "2.6.0",
+        ),
+    CommandSpec::new("fcall", -3, NOSCRIPT | STALE, fcall_command)
+        .keys(KeySpec::Counted { index: 2 })
+        .categories(&["@scripting"])
+        .doc("scripting", "Invokes a function.", "7.0.0"),
+    CommandSpec::new("fcall_ro", -3, NOSCRIPT | STALE | READONLY, fcall_command)
+        .keys(KeySpec::Counted { index: 2 })
+        .categories(&["@scripting"])
+        .doc("scripting", "Invokes a read-only function.", "7.0.0"),
+    CommandSpec::new("function", -2, NOSCRIPT, function_command)
+        .categories(&["@scripting"])
+        .doc("scripting", "A container for function commands.", "7.0.0"),
+    CommandSpec::new("script", -2, NOSCRIPT, script_command)
+        .categories(&["@scripting"])
+        .doc(
+            "scripting",
+            "A container for Lua scripts management commands.",
+            "2.6.0",
+        ),
+];
+
+pub fn eval_command(mut ctx: Context) -> CommandResult {
+    let script = ctx.args.next_bytes()?;
+    let (keys, argv) = script_keys_and_args(&mut ctx.args)?;
+
+    let sha = if ctx.command_name == "EVAL" {
+        Bytes::from(ctx.state.scripting.load(&script)?)
+    } else {
+        script
+    };
+    let mut call = script_caller(ctx.storage, ctx.session, ctx.state, false);
+    // the commands a script ran are logged instead of the script itself
+    begin_atomic(ctx.state);
+    let reply = ctx.state.scripting.run(&sha, keys, argv, &mut call);
+    end_atomic(ctx.state);
+    Ok(reply)
+}
+
+pub fn fcall_command(mut ctx: Context) -> CommandResult {
+    let function = ctx.args.next_bytes()?;
+    let (keys, argv) = script_keys_and_args(&mut ctx.args)?;
+
+    let Some(no_writes) = ctx.state.scripting.function_is_read_only(&function) else {
+        return Err(RespFrame::Error("ERR Function not found".to_string()));
+    };
+    if ctx.command_name == "FCALL_RO" && !no_writes {
+        return Err(RespFrame::Error(
+            "ERR Can not execute a script with write flag using *_ro command.".to_string(),
+        ));
+    }
+
+    let mut call = script_caller(ctx.storage, ctx.session, ctx.state, no_writes);
+    begin_atomic(ctx.state);
+    let reply = ctx.state.scripting.fcall(&function, keys, argv, &mut call);
+    end_atomic(ctx.state);
+    Ok(reply)
+}
+
+pub fn function_command(mut ctx: Context) -> CommandResult {
+    let subcommand = ctx.args.next_keyword()?;
+    let rest = ctx.args.rest()?;
+    let Context { session, state, .. } = ctx;
+
+    match (subcommand.as_str(), rest.as_slice()) {
+        ("LOAD", [code]) => Ok(RespFrame::BulkString(Bytes::from(
+            state.scripting.load_library(code, false)?,
+        ))),
+        ("LOAD", [replace, code]) if replace.eq_ignore_ascii_case(b"REPLACE") => Ok(
+            RespFrame::BulkString(Bytes::from(state.scripting.load_library(code, true)?)),
+        ),
+        ("DELETE", [name]) => {
+            if state.scripting.delete_library(name) {
+                ok()
+            } else {
+                Err(RespFrame::Error("ERR Library not found".to_string()))
+            }
+        }
+        ("FLUSH", [] | [_]) => {
+            if let Some(mode) = rest.first()
+                && !mode.eq_ignore_ascii_case(b"ASYNC")
+                && !mode.eq_ignore_ascii_case(b"SYNC")
+            {
+                return Err(RespFrame::Error(
+                    "ERR FUNCTION FLUSH only supports SYNC|ASYNC option".to_string(),
+                ));
+            }
+            state.scripting.flush_functions();
+            ok()
+        }
+        ("LIST", _) => {
+            let mut with_code = false;
+            let mut pattern = None;
+            let mut options = rest.iter();
+            while let Some(option) = options.next() {
+                if option.eq_ignore_ascii_case(b"WITHCODE") {
+                    with_code = true;
+                } else if option.eq_ignore_ascii_case(b"LIBRARYNAME") {
+                    let Some(name) = options.next() else {
+                        return Err(RespFrame::Error(
+                            "ERR library name argument was not given".to_string(),
+                        ));
+                    };
+                    pattern = Some(name.clone());
+                } else {
+                    return Err(RespFrame::Error(format!(
+                        "ERR Unknown argument {}",
+                        String::from_utf8_lossy(option)
+                    )));
+                }
+            }
+
+            let libraries = state
+                .scripting
+                .libraries()
+                .into_iter()
+                .filter(|library| {
+                    pattern
+                        .as_ref()
+                        .is_none_or(|pattern| glob::glob_match(pattern, library.name.as_bytes()))
+                })
+                .map(|library| {
+                    let functions = library
+                        .functions
+                        .iter()
+                        .map(|function| {
+                            let description = function
+                                .description
+                                .as_deref()
+                                .map_or(RespFrame::Null, bulk);
+                            let flags = function.flags.iter().map(|flag| bulk(flag)).collect();
+                            map_reply(
+                                vec![
+                                    (bulk("name"), bulk(&function.name)),
+                                    (bulk("description"), description),
+                                    (bulk("flags"), RespFrame::Array(flags)),
+                                ],
+                                session.resp3,
+                            )
+                        })
+                        .collect();
+
+                    let mut fields = vec![
+                        (bulk("library_name"), bulk(&library.name)),
+                        (bulk("engine"), bulk("LUA")),
+                        (bulk("functions"), RespFrame::Array(functions)),
+                    ];
+                    if with_code {
+                        fields.push((bulk("library_code"), RespFrame::BulkString(library.code)));
+                    }
+                    map_reply(fields, session.resp3)
+                })
+                .collect();
+            Ok(RespFrame::Array(libraries))
+        }
+        ("DUMP", []) => Ok(RespFrame::BulkString(Bytes::from(
+            state.scripting.dump_functions(),
+        ))),
+        ("RESTORE", [payload, policy @ ..]) if policy.len() <= 1 => {
+            let policy = match policy.first().map(|p| p.to_ascii_uppercase()) {
+                None => RestorePolicy::Append,
+                Some(p) if p == b"APPEND" => RestorePolicy::Append,
+                Some(p) if p == b"REPLACE" => RestorePolicy::Replace,
+                Some(p) if p == b"FLUSH" => RestorePolicy::Flush,
+                Some(_) => {
+                    return Err(RespFrame::Error(
+                        "ERR Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE."
+                            .to_string(),
+                    ));
+                }
+            };
+            state.scripting.restore_functions(payload, policy)?;
+            ok()
+        }
+        ("KILL", []) => Ok(state.scripting.kill()),
+        ("HELP", []) => help_reply(&[
+            "FUNCTION <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "LOAD [REPLACE] <FUNCTION CODE>",
+            "    Create a new library with the given library name and code.",
+            "DELETE <LIBRARY NAME>",
+            "    Delete the given library.",
+            "LIST [LIBRARYNAME PATTERN] [WITHCODE]",
+            "    Return general information on all the libraries.",
+            "FLUSH [ASYNC|SYNC]",
+            "    Delete all the libraries.",
+            "DUMP",
+            "    Return a serialized payload representing the current libraries.",
+            "RESTORE <PAYLOAD> [FLUSH|APPEND|REPLACE]",
+            "    Restore the libraries represented by the given payload.",
+            "KILL",
+            "    Kill the current running function.",
+        ]),
+        _ => Err(unknown_subcommand(&subcommand, "FUNCTION")),
+    }
+}
+
+pub fn script_command(mut ctx: Context) -> CommandResult {
+    let subcommand = ctx.args.next_keyword()?;
+    let rest = ctx.args.rest()?;
+    let state = ctx.state;
+
+    match (subcommand.as_str(), rest.len()) {
+        ("LOAD", 1) => Ok(RespFrame::BulkString(Bytes::from(
+            state.scripting.load(&rest[0])?,
+        ))),
+        ("EXISTS", 1..) => Ok(RespFrame::Array(
+            rest.iter()
+                .map(|sha| RespFrame::Integer(state.scripting.exists(sha) as i64))
+                .collect(),
+        )),
+        ("FLUSH", 0 | 1) => {
+            if let Some(mode) = rest.first()
+                && !mode.eq_ignore_ascii_case(b"ASYNC")
+                && !mode.eq_ignore_ascii_case(b"SYNC")
+            {
+                return Err(RespFrame::Error(
+                    "ERR SCRIPT FLUSH only support SYNC|ASYNC option".to_string(),
+                ));
+            }
+            state.scripting.flush();
+            ok()
+        }
+        ("KILL", 0) => Ok(state.scripting.kill()),
+        ("HELP", 0) => help_reply(&[
+            "SCRIPT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "EXISTS <sha1> [<sha1> ...]",
+            "    Return information about the existence of the scripts in the script cache.",
+            "FLUSH [ASYNC|SYNC]",
+            "    Flush the Lua scripts cache.",
+            "KILL",
+            "    Kill the currently executing Lua script.",
+            "LOAD <script>",
+            "    Load a script into the scripts cache without executing it.",
+        ]),
+        _ => Err(unknown_subcommand(&subcommand, "SCRIPT")),
+    }
+}
+
+// splits `numkeys key... arg...` as taken by EVAL and FCALL
+fn script_keys_and_args(args: &mut Args) -> Result<(Vec<Bytes>, Vec<Bytes>), RespFrame> {
+    let numkeys = args.next_i64()?;
+    if numkeys < 0 {
+        return Err(RespFrame::Error(
+            "ERR Number of keys can't be negative".to_string(),
+        ));
+    }
+    let numkeys = numkeys as usize;
+    if numkeys > args.remaining() {
+        return Err(RespFrame::Error(
+            "ERR Number of keys can't be greater than number of args".to_string(),
+        ));
+    }
+    let mut argv = args.rest()?;
+    let keys = argv.drain(..numkeys).collect();
+    Ok((keys, argv))
+}
+
+// Runs the commands a script issues as a separate client that starts in the
+// caller's database. Read-only scripts are refused write commands.
+fn script_caller<'a>(
+    storage: &'a mut Storage,
+    session: &Session,
+    state: &'a ServerState,
+    read_only: bool,
+) -> impl FnMut(Vec<Bytes>) -> RespFrame + 'a {
+    let (outbox, _) = outbox::channel();
+    let mut script_session = Session::new(outbox);
+    script_session.db_index = session.db_index;
+
+    move |args: Vec<Bytes>| {
+        if let Some(spec) = commands::lookup(&String::from_utf8_lossy(&args[0])) {
+            if spec.has_flag(NOSCRIPT) {
+                return RespFrame::Error(
+                    "ERR This Redis command is not allowed from script".to_string(),
+                );
+            }
+            if read_only && spec.has_flag(WRITE) {
+                return RespFrame::Error(
+                    "ERR Write commands are not allowed from read-only scripts.".to_string(),
+                );
+            }
+        }
+
+        let frame = RespFrame::Array(args.into_iter().map(RespFrame::BulkString).collect());
+        let dirty = storage.dirty();
+        let reply = handle_command(frame, storage, &mut script_session, state);
+        if storage.dirty() != dirty {
+            state.scripting.record_write();
+        }
+        reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::client::{Client, ClientError};
+    use crate::execute;
+    use crate::resp_frame::RespFrame;
+    use crate::scripting::sha1_hex;
+    use crate::test_util::{TestDb, bulk, ok, run, setup_db};
+    use crate::testing::TestServer;
+
+    #[test]
+    fn test_eval_runs_commands_atomically() {
+        let mut db = setup_db();
+        let script =
+            "local n = redis.call('INCR', KEYS[1]) redis.call('SET', KEYS[2], ARGV[1]) return n";
+
+        assert_eq!(
+            run(&mut db, &["EVAL", script, "2", "live", "copy", "x"]),
+            RespFrame::Integer(2)
+        );
+        assert_eq!(run(&mut db, &["GET", "copy"]), bulk("x"));
+
+        let sha = sha1_hex(script.as_bytes());
+        assert_eq!(
+            run(&mut db, &["SCRIPT", "EXISTS", &sha, "ffff"]),
+            RespFrame::Array(vec![RespFrame::Integer(1), RespFrame::Integer(0)])
+        );
+        assert_eq!(
+            run(&mut db, &["EVALSHA", &sha, "2", "live", "copy", "y"]),
+            RespFrame::Integer(3)
+        );
+    }
+
+    #[test]
+    fn test_eval_sees_expiry_and_the_selected_database() {
+        let mut db = setup_db();
+        assert_eq!(
+            run(
+                &mut db,
+                &["EVAL", "return redis.call('GET', KEYS[1])", "1", "gone"]
+            ),
+            RespFrame::Null
+        );
+
+        run(&mut db, &["SELECT", "2"]);
+        run(&mut db, &["SET", "here", "2"]);
+        assert_eq!(
+            run(&mut db, &["EVAL", "return redis.call('GET', 'here')", "0"]),
+            bulk("2")
+        );
+    }
+
+    #[test]
+    fn test_eval_argument_errors() {
+        let mut db = setup_db();
+        assert_eq!(
+            run(&mut db, &["EVAL", "return 1", "-1"]),
+            RespFrame::Error("ERR Number of keys can't be negative".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["EVAL", "return 1", "2", "a"]),
+            RespFrame::Error("ERR Number of keys can't be greater than number of args".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["EVALSHA", "ffff", "0"]),
+            RespFrame::Error("NOSCRIPT No matching script. Please use EVAL.".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["SCRIPT", "KILL"]),
+            RespFrame::Error("NOTBUSY No scripts in execution right now.".to_string())
+        );
+    }
+
+    // sends SCRIPT KILL until a script is running to be killed
+    async fn kill_script(killer: &Client) -> RespFrame {
+        let killed = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                match killer.command(&["SCRIPT", "KILL"]).await {
+                    Err(ClientError::Server(message)) if message.starts_with("NOTBUSY") => {}
+                    Err(ClientError::Server(message)) => return RespFrame::Error(message),
+                    reply => return reply.unwrap(),
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        });
+        killed.await.expect("SCRIPT KILL was never answered")
+    }
+
+    // a single runtime worker, as on a one cpu host: the looping script must
+    // not keep the connection sending SCRIPT KILL from being served
+    #[tokio::test]
+    async fn test_script_kill_reaches_a_looping_script_over_the_network() {
+        let server = TestServer::start().await;
+        let runner = Client::connect(server.addr()).await.unwrap();
+        let killer = Client::connect(server.addr()).await.unwrap();
+
+        let looping =
+            tokio::spawn(async move { runner.command(&["EVAL", "while 1 do end", "0"]).await });
+        assert_eq!(kill_script(&killer).await, ok());
+        match looping.await.unwrap() {
+            Err(ClientError::Server(message)) => {
+                assert_eq!(message, "ERR Script killed by user with SCRIPT KILL...")
+            }
+            reply => panic!("the script was not killed: {:?}", reply),
+        }
+        killer.ping().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_script_kill_ignores_writes_that_failed() {
+        let server = TestServer::start().await;
+        let runner = Client::connect(server.addr()).await.unwrap();
+        let killer = Client::connect(server.addr()).await.unwrap();
+        killer.command(&["SET", "text", "abc"]).await.unwrap();
+
+        let script = "redis.pcall('INCR', KEYS[1]) while 1 do end";
+        let looping =
+            tokio::spawn(async move { runner.command(&["EVAL", script, "1", "text"]).await });
+        assert_eq!(kill_script(&killer).await, ok());
+        assert!(looping.await.unwrap().is_err());
+        assert_eq!(killer.command(&["GET", "text"]).await.unwrap(), bulk("abc"));
+    }
+
+    #[test]
+    fn test_fcall_and_fcall_ro() {
+        let mut db = setup_db();
+        let library = "#!lua name=counters\n\
+            redis.register_function('bump', function(keys) return redis.call('INCR', keys[1]) end)\n\
+            redis.register_function{function_name='peek', \
+                callback=function(keys) return redis.call('GET', keys[1]) end, flags={'no-writes'}}";
+
+        assert_eq!(
+            run(&mut db, &["FUNCTION", "LOAD", library]),
+            bulk("counters")
+        );
+        assert_eq!(
+            run(&mut db, &["FUNCTION", "LOAD", library]),
+            RespFrame::Error("ERR Library 'counters' already exists".to_string())
+        );
+
+        assert_eq!(
+            run(&mut db, &["FCALL", "bump", "1", "live"]),
+            RespFrame::Integer(2)
+        );
+        assert_eq!(run(&mut db, &["FCALL_RO", "peek", "1", "live"]), bulk("2"));
+        assert_eq!(
+            run(&mut db, &["FCALL_RO", "bump", "1", "live"]),
+            RespFrame::Error(
+                "ERR Can not execute a script with write flag using *_ro command.".to_string()
+            )
+        );
+        assert_eq!(
+            run(&mut db, &["FCALL", "missing", "0"]),
+            RespFrame::Error("ERR Function not found".to_string())
+        );
+
+        assert_eq!(
+            run(&mut db, &["FUNCTION", "LIST", "LIBRARYNAME", "count*"]),
+            RespFrame::Array(vec![RespFrame::Array(vec![
+                bulk("library_name"),
+                bulk("counters"),
+                bulk("engine"),
+                bulk("LUA"),
+                bulk("functions"),
+                RespFrame::Array(vec![
+                    RespFrame::Array(vec![
+                        bulk("name"),
+                        bulk("bump"),
+                        bulk("description"),
+                        RespFrame::Null,
+                        bulk("flags"),
+                        RespFrame::Array(vec![]),
+                    ]),
+                    RespFrame::Array(vec![
+                        bulk("name"),
+                        bulk("peek"),
+                        bulk("description"),
+                        RespFrame::Null,
+                        bulk("flags"),
+                        RespFrame::Array(vec![bulk("no-writes")]),
+                    ]),
+                ]),
+            ])])
+        );
+    }
+
+    #[test]
+    fn test_no_writes_functions_cannot_write() {
+        let mut db = setup_db();
+        let library = "#!lua name=sneaky\n\
+            redis.register_function{function_name='sneak', \
+                callback=function(keys) return redis.call('SET', keys[1], 'x') end, flags={'no-writes'}}";
+        run(&mut db, &["FUNCTION", "LOAD", library]);
+
+        assert!(matches!(
+            run(&mut db, &["FCALL", "sneak", "1", "k"]),
+            RespFrame::Error(e) if e.contains("Write commands are not allowed from read-only scripts")
+        ));
+        assert_eq!(run(&mut db, &["GET", "k"]), RespFrame::Null);
+    }
+
+    #[test]
+    fn test_function_dump_and_restore() {
+        let mut db = setup_db();
+        let library = "#!lua name=lib\nredis.register_function('one', function() return 1 end)";
+        run(&mut db, &["FUNCTION", "LOAD", library]);
+
+        let RespFrame::BulkString(payload) = run(&mut db, &["FUNCTION", "DUMP"]) else {
+            panic!("FUNCTION DUMP did not return a bulk string");
+        };
+        // the payload is binary, so it can't go through `run`
+        let restore = |db: &mut TestDb, policy: Option<&str>| {
+            let mut frame = vec![
+                bulk("FUNCTION"),
+                bulk("RESTORE"),
+                RespFrame::BulkString(payload.clone()),
+            ];
+            frame.extend(policy.map(bulk));
+            execute(RespFrame::Array(frame), &db.state, &mut db.session)
+        };
+
+        assert_eq!(run(&mut db, &["FUNCTION", "FLUSH"]), ok());
+        assert_eq!(
+            run(&mut db, &["FCALL", "one", "0"]),
+            RespFrame::Error("ERR Function not found".to_string())
+        );
+
+        assert_eq!(restore(&mut db, None), ok());
+        assert_eq!(run(&mut db, &["FCALL", "one", "0"]), RespFrame::Integer(1));
+        assert_eq!(
+            restore(&mut db, None),
+            RespFrame::Error("ERR Library 'lib' already exists".to_string())
+        );
+        assert_eq!(restore(&mut db, Some("REPLACE")), ok());
+        assert_eq!(
+            run(&mut db, &["FUNCTION", "RESTORE", "garbage"]),
+            RespFrame::Error("ERR payload version or checksum are wrong".to_string())
+        );
+    }
+
+    #[test]
+    fn test_scripts_cannot_call_noscript_commands() {
+        let mut db = setup_db();
+        assert_eq!(
+            run(&mut db, &["EVAL", "return redis.pcall('MULTI')", "0"]),
+            RespFrame::Error("ERR This Redis command is not allowed from script".to_string())
+        );
+    }
+}
@@ -0,0 +1,850 @@
+use bytes::Bytes;
+
+use crate::ServerState;
+use crate::aof::Rewrite;
+use crate::commands::args::syntax_error;
+use crate::commands::{
+    self, ADMIN, CommandResult, CommandSpec, Context, FAST, KeySpec, LOADING, NOSCRIPT, READONLY,
+    STALE, WRITE, bulk, help_reply, map_reply, next_db_index, ok, unknown_subcommand,
+};
+use crate::config::{self, AppendFsync, SavePoint, SlotRange};
+use crate::glob;
+use crate::notify::NotifyFlags;
+use crate::persistence::BgSave;
+use crate::publish_keyspace_events;
+use crate::resp_frame::RespFrame;
+use crate::storage::{Keyspace, Storage};
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("command", -1, LOADING | STALE, command_command)
+        .categories(&["@connection"])
+        .doc(
+            "server",
+            "Returns detailed information about all commands.",
+            "2.8.13",
+        ),
+    CommandSpec::new("swapdb", 3, WRITE | FAST, swapdb_command)
+        .categories(&["@keyspace", "@dangerous"])
+        .doc("server", "Swaps two Redis databases.", "4.0.0"),
+    CommandSpec::new("flushdb", -1, WRITE, flushdb_command)
+        .categories(&["@keyspace", "@dangerous"])
+        .doc(
+            "server",
+            "Removes all keys from the current database.",
+            "1.0.0",
+        ),
+    CommandSpec::new("flushall", -1, WRITE, flushdb_command)
+        .categories(&["@keyspace", "@dangerous"])
+        .doc("server", "Removes all keys from all databases.", "1.0.0"),
+    CommandSpec::new("dbsize", 1, READONLY | FAST, dbsize_command)
+        .categories(&["@keyspace"])
+        .doc(
+            "server",
+            "Returns the number of keys in the database.",
+            "1.0.0",
+        ),
+    CommandSpec::new(
+        "config",
+        -2,
+        ADMIN | NOSCRIPT | LOADING | STALE,
+        config_command,
+    )
+    .doc(
+        "server",
+        "A container for server configuration commands.",
+        "2.0.0",
+    ),
+    CommandSpec::new("save", 1, ADMIN | NOSCRIPT, save_command)
+        .categories(&["@dangerous"])
+        .doc(
+            "server",
+            "Synchronously saves the database(s) to disk.",
+            "1.0.0",
+        ),
+    CommandSpec::new("bgsave", -1, ADMIN | NOSCRIPT, bgsave_command)
+        .categories(&["@dangerous"])
+        .doc(
+            "server",
+            "Asynchronously saves the database(s) to disk.",
+            "1.0.0",
+        ),
+    CommandSpec::new("bgrewriteaof", 1, ADMIN | NOSCRIPT, bgrewriteaof_command)
+        .categories(&["@dangerous"])
+        .doc(
+            "server",
+            "Asynchronously rewrites the append-only file to disk.",
+            "1.0.0",
+        ),
+    CommandSpec::new("lastsave", 1, LOADING | STALE | FAST, lastsave_command)
+        .categories(&["@admin", "@dangerous"])
+        .doc(
+            "server",
+            "Returns the Unix timestamp of the last successful save to disk.",
+            "1.0.0",
+        ),
+    CommandSpec::new("info", -1, LOADING | STALE, info_command)
+        .categories(&["@dangerous"])
+        .doc(
+            "server",
+            "Returns information and statistics about the server.",
+            "1.0.0",
+        ),
+];
+
+pub fn command_command(mut ctx: Context) -> CommandResult {
+    let resp3 = ctx.session.resp3;
+    let all_info = || {
+        RespFrame::Array(
+            commands::all()
+                .map(|spec| command_info(spec, resp3))
+                .collect(),
+        )
+    };
+    if ctx.args.is_empty() {
+        return Ok(all_info());
+    }
+    let subcommand = ctx.args.next_keyword()?;
+    let rest = ctx.args.rest()?;
+    let name = |name: &Bytes| String::from_utf8_lossy(name).into_owned();
+
+    match (subcommand.as_str(), rest.as_slice()) {
+        ("COUNT", []) => Ok(RespFrame::Integer(commands::all().count() as i64)),
+        ("INFO", []) => Ok(all_info()),
+        ("INFO", names) => Ok(RespFrame::Array(
+            names
+                .iter()
+                .map(|command| match commands::lookup(&name(command)) {
+                    Some(spec) => command_info(spec, resp3),
+                    None => RespFrame::Null,
+                })
+                .collect(),
+        )),
+        // unknown names are left out rather than answered with a null
+        ("DOCS", names) => {
+            let specs: Vec<&CommandSpec> = if names.is_empty() {
+                commands::all().collect()
+            } else {
+                names
+                    .iter()
+                    .filter_map(|command| commands::lookup(&name(command)))
+                    .collect()
+            };
+            let docs = specs
+                .into_iter()
+                .map(|spec| (bulk(spec.name), command_docs(spec, resp3)))
+                .collect();
+            Ok(map_reply(docs, resp3))
+        }
+        ("GETKEYS", [command, ..]) => {
+            let Some(spec) = commands::lookup(&name(command)) else {
+                return Err(RespFrame::Error(
+                    "ERR Invalid command specified".to_string(),
+                ));
+            };
+            if !spec.accepts(rest.len()) {
+                return Err(RespFrame::Error(
+                    "ERR Invalid number of arguments specified for command".to_string(),
+                ));
+            }
+            match spec.key_positions(&rest) {
+                None => Err(RespFrame::Error(
+                    "ERR Invalid arguments specified for command".to_string(),
+                )),
+                Some(positions) if positions.is_empty() => Err(RespFrame::Error(
+                    "ERR The command has no key arguments".to_string(),
+                )),
+                Some(positions) => Ok(RespFrame::Array(
+                    positions
+                        .into_iter()
+                        .map(|position| RespFrame::BulkString(rest[position].clone()))
+                        .collect(),
+                )),
+            }
+        }
+        ("LIST", filter) => {
+            let keep: Box<dyn Fn(&CommandSpec) -> bool> = match filter {
+                [] => Box::new(|_| true),
+                [filterby, kind, value] if filterby.eq_ignore_ascii_case(b"FILTERBY") => {
+                    match kind.to_ascii_uppercase().as_slice() {
+                        // there are no modules, so no command belongs to one
+                        b"MODULE" => Box::new(|_| false),
+                        b"ACLCAT" => {
+                            let category = format!("@{}", name(value).to_lowercase());
+                            Box::new(move |spec| spec.acl_categories().contains(&category.as_str()))
+                        }
+                        b"PATTERN" => {
+                            let pattern = value.to_ascii_lowercase();
+                            Box::new(move |spec| glob::glob_match(&pattern, spec.name.as_bytes()))
+                        }
+                        _ => return Err(syntax_error()),
+                    }
+                }
+                _ => return Err(syntax_error()),
+            };
+            Ok(RespFrame::Array(
+                commands::all()
+                    .filter(|spec| keep(spec))
+                    .map(|spec| bulk(spec.name))
+                    .collect(),
+            ))
+        }
+        ("HELP", []) => help_reply(&[
+            "COMMAND <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "(no subcommand)",
+            "    Return details about all Redis commands.",
+            "COUNT",
+            "    Return the total number of commands in this Redis server.",
+            "LIST [FILTERBY (MODULE <module-name>|ACLCAT <category>|PATTERN <pattern>)]",
+            "    Return a list of all commands in this Redis server.",
+            "INFO [<command-name> ...]",
+            "    Return details about multiple Redis commands.",
+            "    If no command names are given, documentation details for all",
+            "    commands are returned.",
+            "DOCS [<command-name> ...]",
+            "    Return documentation details about multiple Redis commands.",
+            "    If no command names are given, documentation details for all",
+            "    commands are returned.",
+            "GETKEYS <full-command>",
+            "    Return the keys from a full Redis command.",
+        ]),
+        _ => Err(unknown_subcommand(&subcommand, "COMMAND")),
+    }
+}
+
+// name, arity, flags, first key, last key, step, ACL categories, tips, key
+// specifications and subcommands, in the order redis replies with them
+fn command_info(spec: &CommandSpec, resp3: bool) -> RespFrame {
+    let status = |names: Vec<&str>| {
+        RespFrame::Array(
+            names
+                .into_iter()
+                .map(|name| RespFrame::SimpleString(name.to_string()))
+                .collect(),
+        )
+    };
+    let (first, last, step) = match spec.keys {
+        KeySpec::Range { first, last, step } => (first as i64, last, step as i64),
+        KeySpec::None | KeySpec::Counted { .. } => (0, 0, 0),
+    };
+
+    RespFrame::Array(vec![
+        bulk(spec.name),
+        RespFrame::Integer(spec.arity),
+        status(spec.flag_names()),
+        RespFrame::Integer(first),
+        RespFrame::Integer(last),
+        RespFrame::Integer(step),
+        status(spec.acl_categories()),
+        RespFrame::Array(vec![]),
+        RespFrame::Array(key_specs(spec, resp3)),
+        RespFrame::Array(vec![]),
+    ])
+}
+
+fn key_specs(spec: &CommandSpec, resp3: bool) -> Vec<RespFrame> {
+    let integer = |n: i64| RespFrame::Integer(n);
+    let search = |kind: &str, spec: Vec<(RespFrame, RespFrame)>| {
+        map_reply(
+            vec![
+                (bulk("type"), bulk(kind)),
+                (bulk("spec"), map_reply(spec, resp3)),
+            ],
+            resp3,
+        )
+    };
+
+    let (index, find_keys) = match spec.keys {
+        KeySpec::None => return Vec::new(),
+        KeySpec::Range { first, last, step } => {
+            // the last key is given relative to the first one
+            let last = if last < 0 { last } else { last - first as i64 };
+            let find_keys = search(
+                "range",
+                vec![
+                    (bulk("lastkey"), integer(last)),
+                    (bulk("keystep"), integer(step as i64)),
+                    (bulk("limit"), integer(0)),
+                ],
+            );
+            (first, find_keys)
+        }
+        KeySpec::Counted { index } => {
+            let find_keys = search(
+                "keynum",
+                vec![
+                    (bulk("keynumidx"), integer(0)),
+                    (bulk("firstkey"), integer(1)),
+                    (bulk("keystep"), integer(1)),
+                ],
+            );
+            (index, find_keys)
+        }
+    };
+
+    let access = if spec.has_flag(READONLY) { "RO" } else { "RW" };
+    vec![map_reply(
+        vec![
+            (
+                bulk("flags"),
+                RespFrame::Array(vec![RespFrame::SimpleString(access.to_string())]),
+            ),
+            (
+                bulk("begin_search"),
+                search("index", vec![(bulk("index"), integer(index as i64))]),
+            ),
+            (bulk("find_keys"), find_keys),
+        ],
+        resp3,
+    )]
+}
+
+fn command_docs(spec: &CommandSpec, resp3: bool) -> RespFrame {
+    map_reply(
+        vec![
+            (bulk("summary"), bulk(spec.summary)),
+            (bulk("since"), bulk(spec.since)),
+            (bulk("group"), bulk(spec.group)),
+        ],
+        resp3,
+    )
+}
+
+pub fn swapdb_command(mut ctx: Context) -> CommandResult {
+    let first = next_db_index(&mut ctx.args, ctx.storage)
+        .map_err(|_| RespFrame::Error("ERR invalid first DB index".to_string()))?;
+    let second = next_db_index(&mut ctx.args, ctx.storage)
+        .map_err(|_| RespFrame::Error("ERR invalid second DB index".to_string()))?;
+
+    // events still pending belong to the databases as numbered before the swap
+    publish_keyspace_events(ctx.storage, ctx.state);
+    ctx.storage.swap(first, second);
+    ok()
+}
+
+pub fn flushdb_command(mut ctx: Context) -> CommandResult {
+    let lazy = if ctx.args.next_flag("ASYNC") {
+        true
+    } else {
+        ctx.args.next_flag("SYNC");
+        false
+    };
+    ctx.args.finish()?;
+
+    let flushed: Vec<Keyspace> = if ctx.command_name == "FLUSHALL" {
+        ctx.storage
+            .databases_mut()
+            .map(|keyspace| keyspace.flush())
+            .collect()
+    } else {
+        vec![ctx.storage.db(ctx.session.db_index).flush()]
+    };
+
+    // ASYNC frees the old keys on another thread instead of blocking the caller
+    if lazy {
+        std::thread::spawn(move || drop(flushed));
+    }
+
+    ok()
+}
+
+pub fn dbsize_command(ctx: Context) -> CommandResult {
+    Ok(RespFrame::Integer(
+        ctx.storage.db(ctx.session.db_index).len() as i64,
+    ))
+}
+
+pub fn save_command(ctx: Context) -> CommandResult {
+    let persistence = &ctx.state.persistence;
+    if persistence.in_progress() {
+        return Err(RespFrame::Error(
+            "ERR Background save already in progress".to_string(),
+        ));
+    }
+    match persistence.save(ctx.storage, &ctx.state.scripting) {
+        Ok(()) => ok(),
+        Err(e) => {
+            println!("Error saving DB on disk: {}", e);
+            Err(RespFrame::Error("ERR".to_string()))
+        }
+    }
+}
+
+pub fn bgsave_command(mut ctx: Context) -> CommandResult {
+    let schedule = ctx.args.next_flag("SCHEDULE");
+    ctx.args.finish()?;
+
+    match ctx
+        .state
+        .persistence
+        .bgsave(ctx.storage, &ctx.state.scripting, schedule)
+    {
+        BgSave::Started => Ok(RespFrame::simple("Background saving started")),
+        BgSave::Scheduled => Ok(RespFrame::simple("Background saving scheduled")),
+        BgSave::AlreadyRunning => Err(RespFrame::Error(
+            "ERR Background save already in progress".to_string(),
+        )),
+    }
+}
+
+pub fn bgrewriteaof_command(ctx: Context) -> CommandResult {
+    match ctx.state.aof.rewrite(ctx.storage, &ctx.state.scripting) {
+        Rewrite::Started => Ok(RespFrame::simple(
+            "Background append only file rewriting started",
+        )),
+        Rewrite::AlreadyRunning => Err(RespFrame::Error(
+            "ERR Background append only file rewriting already in progress".to_string(),
+        )),
+        Rewrite::Failed => Err(RespFrame::Error(
+            "ERR Can't open the append-only file".to_string(),
+        )),
+    }
+}
+
+pub fn lastsave_command(ctx: Context) -> CommandResult {
+    Ok(RespFrame::Integer(ctx.state.persistence.last_save() as i64))
+}
+
+pub fn config_command(mut ctx: Context) -> CommandResult {
+    let subcommand = ctx.args.next_keyword()?;
+    let params = ctx.args.rest()?;
+    let storage = ctx.storage;
+
+    match subcommand.as_str() {
+        "GET" if !params.is_empty() => {
+            let config = &ctx.state.config;
+            let save_points: Vec<String> = ctx
+                .state
+                .persistence
+                .save_points()
+                .iter()
+                .map(SavePoint::to_string)
+                .collect();
+            let yes_no = |enabled: bool| if enabled { "yes" } else { "no" }.to_string();
+            let current = [
+                ("bind", config.bind.clone()),
+                ("port", config.port.to_string()),
+                ("databases", config.databases.to_string()),
+                ("notify-keyspace-events", storage.notify_flags().to_string()),
+                ("dir", config.dir.display().to_string()),
+                ("dbfilename", config.dbfilename.clone()),
+                ("save", save_points.join(" ")),
+                ("appendonly", yes_no(ctx.state.aof.is_enabled())),
+                ("appendfsync", ctx.state.aof.fsync_policy().to_string()),
+                ("appendfilename", config.appendfilename.clone()),
+                ("appenddirname", config.appenddirname.clone()),
+                ("aof-load-truncated", yes_no(config.aof_load_truncated)),
+                ("cluster-enabled", yes_no(config.cluster_enabled)),
+                (
+                    "cluster-slots",
+                    config
+                        .cluster_slots
+                        .iter()
+                        .map(SlotRange::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ),
+            ];
+
+            let mut reply = Vec::new();
+            for (name, value) in current {
+                if params
+                    .iter()
+                    .any(|pattern| glob::glob_match(&pattern.to_ascii_lowercase(), name.as_bytes()))
+                {
+                    reply.push(bulk(name));
+                    reply.push(RespFrame::BulkString(Bytes::from(value)));
+                }
+            }
+            Ok(RespFrame::Array(reply))
+        }
+        "SET" if !params.is_empty() && params.len() % 2 == 0 => {
+            // every pair is checked before the first is applied, so a
+            // refused CONFIG SET changes nothing
+            let changes = params
+                .chunks_exact(2)
+                .map(|pair| ConfigChange::parse(&pair[0], &pair[1]))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            // turning the AOF on is the one change that can still fail
+            let aof = &ctx.state.aof;
+            for change in &changes {
+                if let ConfigChange::AppendOnly(true) = change
+                    && !aof.is_enabled()
+                    && aof.start(storage, &ctx.state.scripting, true) != Rewrite::Started
+                {
+                    return Err(RespFrame::Error(
+                        "ERR Unable to turn on AOF. Check server logs.".to_string(),
+                    ));
+                }
+            }
+            for change in changes {
+                match change {
+                    ConfigChange::NotifyKeyspaceEvents(flags) => storage.set_notify_flags(flags),
+                    ConfigChange::Save(save_points) => {
+                        ctx.state.persistence.set_save_points(save_points)
+                    }
+                    ConfigChange::AppendFsync(fsync) => aof.set_fsync_policy(fsync),
+                    ConfigChange::AppendOnly(false) => aof.stop(),
+                    ConfigChange::AppendOnly(true) => {}
+                }
+            }
+            ok()
+        }
+        _ => Err(unknown_subcommand(&subcommand, "CONFIG")),
+    }
+}
+
+// a parameter CONFIG SET can change, with its new value
+enum ConfigChange {
+    NotifyKeyspaceEvents(NotifyFlags),
+    Save(Vec<SavePoint>),
+    AppendFsync(AppendFsync),
+    AppendOnly(bool),
+}
+
+impl ConfigChange {
+    fn parse(name: &[u8], value: &[u8]) -> Result<ConfigChange, RespFrame> {
+        let name = String::from_utf8_lossy(name).to_lowercase();
+        let value = std::str::from_utf8(value).ok();
+        let change = match name.as_str() {
+            "notify-keyspace-events" => value
+                .and_then(NotifyFlags::parse)
+                .map(ConfigChange::NotifyKeyspaceEvents),
+            "save" => value
+                .and_then(SavePoint::parse_list)
+                .map(ConfigChange::Save),
+            "appendfsync" => value
+                .and_then(AppendFsync::parse)
+                .map(ConfigChange::AppendFsync),
+            "appendonly" => value
+                .and_then(config::parse_yes_no)
+                .map(ConfigChange::AppendOnly),
+            _ => {
+                return Err(RespFrame::Error(format!(
+                    "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+                    name
+                )));
+            }
+        };
+        change.ok_or_else(|| {
+            RespFrame::Error(format!("ERR Invalid argument for CONFIG SET '{}'", name))
+        })
+    }
+}
+
+pub fn info_command(mut ctx: Context) -> CommandResult {
+    let sections: Vec<String> = ctx
+        .args
+        .rest()?
+        .iter()
+        .map(|section| String::from_utf8_lossy(section).to_lowercase())
+        .collect();
+
+    Ok(RespFrame::BulkString(Bytes::from(build_info(
+        ctx.storage,
+        ctx.state,
+        &sections,
+    ))))
+}
+
+// INFO output is plain "field:value" lines grouped under "# Section" headers
+fn build_info(storage: &Storage, state: &ServerState, sections: &[String]) -> String {
+    let wanted = |section: &str| {
+        sections.is_empty()
+            || sections
+                .iter()
+                .any(|s| s == section || s == "all" || s == "everything" || s == "default")
+    };
+
+    let mut info = String::new();
+
+    if wanted("server") {
+        info.push_str("# Server\r\n");
+        // clients gate features on the redis version, so report one we are compatible with
+        info.push_str("redis_version:7.0.0\r\n");
+        info.push_str(&format!("resprs_version:{}\r\n", env!("CARGO_PKG_VERSION")));
+        info.push_str(&format!("process_id:{}\r\n", std::process::id()));
+        info.push_str("\r\n");
+    }
+
+    if wanted("persistence") {
+        let persistence = &state.persistence;
+        let status = |ok: bool| if ok { "ok" } else { "err" };
+        info.push_str("# Persistence\r\n");
+        info.push_str("loading:0\r\n");
+        info.push_str(&format!(
+            "rdb_changes_since_last_save:{}\r\n",
+            persistence.changes_since_last_save(storage)
+        ));
+        info.push_str(&format!(
+            "rdb_bgsave_in_progress:{}\r\n",
+            persistence.in_progress() as u8
+        ));
+        info.push_str(&format!(
+            "rdb_last_save_time:{}\r\n",
+            persistence.last_save()
+        ));
+        info.push_str(&format!(
+            "rdb_last_bgsave_status:{}\r\n",
+            status(persistence.last_save_ok())
+        ));
+        info.push_str(&format!("aof_enabled:{}\r\n", state.aof.is_enabled() as u8));
+        info.push_str(&format!(
+            "aof_rewrite_in_progress:{}\r\n",
+            state.aof.rewrite_in_progress() as u8
+        ));
+        info.push_str(&format!(
+            "aof_last_bgrewrite_status:{}\r\n",
+            status(state.aof.last_rewrite_ok())
+        ));
+        info.push_str(&format!(
+            "aof_last_write_status:{}\r\n",
+            status(state.aof.last_write_ok())
+        ));
+        info.push_str("\r\n");
+    }
+
+    if wanted("replication") {
+        info.push_str("# Replication\r\n");
+        info.push_str(&state.replication.info());
+        info.push_str("\r\n");
+    }
+
+    if wanted("keyspace") {
+        info.push_str("# Keyspace\r\n");
+        for (index, keyspace) in storage.databases() {
+            if keyspace.is_empty() {
+                continue;
+            }
+            let (expires, avg_ttl) = keyspace.expiry_stats();
+            info.push_str(&format!(
+                "db{}:keys={},expires={},avg_ttl={}\r\n",
+                index,
+                keyspace.len(),
+                expires,
+                avg_ttl
+            ));
+        }
+    }
+
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::resp_frame::RespFrame;
+    use crate::test_util::{bulk, ok, run, setup_db};
+
+    #[test]
+    fn test_config_set_changes_nothing_when_a_pair_is_invalid() {
+        let mut db = setup_db();
+        let get = |db: &mut _| {
+            run(
+                db,
+                &[
+                    "CONFIG",
+                    "GET",
+                    "notify-keyspace-events",
+                    "save",
+                    "appendfsync",
+                ],
+            )
+        };
+        let before = get(&mut db);
+
+        assert_eq!(
+            run(
+                &mut db,
+                &[
+                    "CONFIG",
+                    "SET",
+                    "notify-keyspace-events",
+                    "KEA",
+                    "save",
+                    "900 1",
+                    "appendfsync",
+                    "sometimes",
+                ]
+            ),
+            RespFrame::Error("ERR Invalid argument for CONFIG SET 'appendfsync'".to_string())
+        );
+        assert_eq!(get(&mut db), before);
+        assert_eq!(
+            run(
+                &mut db,
+                &["CONFIG", "SET", "save", "900 1", "maxmemory", "1"]
+            ),
+            RespFrame::Error(
+                "ERR Unknown option or number of arguments for CONFIG SET - 'maxmemory'"
+                    .to_string()
+            )
+        );
+        assert_eq!(get(&mut db), before);
+
+        assert_eq!(
+            run(
+                &mut db,
+                &["CONFIG", "SET", "save", "900 1", "appendfsync", "no"]
+            ),
+            ok()
+        );
+        assert_eq!(
+            run(&mut db, &["CONFIG", "GET", "save", "appendfsync"]),
+            RespFrame::Array(vec![
+                bulk("save"),
+                bulk("900 1"),
+                bulk("appendfsync"),
+                bulk("no")
+            ])
+        );
+    }
+
+    #[test]
+    fn test_dbsize_counts_the_selected_database() {
+        let mut db = setup_db();
+        assert_eq!(run(&mut db, &["DBSIZE"]), RespFrame::Integer(2));
+        run(&mut db, &["SELECT", "1"]);
+        assert_eq!(run(&mut db, &["DBSIZE"]), RespFrame::Integer(0));
+        run(&mut db, &["SET", "a", "1"]);
+        run(&mut db, &["SET", "b", "2"]);
+        run(&mut db, &["DEL", "a"]);
+        assert_eq!(run(&mut db, &["DBSIZE"]), RespFrame::Integer(1));
+    }
+
+    #[test]
+    fn test_swapdb() {
+        let mut db = setup_db();
+
+        assert_eq!(run(&mut db, &["SWAPDB", "0", "3"]), ok());
+        assert_eq!(run(&mut db, &["DBSIZE"]), RespFrame::Integer(0));
+        assert_eq!(run(&mut db, &["SELECT", "3"]), ok());
+        assert_eq!(run(&mut db, &["GET", "live"]), bulk("1"));
+    }
+
+    #[test]
+    fn test_flushdb_and_flushall() {
+        let mut db = setup_db();
+        assert_eq!(run(&mut db, &["SELECT", "1"]), ok());
+        assert_eq!(run(&mut db, &["SET", "key", "value"]), ok());
+
+        assert_eq!(run(&mut db, &["FLUSHDB", "ASYNC"]), ok());
+        assert_eq!(run(&mut db, &["DBSIZE"]), RespFrame::Integer(0));
+        assert_eq!(run(&mut db, &["SELECT", "0"]), ok());
+        assert_eq!(run(&mut db, &["EXISTS", "live"]), RespFrame::Integer(1));
+
+        assert_eq!(run(&mut db, &["FLUSHALL", "SYNC"]), ok());
+        assert_eq!(run(&mut db, &["DBSIZE"]), RespFrame::Integer(0));
+        assert_eq!(
+            run(&mut db, &["FLUSHALL", "LATER"]),
+            RespFrame::Error("ERR syntax error".to_string())
+        );
+    }
+
+    #[test]
+    fn test_info_keyspace_lists_each_database() {
+        let mut db = setup_db();
+        assert_eq!(run(&mut db, &["SELECT", "2"]), ok());
+        assert_eq!(run(&mut db, &["SET", "key", "value"]), ok());
+
+        let RespFrame::BulkString(info) = run(&mut db, &["INFO", "keyspace"]) else {
+            panic!("INFO should return a bulk string");
+        };
+        let info = String::from_utf8(info.to_vec()).unwrap();
+        assert!(info.contains("db0:keys=2,expires=1"));
+        assert!(info.contains("db2:keys=1,expires=0,avg_ttl=0"));
+        assert!(!info.contains("# Server"));
+    }
+
+    #[test]
+    fn test_command_info_and_count() {
+        let mut db = setup_db();
+        let RespFrame::Integer(count) = run(&mut db, &["COMMAND", "COUNT"]) else {
+            panic!("COMMAND COUNT did not return an integer");
+        };
+        let RespFrame::Array(all) = run(&mut db, &["COMMAND"]) else {
+            panic!("COMMAND did not return an array");
+        };
+        assert_eq!(all.len() as i64, count);
+
+        let status = |s: &str| RespFrame::SimpleString(s.to_string());
+        let RespFrame::Array(infos) = run(&mut db, &["COMMAND", "INFO", "get", "nope"]) else {
+            panic!("COMMAND INFO did not return an array");
+        };
+        assert_eq!(infos[1], RespFrame::Null);
+        let RespFrame::Array(get) = &infos[0] else {
+            panic!("COMMAND INFO get is not an array");
+        };
+        assert_eq!(
+            get[..7],
+            [
+                bulk("get"),
+                RespFrame::Integer(2),
+                RespFrame::Array(vec![status("readonly"), status("fast")]),
+                RespFrame::Integer(1),
+                RespFrame::Integer(1),
+                RespFrame::Integer(1),
+                RespFrame::Array(vec![status("@read"), status("@fast"), status("@string")]),
+            ]
+        );
+
+        assert_eq!(
+            run(&mut db, &["COMMAND", "DOCS", "ttl"]),
+            RespFrame::Array(vec![
+                bulk("ttl"),
+                RespFrame::Array(vec![
+                    bulk("summary"),
+                    bulk("Returns the expiration time in seconds of a key."),
+                    bulk("since"),
+                    bulk("1.0.0"),
+                    bulk("group"),
+                    bulk("generic"),
+                ]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_command_getkeys_and_list() {
+        let mut db = setup_db();
+        assert_eq!(
+            run(&mut db, &["COMMAND", "GETKEYS", "MSET", "a", "1", "b", "2"]),
+            RespFrame::Array(vec![bulk("a"), bulk("b")])
+        );
+        assert_eq!(
+            run(
+                &mut db,
+                &["COMMAND", "GETKEYS", "EVAL", "return 1", "1", "k", "arg"]
+            ),
+            RespFrame::Array(vec![bulk("k")])
+        );
+        assert_eq!(
+            run(&mut db, &["COMMAND", "GETKEYS", "PING"]),
+            RespFrame::Error("ERR The command has no key arguments".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["COMMAND", "GETKEYS", "GET"]),
+            RespFrame::Error("ERR Invalid number of arguments specified for command".to_string())
+        );
+
+        assert_eq!(
+            run(
+                &mut db,
+                &["COMMAND", "LIST", "FILTERBY", "PATTERN", "rename*"]
+            ),
+            RespFrame::Array(vec![bulk("rename"), bulk("renamenx")])
+        );
+        assert_eq!(
+            run(
+                &mut db,
+                &["COMMAND", "LIST", "FILTERBY", "ACLCAT", "transaction"]
+            ),
+            RespFrame::Array(vec![
+                bulk("multi"),
+                bulk("exec"),
+                bulk("discard"),
+                bulk("watch"),
+                bulk("unwatch"),
+            ])
+        );
+    }
+}
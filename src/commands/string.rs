@@ -0,0 +1,317 @@
+use bytes::Bytes;
+
+use crate::commands::args::{Args, Parse, not_an_integer};
+use crate::commands::{
+    CommandResult, CommandSpec, Context, DENYOOM, FAST, KeySpec, READONLY, WRITE, ok,
+};
+use crate::notify;
+use crate::resp_frame::RespFrame;
+use crate::storage::{Keyspace, RedisValue};
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec::new("set", -3, WRITE | DENYOOM, set_command)
+        .keys(KeySpec::single(1))
+        .categories(&["@string"])
+        .doc(
+            "string",
+            "Sets the string value of a key, ignoring its type. The key is created if it doesn't exist.",
+            "1.0.0",
+        ),
+    CommandSpec::new("get", 2, READONLY | FAST, get_command)
+        .keys(KeySpec::single(1))
+        .categories(&["@string"])
+        .doc("string", "Returns the string value of a key.", "1.0.0"),
+    CommandSpec::new("incr", 2, WRITE | DENYOOM | FAST, incr_command)
+        .keys(KeySpec::single(1))
+        .categories(&["@string"])
+        .doc(
+            "string",
+            "Increments the integer value of a key by one. Uses 0 as initial value if the key doesn't exist.",
+            "1.0.0",
+        ),
+    CommandSpec::new("decr", 2, WRITE | DENYOOM | FAST, decr_command)
+        .keys(KeySpec::single(1))
+        .categories(&["@string"])
+        .doc(
+            "string",
+            "Decrements the integer value of a key by one. Uses 0 as initial value if the key doesn't exist.",
+            "1.0.0",
+        ),
+    CommandSpec::new("incrby", 3, WRITE | DENYOOM | FAST, incrby_command)
+        .keys(KeySpec::single(1))
+        .categories(&["@string"])
+        .doc(
+            "string",
+            "Increments the integer value of a key by a number. Uses 0 as initial value if the key doesn't exist.",
+            "1.0.0",
+        ),
+    CommandSpec::new("decrby", 3, WRITE | DENYOOM | FAST, decrby_command)
+        .keys(KeySpec::single(1))
+        .categories(&["@string"])
+        .doc(
+            "string",
+            "Decrements a number from the integer value of a key. Uses 0 as initial value if the key doesn't exist.",
+            "1.0.0",
+        ),
+    CommandSpec::new("mset", -3, WRITE | DENYOOM, mset_command)
+        .keys(KeySpec::range(1, -1, 2))
+        .categories(&["@string"])
+        .doc(
+            "string",
+            "Atomically creates or modifies the string values of one or more keys.",
+            "1.0.1",
+        ),
+    CommandSpec::new("mget", -2, READONLY | FAST, mget_command)
+        .keys(KeySpec::range(1, -1, 1))
+        .categories(&["@string"])
+        .doc(
+            "string",
+            "Atomically returns the string values of one or more keys.",
+            "1.0.0",
+        ),
+    CommandSpec::new("strlen", 2, READONLY | FAST, strlen_command)
+        .keys(KeySpec::single(1))
+        .categories(&["@string"])
+        .doc("string", "Returns the length of a string value.", "2.2.0"),
+    CommandSpec::new("append", 3, WRITE | DENYOOM | FAST, append_command)
+        .keys(KeySpec::single(1))
+        .categories(&["@string"])
+        .doc(
+            "string",
+            "Appends a string to the value of a key. Creates the key if it doesn't exist.",
+            "2.0.0",
+        ),
+    CommandSpec::new("getset", 3, WRITE | DENYOOM | FAST, getset_command)
+        .keys(KeySpec::single(1))
+        .categories(&["@string"])
+        .doc(
+            "string",
+            "Returns the previous string value of a key after setting it to a new value.",
+            "1.0.0",
+        ),
+];
+
+#[derive(Debug, PartialEq)]
+pub struct SetCommand {
+    pub key: Bytes,
+    pub value: Bytes,
+}
+
+impl Parse for SetCommand {
+    fn parse(args: &mut Args) -> Result<Self, RespFrame> {
+        let key = args.next_key()?;
+        let value = args.next_bytes()?;
+        // none of the SET options are supported yet
+        args.finish()?;
+        Ok(SetCommand { key, value })
+    }
+}
+
+pub fn set_command(mut ctx: Context) -> CommandResult {
+    let SetCommand { key, value } = SetCommand::parse(&mut ctx.args)?;
+
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+
+    let mut new_val = RedisValue::new(value);
+    new_val.expires_at = db_guard.peek(&key).and_then(|val| val.expires_at);
+
+    db_guard.insert(key.clone(), new_val);
+    db_guard.notify(notify::STRING, "set", &key);
+
+    ok()
+}
+
+pub fn get_command(mut ctx: Context) -> CommandResult {
+    let key = ctx.args.next_key()?;
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+
+    Ok(db_guard
+        .lookup_read(&key)
+        .map(|value| value.data.clone())
+        .into())
+}
+
+pub fn incr_command(ctx: Context) -> CommandResult {
+    increment(ctx, 1)
+}
+
+pub fn decr_command(ctx: Context) -> CommandResult {
+    increment(ctx, -1)
+}
+
+pub fn incrby_command(mut ctx: Context) -> CommandResult {
+    let key = ctx.args.next_key()?;
+    let amount = ctx.args.next_i64()?;
+
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+    handle_increment(&key, db_guard, amount).map(RespFrame::Integer)
+}
+
+pub fn decrby_command(mut ctx: Context) -> CommandResult {
+    let key = ctx.args.next_key()?;
+    let amount = ctx.args.next_i64()?;
+
+    let Some(neg_amount) = amount.checked_neg() else {
+        return Err(RespFrame::error("ERR decrement would overflow"));
+    };
+
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+    handle_increment(&key, db_guard, neg_amount).map(RespFrame::Integer)
+}
+
+pub fn mset_command(mut ctx: Context) -> CommandResult {
+    if !ctx.args.remaining().is_multiple_of(2) {
+        return Err(RespFrame::error(
+            "ERR wrong number of arguments for 'mset' command",
+        ));
+    }
+
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+
+    while !ctx.args.is_empty() {
+        let key = ctx.args.next_key()?;
+        let value = ctx.args.next_bytes()?;
+        db_guard.insert(key.clone(), RedisValue::new(value));
+        db_guard.notify(notify::STRING, "set", &key);
+    }
+    ok()
+}
+
+pub fn mget_command(mut ctx: Context) -> CommandResult {
+    let keys = ctx.args.rest()?;
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+
+    let values: Vec<Option<Bytes>> = keys
+        .iter()
+        .map(|key| db_guard.lookup_read(key).map(|value| value.data.clone()))
+        .collect();
+    Ok(values.into())
+}
+
+pub fn strlen_command(mut ctx: Context) -> CommandResult {
+    let key = ctx.args.next_key()?;
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+
+    let len = db_guard
+        .lookup_read(&key)
+        .map_or(0, |value| value.data.len());
+    Ok(len.into())
+}
+
+pub fn append_command(mut ctx: Context) -> CommandResult {
+    let key = ctx.args.next_key()?;
+    let value_to_append = ctx.args.next_bytes()?;
+
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+
+    let value_struct = db_guard.lookup_write_or_insert(&key, || RedisValue::new(Bytes::new()));
+
+    // bytes is immutable so copy get a vec then extend then get a bytes again
+    let mut new_data_vec = value_struct.data.to_vec();
+    new_data_vec.extend_from_slice(&value_to_append);
+    let new_len = new_data_vec.len();
+
+    value_struct.data = Bytes::from(new_data_vec);
+    db_guard.mark_modified(&key);
+    db_guard.notify(notify::STRING, "append", &key);
+
+    Ok(new_len.into())
+}
+
+pub fn getset_command(mut ctx: Context) -> CommandResult {
+    let key = ctx.args.next_key()?;
+    let new_value = ctx.args.next_bytes()?;
+
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+
+    let old_value_opt = db_guard.insert(key.clone(), RedisValue::new(new_value));
+    db_guard.notify(notify::STRING, "set", &key);
+
+    Ok(old_value_opt.map(|old_value| old_value.data).into())
+}
+
+// INCR and DECR
+fn increment(mut ctx: Context, amount: i64) -> CommandResult {
+    let key = ctx.args.next_key()?;
+    let db_guard = ctx.storage.db(ctx.session.db_index);
+    handle_increment(&key, db_guard, amount).map(RespFrame::Integer)
+}
+
+fn handle_increment(key: &Bytes, db_guard: &mut Keyspace, amount: i64) -> Result<i64, RespFrame> {
+    let value_struct =
+        db_guard.lookup_write_or_insert(key, || RedisValue::new(Bytes::from_static(b"0")));
+
+    let Some(current_val) = std::str::from_utf8(&value_struct.data)
+        .ok()
+        .and_then(|data_str| data_str.parse::<i64>().ok())
+    else {
+        return Err(not_an_integer());
+    };
+
+    let new_val = match current_val.checked_add(amount) {
+        Some(val) => val,
+        None => {
+            return Err(RespFrame::error(
+                "ERR increment or decrement would overflow",
+            ));
+        }
+    };
+
+    value_struct.data = Bytes::from(new_val.to_string());
+    db_guard.mark_modified(key);
+    db_guard.notify(notify::STRING, "incrby", key);
+
+    Ok(new_val)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::commands::args::{parse, syntax_error};
+    use crate::commands::string::SetCommand;
+    use crate::resp_frame::RespFrame;
+    use crate::test_util::{bulk, run, setup_db};
+
+    #[test]
+    fn test_parse_set() {
+        assert_eq!(
+            parse(&["k", "v"]),
+            Ok(SetCommand {
+                key: Bytes::from("k"),
+                value: Bytes::from("v"),
+            })
+        );
+        assert_eq!(parse::<SetCommand>(&["k", "v", "NX"]), Err(syntax_error()));
+    }
+
+    #[test]
+    fn test_writes_treat_expired_key_as_missing() {
+        let mut db = setup_db();
+        assert_eq!(run(&mut db, &["INCR", "gone"]), RespFrame::Integer(1));
+        assert_eq!(run(&mut db, &["TTL", "gone"]), RespFrame::Integer(-1));
+
+        let mut db = setup_db();
+        assert_eq!(
+            run(&mut db, &["DECRBY", "gone", "5"]),
+            RespFrame::Integer(-5)
+        );
+
+        let mut db = setup_db();
+        assert_eq!(
+            run(&mut db, &["APPEND", "gone", "abc"]),
+            RespFrame::Integer(3)
+        );
+
+        let mut db = setup_db();
+        assert_eq!(run(&mut db, &["GETSET", "gone", "new"]), RespFrame::Null);
+
+        let mut db = setup_db();
+        assert_eq!(
+            run(&mut db, &["SET", "gone", "new"]),
+            RespFrame::SimpleString("OK".to_string())
+        );
+        assert_eq!(run(&mut db, &["GET", "gone"]), bulk("new"));
+        assert_eq!(run(&mut db, &["TTL", "gone"]), RespFrame::Integer(-1));
+    }
+}
@@ -0,0 +1,359 @@
+use crate::commands::{
+    ALLOW_BUSY, CommandResult, CommandSpec, Context, FAST, KeySpec, LOADING, NOSCRIPT, STALE,
+    handle_command, ok,
+};
+use crate::resp_frame::RespFrame;
+use crate::session::{Session, Transaction, WatchedKey};
+use crate::storage::Storage;
+use crate::{begin_atomic, end_atomic};
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec::new(
+        "multi",
+        1,
+        NOSCRIPT | LOADING | STALE | FAST | ALLOW_BUSY,
+        multi_command,
+    )
+    .categories(&["@transaction"])
+    .doc("transactions", "Starts a transaction.", "1.2.0"),
+    CommandSpec::new("exec", 1, NOSCRIPT | LOADING | STALE, exec_command)
+        .categories(&["@transaction"])
+        .doc(
+            "transactions",
+            "Executes all commands in a transaction.",
+            "1.2.0",
+        ),
+    CommandSpec::new(
+        "discard",
+        1,
+        NOSCRIPT | LOADING | STALE | FAST | ALLOW_BUSY,
+        discard_command,
+    )
+    .categories(&["@transaction"])
+    .doc("transactions", "Discards a transaction.", "2.0.0"),
+    CommandSpec::new(
+        "watch",
+        -2,
+        NOSCRIPT | LOADING | STALE | FAST | ALLOW_BUSY,
+        watch_command,
+    )
+    .keys(KeySpec::range(1, -1, 1))
+    .categories(&["@transaction"])
+    .doc(
+        "transactions",
+        "Monitors changes to keys to determine the execution of a transaction.",
+        "2.2.0",
+    ),
+    CommandSpec::new(
+        "unwatch",
+        1,
+        NOSCRIPT | LOADING | STALE | FAST | ALLOW_BUSY,
+        unwatch_command,
+    )
+    .categories(&["@transaction"])
+    .doc(
+        "transactions",
+        "Forgets about watched keys of a transaction.",
+        "2.2.0",
+    ),
+];
+
+pub fn multi_command(ctx: Context) -> CommandResult {
+    if ctx.session.transaction.is_some() {
+        return Err(RespFrame::Error(
+            "ERR MULTI calls can not be nested".to_string(),
+        ));
+    }
+    ctx.session.transaction = Some(Transaction::default());
+    ok()
+}
+
+pub fn exec_command(ctx: Context) -> CommandResult {
+    let Context {
+        storage,
+        session,
+        state,
+        ..
+    } = ctx;
+    let Some(transaction) = session.transaction.take() else {
+        return Err(RespFrame::Error("ERR EXEC without MULTI".to_string()));
+    };
+    let changed = watched_keys_changed(storage, &session.watched_keys);
+    unwatch_all(storage, session);
+
+    if transaction.aborted {
+        return Err(RespFrame::Error(
+            "EXECABORT Transaction discarded because of previous errors.".to_string(),
+        ));
+    }
+    if changed {
+        return Ok(RespFrame::Null);
+    }
+
+    // the caller holds the storage lock for the whole EXEC, so nothing can interleave
+    begin_atomic(state);
+    let results = transaction
+        .queued
+        .into_iter()
+        .map(|frame| handle_command(frame, storage, session, state))
+        .collect();
+    end_atomic(state);
+    Ok(RespFrame::Array(results))
+}
+
+pub fn discard_command(ctx: Context) -> CommandResult {
+    if ctx.session.transaction.take().is_none() {
+        return Err(RespFrame::Error("ERR DISCARD without MULTI".to_string()));
+    }
+    unwatch_all(ctx.storage, ctx.session);
+    ok()
+}
+
+pub fn watch_command(mut ctx: Context) -> CommandResult {
+    // like any other command refused inside MULTI it dooms the transaction
+    if let Some(transaction) = &mut ctx.session.transaction {
+        transaction.aborted = true;
+        return Err(RespFrame::Error(
+            "ERR WATCH inside MULTI is not allowed".to_string(),
+        ));
+    }
+
+    for key in ctx.args.rest()? {
+        let stamp = ctx.storage.db(ctx.session.db_index).watch(&key);
+        ctx.session.watched_keys.push(WatchedKey {
+            db_index: ctx.session.db_index,
+            key,
+            stamp,
+        });
+    }
+    ok()
+}
+
+pub fn unwatch_command(ctx: Context) -> CommandResult {
+    unwatch_all(ctx.storage, ctx.session);
+    ok()
+}
+
+// EXEC fails if any watched key was written, created, deleted, expired or
+// flushed since WATCH, even if it was changed back
+fn watched_keys_changed(storage: &mut Storage, watched_keys: &[WatchedKey]) -> bool {
+    watched_keys.iter().any(|watched| {
+        storage.db(watched.db_index).watch_stamp(&watched.key) != Some(watched.stamp)
+    })
+}
+
+/// Drops the watches of a client, which also happens when it disconnects.
+pub fn unwatch_all(storage: &mut Storage, session: &mut Session) {
+    for watched in session.watched_keys.drain(..) {
+        storage.db(watched.db_index).unwatch(&watched.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+
+    use crate::resp_frame::RespFrame;
+    use crate::test_util::{TestDb, bulk, ok, run, setup_db};
+
+    #[test]
+    fn test_multi_exec_runs_queued_commands() {
+        let mut db = setup_db();
+
+        assert_eq!(run(&mut db, &["MULTI"]), ok());
+        assert_eq!(
+            run(&mut db, &["INCR", "live"]),
+            RespFrame::SimpleString("QUEUED".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["GET", "live"]),
+            RespFrame::SimpleString("QUEUED".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["EXEC"]),
+            RespFrame::Array(vec![RespFrame::Integer(2), bulk("2")])
+        );
+        assert_eq!(
+            run(&mut db, &["EXEC"]),
+            RespFrame::Error("ERR EXEC without MULTI".to_string())
+        );
+    }
+
+    #[test]
+    fn test_exec_aborts_after_queueing_error() {
+        let mut db = setup_db();
+
+        assert_eq!(run(&mut db, &["MULTI"]), ok());
+        assert_eq!(
+            run(&mut db, &["SET", "live", "2"]),
+            RespFrame::SimpleString("QUEUED".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["GET"]),
+            RespFrame::Error("ERR wrong number of arguments for 'get' command".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["NOPE"]),
+            RespFrame::Error("ERR unknown command 'NOPE', with args beginning with: ".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["EXEC"]),
+            RespFrame::Error(
+                "EXECABORT Transaction discarded because of previous errors.".to_string()
+            )
+        );
+        assert_eq!(run(&mut db, &["GET", "live"]), bulk("1"));
+    }
+
+    #[test]
+    fn test_discard_drops_queued_commands() {
+        let mut db = setup_db();
+
+        assert_eq!(run(&mut db, &["MULTI"]), ok());
+        run(&mut db, &["DEL", "live"]);
+        assert_eq!(run(&mut db, &["DISCARD"]), ok());
+        assert_eq!(run(&mut db, &["GET", "live"]), bulk("1"));
+        assert_eq!(
+            run(&mut db, &["DISCARD"]),
+            RespFrame::Error("ERR DISCARD without MULTI".to_string())
+        );
+    }
+
+    // runs a command from a second connection against the same storage
+    fn run_other(db: &mut TestDb, args: &[&str]) -> RespFrame {
+        run(&mut db.connect(), args)
+    }
+
+    fn watched_exec(setup: impl FnOnce(&mut TestDb)) -> RespFrame {
+        let mut db = setup_db();
+        assert_eq!(run(&mut db, &["WATCH", "live", "missing"]), ok());
+        setup(&mut db);
+        assert_eq!(run(&mut db, &["MULTI"]), ok());
+        run(&mut db, &["SET", "result", "done"]);
+        run(&mut db, &["EXEC"])
+    }
+
+    #[test]
+    fn test_watch_allows_exec_when_untouched() {
+        let response = watched_exec(|db| {
+            run_other(db, &["GET", "live"]);
+            run_other(db, &["SET", "unrelated", "x"]);
+        });
+        assert_eq!(response, RespFrame::Array(vec![ok()]));
+    }
+
+    #[test]
+    fn test_watch_fails_exec_when_key_modified() {
+        let response = watched_exec(|db| {
+            run_other(db, &["INCR", "live"]);
+        });
+        assert_eq!(response, RespFrame::Null);
+
+        let response = watched_exec(|db| {
+            run_other(db, &["SET", "missing", "now exists"]);
+        });
+        assert_eq!(response, RespFrame::Null);
+    }
+
+    #[test]
+    fn test_watch_ignores_writes_that_fail() {
+        let mut db = setup_db();
+        assert_eq!(run(&mut db, &["SET", "text", "abc"]), ok());
+        assert_eq!(run(&mut db, &["WATCH", "text"]), ok());
+        assert_eq!(
+            run_other(&mut db, &["INCR", "text"]),
+            RespFrame::Error("ERR value is not an integer or out of range".to_string())
+        );
+        assert_eq!(run(&mut db, &["MULTI"]), ok());
+        run(&mut db, &["SET", "result", "done"]);
+        assert_eq!(run(&mut db, &["EXEC"]), RespFrame::Array(vec![ok()]));
+    }
+
+    #[test]
+    fn test_watch_fails_exec_when_key_expired_or_flushed() {
+        let mut db = setup_db();
+        assert_eq!(
+            run(&mut db, &["EXPIRE", "live", "1"]),
+            RespFrame::Integer(1)
+        );
+        assert_eq!(run(&mut db, &["WATCH", "live"]), ok());
+        db.state
+            .db
+            .lock()
+            .unwrap()
+            .clock()
+            .advance(Duration::from_secs(2));
+        assert_eq!(run(&mut db, &["MULTI"]), ok());
+        run(&mut db, &["SET", "result", "done"]);
+        assert_eq!(run(&mut db, &["EXEC"]), RespFrame::Null);
+        assert_eq!(run(&mut db, &["GET", "result"]), RespFrame::Null);
+
+        let response = watched_exec(|db| {
+            run_other(db, &["FLUSHALL"]);
+        });
+        assert_eq!(response, RespFrame::Null);
+    }
+
+    #[test]
+    fn test_watch_fails_exec_when_key_comes_and_goes() {
+        // "missing" is created and deleted again, like redis this aborts
+        let response = watched_exec(|db| {
+            run_other(db, &["SET", "missing", "v"]);
+            run_other(db, &["DEL", "missing"]);
+        });
+        assert_eq!(response, RespFrame::Null);
+
+        let response = watched_exec(|db| {
+            run_other(db, &["RENAME", "live", "elsewhere"]);
+            run_other(db, &["RENAME", "elsewhere", "live"]);
+        });
+        assert_eq!(response, RespFrame::Null);
+
+        let response = watched_exec(|db| {
+            run_other(db, &["SET", "live", "1"]);
+        });
+        assert_eq!(response, RespFrame::Null);
+
+        let response = watched_exec(|db| {
+            run_other(db, &["SWAPDB", "0", "1"]);
+            run_other(db, &["SWAPDB", "0", "1"]);
+        });
+        assert_eq!(response, RespFrame::Null);
+    }
+
+    #[test]
+    fn test_exec_forgets_watched_keys() {
+        let mut db = setup_db();
+        assert_eq!(run(&mut db, &["WATCH", "live"]), ok());
+        run_other(&mut db, &["SET", "live", "2"]);
+        assert_eq!(run(&mut db, &["MULTI"]), ok());
+        assert_eq!(run(&mut db, &["EXEC"]), RespFrame::Null);
+
+        // nobody watches the key anymore
+        let mut storage = db.state.db.lock().unwrap();
+        assert_eq!(storage.db(0).watch_stamp(&Bytes::from("live")), None);
+    }
+
+    #[test]
+    fn test_unwatch_forgets_watched_keys() {
+        let mut db = setup_db();
+
+        assert_eq!(run(&mut db, &["WATCH", "live"]), ok());
+        run_other(&mut db, &["DEL", "live"]);
+        assert_eq!(run(&mut db, &["UNWATCH"]), ok());
+        assert_eq!(run(&mut db, &["MULTI"]), ok());
+        assert_eq!(
+            run(&mut db, &["WATCH", "live"]),
+            RespFrame::Error("ERR WATCH inside MULTI is not allowed".to_string())
+        );
+        // the refused WATCH dooms the transaction, as redis does
+        assert_eq!(
+            run(&mut db, &["EXEC"]),
+            RespFrame::Error(
+                "EXECABORT Transaction discarded because of previous errors.".to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,400 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::notify::NotifyFlags;
+use crate::slot::SLOT_COUNT;
+
+// Server settings, taken from redis-server style command line flags:
+//   resprs --port 6380 --bind 127.0.0.1 --databases 16 --save "3600 1 300 100"
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind: String,
+    pub port: u16,
+    pub databases: usize,
+    pub notify_keyspace_events: NotifyFlags,
+    // where RDB snapshots are written and loaded from at startup
+    pub dir: PathBuf,
+    pub dbfilename: String,
+    // snapshot after `seconds` once at least `changes` keys changed
+    pub save: Vec<SavePoint>,
+    // the append only file lives in `dir/appenddirname`, its files are named
+    // after `appendfilename`
+    pub appendonly: bool,
+    pub appendfilename: String,
+    pub appenddirname: String,
+    pub appendfsync: AppendFsync,
+    // load what is there when the last command of the AOF was cut short
+    pub aof_load_truncated: bool,
+    // the primary to replicate from, REPLICAOF at startup
+    pub replicaof: Option<MasterAddr>,
+    // shard channels are only served for the slots no other node has
+    pub cluster_enabled: bool,
+    pub cluster_slots: Vec<SlotRange>,
+}
+
+/// Where a replica's primary listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MasterAddr {
+    pub host: String,
+    pub port: u16,
+}
+
+impl MasterAddr {
+    /// Parses the `replicaof` config value, "<host> <port>".
+    pub fn parse(value: &str) -> Option<MasterAddr> {
+        let mut words = value.split_whitespace();
+        let (Some(host), Some(port), None) = (words.next(), words.next(), words.next()) else {
+            return None;
+        };
+        Some(MasterAddr {
+            host: host.to_string(),
+            port: port.parse().ok()?,
+        })
+    }
+}
+
+/// Slots another node of the cluster serves, which clients are sent to with
+/// MOVED. With cluster-enabled every slot not in a range is served here.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotRange {
+    pub start: u16,
+    pub end: u16,
+    // host:port of the node
+    pub node: String,
+}
+
+impl SlotRange {
+    /// Parses the `cluster-slots` config value, comma separated
+    /// "<start>-<end> <host>:<port>" ranges.
+    pub fn parse_list(value: &str) -> Option<Vec<SlotRange>> {
+        value
+            .split(',')
+            .filter(|range| !range.trim().is_empty())
+            .map(SlotRange::parse)
+            .collect()
+    }
+
+    fn parse(value: &str) -> Option<SlotRange> {
+        let mut words = value.split_whitespace();
+        let (Some(slots), Some(node), None) = (words.next(), words.next(), words.next()) else {
+            return None;
+        };
+        let (start, end) = slots.split_once('-').unwrap_or((slots, slots));
+        let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+        let (_, port) = node.rsplit_once(':')?;
+        port.parse::<u16>().ok()?;
+        (start <= end && end < SLOT_COUNT).then(|| SlotRange {
+            start,
+            end,
+            node: node.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for SlotRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{} {}", self.start, self.end, self.node)
+    }
+}
+
+/// When writes to the append only file are flushed to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppendFsync {
+    // after every write, before the client gets its reply
+    Always,
+    // once a second in the background, losing at most a second of writes
+    EverySec,
+    // whenever the operating system decides
+    No,
+}
+
+impl AppendFsync {
+    pub fn parse(value: &str) -> Option<AppendFsync> {
+        match value.to_ascii_lowercase().as_str() {
+            "always" => Some(AppendFsync::Always),
+            "everysec" => Some(AppendFsync::EverySec),
+            "no" => Some(AppendFsync::No),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AppendFsync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AppendFsync::Always => "always",
+            AppendFsync::EverySec => "everysec",
+            AppendFsync::No => "no",
+        })
+    }
+}
+
+/// Parses a redis style yes/no config value.
+pub fn parse_yes_no(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SavePoint {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+impl SavePoint {
+    /// redis' default policy, which the resprs binary uses too.
+    pub fn defaults() -> Vec<SavePoint> {
+        [(3600, 1), (300, 100), (60, 10000)]
+            .into_iter()
+            .map(|(seconds, changes)| SavePoint { seconds, changes })
+            .collect()
+    }
+
+    /// Parses the `save` config value, "<seconds> <changes>" pairs. An empty
+    /// value means no automatic snapshots.
+    pub fn parse_list(value: &str) -> Option<Vec<SavePoint>> {
+        let numbers: Vec<u64> = value
+            .split_whitespace()
+            .map(|n| n.parse().ok())
+            .collect::<Option<_>>()?;
+        if !numbers.len().is_multiple_of(2) {
+            return None;
+        }
+        Some(
+            numbers
+                .chunks_exact(2)
+                .map(|pair| SavePoint {
+                    seconds: pair[0],
+                    changes: pair[1],
+                })
+                .collect(),
+        )
+    }
+}
+
+impl fmt::Display for SavePoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.seconds, self.changes)
+    }
+}
+
+// embedders and tests get no automatic snapshots unless they ask for them,
+// `from_args` starts from redis' save points like redis-server does
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind: "127.0.0.1".to_string(),
+            port: 6380,
+            databases: 16,
+            notify_keyspace_events: NotifyFlags::default(),
+            dir: PathBuf::from("."),
+            dbfilename: "dump.rdb".to_string(),
+            save: Vec::new(),
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            appenddirname: "appendonlydir".to_string(),
+            appendfsync: AppendFsync::EverySec,
+            aof_load_truncated: true,
+            replicaof: None,
+            cluster_enabled: false,
+            cluster_slots: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Config, String> {
+        let mut config = Config {
+            save: SavePoint::defaults(),
+            ..Config::default()
+        };
+
+        while let Some(flag) = args.next() {
+            let Some(name) = flag.strip_prefix("--") else {
+                return Err(format!("unexpected argument '{}'", flag));
+            };
+            let Some(value) = args.next() else {
+                return Err(format!("missing value for '{}'", flag));
+            };
+
+            match name {
+                "bind" => config.bind = value,
+                "port" => {
+                    config.port = value
+                        .parse()
+                        .map_err(|_| format!("invalid port '{}'", value))?;
+                }
+                "databases" => {
+                    config.databases = value
+                        .parse()
+                        .ok()
+                        .filter(|count| *count > 0)
+                        .ok_or_else(|| format!("invalid databases '{}'", value))?;
+                }
+                "notify-keyspace-events" => {
+                    config.notify_keyspace_events = NotifyFlags::parse(&value)
+                        .ok_or_else(|| format!("invalid notify-keyspace-events '{}'", value))?;
+                }
+                "dir" => config.dir = PathBuf::from(value),
+                "dbfilename" => config.dbfilename = value,
+                "save" => {
+                    config.save = SavePoint::parse_list(&value)
+                        .ok_or_else(|| format!("invalid save '{}'", value))?;
+                }
+                "appendonly" => {
+                    config.appendonly = parse_yes_no(&value)
+                        .ok_or_else(|| format!("invalid appendonly '{}'", value))?;
+                }
+                "appendfilename" => config.appendfilename = value,
+                "appenddirname" => config.appenddirname = value,
+                "appendfsync" => {
+                    config.appendfsync = AppendFsync::parse(&value)
+                        .ok_or_else(|| format!("invalid appendfsync '{}'", value))?;
+                }
+                "aof-load-truncated" => {
+                    config.aof_load_truncated = parse_yes_no(&value)
+                        .ok_or_else(|| format!("invalid aof-load-truncated '{}'", value))?;
+                }
+                "replicaof" => {
+                    config.replicaof = Some(
+                        MasterAddr::parse(&value)
+                            .ok_or_else(|| format!("invalid replicaof '{}'", value))?,
+                    );
+                }
+                "cluster-enabled" => {
+                    config.cluster_enabled = parse_yes_no(&value)
+                        .ok_or_else(|| format!("invalid cluster-enabled '{}'", value))?;
+                }
+                "cluster-slots" => {
+                    config.cluster_slots = SlotRange::parse_list(&value)
+                        .ok_or_else(|| format!("invalid cluster-slots '{}'", value))?;
+                }
+                _ => return Err(format!("unknown option '{}'", flag)),
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn bind_addr(&self) -> String {
+        format!("{}:{}", self.bind, self.port)
+    }
+
+    pub fn rdb_path(&self) -> PathBuf {
+        self.dir.join(&self.dbfilename)
+    }
+
+    pub fn aof_dir(&self) -> PathBuf {
+        self.dir.join(&self.appenddirname)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{AppendFsync, Config, MasterAddr, SavePoint, SlotRange};
+
+    fn parse(args: &[&str]) -> Result<Config, String> {
+        Config::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = parse(&[]).unwrap();
+        assert_eq!(config.bind_addr(), "127.0.0.1:6380");
+        assert_eq!(config.databases, 16);
+        assert_eq!(config.save, SavePoint::defaults());
+        assert_eq!(config.rdb_path().to_str(), Some("./dump.rdb"));
+    }
+
+    #[test]
+    fn test_save_points() {
+        let config = parse(&["--save", "900 1 60 1000"]).unwrap();
+        assert_eq!(
+            config.save,
+            vec![
+                SavePoint {
+                    seconds: 900,
+                    changes: 1
+                },
+                SavePoint {
+                    seconds: 60,
+                    changes: 1000
+                },
+            ]
+        );
+        assert!(parse(&["--save", ""]).unwrap().save.is_empty());
+        assert!(parse(&["--save", "900"]).is_err());
+    }
+
+    #[test]
+    fn test_flags() {
+        let config = parse(&[
+            "--port",
+            "7000",
+            "--databases",
+            "4",
+            "--notify-keyspace-events",
+            "KEA",
+        ])
+        .unwrap();
+        assert_eq!(config.port, 7000);
+        assert_eq!(config.databases, 4);
+        assert_eq!(config.notify_keyspace_events.to_string(), "AKE");
+    }
+
+    #[test]
+    fn test_append_only_options() {
+        let config = parse(&["--appendonly", "yes", "--appendfsync", "always"]).unwrap();
+        assert!(config.appendonly);
+        assert_eq!(config.appendfsync, AppendFsync::Always);
+        assert_eq!(config.aof_dir().to_str(), Some("./appendonlydir"));
+        assert!(parse(&["--appendonly", "maybe"]).is_err());
+        assert!(parse(&["--appendfsync", "sometimes"]).is_err());
+    }
+
+    #[test]
+    fn test_replicaof() {
+        let config = parse(&["--replicaof", "10.0.0.1 6379"]).unwrap();
+        assert_eq!(
+            config.replicaof,
+            Some(MasterAddr {
+                host: "10.0.0.1".to_string(),
+                port: 6379
+            })
+        );
+        assert_eq!(parse(&[]).unwrap().replicaof, None);
+        assert!(parse(&["--replicaof", "10.0.0.1"]).is_err());
+        assert!(parse(&["--replicaof", "10.0.0.1 port"]).is_err());
+    }
+
+    #[test]
+    fn test_cluster_options() {
+        let config = parse(&[
+            "--cluster-enabled",
+            "yes",
+            "--cluster-slots",
+            "8192-16383 h:7001",
+        ])
+        .unwrap();
+        assert!(config.cluster_enabled);
+        assert_eq!(
+            config.cluster_slots,
+            vec![SlotRange {
+                start: 8192,
+                end: 16383,
+                node: "h:7001".to_string()
+            }]
+        );
+        assert!(!parse(&[]).unwrap().cluster_enabled);
+        assert!(parse(&["--cluster-slots", "0-1"]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_databases() {
+        assert!(parse(&["--databases", "0"]).is_err());
+        assert!(parse(&["--databases"]).is_err());
+    }
+}
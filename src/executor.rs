@@ -0,0 +1,95 @@
+// Runs commands in process, for embedders that want resprs' keyspace and
+// command set without going through a socket.
+use std::sync::Arc;
+
+use crate::aof::Rewrite;
+use crate::config::Config;
+use crate::outbox::{self, Inbox};
+use crate::persistence::{self, ImportReport};
+use crate::rdb;
+use crate::resp_frame::RespFrame;
+use crate::session::Session;
+use crate::{ServerState, close_session, execute, open_session};
+
+/// One client of a resprs keyspace. Every executor has its own selected
+/// database, transaction and subscriptions, like a connection would.
+///
+/// ```
+/// use bytes::Bytes;
+/// use resprs::{Config, Executor, RespFrame};
+///
+/// let mut executor = Executor::new(Config::default());
+/// let ping = RespFrame::Array(vec![RespFrame::BulkString(Bytes::from("PING"))]);
+/// assert_eq!(executor.execute(ping), RespFrame::SimpleString("PONG".to_string()));
+/// ```
+pub struct Executor {
+    state: Arc<ServerState>,
+    session: Session,
+    // frames sent outside of regular replies, like Pub/Sub messages
+    inbox: Inbox,
+}
+
+impl Executor {
+    /// An executor over a fresh keyspace of its own.
+    pub fn new(config: Config) -> Executor {
+        Executor::with_state(Arc::new(ServerState::new(config)))
+    }
+
+    pub(crate) fn with_state(state: Arc<ServerState>) -> Executor {
+        let (outbox, inbox) = outbox::channel();
+        Executor {
+            session: open_session(outbox, &state),
+            state,
+            inbox,
+        }
+    }
+
+    /// Another client of the same keyspace.
+    pub fn connect(&self) -> Executor {
+        Executor::with_state(self.state.clone())
+    }
+
+    pub fn client_id(&self) -> u64 {
+        self.session.id
+    }
+
+    /// Runs one command, given as an array of bulk strings like on the wire.
+    pub fn execute(&mut self, frame: RespFrame) -> RespFrame {
+        execute(frame, &self.state, &mut self.session)
+    }
+
+    /// Adds the keys and function libraries of an RDB file, as written by
+    /// resprs or by redis 6 and 7, to the keyspace. Existing keys with the
+    /// same names are replaced. Keys of types resprs cannot store are listed
+    /// in the report instead of failing the import.
+    pub fn import_rdb(&mut self, data: &[u8]) -> Result<ImportReport, String> {
+        let snapshot = rdb::read_snapshot(data)?;
+        let aof = &self.state.aof;
+        let mut storage = self.state.db.lock().unwrap();
+        // the imported keys never go through the log, so a new base written
+        // after the import has to hold them
+        if aof.is_enabled() && aof.rewrite_in_progress() {
+            return Err("an append only file rewrite is in progress".to_string());
+        }
+        let report = persistence::restore(&snapshot, &mut storage, &self.state.scripting)?;
+        if aof.is_enabled() && aof.rewrite(&storage, &self.state.scripting) != Rewrite::Started {
+            return Err("the append only file could not be rewritten".to_string());
+        }
+        Ok(report)
+    }
+
+    /// The frames pushed to this client since the last call, in order.
+    pub fn pushed(&mut self) -> Vec<RespFrame> {
+        let mut frames = Vec::new();
+        while let Ok(frame) = self.inbox.try_recv() {
+            frames.push(frame);
+        }
+        frames
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        close_session(&mut self.session, &self.state);
+    }
+}
@@ -0,0 +1,101 @@
+// Redis style glob matching (stringmatchlen), used for PSUBSCRIBE patterns.
+//   *      any sequence of bytes, including none
+//   ?      any single byte
+//   [abc]  one of the listed bytes, [^abc] negates, [a-z] is a range
+//   \x     the byte x literally
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    match pattern.first() {
+        None => string.is_empty(),
+        Some(b'*') => {
+            // collapse runs of stars, then try every possible split
+            let stars = pattern.iter().take_while(|byte| **byte == b'*').count();
+            let rest = &pattern[stars..];
+            if rest.is_empty() {
+                return true;
+            }
+            (0..=string.len()).any(|start| glob_match(rest, &string[start..]))
+        }
+        Some(b'?') => !string.is_empty() && glob_match(&pattern[1..], &string[1..]),
+        Some(b'[') => {
+            let Some((&byte, string_rest)) = string.split_first() else {
+                return false;
+            };
+            let Some((matched, pattern_rest)) = match_class(&pattern[1..], byte) else {
+                // an unterminated class is matched literally, like redis does
+                return byte == b'[' && glob_match(&pattern[1..], string_rest);
+            };
+            matched && glob_match(pattern_rest, string_rest)
+        }
+        Some(b'\\') if pattern.len() > 1 => {
+            string.first() == Some(&pattern[1]) && glob_match(&pattern[2..], &string[1..])
+        }
+        Some(&literal) => {
+            string.first() == Some(&literal) && glob_match(&pattern[1..], &string[1..])
+        }
+    }
+}
+
+// matches `byte` against the class body after '[', returning whether it matched
+// and the remaining pattern after the closing ']'
+fn match_class(pattern: &[u8], byte: u8) -> Option<(bool, &[u8])> {
+    let (negate, mut pattern) = match pattern.first() {
+        Some(b'^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+    let mut matched = false;
+
+    loop {
+        match pattern {
+            [] => return None,
+            [b']', rest @ ..] => return Some((matched != negate, rest)),
+            [b'\\', escaped, rest @ ..] => {
+                matched |= *escaped == byte;
+                pattern = rest;
+            }
+            [start, b'-', end, rest @ ..] if *end != b']' => {
+                let (low, high) = if start <= end {
+                    (start, end)
+                } else {
+                    (end, start)
+                };
+                matched |= (*low..=*high).contains(&byte);
+                pattern = rest;
+            }
+            [literal, rest @ ..] => {
+                matched |= *literal == byte;
+                pattern = rest;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::glob::glob_match;
+
+    #[test]
+    fn test_star_and_question_mark() {
+        assert!(glob_match(b"news.*", b"news.sport"));
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"h?llo", b"hello"));
+        assert!(!glob_match(b"h?llo", b"hllo"));
+        assert!(glob_match(b"a*b*c", b"aXXbYYc"));
+        assert!(!glob_match(b"a*b*c", b"aXXbYY"));
+    }
+
+    #[test]
+    fn test_classes() {
+        assert!(glob_match(b"h[ae]llo", b"hallo"));
+        assert!(!glob_match(b"h[ae]llo", b"hillo"));
+        assert!(glob_match(b"h[^e]llo", b"hallo"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(glob_match(b"h[a-b]llo", b"hbllo"));
+        assert!(!glob_match(b"h[a-b]llo", b"hcllo"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert!(glob_match(b"h\\*llo", b"h*llo"));
+        assert!(!glob_match(b"h\\*llo", b"hello"));
+    }
+}
@@ -0,0 +1,283 @@
+//! resprs is a Redis compatible server. The crate exposes the RESP codec
+//! (`RespFrame`, `parser`, `serializer`), an in-process command `Executor`
+//! and an embeddable `Server`; the `resprs` binary is a thin wrapper around
+//! the latter. `testing::TestServer` runs a server inside integration tests.
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+
+use crate::aof::Aof;
+use crate::migrate::Migrator;
+use crate::outbox::Outbox;
+use crate::persistence::Persistence;
+use crate::pubsub::PubSub;
+use crate::replication::Replication;
+use crate::scripting::Scripting;
+use crate::session::{Clients, Session};
+use crate::storage::{Storage, TrackedKeys};
+use crate::tracking::Tracking;
+
+pub use crate::config::Config;
+pub use crate::executor::Executor;
+pub use crate::persistence::{ImportReport, SkippedKey};
+pub use crate::resp_frame::RespFrame;
+pub use crate::server::{Server, ServerBuilder, ShutdownHandle};
+
+/// The keyspace shared by every client of a server.
+pub type Db = Arc<Mutex<Storage>>;
+
+mod aof;
+pub mod client;
+pub mod clock;
+mod commands;
+pub mod config;
+mod executor;
+mod glob;
+mod migrate;
+mod notify;
+mod outbox;
+pub mod parser;
+mod persistence;
+mod pubsub;
+mod rdb;
+mod replication;
+pub mod resp_frame;
+mod scripting;
+pub mod serializer;
+mod server;
+mod session;
+mod slot;
+pub mod storage;
+#[cfg(test)]
+mod test_util;
+pub mod testing;
+mod tracking;
+
+// state shared by every connection
+pub(crate) struct ServerState {
+    pub db: Db,
+    pub pubsub: PubSub,
+    pub tracking: Tracking,
+    pub scripting: Scripting,
+    pub persistence: Persistence,
+    pub aof: Aof,
+    pub migrator: Migrator,
+    pub replication: Replication,
+    pub clients: Clients,
+    pub config: Config,
+}
+
+impl ServerState {
+    pub fn new(config: Config) -> Self {
+        let mut storage = Storage::new(config.databases);
+        storage.set_notify_flags(config.notify_keyspace_events);
+        storage.set_keep_expired(config.replicaof.is_some());
+
+        ServerState {
+            db: Arc::new(Mutex::new(storage)),
+            pubsub: PubSub::new(),
+            tracking: Tracking::new(),
+            scripting: Scripting::new(),
+            persistence: Persistence::new(&config),
+            aof: Aof::new(&config),
+            migrator: Migrator::new(),
+            replication: Replication::new(config.replicaof.clone()),
+            clients: Clients::new(),
+            config,
+        }
+    }
+}
+
+// a fresh RandomState is seeded randomly, which is enough for RANDOMKEY
+fn random_index(len: usize) -> usize {
+    use std::hash::{BuildHasher, Hasher};
+
+    let hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    (hasher.finish() % len as u64) as usize
+}
+
+// runs one command under the storage lock and publishes the keyspace events it caused
+pub(crate) fn execute(frame: RespFrame, state: &ServerState, session: &mut Session) -> RespFrame {
+    if let Some(reply) = state.scripting.intercept(&frame) {
+        return reply;
+    }
+    let mut storage = state.db.lock().unwrap();
+    // CLIENT CACHING only covers the command right after it
+    let caching = session.caching.take();
+    let mut response = commands::handle_command(frame, &mut storage, session, state);
+    after_command(&mut storage, state, session, caching);
+    if let Some(commands::PendingMigrate { migration, sent }) = session.migration.take() {
+        // other clients go on while the target answers, the keys they
+        // write meanwhile are not deleted
+        drop(storage);
+        let replies = state.migrator.run(migration);
+        let mut storage = state.db.lock().unwrap();
+        response = commands::finish_migrate(sent, replies, &mut storage, session, state);
+        after_command(&mut storage, state, session, caching);
+    }
+    response
+}
+
+// publishes the keyspace events of a command before the lock is released
+fn after_command(
+    storage: &mut Storage,
+    state: &ServerState,
+    session: &Session,
+    caching: Option<bool>,
+) {
+    propagate_expired(storage, state);
+    if storage.key_tracking() {
+        let reader = session.tracks_reads(caching).then_some(session.id);
+        track_keys(storage.take_tracked_keys(), state, Some(session.id), reader);
+    }
+    publish_keyspace_events(storage, state);
+    // logged writes reach the file before the client hears they happened
+    state.aof.flush();
+}
+
+// `execute` for async tasks. SCRIPT KILL and BUSY replies are answered right
+// away, everything else runs on the blocking pool, as a script can hold the
+// storage lock for as long as it loops and a runtime worker waiting on it
+// would stop every other client, the one sending SCRIPT KILL included.
+pub(crate) async fn execute_blocking(
+    frame: RespFrame,
+    state: &Arc<ServerState>,
+    session: &mut Session,
+) -> RespFrame {
+    if let Some(reply) = state.scripting.intercept(&frame) {
+        return reply;
+    }
+    let state = state.clone();
+    with_session_blocking(session, move |session| execute(frame, &state, session)).await
+}
+
+// runs `f` on the blocking pool with the session lent to it
+pub(crate) async fn with_session_blocking<T: Send + 'static>(
+    session: &mut Session,
+    f: impl FnOnce(&mut Session) -> T + Send + 'static,
+) -> T {
+    let mut lent = std::mem::replace(session, Session::placeholder());
+    let (value, lent) = run_blocking(move || (f(&mut lent), lent)).await;
+    *session = lent;
+    value
+}
+
+// for async tasks that take the storage lock, see `execute_blocking`
+pub(crate) async fn run_blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+// hands a write that succeeded to the append only file and the replicas. On
+// a replica the writes of its primary are passed on as they arrived instead.
+pub(crate) fn propagate(
+    state: &ServerState,
+    storage: &Storage,
+    session: &Session,
+    args: &[RespFrame],
+) {
+    let replicate = !session.is_master && state.replication.is_active();
+    if !state.aof.is_enabled() && !replicate {
+        return;
+    }
+    let args = aof::translate(storage, args);
+    state.aof.feed(session.db_index, &args);
+    if replicate {
+        state.replication.feed(session.db_index, &args);
+    }
+}
+
+// deletes the keys that expired here on the AOF and the replicas too, so
+// neither depends on its own clock. Called before the command that evicted
+// them is propagated, which may write the key anew.
+pub(crate) fn propagate_expired(storage: &mut Storage, state: &ServerState) {
+    let expired = storage.take_expired();
+    let replicate = state.replication.is_active();
+    if !state.aof.is_enabled() && !replicate {
+        return;
+    }
+    for (db_index, keys) in expired {
+        for key in keys {
+            let args = [Bytes::from_static(b"DEL"), key];
+            state.aof.feed(db_index, &args);
+            if replicate {
+                state.replication.feed(db_index, &args);
+            }
+        }
+    }
+}
+
+// what is propagated until the matching `end_atomic` is replayed as one
+// transaction
+pub(crate) fn begin_atomic(state: &ServerState) {
+    state.aof.begin_atomic();
+    state.replication.begin_atomic();
+}
+
+pub(crate) fn end_atomic(state: &ServerState) {
+    state.aof.end_atomic();
+    state.replication.end_atomic();
+}
+
+// the session of a client connecting, known to the server until `close_session`
+fn open_session(outbox: Outbox, state: &ServerState) -> Session {
+    let session = Session::new(outbox);
+    state.clients.register(session.id);
+    session
+}
+
+// drops everything a client registered with the server when it goes away
+fn close_session(session: &mut Session, state: &ServerState) {
+    state.clients.unregister(session.id);
+    for channel in session.channels.drain() {
+        state.pubsub.unsubscribe(session.id, &channel);
+    }
+    for pattern in session.patterns.drain() {
+        state.pubsub.punsubscribe(session.id, &pattern);
+    }
+    for channel in session.shard_channels.drain() {
+        state.pubsub.sunsubscribe(session.id, &channel);
+    }
+    if session.tracking.take().is_some() {
+        state.tracking.disable(session.id);
+        sync_key_tracking(&mut state.db.lock().unwrap(), state);
+    }
+    if !session.watched_keys.is_empty() {
+        commands::unwatch_all(&mut state.db.lock().unwrap(), session);
+    }
+}
+
+// invalidates the keys a command changed, then remembers what a tracking client read
+fn track_keys(tracked: TrackedKeys, state: &ServerState, origin: Option<u64>, reader: Option<u64>) {
+    if tracked.flushed {
+        state.tracking.invalidate_all(&state.pubsub);
+    }
+    if !tracked.modified.is_empty() {
+        state
+            .tracking
+            .invalidate(&state.pubsub, &tracked.modified, origin);
+    }
+    if let Some(client_id) = reader {
+        state.tracking.remember(client_id, tracked.read);
+    }
+}
+
+// the keyspace only records keys while at least one client has tracking on
+fn sync_key_tracking(storage: &mut Storage, state: &ServerState) {
+    let active = state.tracking.is_active();
+    if storage.key_tracking() != active {
+        storage.set_key_tracking(active);
+    }
+}
+
+fn publish_keyspace_events(storage: &mut Storage, state: &ServerState) {
+    let flags = storage.notify_flags();
+    if flags.is_empty() {
+        return;
+    }
+    for (db_index, events) in storage.take_events() {
+        notify::publish_events(&state.pubsub, flags, db_index, events);
+    }
+}
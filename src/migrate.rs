@@ -0,0 +1,446 @@
+// The network side of MIGRATE. Commands run synchronously, outside of any
+// runtime, so migrations are handed to a thread of their own that drives the
+// async client on its own runtime while the command waits for the result.
+// `execute` lets go of the storage lock meanwhile, EXEC and scripts can't.
+// Connections to targets stay open for a while, like redis' migrate cache.
+use std::collections::HashMap;
+use std::sync::{Mutex, mpsc};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+use crate::client::{Client, ClientError};
+use crate::resp_frame::RespFrame;
+
+// connections idle for longer are closed, and at most this many are kept
+const CONNECTION_TTL: Duration = Duration::from_secs(10);
+const MAX_CONNECTIONS: usize = 64;
+
+/// The commands one MIGRATE sends to its target.
+#[derive(Debug)]
+pub struct Migration {
+    // host:port
+    pub addr: String,
+    pub db: usize,
+    pub timeout: Duration,
+    // the arguments of AUTH, empty when the target needs none
+    pub auth: Vec<Bytes>,
+    pub restores: Vec<RespFrame>,
+}
+
+struct Job {
+    migration: Migration,
+    reply: mpsc::Sender<Result<Vec<RespFrame>, String>>,
+}
+
+struct Connection {
+    client: Client,
+    // what the last SELECT on this connection chose
+    db: Option<usize>,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+pub struct Migrator {
+    // started by the first MIGRATE, the thread stops once this is dropped
+    jobs: Mutex<Option<UnboundedSender<Job>>>,
+}
+
+impl Migrator {
+    pub fn new() -> Migrator {
+        Migrator::default()
+    }
+
+    /// Sends the RESTORE commands of a migration and returns their replies,
+    /// in order. Failing to reach the target, or it rejecting AUTH or SELECT,
+    /// is an error reply for MIGRATE itself.
+    pub fn run(&self, migration: Migration) -> Result<Vec<RespFrame>, String> {
+        let (reply, result) = mpsc::channel();
+        let job = Job { migration, reply };
+        let sent = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let sender = jobs.get_or_insert_with(start_thread);
+            sender.send(job)
+        };
+        if sent.is_err() {
+            return Err("ERR the migration thread is not running".to_string());
+        }
+        result
+            .recv()
+            .unwrap_or_else(|_| Err("ERR the migration thread is not running".to_string()))
+    }
+}
+
+fn start_thread() -> UnboundedSender<Job> {
+    let (sender, jobs) = unbounded_channel();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build();
+        match runtime {
+            Ok(runtime) => runtime.block_on(serve(jobs)),
+            Err(e) => println!("Error starting the migration thread: {}", e),
+        }
+    });
+    sender
+}
+
+async fn serve(mut jobs: UnboundedReceiver<Job>) {
+    let mut connections = HashMap::new();
+    while let Some(Job { migration, reply }) = jobs.recv().await {
+        let now = Instant::now();
+        connections.retain(|_, connection: &mut Connection| {
+            now - connection.last_used < CONNECTION_TTL && !connection.client.is_closed()
+        });
+        let _ = reply.send(migrate(&mut connections, &migration).await);
+    }
+}
+
+async fn migrate(
+    connections: &mut HashMap<String, Connection>,
+    migration: &Migration,
+) -> Result<Vec<RespFrame>, String> {
+    // a cached connection may have been closed by the target in the
+    // meantime, which only shows once it is used, so that gets one retry as
+    // long as nothing was answered yet
+    let mut retried = false;
+    loop {
+        let cached = connections.contains_key(&migration.addr);
+        if !cached {
+            let client = connect(migration).await?;
+            if connections.len() >= MAX_CONNECTIONS {
+                evict_oldest(connections);
+            }
+            let connection = Connection {
+                client,
+                db: None,
+                last_used: Instant::now(),
+            };
+            connections.insert(migration.addr.clone(), connection);
+        }
+        let connection = connections.get_mut(&migration.addr).unwrap();
+        connection.last_used = Instant::now();
+
+        let mut answered = 0;
+        let sent = send(connection, migration, &mut answered);
+        match tokio::time::timeout(migration.timeout, sent).await {
+            Ok(Ok(replies)) => return Ok(replies),
+            Ok(Err(ClientError::Server(message))) => {
+                return Err(format!(
+                    "ERR Target instance replied with error: {}",
+                    message
+                ));
+            }
+            Ok(Err(ClientError::Closed | ClientError::Io(_)))
+                if cached && !retried && answered == 0 =>
+            {
+                connections.remove(&migration.addr);
+                retried = true;
+            }
+            _ => {
+                connections.remove(&migration.addr);
+                return Err("IOERR error or timeout reading to target instance".to_string());
+            }
+        }
+    }
+}
+
+async fn connect(migration: &Migration) -> Result<Client, String> {
+    match tokio::time::timeout(migration.timeout, Client::connect(&migration.addr)).await {
+        Ok(Ok(client)) => Ok(client),
+        _ => Err("IOERR error or timeout connecting to the client".to_string()),
+    }
+}
+
+// AUTH and SELECT fail the whole migration, the RESTOREs answer one key each
+async fn send(
+    connection: &mut Connection,
+    migration: &Migration,
+    answered: &mut usize,
+) -> Result<Vec<RespFrame>, ClientError> {
+    if !migration.auth.is_empty() {
+        let mut auth = vec![Bytes::from("AUTH")];
+        auth.extend(migration.auth.iter().cloned());
+        connection.client.command(&auth).await?;
+        *answered += 1;
+    }
+    if connection.db != Some(migration.db) {
+        let db = migration.db.to_string();
+        connection.client.command(&["SELECT", db.as_str()]).await?;
+        connection.db = Some(migration.db);
+        *answered += 1;
+    }
+    let mut replies = Vec::new();
+    for restore in &migration.restores {
+        replies.push(connection.client.send(restore.clone()).await?);
+        *answered += 1;
+    }
+    Ok(replies)
+}
+
+fn evict_oldest(connections: &mut HashMap<String, Connection>) {
+    let oldest = connections
+        .iter()
+        .min_by_key(|(_, connection)| connection.last_used)
+        .map(|(addr, _)| addr.clone());
+    if let Some(addr) = oldest {
+        connections.remove(&addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use tokio::io::BufReader;
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+
+    use crate::client::{Client, ClientError};
+    use crate::resp_frame::RespFrame;
+    use crate::testing::TestServer;
+    use crate::{parser, serializer};
+
+    async fn migrate(client: &Client, port: u16, args: &[&str]) -> RespFrame {
+        let port = port.to_string();
+        let mut command = vec!["MIGRATE", "127.0.0.1", port.as_str()];
+        command.extend_from_slice(args);
+        match client.command(&command).await {
+            Ok(reply) => reply,
+            Err(ClientError::Server(message)) => RespFrame::Error(message),
+            Err(e) => panic!("MIGRATE failed: {}", e),
+        }
+    }
+
+    // a target that reports the first RESTORE it reads, then answers OK to
+    // everything once `answer` fires. Dropping `answer` keeps it silent.
+    async fn fake_target(answer: oneshot::Receiver<()>) -> (u16, oneshot::Receiver<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (restored, restore_read) = oneshot::channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read_half, mut write_half) = stream.into_split();
+            let mut reader = BufReader::new(read_half);
+            let mut restored = Some(restored);
+            let mut answer = Some(answer);
+            while let Ok(frame) = parser::parse_frame(&mut reader).await {
+                let is_restore = matches!(&frame, RespFrame::Array(args)
+                    if args.first() == Some(&RespFrame::BulkString(Bytes::from("RESTORE"))));
+                if is_restore {
+                    if let Some(restored) = restored.take() {
+                        let _ = restored.send(());
+                    }
+                    if let Some(answer) = answer.take()
+                        && answer.await.is_err()
+                    {
+                        // hold the connection open without a reply
+                        std::future::pending::<()>().await;
+                    }
+                }
+                let ok = RespFrame::simple("OK");
+                if serializer::serialize_frame(&mut write_half, ok)
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        });
+        (port, restore_read)
+    }
+
+    #[tokio::test]
+    async fn test_migrate_moves_a_key() {
+        let source = TestServer::start().await;
+        let target = TestServer::start().await;
+        let client = Client::connect(source.addr()).await.unwrap();
+        let on_target = Client::connect(target.addr()).await.unwrap();
+        let port = target.addr().port();
+
+        client.set("k", "v").await.unwrap();
+        client.expire("k", 100).await.unwrap();
+        assert_eq!(
+            migrate(&client, port, &["k", "0", "5000"]).await,
+            RespFrame::simple("OK")
+        );
+        assert_eq!(client.get("k").await.unwrap(), None);
+        assert_eq!(on_target.get("k").await.unwrap(), Some(Bytes::from("v")));
+        assert!(matches!(on_target.ttl("k").await.unwrap(), 99 | 100));
+
+        assert_eq!(
+            migrate(&client, port, &["k", "0", "5000"]).await,
+            RespFrame::simple("NOKEY")
+        );
+        assert_eq!(
+            migrate(&client, port, &["", "0", "5000", "KEYS", "k", "missing"]).await,
+            RespFrame::simple("NOKEY")
+        );
+        assert_eq!(
+            migrate(&client, port, &["b", "0", "5000", "KEYS", "a"]).await,
+            RespFrame::Error(
+                "ERR When using MIGRATE KEYS option, the key argument must be set to the empty string"
+                    .to_string()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migrate_is_refused_in_transactions_and_scripts() {
+        // both would wait for the target with the storage lock held, which a
+        // target that never answers would keep forever
+        let (answer, silent) = oneshot::channel();
+        drop(answer);
+        let (port, mut restore_read) = fake_target(silent).await;
+        let source = TestServer::start().await;
+        let client = Client::connect(source.addr()).await.unwrap();
+        client.set("k", "v").await.unwrap();
+
+        client.command(&["MULTI"]).await.unwrap();
+        assert_eq!(
+            migrate(&client, port, &["k", "0", "5000"]).await,
+            RespFrame::Error("ERR Command not allowed inside a transaction".to_string())
+        );
+        assert!(matches!(
+            client.command(&["EXEC"]).await,
+            Err(ClientError::Server(message)) if message.starts_with("EXECABORT")
+        ));
+
+        let script = format!(
+            "return redis.pcall('MIGRATE', '127.0.0.1', '{}', 'k', '0', '5000')",
+            port
+        );
+        assert!(matches!(
+            client.command(&["EVAL", script.as_str(), "0"]).await,
+            Err(ClientError::Server(message))
+                if message == "ERR This Redis command is not allowed from script"
+        ));
+
+        assert_eq!(client.get("k").await.unwrap(), Some(Bytes::from("v")));
+        assert!(restore_read.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_keys_copy_and_replace() {
+        let source = TestServer::start().await;
+        let target = TestServer::start().await;
+        let client = Client::connect(source.addr()).await.unwrap();
+        let on_target = Client::connect(target.addr()).await.unwrap();
+        let port = target.addr().port();
+        client.set("a", "1").await.unwrap();
+        client.set("b", "2").await.unwrap();
+        client.set("c", "3").await.unwrap();
+
+        // KEYS skips the missing ones, COPY leaves the rest where they are
+        assert_eq!(
+            migrate(
+                &client,
+                port,
+                &["", "1", "5000", "COPY", "KEYS", "a", "b", "missing"]
+            )
+            .await,
+            RespFrame::simple("OK")
+        );
+        assert_eq!(client.exists(&["a", "b"]).await.unwrap(), 2);
+        on_target.command(&["SELECT", "1"]).await.unwrap();
+        assert_eq!(
+            on_target.mget(&["a", "b", "missing"]).await.unwrap(),
+            vec![Some(Bytes::from("1")), Some(Bytes::from("2")), None]
+        );
+
+        // a key the target refuses stays, the others in the same call move
+        client.set("a", "changed").await.unwrap();
+        assert_eq!(
+            migrate(&client, port, &["", "1", "5000", "KEYS", "a", "c"]).await,
+            RespFrame::Error(
+                "ERR Target instance replied with error: BUSYKEY Target key name already exists."
+                    .to_string()
+            )
+        );
+        assert_eq!(client.get("a").await.unwrap(), Some(Bytes::from("changed")));
+        assert_eq!(client.get("c").await.unwrap(), None);
+        assert_eq!(on_target.get("a").await.unwrap(), Some(Bytes::from("1")));
+        assert_eq!(on_target.get("c").await.unwrap(), Some(Bytes::from("3")));
+
+        assert_eq!(
+            migrate(&client, port, &["a", "1", "5000", "REPLACE"]).await,
+            RespFrame::simple("OK")
+        );
+        assert_eq!(client.get("a").await.unwrap(), None);
+        assert_eq!(
+            on_target.get("a").await.unwrap(),
+            Some(Bytes::from("changed"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migrate_to_a_stopped_target_fails() {
+        let source = TestServer::start().await;
+        let target = TestServer::start().await;
+        let client = Client::connect(source.addr()).await.unwrap();
+        let port = target.addr().port();
+        client.set("a", "1").await.unwrap();
+        client.set("b", "2").await.unwrap();
+        assert_eq!(
+            migrate(&client, port, &["a", "0", "5000"]).await,
+            RespFrame::simple("OK")
+        );
+
+        // the cached connection to a stopped target is dropped and reconnecting fails
+        target.shutdown().await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(
+            migrate(&client, port, &["b", "0", "200"]).await,
+            RespFrame::Error("IOERR error or timeout connecting to the client".to_string())
+        );
+        assert_eq!(client.get("b").await.unwrap(), Some(Bytes::from("2")));
+    }
+
+    #[tokio::test]
+    async fn test_migrate_times_out_without_blocking_other_clients() {
+        let source = TestServer::start().await;
+        let client = Client::connect(source.addr()).await.unwrap();
+        let other = Client::connect(source.addr()).await.unwrap();
+        client.set("k", "v").await.unwrap();
+        let (silence, answer) = oneshot::channel();
+        drop(silence);
+        let (port, restore_read) = fake_target(answer).await;
+
+        let migrating = tokio::spawn(async move {
+            let reply = migrate(&client, port, &["k", "0", "500"]).await;
+            (client, reply)
+        });
+        restore_read.await.unwrap();
+        // the source answers while MIGRATE waits for the target
+        other.set("other", "1").await.unwrap();
+        assert!(!migrating.is_finished());
+
+        let (client, reply) = migrating.await.unwrap();
+        assert_eq!(
+            reply,
+            RespFrame::Error("IOERR error or timeout reading to target instance".to_string())
+        );
+        assert_eq!(client.get("k").await.unwrap(), Some(Bytes::from("v")));
+    }
+
+    #[tokio::test]
+    async fn test_migrate_keeps_a_key_written_while_it_was_sent() {
+        let source = TestServer::start().await;
+        let client = Client::connect(source.addr()).await.unwrap();
+        let other = Client::connect(source.addr()).await.unwrap();
+        client.set("k", "v").await.unwrap();
+        let (allow, answer) = oneshot::channel();
+        let (port, restore_read) = fake_target(answer).await;
+
+        let migrating =
+            tokio::spawn(async move { migrate(&client, port, &["k", "0", "5000"]).await });
+        restore_read.await.unwrap();
+        other.set("k", "newer").await.unwrap();
+        allow.send(()).unwrap();
+
+        assert_eq!(migrating.await.unwrap(), RespFrame::simple("OK"));
+        assert_eq!(other.get("k").await.unwrap(), Some(Bytes::from("newer")));
+    }
+}
@@ -0,0 +1,277 @@
+use bytes::Bytes;
+
+use crate::pubsub::PubSub;
+
+// Event classes selected by notify-keyspace-events, one bit per flag letter.
+pub const KEYSPACE: u16 = 1 << 0; // K
+pub const KEYEVENT: u16 = 1 << 1; // E
+pub const GENERIC: u16 = 1 << 2; // g
+pub const STRING: u16 = 1 << 3; // $
+pub const LIST: u16 = 1 << 4; // l
+pub const SET: u16 = 1 << 5; // s
+pub const HASH: u16 = 1 << 6; // h
+pub const ZSET: u16 = 1 << 7; // z
+pub const EXPIRED: u16 = 1 << 8; // x
+pub const EVICTED: u16 = 1 << 9; // e
+pub const STREAM: u16 = 1 << 10; // t
+pub const KEY_MISS: u16 = 1 << 11; // m
+pub const NEW: u16 = 1 << 12; // n
+
+// "A" is an alias for every class except key misses and new keys, like in redis
+const ALL: u16 = GENERIC | STRING | LIST | SET | HASH | ZSET | EXPIRED | EVICTED | STREAM;
+
+const FLAG_LETTERS: &[(char, u16)] = &[
+    ('g', GENERIC),
+    ('$', STRING),
+    ('l', LIST),
+    ('s', SET),
+    ('h', HASH),
+    ('z', ZSET),
+    ('x', EXPIRED),
+    ('e', EVICTED),
+    ('t', STREAM),
+    ('m', KEY_MISS),
+    ('n', NEW),
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NotifyFlags(u16);
+
+impl NotifyFlags {
+    pub fn parse(flags: &str) -> Option<NotifyFlags> {
+        let mut bits = 0;
+        for letter in flags.chars() {
+            bits |= match letter {
+                'K' => KEYSPACE,
+                'E' => KEYEVENT,
+                'A' => ALL,
+                _ => FLAG_LETTERS
+                    .iter()
+                    .find(|(flag, _)| *flag == letter)
+                    .map(|(_, bit)| *bit)?,
+            };
+        }
+        Some(NotifyFlags(bits))
+    }
+
+    /// Whether an event of `class` should be recorded at all. Without K or E
+    /// nothing is ever published, so nothing is recorded either.
+    pub fn wants(self, class: u16) -> bool {
+        self.0 & (KEYSPACE | KEYEVENT) != 0 && self.0 & class != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+// the normalized form CONFIG GET reports, in the same order redis uses
+impl std::fmt::Display for NotifyFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = String::new();
+        let letters = |out: &mut String, classes: u16| {
+            for (letter, bit) in FLAG_LETTERS {
+                if bit & classes != 0 && self.0 & bit != 0 {
+                    out.push(*letter);
+                }
+            }
+        };
+
+        if self.0 & ALL == ALL {
+            out.push('A');
+        } else {
+            letters(&mut out, ALL);
+        }
+        if self.0 & KEYSPACE != 0 {
+            out.push('K');
+        }
+        if self.0 & KEYEVENT != 0 {
+            out.push('E');
+        }
+        letters(&mut out, KEY_MISS | NEW);
+
+        f.write_str(&out)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyspaceEvent {
+    pub event: &'static str,
+    pub key: Bytes,
+}
+
+/// Publishes recorded events on __keyspace@<db>__:<key> and
+/// __keyevent@<db>__:<event>, depending on which of K and E are enabled.
+pub fn publish_events(
+    pubsub: &PubSub,
+    flags: NotifyFlags,
+    db_index: usize,
+    events: Vec<KeyspaceEvent>,
+) {
+    for KeyspaceEvent { event, key } in events {
+        if flags.0 & KEYSPACE != 0 {
+            let mut channel = format!("__keyspace@{}__:", db_index).into_bytes();
+            channel.extend_from_slice(&key);
+            pubsub.publish(&Bytes::from(channel), &Bytes::from_static(event.as_bytes()));
+        }
+        if flags.0 & KEYEVENT != 0 {
+            let channel = format!("__keyevent@{}__:{}", db_index, event);
+            pubsub.publish(&Bytes::from(channel), &key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::notify::{EXPIRED, GENERIC, KEY_MISS, NotifyFlags, STRING};
+    use crate::resp_frame::RespFrame;
+    use crate::test_util::{TestDb, bulk, ok, run, setup_db};
+
+    #[test]
+    fn test_parse_and_display() {
+        let flags = NotifyFlags::parse("KEA").unwrap();
+        assert!(flags.wants(GENERIC));
+        assert!(flags.wants(EXPIRED));
+        assert!(!flags.wants(KEY_MISS));
+        assert_eq!(flags.to_string(), "AKE");
+
+        assert_eq!(NotifyFlags::parse("Ex$").unwrap().to_string(), "$xE");
+        assert!(NotifyFlags::parse("Q").is_none());
+    }
+
+    #[test]
+    fn test_classes_need_keyspace_or_keyevent() {
+        let flags = NotifyFlags::parse("g$").unwrap();
+        assert!(!flags.wants(STRING));
+        assert!(NotifyFlags::parse("").unwrap().is_empty());
+    }
+
+    fn notification(channel: &str, message: &str) -> RespFrame {
+        RespFrame::Array(vec![bulk("message"), bulk(channel), bulk(message)])
+    }
+
+    // subscribes a fresh connection to every keyspace and keyevent channel
+    fn listen(db: &TestDb) -> TestDb {
+        let mut listener = db.connect();
+        run(&mut listener, &["PSUBSCRIBE", "__key*__:*"]);
+        listener
+    }
+
+    #[test]
+    fn test_keyspace_notifications_are_off_by_default() {
+        let mut db = setup_db();
+        let mut listener = listen(&db);
+
+        run(&mut db, &["SET", "key", "value"]);
+        run(&mut db, &["GET", "gone"]);
+        assert!(listener.pushed().is_empty());
+    }
+
+    #[test]
+    fn test_keyspace_and_keyevent_messages() {
+        let mut db = setup_db();
+        assert_eq!(
+            run(&mut db, &["CONFIG", "SET", "notify-keyspace-events", "KEA"]),
+            ok()
+        );
+        assert_eq!(
+            run(&mut db, &["CONFIG", "GET", "notify-keyspace-events"]),
+            RespFrame::Array(vec![bulk("notify-keyspace-events"), bulk("AKE")])
+        );
+
+        let mut listener = db.connect();
+        run(
+            &mut listener,
+            &["SUBSCRIBE", "__keyspace@0__:live", "__keyevent@0__:expired"],
+        );
+        listener.pushed();
+
+        run(&mut db, &["INCR", "live"]);
+        run(&mut db, &["GET", "gone"]);
+        assert_eq!(
+            listener.pushed(),
+            vec![
+                notification("__keyspace@0__:live", "incrby"),
+                notification("__keyevent@0__:expired", "gone"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_event_classes_filter_notifications() {
+        let mut db = setup_db();
+        run(&mut db, &["CONFIG", "SET", "notify-keyspace-events", "Eg"]);
+        let mut listener = listen(&db);
+
+        run(&mut db, &["SET", "key", "value"]);
+        run(&mut db, &["RENAME", "key", "other"]);
+        run(&mut db, &["DEL", "other"]);
+
+        let events: Vec<RespFrame> = listener
+            .pushed()
+            .into_iter()
+            .filter_map(|frame| match frame {
+                RespFrame::Array(mut items) => items.pop(),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(events, vec![bulk("key"), bulk("other"), bulk("other")]);
+    }
+
+    #[test]
+    fn test_new_and_keymiss_events() {
+        let mut db = setup_db();
+        run(&mut db, &["CONFIG", "SET", "notify-keyspace-events", "Enm"]);
+        let mut listener = listen(&db);
+
+        run(&mut db, &["SET", "fresh", "value"]);
+        run(&mut db, &["SET", "fresh", "again"]);
+        run(&mut db, &["GET", "missing"]);
+
+        let channels: Vec<RespFrame> = listener
+            .pushed()
+            .into_iter()
+            .filter_map(|frame| match frame {
+                RespFrame::Array(items) => items.get(2).cloned(),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            channels,
+            vec![bulk("__keyevent@0__:new"), bulk("__keyevent@0__:keymiss")]
+        );
+    }
+
+    #[test]
+    fn test_move_notifies_both_databases() {
+        let mut db = setup_db();
+        run(&mut db, &["CONFIG", "SET", "notify-keyspace-events", "Kg"]);
+        let mut listener = listen(&db);
+
+        run(&mut db, &["MOVE", "live", "3"]);
+        let frames = listener.pushed();
+        assert!(frames.contains(&RespFrame::Array(vec![
+            bulk("pmessage"),
+            bulk("__key*__:*"),
+            bulk("__keyspace@0__:live"),
+            bulk("move_from")
+        ])));
+        assert!(frames.contains(&RespFrame::Array(vec![
+            bulk("pmessage"),
+            bulk("__key*__:*"),
+            bulk("__keyspace@3__:live"),
+            bulk("move_to")
+        ])));
+    }
+
+    #[test]
+    fn test_config_set_rejects_bad_flags() {
+        let mut db = setup_db();
+        assert_eq!(
+            run(&mut db, &["CONFIG", "SET", "notify-keyspace-events", "Q"]),
+            RespFrame::Error(
+                "ERR Invalid argument for CONFIG SET 'notify-keyspace-events'".to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,143 @@
+// The queue between a connection and the task writing to its client:
+// replies, pub/sub messages and invalidations all go through it. What is
+// queued and not taken by the writer yet is counted, so a subscriber that
+// does not read what it is sent is disconnected before its backlog eats the
+// memory, like redis' client-output-buffer-limit for pubsub clients.
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::sync::Notify;
+use tokio::sync::mpsc::error::{SendError, TryRecvError};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+use crate::resp_frame::RespFrame;
+
+/// How far a subscriber may fall behind, redis' hard limit for pubsub clients.
+pub const PUBSUB_OUTPUT_LIMIT: usize = 32 * 1024 * 1024;
+
+#[derive(Debug, Default)]
+struct Backlog {
+    // about the bytes queued, see `queued_size`
+    queued: AtomicUsize,
+    overflowed: Notify,
+}
+
+#[derive(Debug, Clone)]
+pub struct Outbox {
+    sender: UnboundedSender<RespFrame>,
+    backlog: Arc<Backlog>,
+}
+
+#[derive(Debug)]
+pub struct Inbox {
+    receiver: UnboundedReceiver<RespFrame>,
+    backlog: Arc<Backlog>,
+}
+
+pub fn channel() -> (Outbox, Inbox) {
+    let (sender, receiver) = unbounded_channel();
+    let backlog = Arc::new(Backlog::default());
+    (
+        Outbox {
+            sender,
+            backlog: backlog.clone(),
+        },
+        Inbox { receiver, backlog },
+    )
+}
+
+impl Outbox {
+    pub fn send(&self, frame: RespFrame) -> Result<(), SendError<RespFrame>> {
+        let size = queued_size(&frame);
+        // counted first, the writer may take the frame right away
+        self.backlog.queued.fetch_add(size, Ordering::Relaxed);
+        self.sender.send(frame).inspect_err(|_| {
+            self.backlog.queued.fetch_sub(size, Ordering::Relaxed);
+        })
+    }
+
+    /// Sends a pub/sub message, unless more than `limit` bytes are already
+    /// waiting. Then the message is dropped, the connection is told to close
+    /// through `overflowed` and false is returned.
+    pub fn send_message(&self, frame: RespFrame, limit: usize) -> bool {
+        if self.backlog.queued.load(Ordering::Relaxed) > limit {
+            self.backlog.overflowed.notify_one();
+            return false;
+        }
+        self.send(frame).is_ok()
+    }
+
+    /// Resolves once a message was refused by `send_message`. It holds no
+    /// sender, so the writer still ends when the session goes away.
+    pub fn overflowed(&self) -> impl Future<Output = ()> + Send + use<> {
+        let backlog = self.backlog.clone();
+        async move { backlog.overflowed.notified().await }
+    }
+}
+
+impl Inbox {
+    pub async fn recv(&mut self) -> Option<RespFrame> {
+        let frame = self.receiver.recv().await?;
+        self.taken(&frame);
+        Some(frame)
+    }
+
+    pub fn try_recv(&mut self) -> Result<RespFrame, TryRecvError> {
+        let frame = self.receiver.try_recv()?;
+        self.taken(&frame);
+        Ok(frame)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.receiver.is_empty()
+    }
+
+    fn taken(&self, frame: &RespFrame) {
+        self.backlog
+            .queued
+            .fetch_sub(queued_size(frame), Ordering::Relaxed);
+    }
+}
+
+// about what a frame takes on the wire, without serializing it
+fn queued_size(frame: &RespFrame) -> usize {
+    // the type byte, a length and the line endings
+    const OVERHEAD: usize = 16;
+    OVERHEAD
+        + match frame {
+            RespFrame::SimpleString(s) | RespFrame::Error(s) => s.len(),
+            RespFrame::BulkString(bytes) => bytes.len(),
+            RespFrame::Array(items) | RespFrame::Push(items) => items.iter().map(queued_size).sum(),
+            RespFrame::Map(fields) => fields
+                .iter()
+                .map(|(key, value)| queued_size(key) + queued_size(value))
+                .sum(),
+            RespFrame::Integer(_) | RespFrame::Null => 0,
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::outbox::channel;
+    use crate::resp_frame::RespFrame;
+
+    #[tokio::test]
+    async fn test_messages_stop_once_the_backlog_is_over_the_limit() {
+        let (outbox, mut inbox) = channel();
+        let message = RespFrame::BulkString(Bytes::from(vec![b'x'; 1000]));
+
+        assert!(outbox.send_message(message.clone(), 1500));
+        assert!(outbox.send_message(message.clone(), 1500));
+        // replies are never refused, but count towards the backlog
+        assert!(outbox.send(RespFrame::simple("OK")).is_ok());
+        assert!(!outbox.send_message(message.clone(), 1500));
+        outbox.overflowed().await;
+
+        inbox.recv().await.unwrap();
+        inbox.recv().await.unwrap();
+        assert!(outbox.send_message(message, 1500));
+        assert_eq!(inbox.try_recv(), Ok(RespFrame::simple("OK")));
+    }
+}
@@ -15,6 +15,11 @@ where
         b':' => parse_integer(stream).await,
         b'$' => parse_bulk_string(stream).await,
         b'*' => Box::pin(parse_array(stream)).await,
+        b'%' => Box::pin(parse_map(stream)).await,
+        b'>' => match Box::pin(parse_array(stream)).await? {
+            RespFrame::Array(items) => Ok(RespFrame::Push(items)),
+            other => Ok(other),
+        },
         _ => {
             println!(
                 "[parser] Received unkown prefix: {} (char : {})",
@@ -56,6 +61,30 @@ where
     Ok(RespFrame::Array(elements))
 }
 
+async fn parse_map<R>(stream: &mut BufReader<R>) -> std::io::Result<RespFrame>
+where
+    R: AsyncRead + Unpin,
+{
+    let line = read_line_as_string(stream).await?;
+
+    let length: usize = line.parse().map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Could not parse map length",
+        )
+    })?;
+
+    let mut entries = Vec::with_capacity(length);
+
+    for _ in 0..length {
+        let key = parse_frame(stream).await?;
+        let value = parse_frame(stream).await?;
+        entries.push((key, value));
+    }
+
+    Ok(RespFrame::Map(entries))
+}
+
 async fn parse_bulk_string<R>(stream: &mut BufReader<R>) -> std::io::Result<RespFrame>
 where
     R: AsyncRead + Unpin,
@@ -252,4 +281,38 @@ mod tests {
         let frame = result.unwrap();
         assert_eq!(frame, RespFrame::Null);
     }
+
+    #[tokio::test]
+    async fn test_parse_map() {
+        let input_bytes = b"%1\r\n+proto\r\n:3\r\n";
+        let mut reader = BufReader::new(&input_bytes[..]);
+
+        let result = parse_frame(&mut reader).await;
+
+        assert!(result.is_ok());
+        let frame = result.unwrap();
+
+        let expected = RespFrame::Map(vec![(
+            RespFrame::SimpleString("proto".to_string()),
+            RespFrame::Integer(3),
+        )]);
+        assert_eq!(frame, expected);
+    }
+
+    #[tokio::test]
+    async fn test_parse_push() {
+        let input_bytes = b">2\r\n$7\r\nmessage\r\n:1\r\n";
+        let mut reader = BufReader::new(&input_bytes[..]);
+
+        let result = parse_frame(&mut reader).await;
+
+        assert!(result.is_ok());
+        let frame = result.unwrap();
+
+        let expected = RespFrame::Push(vec![
+            RespFrame::BulkString(Bytes::from("message")),
+            RespFrame::Integer(1),
+        ]);
+        assert_eq!(frame, expected);
+    }
 }
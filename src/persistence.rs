@@ -0,0 +1,501 @@
+// RDB snapshots: SAVE and BGSAVE, the save points that start a BGSAVE on
+// their own, and loading the file at startup. Snapshots are written to a temp
+// file that is renamed over the old one, so a crash never leaves half a file.
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+
+use crate::config::{Config, SavePoint};
+use crate::rdb::{self, Entry, Snapshot, Value};
+use crate::resp_frame::RespFrame;
+use crate::scripting::Scripting;
+use crate::storage::{RedisValue, Storage};
+
+// after a failed save, save points wait this long before trying again
+const SAVE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// temp file names only need to be unique within this process
+static NEXT_TEMP_FILE: AtomicU64 = AtomicU64::new(0);
+
+/// What loading an RDB file added to the keyspace.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImportReport {
+    /// Keys now in the keyspace.
+    pub keys: usize,
+    /// Keys left out because their TTL ran out before they were loaded.
+    pub expired: usize,
+    pub functions: usize,
+    /// Keys of types resprs does not store, in file order.
+    pub skipped: Vec<SkippedKey>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkippedKey {
+    pub db: usize,
+    pub key: Bytes,
+    /// The redis type of the value, as TYPE would name it.
+    pub kind: &'static str,
+}
+
+impl fmt::Display for SkippedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} key '{}' in db {}",
+            self.kind,
+            String::from_utf8_lossy(&self.key),
+            self.db
+        )
+    }
+}
+
+pub struct Persistence {
+    path: PathBuf,
+    // CONFIG SET save changes these while the server runs
+    save_points: Mutex<Vec<SavePoint>>,
+    // shared with the thread writing a background save
+    status: Arc<Mutex<SaveStatus>>,
+}
+
+struct SaveStatus {
+    // unix time of the last successful save, or of startup
+    last_save: u64,
+    // `Storage::dirty` as of the data the last successful save contained
+    dirty_at_last_save: u64,
+    in_progress: bool,
+    last_ok: bool,
+    last_attempt: u64,
+    // BGSAVE SCHEDULE asked for a save once the running one finishes
+    scheduled: bool,
+}
+
+/// How BGSAVE went, for its reply.
+#[derive(Debug, PartialEq)]
+pub enum BgSave {
+    Started,
+    Scheduled,
+    AlreadyRunning,
+}
+
+impl Persistence {
+    pub fn new(config: &Config) -> Self {
+        Persistence {
+            path: config.rdb_path(),
+            save_points: Mutex::new(config.save.clone()),
+            status: Arc::new(Mutex::new(SaveStatus {
+                last_save: unix_time().as_secs(),
+                dirty_at_last_save: 0,
+                in_progress: false,
+                last_ok: true,
+                last_attempt: 0,
+                scheduled: false,
+            })),
+        }
+    }
+
+    pub fn save_points(&self) -> Vec<SavePoint> {
+        self.save_points.lock().unwrap().clone()
+    }
+
+    pub fn set_save_points(&self, save_points: Vec<SavePoint>) {
+        *self.save_points.lock().unwrap() = save_points;
+    }
+
+    /// Unix time in seconds of the last successful save.
+    pub fn last_save(&self) -> u64 {
+        self.status.lock().unwrap().last_save
+    }
+
+    pub fn changes_since_last_save(&self, storage: &Storage) -> u64 {
+        storage.dirty() - self.status.lock().unwrap().dirty_at_last_save
+    }
+
+    pub fn in_progress(&self) -> bool {
+        self.status.lock().unwrap().in_progress
+    }
+
+    pub fn last_save_ok(&self) -> bool {
+        self.status.lock().unwrap().last_ok
+    }
+
+    /// Writes a snapshot before returning, blocking every client meanwhile.
+    pub fn save(&self, storage: &Storage, scripting: &Scripting) -> io::Result<()> {
+        let dirty = storage.dirty();
+        let result = write_snapshot(&self.path, &snapshot(storage, scripting));
+        finish_save(&self.status, dirty, &result);
+        result
+    }
+
+    /// Takes a snapshot under the caller's storage lock and writes it on
+    /// another thread. With `schedule` a save that is already running makes
+    /// this one wait for it instead of failing.
+    pub fn bgsave(&self, storage: &Storage, scripting: &Scripting, schedule: bool) -> BgSave {
+        let mut status = self.status.lock().unwrap();
+        if status.in_progress {
+            if !schedule {
+                return BgSave::AlreadyRunning;
+            }
+            status.scheduled = true;
+            return BgSave::Scheduled;
+        }
+        status.in_progress = true;
+        status.scheduled = false;
+        drop(status);
+
+        let dirty = storage.dirty();
+        let snapshot = snapshot(storage, scripting);
+        let path = self.path.clone();
+        let status = self.status.clone();
+        std::thread::spawn(move || {
+            let result = write_snapshot(&path, &snapshot);
+            if let Err(e) = &result {
+                println!("Background saving error: {}", e);
+            }
+            finish_save(&status, dirty, &result);
+        });
+        BgSave::Started
+    }
+
+    /// Run once a second: starts a background save when a save point is
+    /// reached or one was scheduled.
+    pub fn cron(&self, storage: &Storage, scripting: &Scripting) {
+        let now = unix_time().as_secs();
+        let due = {
+            let status = self.status.lock().unwrap();
+            let changes = storage.dirty() - status.dirty_at_last_save;
+            let may_retry =
+                status.last_ok || now - status.last_attempt > SAVE_RETRY_DELAY.as_secs();
+            !status.in_progress
+                && (status.scheduled
+                    || may_retry
+                        && self.save_points().iter().any(|point| {
+                            changes >= point.changes && now - status.last_save > point.seconds
+                        }))
+        };
+        if due {
+            self.bgsave(storage, scripting, false);
+        }
+    }
+
+    /// Fills an empty keyspace from the snapshot file. A missing file is not
+    /// an error, there is simply nothing to load.
+    pub fn load(&self, storage: &mut Storage, scripting: &Scripting) -> io::Result<bool> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let snapshot = rdb::read_snapshot(&data).map_err(|message| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", self.path.display(), message),
+            )
+        })?;
+        let report = restore(&snapshot, storage, scripting)
+            .map_err(|message| io::Error::new(io::ErrorKind::InvalidData, message))?;
+        warn_skipped(&report);
+
+        // loading is not a change that needs saving
+        let mut status = self.status.lock().unwrap();
+        status.dirty_at_last_save = storage.dirty();
+        status.last_save = unix_time().as_secs();
+        Ok(true)
+    }
+}
+
+fn finish_save(status: &Mutex<SaveStatus>, dirty: u64, result: &io::Result<()>) {
+    let mut status = status.lock().unwrap();
+    status.in_progress = false;
+    status.last_attempt = unix_time().as_secs();
+    status.last_ok = result.is_ok();
+    if result.is_ok() {
+        status.last_save = status.last_attempt;
+        status.dirty_at_last_save = dirty;
+    }
+}
+
+fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// The dataset as it is now, every live key and function library.
+pub fn snapshot(storage: &Storage, scripting: &Scripting) -> Snapshot {
+    let clock = storage.clock();
+    let databases = storage
+        .databases()
+        .filter_map(|(index, keyspace)| {
+            let entries: Vec<Entry> = keyspace
+                .live_entries()
+                .map(|(key, value)| Entry {
+                    key: key.clone(),
+                    value: Value::String(value.data.clone()),
+                    expires_at_ms: value.expires_at.map(|at| clock.to_unix_ms(at)),
+                })
+                .collect();
+            (!entries.is_empty()).then_some((index, entries))
+        })
+        .collect();
+    let functions = scripting
+        .libraries()
+        .into_iter()
+        .map(|library| library.code.to_vec())
+        .collect();
+
+    Snapshot {
+        databases,
+        functions,
+        repl_stream_db: None,
+    }
+}
+
+/// Adds the contents of a snapshot to the keyspace and function libraries.
+/// Keys resprs has no type for are left out and listed in the report.
+pub fn restore(
+    snapshot: &Snapshot,
+    storage: &mut Storage,
+    scripting: &Scripting,
+) -> Result<ImportReport, String> {
+    let mut report = ImportReport::default();
+    for code in &snapshot.functions {
+        scripting.load_library(code, true).map_err(|e| match e {
+            RespFrame::Error(message) => message,
+            other => other.to_string(),
+        })?;
+        report.functions += 1;
+    }
+
+    let clock = storage.clock().clone();
+    for (index, entries) in &snapshot.databases {
+        let Some(index) = storage.database_index(*index as i64) else {
+            return Err(format!(
+                "the snapshot uses database {}, configure more databases",
+                index
+            ));
+        };
+        let keyspace = storage.db(index);
+        for entry in entries {
+            let Value::String(data) = &entry.value else {
+                report.skipped.push(SkippedKey {
+                    db: index,
+                    key: entry.key.clone(),
+                    kind: entry.value.type_name(),
+                });
+                continue;
+            };
+            let mut value = RedisValue::new(data.clone());
+            if let Some(at) = entry.expires_at_ms {
+                // keys that expired while the server was down are dropped
+                let Some(at) = clock.from_unix_ms(at) else {
+                    report.expired += 1;
+                    continue;
+                };
+                value.expires_at = Some(at);
+            }
+            keyspace.insert(entry.key.clone(), value);
+            report.keys += 1;
+        }
+    }
+    // nobody could have subscribed to the events loading caused
+    storage.take_events();
+    Ok(report)
+}
+
+// a startup load goes on without the keys it cannot hold, but says so
+pub fn warn_skipped(report: &ImportReport) {
+    for skipped in &report.skipped {
+        println!("Skipped {}: only strings are supported", skipped);
+    }
+}
+
+pub fn write_snapshot(path: &Path, snapshot: &Snapshot) -> io::Result<()> {
+    write_file(path, &encode_snapshot(snapshot))
+}
+
+/// A snapshot as the bytes of an RDB file.
+pub fn encode_snapshot(snapshot: &Snapshot) -> Vec<u8> {
+    rdb::write_snapshot(snapshot, unix_time().as_secs())
+}
+
+/// Replaces `path` with `data`. The temp file is synced before the rename, so
+/// the file on disk is always either the old one or the complete new one.
+pub fn write_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+    let temp_path = dir.join(format!(
+        "temp-{}-{}.tmp",
+        std::process::id(),
+        NEXT_TEMP_FILE.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let written = (|| {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+
+    use crate::config::Config;
+    use crate::resp_frame::RespFrame;
+    use crate::test_util::{TempDir, bulk, ok, run, setup_db};
+
+    #[test]
+    fn test_save_is_loaded_on_restart() {
+        let dir = TempDir::new("save");
+        let mut db = dir.server();
+        run(&mut db, &["SET", "k", "v"]);
+        run(&mut db, &["SET", "t", "v"]);
+        run(&mut db, &["EXPIRE", "t", "100"]);
+        run(&mut db, &["SELECT", "2"]);
+        run(&mut db, &["SET", "other", "1"]);
+        let library = "#!lua name=lib\nredis.register_function('one', function() return 1 end)";
+        run(&mut db, &["FUNCTION", "LOAD", library]);
+
+        assert_eq!(run(&mut db, &["SAVE"]), ok());
+        let RespFrame::BulkString(info) = run(&mut db, &["INFO", "persistence"]) else {
+            panic!("INFO should return a bulk string");
+        };
+        assert!(String::from_utf8_lossy(&info).contains("rdb_changes_since_last_save:0\r\n"));
+        assert!(dir.0.join("dump.rdb").exists());
+
+        let mut restarted = dir.server();
+        assert_eq!(run(&mut restarted, &["GET", "k"]), bulk("v"));
+        assert!(matches!(
+            run(&mut restarted, &["TTL", "t"]),
+            RespFrame::Integer(99 | 100)
+        ));
+        assert_eq!(
+            run(&mut restarted, &["FCALL", "one", "0"]),
+            RespFrame::Integer(1)
+        );
+        run(&mut restarted, &["SELECT", "2"]);
+        assert_eq!(run(&mut restarted, &["GET", "other"]), bulk("1"));
+        assert_eq!(run(&mut restarted, &["DBSIZE"]), RespFrame::Integer(1));
+    }
+
+    #[test]
+    fn test_import_redis_dump() {
+        use crate::rdb::{self, OPCODE_EOF, OPCODE_EXPIRETIME_MS, OPCODE_SELECTDB};
+        use crate::{Executor, ImportReport, SkippedKey};
+
+        let mut dump = b"REDIS0011".to_vec();
+        dump.push(OPCODE_SELECTDB);
+        rdb::write_length(&mut dump, 0);
+        for (value_type, key, value) in [(0, "k", "v"), (0, "old", "v")] {
+            if key == "old" {
+                dump.push(OPCODE_EXPIRETIME_MS);
+                dump.extend_from_slice(&1_000u64.to_le_bytes());
+            }
+            dump.push(value_type);
+            rdb::write_string(&mut dump, key.as_bytes());
+            rdb::write_string(&mut dump, value.as_bytes());
+        }
+        // a list of two elements
+        dump.push(1);
+        rdb::write_string(&mut dump, b"queue");
+        rdb::write_length(&mut dump, 2);
+        rdb::write_string(&mut dump, b"a");
+        rdb::write_string(&mut dump, b"b");
+        dump.push(OPCODE_EOF);
+        // redis writes a zero checksum with rdbchecksum off
+        dump.extend_from_slice(&[0; 8]);
+
+        let mut executor = Executor::new(Config::default());
+        assert_eq!(
+            executor.import_rdb(&dump),
+            Ok(ImportReport {
+                keys: 1,
+                expired: 1,
+                functions: 0,
+                skipped: vec![SkippedKey {
+                    db: 0,
+                    key: Bytes::from("queue"),
+                    kind: "list",
+                }],
+            })
+        );
+        let get = RespFrame::Array(vec![
+            RespFrame::BulkString(Bytes::from("GET")),
+            RespFrame::BulkString(Bytes::from("k")),
+        ]);
+        assert_eq!(executor.execute(get), bulk("v"));
+        assert!(executor.import_rdb(b"REDIS0011\x01").is_err());
+
+        // loading at startup carries on past the list as well
+        let dir = TempDir::new("import");
+        std::fs::create_dir_all(&dir.0).unwrap();
+        std::fs::write(dir.0.join("dump.rdb"), &dump).unwrap();
+        let mut db = dir.server();
+        assert_eq!(run(&mut db, &["GET", "k"]), bulk("v"));
+        assert_eq!(run(&mut db, &["DBSIZE"]), RespFrame::Integer(1));
+    }
+
+    #[test]
+    fn test_bgsave_and_lastsave() {
+        let dir = TempDir::new("bgsave");
+        let mut db = dir.server();
+        let RespFrame::Integer(started) = run(&mut db, &["LASTSAVE"]) else {
+            panic!("LASTSAVE should return an integer");
+        };
+        run(&mut db, &["SET", "k", "v"]);
+
+        assert_eq!(
+            run(&mut db, &["BGSAVE"]),
+            RespFrame::SimpleString("Background saving started".to_string())
+        );
+        while db.state.persistence.in_progress() {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let RespFrame::Integer(saved) = run(&mut db, &["LASTSAVE"]) else {
+            panic!("LASTSAVE should return an integer");
+        };
+        assert!(saved >= started);
+        assert_eq!(run(&mut dir.server(), &["GET", "k"]), bulk("v"));
+        assert_eq!(
+            run(&mut db, &["BGSAVE", "NOW"]),
+            RespFrame::Error("ERR syntax error".to_string())
+        );
+    }
+
+    #[test]
+    fn test_config_save_points() {
+        let mut db = setup_db();
+        assert_eq!(
+            run(&mut db, &["CONFIG", "GET", "save"]),
+            RespFrame::Array(vec![bulk("save"), bulk("")])
+        );
+        assert_eq!(
+            run(&mut db, &["CONFIG", "SET", "save", "900 1 60 100"]),
+            ok()
+        );
+        assert_eq!(
+            run(&mut db, &["CONFIG", "GET", "save"]),
+            RespFrame::Array(vec![bulk("save"), bulk("900 1 60 100")])
+        );
+        assert_eq!(
+            run(&mut db, &["CONFIG", "SET", "save", "900"]),
+            RespFrame::Error("ERR Invalid argument for CONFIG SET 'save'".to_string())
+        );
+    }
+}
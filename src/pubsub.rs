@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use bytes::Bytes;
+
+use crate::glob::glob_match;
+use crate::outbox::{Outbox, PUBSUB_OUTPUT_LIMIT};
+use crate::resp_frame::RespFrame;
+use crate::slot::key_hash_slot;
+use crate::tracking::INVALIDATE_CHANNEL;
+
+// a subscribed connection: where to deliver and whether it speaks RESP3
+#[derive(Clone)]
+struct Subscriber {
+    outbox: Outbox,
+    resp3: bool,
+}
+
+impl Subscriber {
+    fn deliver(&self, items: Vec<RespFrame>) -> bool {
+        self.outbox
+            .send_message(push_frame(items, self.resp3), PUBSUB_OUTPUT_LIMIT)
+    }
+}
+
+// Pub/Sub messages are out of band: RESP3 clients get push frames, RESP2
+// clients plain arrays.
+pub fn push_frame(items: Vec<RespFrame>, resp3: bool) -> RespFrame {
+    if resp3 {
+        RespFrame::Push(items)
+    } else {
+        RespFrame::Array(items)
+    }
+}
+
+type Subscribers = HashMap<Bytes, HashMap<u64, Subscriber>>;
+
+#[derive(Default)]
+struct Registry {
+    // channel or pattern -> client id -> subscriber
+    channels: Subscribers,
+    patterns: Subscribers,
+    // shard channels are grouped by hash slot, like keys
+    shard_channels: HashMap<u16, Subscribers>,
+}
+
+// The channel registry shared by all connections. It has its own lock so
+// PUBLISH never waits on the keyspace.
+#[derive(Default)]
+pub struct PubSub {
+    registry: Mutex<Registry>,
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self, client_id: u64, channel: Bytes, outbox: Outbox, resp3: bool) {
+        let mut registry = self.registry.lock().unwrap();
+        registry
+            .channels
+            .entry(channel)
+            .or_default()
+            .insert(client_id, Subscriber { outbox, resp3 });
+    }
+
+    pub fn unsubscribe(&self, client_id: u64, channel: &Bytes) {
+        let mut registry = self.registry.lock().unwrap();
+        remove_subscriber(&mut registry.channels, client_id, channel);
+    }
+
+    pub fn psubscribe(&self, client_id: u64, pattern: Bytes, outbox: Outbox, resp3: bool) {
+        let mut registry = self.registry.lock().unwrap();
+        registry
+            .patterns
+            .entry(pattern)
+            .or_default()
+            .insert(client_id, Subscriber { outbox, resp3 });
+    }
+
+    pub fn punsubscribe(&self, client_id: u64, pattern: &Bytes) {
+        let mut registry = self.registry.lock().unwrap();
+        remove_subscriber(&mut registry.patterns, client_id, pattern);
+    }
+
+    pub fn ssubscribe(&self, client_id: u64, channel: Bytes, outbox: Outbox, resp3: bool) {
+        let mut registry = self.registry.lock().unwrap();
+        registry
+            .shard_channels
+            .entry(key_hash_slot(&channel))
+            .or_default()
+            .entry(channel)
+            .or_default()
+            .insert(client_id, Subscriber { outbox, resp3 });
+    }
+
+    pub fn sunsubscribe(&self, client_id: u64, channel: &Bytes) {
+        let mut registry = self.registry.lock().unwrap();
+        let slot = key_hash_slot(channel);
+        if let Some(channels) = registry.shard_channels.get_mut(&slot) {
+            remove_subscriber(channels, client_id, channel);
+            if channels.is_empty() {
+                registry.shard_channels.remove(&slot);
+            }
+        }
+    }
+
+    /// Delivers a message to the subscribers of a shard channel. Pattern
+    /// subscriptions never see shard messages.
+    pub fn spublish(&self, channel: &Bytes, message: &Bytes) -> usize {
+        let registry = self.registry.lock().unwrap();
+        let Some(subscribers) = registry
+            .shard_channels
+            .get(&key_hash_slot(channel))
+            .and_then(|channels| channels.get(channel))
+        else {
+            return 0;
+        };
+
+        subscribers
+            .values()
+            .filter(|subscriber| {
+                subscriber.deliver(vec![
+                    bulk("smessage"),
+                    RespFrame::BulkString(channel.clone()),
+                    RespFrame::BulkString(message.clone()),
+                ])
+            })
+            .count()
+    }
+
+    pub fn shard_channels(&self, pattern: Option<&[u8]>) -> Vec<Bytes> {
+        let registry = self.registry.lock().unwrap();
+        registry
+            .shard_channels
+            .values()
+            .flat_map(|channels| channels.keys())
+            .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel)))
+            .cloned()
+            .collect()
+    }
+
+    pub fn shard_subscriber_count(&self, channel: &Bytes) -> usize {
+        let registry = self.registry.lock().unwrap();
+        registry
+            .shard_channels
+            .get(&key_hash_slot(channel))
+            .and_then(|channels| channels.get(channel))
+            .map_or(0, |subscribers| subscribers.len())
+    }
+
+    /// Delivers a message to every subscriber of the channel and of every
+    /// matching pattern, returning how many received it.
+    pub fn publish(&self, channel: &Bytes, message: &Bytes) -> usize {
+        let registry = self.registry.lock().unwrap();
+        let mut receivers = 0;
+
+        if let Some(subscribers) = registry.channels.get(channel) {
+            for subscriber in subscribers.values() {
+                let items = vec![
+                    bulk("message"),
+                    RespFrame::BulkString(channel.clone()),
+                    RespFrame::BulkString(message.clone()),
+                ];
+                if subscriber.deliver(items) {
+                    receivers += 1;
+                }
+            }
+        }
+
+        for (pattern, subscribers) in &registry.patterns {
+            if !glob_match(pattern, channel) {
+                continue;
+            }
+            for subscriber in subscribers.values() {
+                let items = vec![
+                    bulk("pmessage"),
+                    RespFrame::BulkString(pattern.clone()),
+                    RespFrame::BulkString(channel.clone()),
+                    RespFrame::BulkString(message.clone()),
+                ];
+                if subscriber.deliver(items) {
+                    receivers += 1;
+                }
+            }
+        }
+
+        receivers
+    }
+
+    /// Active channels, optionally filtered by a glob pattern.
+    pub fn channels(&self, pattern: Option<&[u8]>) -> Vec<Bytes> {
+        let registry = self.registry.lock().unwrap();
+        registry
+            .channels
+            .keys()
+            .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel)))
+            .cloned()
+            .collect()
+    }
+
+    pub fn subscriber_count(&self, channel: &Bytes) -> usize {
+        let registry = self.registry.lock().unwrap();
+        registry
+            .channels
+            .get(channel)
+            .map_or(0, |subscribers| subscribers.len())
+    }
+
+    /// Number of distinct patterns with at least one subscriber.
+    pub fn pattern_count(&self) -> usize {
+        self.registry.lock().unwrap().patterns.len()
+    }
+
+    /// Delivers a CLIENT TRACKING invalidation to one client, if it is
+    /// subscribed to __redis__:invalidate.
+    pub fn invalidate(&self, client_id: u64, keys: RespFrame) {
+        let registry = self.registry.lock().unwrap();
+        let Some(subscriber) = registry
+            .channels
+            .get(INVALIDATE_CHANNEL.as_bytes())
+            .and_then(|subscribers| subscribers.get(&client_id))
+        else {
+            return;
+        };
+
+        let items = if subscriber.resp3 {
+            vec![bulk("invalidate"), keys]
+        } else {
+            vec![bulk("message"), bulk(INVALIDATE_CHANNEL), keys]
+        };
+        subscriber.deliver(items);
+    }
+
+    /// Switches the reply format of an existing subscriber after HELLO.
+    pub fn set_protocol(&self, client_id: u64, resp3: bool) {
+        let mut registry = self.registry.lock().unwrap();
+        let Registry {
+            channels,
+            patterns,
+            shard_channels,
+        } = &mut *registry;
+
+        let all = channels.values_mut().chain(patterns.values_mut()).chain(
+            shard_channels
+                .values_mut()
+                .flat_map(|channels| channels.values_mut()),
+        );
+        for subscribers in all {
+            if let Some(subscriber) = subscribers.get_mut(&client_id) {
+                subscriber.resp3 = resp3;
+            }
+        }
+    }
+}
+
+fn remove_subscriber(map: &mut Subscribers, client_id: u64, name: &Bytes) {
+    if let Some(subscribers) = map.get_mut(name) {
+        subscribers.remove(&client_id);
+        if subscribers.is_empty() {
+            map.remove(name);
+        }
+    }
+}
+
+fn bulk(s: &'static str) -> RespFrame {
+    RespFrame::BulkString(Bytes::from_static(s.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::outbox::channel;
+    use bytes::Bytes;
+
+    use crate::pubsub::PubSub;
+    use crate::resp_frame::RespFrame;
+
+    fn bulk(s: &str) -> RespFrame {
+        RespFrame::BulkString(Bytes::copy_from_slice(s.as_bytes()))
+    }
+
+    #[test]
+    fn test_publish_reaches_channel_and_pattern_subscribers() {
+        let pubsub = PubSub::new();
+        let (tx, mut rx) = channel();
+        let (ptx, mut prx) = channel();
+
+        pubsub.subscribe(1, Bytes::from("news.sport"), tx, false);
+        pubsub.psubscribe(2, Bytes::from("news.*"), ptx, true);
+
+        assert_eq!(
+            pubsub.publish(&Bytes::from("news.sport"), &Bytes::from("goal")),
+            2
+        );
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            RespFrame::Array(vec![bulk("message"), bulk("news.sport"), bulk("goal")])
+        );
+        assert_eq!(
+            prx.try_recv().unwrap(),
+            RespFrame::Push(vec![
+                bulk("pmessage"),
+                bulk("news.*"),
+                bulk("news.sport"),
+                bulk("goal")
+            ])
+        );
+    }
+
+    #[test]
+    fn test_unsubscribe_removes_empty_channels() {
+        let pubsub = PubSub::new();
+        let (tx, _rx) = channel();
+        let channel = Bytes::from("chan");
+
+        pubsub.subscribe(1, channel.clone(), tx, false);
+        assert_eq!(pubsub.channels(None), vec![channel.clone()]);
+        assert_eq!(pubsub.subscriber_count(&channel), 1);
+
+        pubsub.unsubscribe(1, &channel);
+        assert!(pubsub.channels(None).is_empty());
+        assert_eq!(pubsub.publish(&channel, &Bytes::from("x")), 0);
+    }
+
+    #[test]
+    fn test_shard_channels_are_separate_from_channels() {
+        let pubsub = PubSub::new();
+        let (tx, mut rx) = channel();
+        let channel = Bytes::from("orders");
+
+        pubsub.ssubscribe(1, channel.clone(), tx.clone(), false);
+        pubsub.psubscribe(1, Bytes::from("*"), tx, false);
+
+        assert_eq!(pubsub.spublish(&channel, &Bytes::from("x")), 1);
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            RespFrame::Array(vec![bulk("smessage"), bulk("orders"), bulk("x")])
+        );
+        assert!(rx.try_recv().is_err());
+        assert!(pubsub.channels(None).is_empty());
+        assert_eq!(pubsub.shard_channels(None), vec![channel.clone()]);
+
+        pubsub.sunsubscribe(1, &channel);
+        assert_eq!(pubsub.shard_subscriber_count(&channel), 0);
+    }
+}
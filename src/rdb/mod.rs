@@ -0,0 +1,530 @@
+// The redis RDB format: length and string encodings, the version and CRC64
+// footer that DUMP style payloads carry, and whole snapshot files. Snapshots
+// are plain data here, persistence.rs moves them in and out of the keyspace.
+mod value;
+
+use bytes::Bytes;
+use crc::{CRC_64_REDIS, Crc};
+
+pub use value::Value;
+
+// the RDB version redis 7.0 writes
+pub const RDB_VERSION: u16 = 10;
+// redis 7.4 writes version 12, whose strings and opcodes we can still read
+const MAX_LOADABLE_VERSION: u16 = 12;
+
+const MAGIC: &[u8] = b"REDIS";
+
+pub const OPCODE_SLOT_INFO: u8 = 244;
+pub const OPCODE_FUNCTION2: u8 = 245;
+pub const OPCODE_FUNCTION_PRE_GA: u8 = 246;
+pub const OPCODE_MODULE_AUX: u8 = 247;
+pub const OPCODE_IDLE: u8 = 248;
+pub const OPCODE_FREQ: u8 = 249;
+pub const OPCODE_AUX: u8 = 250;
+pub const OPCODE_RESIZEDB: u8 = 251;
+pub const OPCODE_EXPIRETIME_MS: u8 = 252;
+pub const OPCODE_EXPIRETIME: u8 = 253;
+pub const OPCODE_SELECTDB: u8 = 254;
+pub const OPCODE_EOF: u8 = 255;
+
+pub const TYPE_STRING: u8 = 0;
+
+const CRC64: Crc<u64> = Crc::<u64>::new(&CRC_64_REDIS);
+
+// the top two bits of the first length byte select the encoding
+const LEN_6BIT: u8 = 0;
+const LEN_14BIT: u8 = 1;
+const LEN_32BIT: u8 = 0x80;
+const LEN_64BIT: u8 = 0x81;
+const ENCODED: u8 = 3;
+
+const ENC_INT8: u8 = 0;
+const ENC_INT16: u8 = 1;
+const ENC_INT32: u8 = 2;
+const ENC_LZF: u8 = 3;
+// how many times longer than its input LZF output can be
+const LZF_MAX_EXPANSION: usize = 88;
+
+pub fn crc64(data: &[u8]) -> u64 {
+    CRC64.checksum(data)
+}
+
+pub fn write_length(out: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        out.push((LEN_6BIT << 6) | len as u8);
+    } else if len < 1 << 14 {
+        out.push((LEN_14BIT << 6) | (len >> 8) as u8);
+        out.push(len as u8);
+    } else if len <= u32::MAX as u64 {
+        out.push(LEN_32BIT);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        out.push(LEN_64BIT);
+        out.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+pub fn write_string(out: &mut Vec<u8>, s: &[u8]) {
+    write_length(out, s.len() as u64);
+    out.extend_from_slice(s);
+}
+
+/// Reads a string in any of the encodings redis writes.
+pub fn read_string(input: &mut &[u8]) -> Option<Vec<u8>> {
+    match read_length_or_encoding(input)? {
+        Length::Plain(len) => {
+            let len = usize::try_from(len).ok()?;
+            Some(take(input, len)?.to_vec())
+        }
+        Length::Encoded(ENC_INT8) => Some((take(input, 1)?[0] as i8).to_string().into_bytes()),
+        Length::Encoded(ENC_INT16) => {
+            let bytes = take(input, 2)?.try_into().ok()?;
+            Some(i16::from_le_bytes(bytes).to_string().into_bytes())
+        }
+        Length::Encoded(ENC_INT32) => {
+            let bytes = take(input, 4)?.try_into().ok()?;
+            Some(i32::from_le_bytes(bytes).to_string().into_bytes())
+        }
+        Length::Encoded(ENC_LZF) => {
+            let compressed_len = usize::try_from(read_length(input)?).ok()?;
+            let len = usize::try_from(read_length(input)?).ok()?;
+            lzf_decompress(take(input, compressed_len)?, len)
+        }
+        Length::Encoded(_) => None,
+    }
+}
+
+pub fn read_length(input: &mut &[u8]) -> Option<u64> {
+    match read_length_or_encoding(input)? {
+        Length::Plain(len) => Some(len),
+        Length::Encoded(_) => None,
+    }
+}
+
+// redis compresses strings longer than 20 bytes with LZF. A control byte
+// below 32 starts a run of that many plus one literal bytes, anything else
+// copies `length + 2` bytes from `offset + 1` back in the output.
+// The uncompressed length comes from the payload, so it is only trusted as
+// far as the input could expand: a 3 byte back reference copies at most 264
+// bytes.
+fn lzf_decompress(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    if len > input.len().saturating_mul(LZF_MAX_EXPANSION) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(len);
+    let mut input = input.iter().copied();
+    while let Some(control) = input.next() {
+        let control = control as usize;
+        if control < 32 {
+            if out.len() + control >= len {
+                return None;
+            }
+            for _ in 0..=control {
+                out.push(input.next()?);
+            }
+            continue;
+        }
+        let mut run = control >> 5;
+        if run == 7 {
+            run += input.next()? as usize;
+        }
+        let offset = ((control & 0x1f) << 8) + input.next()? as usize + 1;
+        let start = out.len().checked_sub(offset)?;
+        if out.len() + run + 2 > len {
+            return None;
+        }
+        // the copy may overlap what it is appending, so go byte by byte
+        for index in start..start + run + 2 {
+            out.push(out[index]);
+        }
+    }
+    (out.len() == len).then_some(out)
+}
+
+/// Appends the 2 byte RDB version and the CRC64 of everything before it.
+pub fn write_footer(out: &mut Vec<u8>) {
+    out.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let crc = crc64(out);
+    out.extend_from_slice(&crc.to_le_bytes());
+}
+
+/// Checks the footer of a payload, returning the body before it. Fails if
+/// the payload is too short, comes from an RDB version newer than we can
+/// read or has a bad checksum.
+pub fn verify_footer(payload: &[u8]) -> Option<&[u8]> {
+    let body_len = payload.len().checked_sub(10)?;
+    let (with_version, crc) = payload.split_at(body_len + 2);
+    let version = u16::from_le_bytes(with_version[body_len..].try_into().ok()?);
+    let crc = u64::from_le_bytes(crc.try_into().ok()?);
+    if version > MAX_LOADABLE_VERSION || crc64(with_version) != crc {
+        return None;
+    }
+    Some(&payload[..body_len])
+}
+
+/// The payload DUMP replies with for a string value.
+pub fn dump_string(value: &[u8]) -> Vec<u8> {
+    let mut payload = vec![TYPE_STRING];
+    write_string(&mut payload, value);
+    write_footer(&mut payload);
+    payload
+}
+
+/// Decodes the value in a DUMP payload whose footer `verify_footer` has
+/// already stripped.
+pub fn read_dumped_value(mut body: &[u8]) -> Option<Value> {
+    let value_type = take(&mut body, 1)?[0];
+    let value = value::read_value(&mut body, value_type).ok()?;
+    body.is_empty().then_some(value)
+}
+
+/// The contents of an RDB file.
+#[derive(Debug, Default, PartialEq)]
+pub struct Snapshot {
+    // non-empty databases by index
+    pub databases: Vec<(usize, Vec<Entry>)>,
+    // the code of every function library
+    pub functions: Vec<Vec<u8>>,
+    // the database a replica's stream from its primary continues in, for
+    // the snapshot of a full sync
+    pub repl_stream_db: Option<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub key: Bytes,
+    pub value: Value,
+    // unix time in milliseconds
+    pub expires_at_ms: Option<u64>,
+}
+
+/// Encodes a snapshot as an RDB file, the way redis 7.0 writes one but
+/// without compressing strings.
+pub fn write_snapshot(snapshot: &Snapshot, ctime: u64) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(format!("{:04}", RDB_VERSION).as_bytes());
+
+    let repl_stream_db = snapshot
+        .repl_stream_db
+        .map(|db| ("repl-stream-db", db.to_string()));
+    for (name, value) in [
+        ("redis-ver", "7.0.0".to_string()),
+        ("redis-bits", "64".to_string()),
+        ("ctime", ctime.to_string()),
+    ]
+    .into_iter()
+    .chain(repl_stream_db)
+    {
+        out.push(OPCODE_AUX);
+        write_string(&mut out, name.as_bytes());
+        write_string(&mut out, value.as_bytes());
+    }
+
+    for code in &snapshot.functions {
+        out.push(OPCODE_FUNCTION2);
+        write_string(&mut out, code);
+    }
+
+    for (index, entries) in &snapshot.databases {
+        out.push(OPCODE_SELECTDB);
+        write_length(&mut out, *index as u64);
+        out.push(OPCODE_RESIZEDB);
+        write_length(&mut out, entries.len() as u64);
+        let expires = entries.iter().filter(|entry| entry.expires_at_ms.is_some());
+        write_length(&mut out, expires.count() as u64);
+
+        // resprs only ever stores strings
+        let entries = entries.iter().filter_map(|entry| match &entry.value {
+            Value::String(value) => Some((entry, value)),
+            _ => None,
+        });
+        for (entry, value) in entries {
+            if let Some(at) = entry.expires_at_ms {
+                out.push(OPCODE_EXPIRETIME_MS);
+                out.extend_from_slice(&at.to_le_bytes());
+            }
+            out.push(TYPE_STRING);
+            write_string(&mut out, &entry.key);
+            write_string(&mut out, value);
+        }
+    }
+
+    out.push(OPCODE_EOF);
+    let crc = crc64(&out);
+    out.extend_from_slice(&crc.to_le_bytes());
+    out
+}
+
+/// Decodes an RDB file written by resprs or redis 2.6 and later. Values of
+/// every type are decoded, apart from streams and module values, which are
+/// only stepped over. Module types from before redis 5 fail the whole file.
+pub fn read_snapshot(data: &[u8]) -> Result<Snapshot, String> {
+    let truncated = || "unexpected end of file".to_string();
+
+    let version = data
+        .strip_prefix(MAGIC)
+        .and_then(|rest| rest.get(..4))
+        .and_then(|digits| std::str::from_utf8(digits).ok())
+        .and_then(|digits| digits.parse::<u16>().ok())
+        .ok_or("not an RDB file")?;
+    if version > MAX_LOADABLE_VERSION {
+        return Err(format!("can't handle RDB format version {}", version));
+    }
+
+    let mut input = &data[MAGIC.len() + 4..];
+    let mut snapshot = Snapshot::default();
+    let mut db_index = 0;
+    let mut expires_at_ms = None;
+
+    loop {
+        let opcode = take(&mut input, 1).ok_or_else(truncated)?[0];
+        match opcode {
+            OPCODE_EOF => break,
+            OPCODE_AUX => {
+                let name = read_string(&mut input).ok_or_else(truncated)?;
+                let value = read_string(&mut input).ok_or_else(truncated)?;
+                if name == b"repl-stream-db" {
+                    snapshot.repl_stream_db = std::str::from_utf8(&value)
+                        .ok()
+                        .and_then(|db| db.parse().ok());
+                }
+            }
+            OPCODE_SELECTDB => {
+                let index = read_length(&mut input).ok_or_else(truncated)?;
+                db_index = usize::try_from(index).map_err(|_| "bad database index")?;
+            }
+            OPCODE_RESIZEDB => {
+                read_length(&mut input).ok_or_else(truncated)?;
+                read_length(&mut input).ok_or_else(truncated)?;
+            }
+            OPCODE_SLOT_INFO => {
+                for _ in 0..3 {
+                    read_length(&mut input).ok_or_else(truncated)?;
+                }
+            }
+            OPCODE_EXPIRETIME_MS => {
+                let bytes = take(&mut input, 8).ok_or_else(truncated)?;
+                expires_at_ms = Some(u64::from_le_bytes(bytes.try_into().unwrap()));
+            }
+            OPCODE_EXPIRETIME => {
+                let bytes = take(&mut input, 4).ok_or_else(truncated)?;
+                expires_at_ms = Some(u32::from_le_bytes(bytes.try_into().unwrap()) as u64 * 1000);
+            }
+            // LRU and LFU hints, resprs keeps its own
+            OPCODE_IDLE => {
+                read_length(&mut input).ok_or_else(truncated)?;
+            }
+            OPCODE_FREQ => {
+                take(&mut input, 1).ok_or_else(truncated)?;
+            }
+            OPCODE_FUNCTION2 => {
+                let code = read_string(&mut input).ok_or_else(truncated)?;
+                snapshot.functions.push(code);
+            }
+            OPCODE_FUNCTION_PRE_GA => return Err("pre-release function format".to_string()),
+            // module ID, when the module saved it, then its data
+            OPCODE_MODULE_AUX => {
+                read_length(&mut input).ok_or_else(truncated)?;
+                read_length(&mut input).ok_or_else(truncated)?;
+                read_length(&mut input).ok_or_else(truncated)?;
+                value::skip_module_data(&mut input).ok_or_else(truncated)?;
+            }
+            value_type => {
+                let key = read_string(&mut input).ok_or_else(truncated)?;
+                let value = value::read_value(&mut input, value_type).map_err(|message| {
+                    format!("{} for key '{}'", message, String::from_utf8_lossy(&key))
+                })?;
+                let entry = Entry {
+                    key: Bytes::from(key),
+                    value,
+                    expires_at_ms: expires_at_ms.take(),
+                };
+                match snapshot.databases.last_mut() {
+                    Some((index, entries)) if *index == db_index => entries.push(entry),
+                    _ => snapshot.databases.push((db_index, vec![entry])),
+                }
+            }
+        }
+    }
+
+    // version 5 added the checksum, redis writes zero when it is turned off
+    if version >= 5 {
+        let body_len = data.len() - input.len();
+        let crc = take(&mut input, 8).ok_or_else(truncated)?;
+        let crc = u64::from_le_bytes(crc.try_into().unwrap());
+        if crc != 0 && crc != crc64(&data[..body_len]) {
+            return Err("wrong RDB checksum".to_string());
+        }
+    }
+    Ok(snapshot)
+}
+
+enum Length {
+    Plain(u64),
+    Encoded(u8),
+}
+
+fn read_length_or_encoding(input: &mut &[u8]) -> Option<Length> {
+    let first = take(input, 1)?[0];
+    Some(match first >> 6 {
+        LEN_6BIT => Length::Plain((first & 0x3f) as u64),
+        LEN_14BIT => Length::Plain((((first & 0x3f) as u64) << 8) | take(input, 1)?[0] as u64),
+        ENCODED => Length::Encoded(first & 0x3f),
+        _ if first == LEN_32BIT => {
+            Length::Plain(u32::from_be_bytes(take(input, 4)?.try_into().ok()?) as u64)
+        }
+        _ if first == LEN_64BIT => {
+            Length::Plain(u64::from_be_bytes(take(input, 8)?.try_into().ok()?))
+        }
+        _ => return None,
+    })
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if input.len() < len {
+        return None;
+    }
+    let (taken, rest) = input.split_at(len);
+    *input = rest;
+    Some(taken)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::rdb::{
+        Entry, Length, Snapshot, Value, crc64, read_length_or_encoding, read_snapshot, read_string,
+        verify_footer, write_footer, write_length, write_snapshot, write_string,
+    };
+
+    #[test]
+    fn test_crc64_matches_redis() {
+        assert_eq!(crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+    }
+
+    #[test]
+    fn test_lengths_round_trip() {
+        for len in [
+            0,
+            63,
+            64,
+            16383,
+            16384,
+            u32::MAX as u64,
+            u32::MAX as u64 + 1,
+        ] {
+            let mut out = Vec::new();
+            write_length(&mut out, len);
+            let mut input = out.as_slice();
+            assert!(
+                matches!(read_length_or_encoding(&mut input), Some(Length::Plain(n)) if n == len)
+            );
+            assert!(input.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_strings_and_int_encodings() {
+        let mut out = Vec::new();
+        write_string(&mut out, b"hello");
+        // an int16 encoded -2
+        out.extend_from_slice(&[0xc1, 0xfe, 0xff]);
+
+        let mut input = out.as_slice();
+        assert_eq!(read_string(&mut input), Some(b"hello".to_vec()));
+        assert_eq!(read_string(&mut input), Some(b"-2".to_vec()));
+        assert_eq!(read_string(&mut input), None);
+    }
+
+    #[test]
+    fn test_footer_detects_corruption() {
+        let mut payload = b"body".to_vec();
+        write_footer(&mut payload);
+        assert_eq!(verify_footer(&payload), Some(&b"body"[..]));
+
+        payload[0] = b'B';
+        assert_eq!(verify_footer(&payload), None);
+        assert_eq!(verify_footer(b"short"), None);
+    }
+
+    #[test]
+    fn test_lzf_strings() {
+        // "a" as a literal, then 9 bytes copied from one back
+        let mut input = &[0xc3, 0x05, 0x0a, 0x00, b'a', 0xe0, 0x00, 0x00][..];
+        assert_eq!(read_string(&mut input), Some(b"aaaaaaaaaa".to_vec()));
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn test_lzf_lengths_are_not_trusted() {
+        // the same string claiming to be 2^62 bytes long
+        let mut huge = vec![0xc3, 0x05];
+        write_length(&mut huge, 1 << 62);
+        huge.extend_from_slice(&[0x00, b'a', 0xe0, 0x00, 0x00]);
+        assert_eq!(read_string(&mut &huge[..]), None);
+
+        // or a little shorter or longer than it is
+        for len in [9, 11] {
+            let mut input = &[0xc3, 0x05, len, 0x00, b'a', 0xe0, 0x00, 0x00][..];
+            assert_eq!(read_string(&mut input), None);
+        }
+    }
+
+    #[test]
+    fn test_snapshots_round_trip() {
+        let snapshot = Snapshot {
+            databases: vec![
+                (
+                    0,
+                    vec![Entry {
+                        key: Bytes::from("k"),
+                        value: Value::String(Bytes::from("v")),
+                        expires_at_ms: Some(1_700_000_000_000),
+                    }],
+                ),
+                (
+                    3,
+                    vec![Entry {
+                        key: Bytes::from("n"),
+                        value: Value::String(Bytes::from("12")),
+                        expires_at_ms: None,
+                    }],
+                ),
+            ],
+            functions: vec![b"#!lua name=lib".to_vec()],
+            repl_stream_db: Some(3),
+        };
+        let file = write_snapshot(&snapshot, 1_700_000_000);
+        assert!(file.starts_with(b"REDIS0010"));
+        assert_eq!(read_snapshot(&file), Ok(snapshot));
+    }
+
+    #[test]
+    fn test_snapshot_errors() {
+        let mut file = write_snapshot(&Snapshot::default(), 0);
+        assert_eq!(
+            read_snapshot(&file[..file.len() - 3]),
+            Err("unexpected end of file".to_string())
+        );
+        assert_eq!(
+            read_snapshot(b"NOPE0010"),
+            Err("not an RDB file".to_string())
+        );
+
+        let last = file.len() - 1;
+        file[last] ^= 1;
+        assert_eq!(read_snapshot(&file), Err("wrong RDB checksum".to_string()));
+
+        // a module value from before redis 5
+        let mut module = b"REDIS0010".to_vec();
+        module.push(6);
+        write_string(&mut module, b"bloom");
+        assert_eq!(
+            read_snapshot(&module),
+            Err("unsupported value type 6 for key 'bloom'".to_string())
+        );
+    }
+}
@@ -0,0 +1,542 @@
+// Values of every type redis keeps, decoded from the encodings RDB files have
+// used over the years: plain, zipmap, ziplist, intset, quicklist and
+// listpack. resprs only stores strings, the other types are decoded so an
+// import can name what it skips and carry on after it.
+use bytes::Bytes;
+
+use crate::rdb::{TYPE_STRING, read_length, read_string, take};
+
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_MODULE_2: u8 = 7;
+const TYPE_HASH_ZIPMAP: u8 = 9;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_SET_LISTPACK: u8 = 20;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+// quicklist 2 nodes hold either one big element or a listpack of small ones
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+const QUICKLIST_NODE_PACKED: u64 = 2;
+
+// the opcodes module values are serialised with
+const MODULE_OPCODE_EOF: u64 = 0;
+const MODULE_OPCODE_SINT: u64 = 1;
+const MODULE_OPCODE_UINT: u64 = 2;
+const MODULE_OPCODE_FLOAT: u64 = 3;
+const MODULE_OPCODE_DOUBLE: u64 = 4;
+const MODULE_OPCODE_STRING: u64 = 5;
+
+const END: u8 = 0xff;
+
+/// A value read from an RDB file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(Bytes),
+    List(Vec<Bytes>),
+    Set(Vec<Bytes>),
+    Hash(Vec<(Bytes, Bytes)>),
+    SortedSet(Vec<(Bytes, f64)>),
+    // streams and module values are stepped over without decoding them
+    Stream,
+    Module,
+}
+
+impl Value {
+    /// The name TYPE replies with for keys holding this value.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::Hash(_) => "hash",
+            Value::SortedSet(_) => "zset",
+            Value::Stream => "stream",
+            Value::Module => "module",
+        }
+    }
+}
+
+/// Reads a value of RDB type `value_type`. Errors name the type when it is
+/// unknown or its encoding is damaged.
+pub fn read_value(input: &mut &[u8], value_type: u8) -> Result<Value, String> {
+    let corrupt = || format!("corrupt value of type {}", value_type);
+    let value = match value_type {
+        TYPE_STRING => Some(Value::String(string(input).ok_or_else(corrupt)?)),
+        TYPE_LIST => strings(input).map(Value::List),
+        TYPE_SET => strings(input).map(Value::Set),
+        TYPE_ZSET | TYPE_ZSET_2 => sorted_set(input, value_type == TYPE_ZSET_2),
+        TYPE_HASH => hash(input),
+        TYPE_MODULE_2 => skip_module_value(input).map(|_| Value::Module),
+        TYPE_HASH_ZIPMAP => string(input)
+            .and_then(|blob| zipmap(&blob))
+            .map(Value::Hash),
+        TYPE_LIST_ZIPLIST => string(input)
+            .and_then(|blob| ziplist(&blob))
+            .map(Value::List),
+        TYPE_SET_INTSET => string(input).and_then(|blob| intset(&blob)).map(Value::Set),
+        TYPE_ZSET_ZIPLIST => string(input)
+            .and_then(|blob| ziplist(&blob))
+            .and_then(scored_pairs)
+            .map(Value::SortedSet),
+        TYPE_HASH_ZIPLIST => string(input)
+            .and_then(|blob| ziplist(&blob))
+            .and_then(pairs)
+            .map(Value::Hash),
+        TYPE_LIST_QUICKLIST => quicklist(input).map(Value::List),
+        TYPE_LIST_QUICKLIST_2 => quicklist_2(input).map(Value::List),
+        TYPE_HASH_LISTPACK => string(input)
+            .and_then(|blob| listpack(&blob))
+            .and_then(pairs)
+            .map(Value::Hash),
+        TYPE_ZSET_LISTPACK => string(input)
+            .and_then(|blob| listpack(&blob))
+            .and_then(scored_pairs)
+            .map(Value::SortedSet),
+        TYPE_SET_LISTPACK => string(input)
+            .and_then(|blob| listpack(&blob))
+            .map(Value::Set),
+        TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+            skip_stream(input, value_type).map(|_| Value::Stream)
+        }
+        _ => return Err(format!("unsupported value type {}", value_type)),
+    };
+    value.ok_or_else(corrupt)
+}
+
+/// Steps over the data a module saved, up to its EOF opcode. Module values
+/// and module aux fields both end with it.
+pub fn skip_module_data(input: &mut &[u8]) -> Option<()> {
+    loop {
+        match read_length(input)? {
+            MODULE_OPCODE_EOF => return Some(()),
+            MODULE_OPCODE_SINT | MODULE_OPCODE_UINT => {
+                read_length(input)?;
+            }
+            MODULE_OPCODE_FLOAT => {
+                take(input, 4)?;
+            }
+            MODULE_OPCODE_DOUBLE => {
+                take(input, 8)?;
+            }
+            MODULE_OPCODE_STRING => {
+                read_string(input)?;
+            }
+            _ => return None,
+        }
+    }
+}
+
+fn string(input: &mut &[u8]) -> Option<Bytes> {
+    read_string(input).map(Bytes::from)
+}
+
+fn strings(input: &mut &[u8]) -> Option<Vec<Bytes>> {
+    (0..read_length(input)?).map(|_| string(input)).collect()
+}
+
+fn hash(input: &mut &[u8]) -> Option<Value> {
+    let fields = (0..read_length(input)?)
+        .map(|_| Some((string(input)?, string(input)?)))
+        .collect::<Option<_>>()?;
+    Some(Value::Hash(fields))
+}
+
+// the first zset type wrote scores as strings with a length byte, which
+// reserves 253 to 255 for nan and the infinities, the second as doubles
+fn sorted_set(input: &mut &[u8], binary_scores: bool) -> Option<Value> {
+    let members = (0..read_length(input)?)
+        .map(|_| {
+            let member = string(input)?;
+            let score = if binary_scores {
+                f64::from_le_bytes(take(input, 8)?.try_into().ok()?)
+            } else {
+                match take(input, 1)?[0] {
+                    253 => f64::NAN,
+                    254 => f64::INFINITY,
+                    255 => f64::NEG_INFINITY,
+                    len => std::str::from_utf8(take(input, len as usize)?)
+                        .ok()?
+                        .parse()
+                        .ok()?,
+                }
+            };
+            Some((member, score))
+        })
+        .collect::<Option<_>>()?;
+    Some(Value::SortedSet(members))
+}
+
+fn pairs(elements: Vec<Bytes>) -> Option<Vec<(Bytes, Bytes)>> {
+    if !elements.len().is_multiple_of(2) {
+        return None;
+    }
+    let pairs = elements
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()));
+    Some(pairs.collect())
+}
+
+// compact sorted sets alternate members and their scores written as numbers
+fn scored_pairs(elements: Vec<Bytes>) -> Option<Vec<(Bytes, f64)>> {
+    pairs(elements)?
+        .into_iter()
+        .map(|(member, score)| Some((member, std::str::from_utf8(&score).ok()?.parse().ok()?)))
+        .collect()
+}
+
+// a quicklist is a list of ziplists
+fn quicklist(input: &mut &[u8]) -> Option<Vec<Bytes>> {
+    let mut elements = Vec::new();
+    for _ in 0..read_length(input)? {
+        elements.extend(ziplist(&read_string(input)?)?);
+    }
+    Some(elements)
+}
+
+fn quicklist_2(input: &mut &[u8]) -> Option<Vec<Bytes>> {
+    let mut elements = Vec::new();
+    for _ in 0..read_length(input)? {
+        let container = read_length(input)?;
+        let node = string(input)?;
+        match container {
+            QUICKLIST_NODE_PLAIN => elements.push(node),
+            QUICKLIST_NODE_PACKED => elements.extend(listpack(&node)?),
+            _ => return None,
+        }
+    }
+    Some(elements)
+}
+
+// a zipmap starts with a count byte, then alternates lengths and strings.
+// Lengths below 254 take one byte, 254 is followed by a 4 byte length, and
+// every value carries a byte saying how much free space trails it.
+fn zipmap(blob: &[u8]) -> Option<Vec<(Bytes, Bytes)>> {
+    let mut input = blob.get(1..)?;
+    let mut fields = Vec::new();
+    loop {
+        let Some(field) = zipmap_string(&mut input, false)? else {
+            return Some(fields);
+        };
+        let value = zipmap_string(&mut input, true)??;
+        fields.push((field, value));
+    }
+}
+
+fn zipmap_string(input: &mut &[u8], with_free: bool) -> Option<Option<Bytes>> {
+    let len = match take(input, 1)?[0] {
+        END => return Some(None),
+        254 => u32::from_le_bytes(take(input, 4)?.try_into().ok()?) as usize,
+        len => len as usize,
+    };
+    let free = if with_free {
+        take(input, 1)?[0] as usize
+    } else {
+        0
+    };
+    let s = Bytes::copy_from_slice(take(input, len)?);
+    take(input, free)?;
+    Some(Some(s))
+}
+
+// an intset is a 4 byte integer width, a 4 byte count and the sorted integers
+fn intset(blob: &[u8]) -> Option<Vec<Bytes>> {
+    let mut input = blob;
+    let width = u32::from_le_bytes(take(&mut input, 4)?.try_into().ok()?) as usize;
+    let len = u32::from_le_bytes(take(&mut input, 4)?.try_into().ok()?);
+    (0..len)
+        .map(|_| {
+            let bytes = take(&mut input, width)?;
+            let n = match width {
+                2 => i16::from_le_bytes(bytes.try_into().ok()?) as i64,
+                4 => i32::from_le_bytes(bytes.try_into().ok()?) as i64,
+                8 => i64::from_le_bytes(bytes.try_into().ok()?),
+                _ => return None,
+            };
+            Some(integer(n))
+        })
+        .collect()
+}
+
+// a ziplist has a 10 byte header, then entries made of the previous entry's
+// length, an encoding byte and the data, and ends with 0xff. The top two
+// bits of the encoding give a string length in 6, 14 or 32 bits, 11 marks
+// an integer.
+fn ziplist(blob: &[u8]) -> Option<Vec<Bytes>> {
+    let mut input = blob.get(10..)?;
+    let mut elements = Vec::new();
+    loop {
+        let first = *input.first()?;
+        if first == END {
+            return Some(elements);
+        }
+        take(&mut input, if first == 0xfe { 5 } else { 1 })?;
+
+        let encoding = take(&mut input, 1)?[0];
+        let len = match encoding >> 6 {
+            0 => (encoding & 0x3f) as usize,
+            1 => (((encoding & 0x3f) as usize) << 8) | take(&mut input, 1)?[0] as usize,
+            2 => u32::from_be_bytes(take(&mut input, 4)?.try_into().ok()?) as usize,
+            _ => {
+                let n = match encoding {
+                    0xc0 => i16::from_le_bytes(take(&mut input, 2)?.try_into().ok()?) as i64,
+                    0xd0 => i32::from_le_bytes(take(&mut input, 4)?.try_into().ok()?) as i64,
+                    0xe0 => i64::from_le_bytes(take(&mut input, 8)?.try_into().ok()?),
+                    0xf0 => int24(take(&mut input, 3)?),
+                    0xfe => take(&mut input, 1)?[0] as i8 as i64,
+                    // 0 to 12 kept in the encoding itself, offset by one
+                    0xf1..=0xfd => (encoding & 0x0f) as i64 - 1,
+                    _ => return None,
+                };
+                elements.push(integer(n));
+                continue;
+            }
+        };
+        elements.push(Bytes::copy_from_slice(take(&mut input, len)?));
+    }
+}
+
+// a listpack has a 6 byte header, then entries made of an encoding, the data
+// and the entry's own length written backwards, and ends with 0xff
+fn listpack(blob: &[u8]) -> Option<Vec<Bytes>> {
+    let mut input = blob.get(6..)?;
+    let mut elements = Vec::new();
+    loop {
+        let encoding = *input.first()?;
+        if encoding == END {
+            return Some(elements);
+        }
+        let (element, len) = if encoding & 0x80 == 0 {
+            (integer((encoding & 0x7f) as i64), 1)
+        } else if encoding & 0xc0 == 0x80 {
+            listpack_string(input, 1, (encoding & 0x3f) as usize)?
+        } else if encoding & 0xe0 == 0xc0 {
+            let n = (((encoding & 0x1f) as i64) << 8) | *input.get(1)? as i64;
+            // 13 bit two's complement
+            let n = if n >= 1 << 12 { n - (1 << 13) } else { n };
+            (integer(n), 2)
+        } else if encoding & 0xf0 == 0xe0 {
+            let len = (((encoding & 0x0f) as usize) << 8) | *input.get(1)? as usize;
+            listpack_string(input, 2, len)?
+        } else {
+            let data = input.get(1..)?;
+            match encoding {
+                0xf0 => {
+                    let len = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+                    listpack_string(input, 5, len)?
+                }
+                0xf1 => (
+                    integer(i16::from_le_bytes(data.get(..2)?.try_into().ok()?) as i64),
+                    3,
+                ),
+                0xf2 => (integer(int24(data.get(..3)?)), 4),
+                0xf3 => (
+                    integer(i32::from_le_bytes(data.get(..4)?.try_into().ok()?) as i64),
+                    5,
+                ),
+                0xf4 => (
+                    integer(i64::from_le_bytes(data.get(..8)?.try_into().ok()?)),
+                    9,
+                ),
+                _ => return None,
+            }
+        };
+        take(&mut input, len + backlen_size(len))?;
+        elements.push(element);
+    }
+}
+
+fn listpack_string(entry: &[u8], header: usize, len: usize) -> Option<(Bytes, usize)> {
+    let s = entry.get(header..header.checked_add(len)?)?;
+    Some((Bytes::copy_from_slice(s), header + len))
+}
+
+// the back length uses 7 bits per byte
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+fn int24(bytes: &[u8]) -> i64 {
+    let n = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]);
+    (n >> 8) as i64
+}
+
+fn integer(n: i64) -> Bytes {
+    Bytes::from(n.to_string())
+}
+
+// streams are read field by field only to find where they end
+fn skip_stream(input: &mut &[u8], value_type: u8) -> Option<()> {
+    // listpacks keyed by their master entry ID
+    for _ in 0..read_length(input)? {
+        read_string(input)?;
+        read_string(input)?;
+    }
+    // length and last ID, then the first ID, max deleted ID and entries
+    // added since version 2
+    let lengths = if value_type >= TYPE_STREAM_LISTPACKS_2 {
+        8
+    } else {
+        3
+    };
+    for _ in 0..lengths {
+        read_length(input)?;
+    }
+    for _ in 0..read_length(input)? {
+        // consumer group name, last delivered ID and entries read
+        read_string(input)?;
+        read_length(input)?;
+        read_length(input)?;
+        if value_type >= TYPE_STREAM_LISTPACKS_2 {
+            read_length(input)?;
+        }
+        // pending entries: raw ID, delivery time and delivery count
+        for _ in 0..read_length(input)? {
+            take(input, 16 + 8)?;
+            read_length(input)?;
+        }
+        for _ in 0..read_length(input)? {
+            // consumer name, seen time, active time since version 3, and
+            // the raw IDs of its pending entries
+            read_string(input)?;
+            take(input, 8)?;
+            if value_type >= TYPE_STREAM_LISTPACKS_3 {
+                take(input, 8)?;
+            }
+            for _ in 0..read_length(input)? {
+                take(input, 16)?;
+            }
+        }
+    }
+    Some(())
+}
+
+fn skip_module_value(input: &mut &[u8]) -> Option<()> {
+    // the 64 bit module ID, then what the module wrote
+    read_length(input)?;
+    skip_module_data(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::rdb::value::{
+        TYPE_HASH_ZIPMAP, TYPE_LIST_QUICKLIST, TYPE_LIST_QUICKLIST_2, TYPE_SET_INTSET, TYPE_ZSET_2,
+        TYPE_ZSET_LISTPACK, Value, read_value,
+    };
+    use crate::rdb::{write_length, write_string};
+
+    fn strings(values: &[&str]) -> Vec<Bytes> {
+        values.iter().map(|s| Bytes::from(s.to_string())).collect()
+    }
+
+    fn read(value_type: u8, data: &[u8]) -> Result<Value, String> {
+        let mut input = data;
+        let value = read_value(&mut input, value_type);
+        assert!(value.is_err() || input.is_empty());
+        value
+    }
+
+    #[test]
+    fn test_ziplists_in_a_quicklist() {
+        let mut ziplist = vec![0; 10];
+        // "ab", then 300 as an int16, 7 as an immediate and -2 as an int8
+        ziplist.extend_from_slice(&[0x00, 0x02, b'a', b'b']);
+        ziplist.extend_from_slice(&[0x04, 0xc0, 0x2c, 0x01]);
+        ziplist.extend_from_slice(&[0x04, 0xf8]);
+        ziplist.extend_from_slice(&[0x02, 0xfe, 0xfe, 0xff]);
+
+        let mut data = Vec::new();
+        write_length(&mut data, 1);
+        write_string(&mut data, &ziplist);
+        assert_eq!(
+            read(TYPE_LIST_QUICKLIST, &data),
+            Ok(Value::List(strings(&["ab", "300", "7", "-2"])))
+        );
+    }
+
+    #[test]
+    fn test_listpacks() {
+        let mut listpack = vec![0; 6];
+        // "a" with score 1, then "b" with score -3 as a 13 bit int
+        listpack.extend_from_slice(&[0x81, b'a', 0x02, 0x01, 0x01]);
+        listpack.extend_from_slice(&[0x81, b'b', 0x02, 0xdf, 0xfd, 0x02, 0xff]);
+
+        let mut data = Vec::new();
+        write_string(&mut data, &listpack);
+        assert_eq!(
+            read(TYPE_ZSET_LISTPACK, &data),
+            Ok(Value::SortedSet(vec![
+                (Bytes::from("a"), 1.0),
+                (Bytes::from("b"), -3.0)
+            ]))
+        );
+
+        // a plain node next to a packed one
+        let mut data = Vec::new();
+        write_length(&mut data, 2);
+        write_length(&mut data, 1);
+        write_string(&mut data, b"big");
+        write_length(&mut data, 2);
+        let mut listpack = vec![0; 6];
+        listpack.extend_from_slice(&[0xf1, 0xe8, 0x03, 0x03, 0xff]);
+        write_string(&mut data, &listpack);
+        assert_eq!(
+            read(TYPE_LIST_QUICKLIST_2, &data),
+            Ok(Value::List(strings(&["big", "1000"])))
+        );
+    }
+
+    #[test]
+    fn test_intsets_and_zipmaps() {
+        let mut intset = Vec::new();
+        intset.extend_from_slice(&2u32.to_le_bytes());
+        intset.extend_from_slice(&2u32.to_le_bytes());
+        intset.extend_from_slice(&(-5i16).to_le_bytes());
+        intset.extend_from_slice(&9i16.to_le_bytes());
+        let mut data = Vec::new();
+        write_string(&mut data, &intset);
+        assert_eq!(
+            read(TYPE_SET_INTSET, &data),
+            Ok(Value::Set(strings(&["-5", "9"])))
+        );
+
+        let zipmap = [0x01, 0x01, b'f', 0x02, 0x01, b'v', b'1', 0x00, 0xff];
+        let mut data = Vec::new();
+        write_string(&mut data, &zipmap);
+        assert_eq!(
+            read(TYPE_HASH_ZIPMAP, &data),
+            Ok(Value::Hash(vec![(Bytes::from("f"), Bytes::from("v1"))]))
+        );
+    }
+
+    #[test]
+    fn test_bad_values() {
+        let mut data = Vec::new();
+        write_length(&mut data, 1);
+        write_string(&mut data, b"member");
+        assert_eq!(
+            read(TYPE_ZSET_2, &data),
+            Err("corrupt value of type 5".to_string())
+        );
+        assert_eq!(read(6, &[]), Err("unsupported value type 6".to_string()));
+    }
+}
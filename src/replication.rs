@@ -0,0 +1,1175 @@
+// Primary/replica replication. A primary sends its replicas every write in
+// the form the AOF logs it and keeps the most recent part of that stream in
+// a backlog. The stream is named by a replication ID and a byte offset, so a
+// replica whose link dropped asks for what it missed with PSYNC and only
+// needs a full copy of the dataset when the backlog no longer has it.
+//
+// A replica follows its primary from the task `run` starts. It applies the
+// stream through a session of its own and passes it on byte for byte to
+// replicas of its own, so an offset means the same thing along a chain.
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::watch;
+
+use crate::aof::{self, Parsed};
+use crate::config::MasterAddr;
+use crate::outbox;
+use crate::persistence;
+use crate::rdb;
+use crate::resp_frame::RespFrame;
+use crate::scripting::{Scripting, sha1_hex};
+use crate::session::Session;
+use crate::storage::Storage;
+use crate::{
+    ServerState, execute_blocking, parser, run_blocking, track_keys, with_session_blocking,
+};
+
+// how much of the stream is kept for replicas that reconnect
+const BACKLOG_SIZE: usize = 1024 * 1024;
+// a replica that has more of the stream waiting to be sent is dropped,
+// redis' hard client-output-buffer-limit for replicas
+const REPLICA_OUTPUT_LIMIT: usize = 256 * 1024 * 1024;
+// a quiet primary pings its replicas, which report their offset every second
+const PING_PERIOD: Duration = Duration::from_secs(10);
+const ACK_PERIOD: Duration = Duration::from_secs(1);
+// a link that stays silent for longer is given up
+const LINK_TIMEOUT: Duration = Duration::from_secs(60);
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+// what INFO shows while there is no previous replication ID
+const NO_REPLID: &str = "0000000000000000000000000000000000000000";
+
+pub struct Replication {
+    // whether there is a stream to feed, checked before taking the lock. It
+    // starts with the first replica and goes on from then.
+    active: AtomicBool,
+    // None on a primary, the task of `run` follows whatever is set here
+    master: watch::Sender<Option<MasterAddr>>,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    replid: String,
+    // the ID of the history before the last change of primary and the
+    // offset up to which it agrees with the current one, so replicas can
+    // continue across a failover
+    replid2: String,
+    second_replid_offset: i64,
+    // how many bytes the stream has carried
+    offset: u64,
+    backlog: Option<Backlog>,
+    replicas: Vec<Replica>,
+    // the database the stream last switched to with SELECT; EXEC and
+    // scripts wrap their writes in MULTI/EXEC, written lazily like the AOF
+    selected_db: Option<usize>,
+    atomic_depth: usize,
+    multi_written: bool,
+    last_ping: Instant,
+    // on a replica, the state of the link to its primary and when it last
+    // heard from it
+    link: LinkState,
+    last_io: Option<Instant>,
+    // the port clients connect to, which a replica tells its primary
+    listening_port: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LinkState {
+    Connecting,
+    Sync,
+    Connected,
+}
+
+struct Backlog {
+    data: VecDeque<u8>,
+    // the offset of the first byte in `data`, counting from 1 like redis
+    first_byte_offset: u64,
+}
+
+struct Replica {
+    session_id: u64,
+    ip: String,
+    port: u16,
+    stream: UnboundedSender<Bytes>,
+    pending: Arc<Pending>,
+    // what the replica last said it has applied, and when
+    ack_offset: u64,
+    last_ack: Instant,
+}
+
+// the part of a replica's stream that is queued but not written yet
+#[derive(Debug, Default)]
+struct Pending {
+    bytes: AtomicUsize,
+    // set when the replica is dropped for falling behind
+    overflowed: AtomicBool,
+}
+
+/// What the connection of a replica sends after PSYNC: the RDB payload or
+/// the missing part of the backlog, then the stream as it is written.
+#[derive(Debug)]
+pub struct ReplicaStream {
+    start: Option<Bytes>,
+    stream: UnboundedReceiver<Bytes>,
+    pending: Arc<Pending>,
+}
+
+impl ReplicaStream {
+    /// The next part to send. None once the primary dropped the replica,
+    /// then whatever is still queued is not sent.
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        if let Some(start) = self.start.take() {
+            return Some(start);
+        }
+        if self.pending.overflowed.load(Ordering::Relaxed) {
+            return None;
+        }
+        let data = self.stream.recv().await?;
+        self.pending.bytes.fetch_sub(data.len(), Ordering::Relaxed);
+        Some(data)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start.is_none() && self.stream.is_empty()
+    }
+}
+
+impl Replica {
+    // false once the replica is gone, or has more than `limit` bytes waiting
+    fn send(&self, data: Bytes, limit: usize) -> bool {
+        let pending = self.pending.bytes.load(Ordering::Relaxed);
+        if pending > limit {
+            println!(
+                "Disconnecting replica {}:{} for overcoming of output buffer limits ({} bytes pending)",
+                self.ip, self.port, pending
+            );
+            self.pending.overflowed.store(true, Ordering::Relaxed);
+            return false;
+        }
+        self.pending.bytes.fetch_add(data.len(), Ordering::Relaxed);
+        self.stream.send(data).is_ok()
+    }
+}
+
+impl Replication {
+    pub fn new(master: Option<MasterAddr>) -> Self {
+        Replication {
+            active: AtomicBool::new(false),
+            master: watch::Sender::new(master),
+            inner: Mutex::new(Inner {
+                replid: random_replid(),
+                replid2: NO_REPLID.to_string(),
+                second_replid_offset: -1,
+                offset: 0,
+                backlog: None,
+                replicas: Vec::new(),
+                selected_db: None,
+                atomic_depth: 0,
+                multi_written: false,
+                last_ping: Instant::now(),
+                link: LinkState::Connecting,
+                last_io: None,
+                listening_port: 0,
+            }),
+        }
+    }
+
+    pub fn set_listening_port(&self, port: u16) {
+        self.inner.lock().unwrap().listening_port = port;
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.master.borrow().is_some()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Follows `master` from now on, or stops following with None. A former
+    /// replica starts a history of its own then, remembering the old one for
+    /// PSYNCs from its replicas. Returns false when nothing changes.
+    pub fn set_master(&self, master: Option<MasterAddr>) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if *self.master.borrow() == master {
+            return false;
+        }
+        if master.is_none() {
+            inner.replid2 = std::mem::replace(&mut inner.replid, random_replid());
+            inner.second_replid_offset = inner.offset as i64 + 1;
+            // the primary's stream may have stopped anywhere
+            inner.selected_db = None;
+            inner.atomic_depth = 0;
+            inner.multi_written = false;
+        }
+        inner.link = LinkState::Connecting;
+        inner.last_io = None;
+        self.master.send_replace(master);
+        true
+    }
+
+    /// Sends a write to the replicas, as `aof::translate` put it. Called
+    /// under the storage lock, so writes go out in the order they ran.
+    pub fn feed(&self, db_index: usize, args: &[Bytes]) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.backlog.is_none() {
+            return;
+        }
+        let mut out = Vec::new();
+        if inner.atomic_depth > 0 && !inner.multi_written {
+            inner.multi_written = true;
+            aof::encode(&mut out, &[Bytes::from("MULTI")]);
+        }
+        if inner.selected_db != Some(db_index) {
+            inner.selected_db = Some(db_index);
+            aof::encode(
+                &mut out,
+                &[Bytes::from("SELECT"), Bytes::from(db_index.to_string())],
+            );
+        }
+        aof::encode(&mut out, args);
+        inner.push(&out);
+    }
+
+    // on a replica: a command of the primary's stream that was applied, and
+    // the database the stream is in after it
+    fn feed_raw(&self, data: &[u8], db_index: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.selected_db = Some(db_index);
+        inner.push(data);
+    }
+
+    pub fn begin_atomic(&self) {
+        if self.is_active() {
+            self.inner.lock().unwrap().atomic_depth += 1;
+        }
+    }
+
+    pub fn end_atomic(&self) {
+        if !self.is_active() {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.atomic_depth = inner.atomic_depth.saturating_sub(1);
+        if inner.atomic_depth == 0 && inner.multi_written {
+            inner.multi_written = false;
+            let mut out = Vec::new();
+            aof::encode(&mut out, &[Bytes::from("EXEC")]);
+            inner.push(&out);
+        }
+    }
+
+    /// Serves PSYNC for the connection of `session`: a partial resync when
+    /// the backlog still holds everything from `offset` on in history
+    /// `replid`, a full one otherwise. Returns the reply and the stream the
+    /// connection sends from then on, which starts with the missing part of
+    /// the backlog or the RDB payload of the dataset.
+    pub fn psync(
+        &self,
+        replid: &[u8],
+        offset: i64,
+        storage: &Storage,
+        scripting: &Scripting,
+        session: &Session,
+    ) -> Result<(RespFrame, ReplicaStream), RespFrame> {
+        let mut inner = self.inner.lock().unwrap();
+        if self.is_replica() && inner.link != LinkState::Connected {
+            return Err(RespFrame::Error(
+                "NOMASTERLINK Can't SYNC while not connected with my master".to_string(),
+            ));
+        }
+
+        // what the replica is sent first is not counted against its limit
+        let start;
+        let reply = match inner.backlog_from(replid, offset) {
+            Some(missing) => {
+                start = missing;
+                format!("CONTINUE {}", inner.replid)
+            }
+            None => {
+                if inner.backlog.is_none() {
+                    inner.backlog = Some(Backlog {
+                        data: VecDeque::new(),
+                        first_byte_offset: inner.offset + 1,
+                    });
+                    self.active.store(true, Ordering::Relaxed);
+                }
+                let mut snapshot = persistence::snapshot(storage, scripting);
+                snapshot.repl_stream_db = inner.selected_db;
+                let rdb = persistence::encode_snapshot(&snapshot);
+                let mut payload = format!("${}\r\n", rdb.len()).into_bytes();
+                payload.extend_from_slice(&rdb);
+                start = Bytes::from(payload);
+                format!("FULLRESYNC {} {}", inner.replid, inner.offset)
+            }
+        };
+
+        let ip = session
+            .peer_addr
+            .as_deref()
+            .and_then(|addr| addr.rsplit_once(':'))
+            .map(|(ip, _)| ip.trim_start_matches('[').trim_end_matches(']'))
+            .unwrap_or("?")
+            .to_string();
+        inner
+            .replicas
+            .retain(|replica| replica.session_id != session.id);
+        let (sender, stream) = mpsc::unbounded_channel();
+        let pending = Arc::new(Pending::default());
+        inner.replicas.push(Replica {
+            session_id: session.id,
+            ip,
+            port: session.replica_port.unwrap_or(0),
+            stream: sender,
+            pending: pending.clone(),
+            ack_offset: 0,
+            last_ack: Instant::now(),
+        });
+        let stream = ReplicaStream {
+            start: Some(start),
+            stream,
+            pending,
+        };
+        Ok((RespFrame::SimpleString(reply), stream))
+    }
+
+    /// REPLCONF ACK from the replica on `session_id`.
+    pub fn ack(&self, session_id: u64, offset: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(replica) = inner
+            .replicas
+            .iter_mut()
+            .find(|replica| replica.session_id == session_id)
+        {
+            replica.ack_offset = offset;
+            replica.last_ack = Instant::now();
+        }
+    }
+
+    pub fn remove_replica(&self, session_id: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .replicas
+            .retain(|replica| replica.session_id != session_id);
+    }
+
+    // run once a second on a primary, the caller holds the storage lock so
+    // the PING can't land in the middle of a transaction
+    fn ping_replicas(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.replicas.is_empty() || inner.last_ping.elapsed() < PING_PERIOD {
+            return;
+        }
+        inner.last_ping = Instant::now();
+        let mut out = Vec::new();
+        aof::encode(&mut out, &[Bytes::from("PING")]);
+        inner.push(&out);
+    }
+
+    // what PSYNC asks for: the history this server has and the first byte
+    // of it that is missing
+    fn psync_position(&self) -> (String, u64) {
+        let inner = self.inner.lock().unwrap();
+        (inner.replid.clone(), inner.offset + 1)
+    }
+
+    fn offset(&self) -> u64 {
+        self.inner.lock().unwrap().offset
+    }
+
+    fn listening_port(&self) -> u16 {
+        self.inner.lock().unwrap().listening_port
+    }
+
+    fn set_link(&self, link: LinkState) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.link = link;
+        if link == LinkState::Connected {
+            inner.last_io = Some(Instant::now());
+        }
+    }
+
+    fn heard_from_master(&self) {
+        self.inner.lock().unwrap().last_io = Some(Instant::now());
+    }
+
+    // after a full sync this server continues the primary's history from
+    // `offset` on. Its own replicas have data from before and must resync.
+    fn full_sync_done(&self, replid: &str, offset: u64, stream_db: Option<usize>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.replid = replid.to_string();
+        inner.replid2 = NO_REPLID.to_string();
+        inner.second_replid_offset = -1;
+        inner.offset = offset;
+        inner.backlog = Some(Backlog {
+            data: VecDeque::new(),
+            first_byte_offset: offset + 1,
+        });
+        inner.selected_db = stream_db;
+        inner.replicas.clear();
+        self.active.store(true, Ordering::Relaxed);
+    }
+
+    // a partial resync with a primary that may have taken over another
+    // history, which then becomes this server's too
+    fn continue_with(&self, replid: Option<&str>) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(replid) = replid
+            && replid != inner.replid
+        {
+            inner.replid2 = std::mem::replace(&mut inner.replid, replid.to_string());
+            inner.second_replid_offset = inner.offset as i64 + 1;
+            inner.replicas.clear();
+        }
+    }
+
+    /// The fields of INFO replication.
+    pub fn info(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let mut info = String::new();
+        match &*self.master.borrow() {
+            None => info.push_str("role:master\r\n"),
+            Some(master) => {
+                info.push_str("role:slave\r\n");
+                info.push_str(&format!("master_host:{}\r\n", master.host));
+                info.push_str(&format!("master_port:{}\r\n", master.port));
+                let up = inner.link == LinkState::Connected;
+                info.push_str(&format!(
+                    "master_link_status:{}\r\n",
+                    if up { "up" } else { "down" }
+                ));
+                let last_io = inner.last_io.map_or(-1, |at| at.elapsed().as_secs() as i64);
+                info.push_str(&format!("master_last_io_seconds_ago:{}\r\n", last_io));
+                info.push_str(&format!(
+                    "master_sync_in_progress:{}\r\n",
+                    (inner.link == LinkState::Sync) as u8
+                ));
+                info.push_str(&format!("slave_read_repl_offset:{}\r\n", inner.offset));
+                info.push_str(&format!("slave_repl_offset:{}\r\n", inner.offset));
+                info.push_str("slave_read_only:1\r\n");
+            }
+        }
+        info.push_str(&format!("connected_slaves:{}\r\n", inner.replicas.len()));
+        for (index, replica) in inner.replicas.iter().enumerate() {
+            info.push_str(&format!(
+                "slave{}:ip={},port={},state=online,offset={},lag={}\r\n",
+                index,
+                replica.ip,
+                replica.port,
+                replica.ack_offset,
+                replica.last_ack.elapsed().as_secs()
+            ));
+        }
+        info.push_str(&format!("master_replid:{}\r\n", inner.replid));
+        info.push_str(&format!("master_replid2:{}\r\n", inner.replid2));
+        info.push_str(&format!("master_repl_offset:{}\r\n", inner.offset));
+        info.push_str(&format!(
+            "second_repl_offset:{}\r\n",
+            inner.second_replid_offset
+        ));
+        let (first_byte_offset, histlen) = inner.backlog.as_ref().map_or((0, 0), |backlog| {
+            (backlog.first_byte_offset, backlog.data.len())
+        });
+        info.push_str(&format!(
+            "repl_backlog_active:{}\r\n",
+            inner.backlog.is_some() as u8
+        ));
+        info.push_str(&format!("repl_backlog_size:{}\r\n", BACKLOG_SIZE));
+        info.push_str(&format!(
+            "repl_backlog_first_byte_offset:{}\r\n",
+            first_byte_offset
+        ));
+        info.push_str(&format!("repl_backlog_histlen:{}\r\n", histlen));
+        info
+    }
+
+    /// The ROLE reply.
+    pub fn role(&self) -> RespFrame {
+        let inner = self.inner.lock().unwrap();
+        let bulk = |s: &str| RespFrame::BulkString(Bytes::copy_from_slice(s.as_bytes()));
+        match &*self.master.borrow() {
+            None => RespFrame::Array(vec![
+                bulk("master"),
+                RespFrame::Integer(inner.offset as i64),
+                RespFrame::Array(
+                    inner
+                        .replicas
+                        .iter()
+                        .map(|replica| {
+                            RespFrame::Array(vec![
+                                bulk(&replica.ip),
+                                bulk(&replica.port.to_string()),
+                                bulk(&replica.ack_offset.to_string()),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ]),
+            Some(master) => RespFrame::Array(vec![
+                bulk("slave"),
+                bulk(&master.host),
+                RespFrame::Integer(master.port as i64),
+                bulk(match inner.link {
+                    LinkState::Connecting => "connecting",
+                    LinkState::Sync => "sync",
+                    LinkState::Connected => "connected",
+                }),
+                RespFrame::Integer(inner.offset as i64),
+            ]),
+        }
+    }
+}
+
+impl Inner {
+    // appends to the stream: the backlog keeps the newest BACKLOG_SIZE
+    // bytes, replicas whose connection is gone or that fell too far behind
+    // are dropped
+    fn push(&mut self, data: &[u8]) {
+        self.offset += data.len() as u64;
+        if let Some(backlog) = &mut self.backlog {
+            backlog.data.extend(data);
+            let excess = backlog.data.len().saturating_sub(BACKLOG_SIZE);
+            backlog.data.drain(..excess);
+            backlog.first_byte_offset += excess as u64;
+        }
+        let data = Bytes::copy_from_slice(data);
+        self.replicas
+            .retain(|replica| replica.send(data.clone(), REPLICA_OUTPUT_LIMIT));
+    }
+
+    // the stream from `offset` on, if it is still in the backlog
+    fn backlog_from(&self, replid: &[u8], offset: i64) -> Option<Bytes> {
+        let backlog = self.backlog.as_ref()?;
+        let same_history = replid == self.replid.as_bytes()
+            || replid == self.replid2.as_bytes() && offset <= self.second_replid_offset;
+        let offset = u64::try_from(offset).ok()?;
+        if !same_history || offset < backlog.first_byte_offset || offset > self.offset + 1 {
+            return None;
+        }
+        let skip = (offset - backlog.first_byte_offset) as usize;
+        Some(backlog.data.iter().skip(skip).copied().collect())
+    }
+}
+
+// 40 hex digits like redis' replication IDs, from a randomly seeded hasher,
+// the time and the process
+fn random_replid() -> String {
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut seed = Vec::new();
+    let hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    seed.extend_from_slice(&hasher.finish().to_le_bytes());
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    seed.extend_from_slice(&now.as_nanos().to_le_bytes());
+    seed.extend_from_slice(&std::process::id().to_le_bytes());
+    sha1_hex(&seed)
+}
+
+/// Follows the primary REPLICAOF names for as long as the server runs, and
+/// pings the replicas while there is none.
+pub(crate) async fn run(state: Arc<ServerState>) {
+    let mut master = state.replication.master.subscribe();
+    let mut cron = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        let following = master.borrow_and_update().clone();
+        let changed = match following {
+            Some(addr) => tokio::select! {
+                changed = master.changed() => changed,
+                () = follow(&state, &addr) => Ok(()),
+            },
+            None => tokio::select! {
+                changed = master.changed() => changed,
+                _ = cron.tick() => {
+                    let state = state.clone();
+                    run_blocking(move || {
+                        let _storage = state.db.lock().unwrap();
+                        state.replication.ping_replicas();
+                    })
+                    .await;
+                    Ok(())
+                }
+            },
+        };
+        if changed.is_err() {
+            return;
+        }
+    }
+}
+
+// keeps a link to the primary, reconnecting whenever it drops. The session
+// outlives the connections, a partial resync continues in its database.
+async fn follow(state: &Arc<ServerState>, master: &MasterAddr) {
+    let (outbox, _) = outbox::channel();
+    let mut session = Session::new(outbox);
+    session.is_master = true;
+    loop {
+        match sync_with(state, master, &mut session).await {
+            Ok(()) => println!("Connection with master lost"),
+            Err(e) => println!(
+                "Error replicating from {}:{}: {}",
+                master.host, master.port, e
+            ),
+        }
+        state.replication.set_link(LinkState::Connecting);
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+// one connection to the primary: the handshake, a full or partial resync,
+// then the stream until the link drops
+async fn sync_with(
+    state: &Arc<ServerState>,
+    master: &MasterAddr,
+    session: &mut Session,
+) -> io::Result<()> {
+    let stream =
+        timeout(async { TcpStream::connect((master.host.as_str(), master.port)).await }).await?;
+    let (read_half, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(read_half);
+
+    send(&mut writer, &["PING"]).await?;
+    if let RespFrame::Error(message) = timeout(parser::parse_frame(&mut reader)).await? {
+        return Err(io::Error::other(format!("PING failed: {}", message)));
+    }
+    // an older primary that knows neither option does without them
+    let port = state.replication.listening_port().to_string();
+    send(&mut writer, &["REPLCONF", "listening-port", &port]).await?;
+    timeout(parser::parse_frame(&mut reader)).await?;
+    send(&mut writer, &["REPLCONF", "capa", "psync2"]).await?;
+    timeout(parser::parse_frame(&mut reader)).await?;
+
+    let (replid, offset) = state.replication.psync_position();
+    send(&mut writer, &["PSYNC", &replid, &offset.to_string()]).await?;
+    state.replication.set_link(LinkState::Sync);
+    let reply = timeout(parser::parse_frame(&mut reader)).await?;
+    let line = match &reply {
+        RespFrame::SimpleString(line) => line.as_str(),
+        _ => "",
+    };
+    if let Some(rest) = line.strip_prefix("FULLRESYNC ") {
+        let (replid, offset) = rest
+            .split_once(' ')
+            .and_then(|(replid, offset)| Some((replid, offset.parse().ok()?)))
+            .ok_or_else(|| invalid(format!("bad FULLRESYNC reply '{}'", line)))?;
+        let payload = timeout(read_payload(&mut reader)).await?;
+        let (state, replid) = (state.clone(), replid.to_string());
+        with_session_blocking(session, move |session| {
+            load_full_sync(&state, session, &replid, offset, &payload)
+        })
+        .await?;
+    } else if let Some(rest) = line.strip_prefix("CONTINUE") {
+        let replid = Some(rest.trim()).filter(|replid| !replid.is_empty());
+        state.replication.continue_with(replid);
+        println!("MASTER <-> REPLICA sync: Master accepted a Partial Resynchronization");
+    } else {
+        return Err(io::Error::other(format!(
+            "unexpected reply to PSYNC: {}",
+            reply
+        )));
+    }
+    state.replication.set_link(LinkState::Connected);
+
+    apply_stream(state, session, reader, writer).await
+}
+
+// the RDB of a full sync, "$<len>\r\n" and the file. Primaries send empty
+// lines to keep the link alive while they prepare it.
+async fn read_payload(reader: &mut BufReader<OwnedReadHalf>) -> io::Result<Vec<u8>> {
+    let mut line = Vec::new();
+    while line.trim_ascii().is_empty() {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+    }
+    let len = line
+        .trim_ascii()
+        .strip_prefix(b"$")
+        .and_then(|digits| std::str::from_utf8(digits).ok())
+        .and_then(|digits| digits.parse().ok())
+        .ok_or_else(|| invalid("bad RDB payload from master".to_string()))?;
+    // read as it arrives rather than trusting the length up front
+    let mut payload = Vec::new();
+    reader.take(len).read_to_end(&mut payload).await?;
+    if payload.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(payload)
+}
+
+// replaces the dataset with the primary's
+fn load_full_sync(
+    state: &ServerState,
+    session: &mut Session,
+    replid: &str,
+    offset: u64,
+    payload: &[u8],
+) -> io::Result<()> {
+    let snapshot = rdb::read_snapshot(payload).map_err(invalid)?;
+    let mut storage = state.db.lock().unwrap();
+    for keyspace in storage.databases_mut() {
+        keyspace.flush();
+    }
+    state.scripting.flush_functions();
+    let report =
+        persistence::restore(&snapshot, &mut storage, &state.scripting).map_err(invalid)?;
+    persistence::warn_skipped(&report);
+    if storage.key_tracking() {
+        track_keys(storage.take_tracked_keys(), state, None, None);
+    }
+    session.db_index = snapshot.repl_stream_db.unwrap_or(0);
+    session.transaction = None;
+    state
+        .replication
+        .full_sync_done(replid, offset, snapshot.repl_stream_db);
+    // the log has to start over from the new dataset
+    if state.aof.is_enabled() {
+        state.aof.rewrite(&storage, &state.scripting);
+    }
+    println!(
+        "MASTER <-> REPLICA sync: Finished with success, {} keys loaded",
+        report.keys
+    );
+    Ok(())
+}
+
+// applies the primary's stream until the link drops, acknowledging the
+// offset every second and whenever the primary asks
+async fn apply_stream(
+    state: &Arc<ServerState>,
+    session: &mut Session,
+    mut reader: BufReader<OwnedReadHalf>,
+    mut writer: OwnedWriteHalf,
+) -> io::Result<()> {
+    let mut buffer = Vec::new();
+    let mut ack = tokio::time::interval(ACK_PERIOD);
+    let mut last_io = Instant::now();
+    loop {
+        tokio::select! {
+            read = reader.read_buf(&mut buffer) => {
+                if read? == 0 {
+                    return Ok(());
+                }
+                last_io = Instant::now();
+                state.replication.heard_from_master();
+                let used = apply_commands(state, session, &buffer, &mut writer).await?;
+                buffer.drain(..used);
+            }
+            _ = ack.tick() => {
+                if last_io.elapsed() > LINK_TIMEOUT {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "master timed out"));
+                }
+                send_ack(state, &mut writer).await?;
+            }
+        }
+    }
+}
+
+// runs the complete commands at the start of `data` and returns how many
+// bytes they took
+async fn apply_commands(
+    state: &Arc<ServerState>,
+    session: &mut Session,
+    data: &[u8],
+    writer: &mut OwnedWriteHalf,
+) -> io::Result<usize> {
+    let mut offset = 0;
+    loop {
+        match aof::parse_command(&data[offset..]) {
+            Parsed::Command(args, used) => {
+                let getack = args.len() > 1
+                    && args[0].eq_ignore_ascii_case(b"REPLCONF")
+                    && args[1].eq_ignore_ascii_case(b"GETACK");
+                if !getack {
+                    let frame =
+                        RespFrame::Array(args.into_iter().map(RespFrame::BulkString).collect());
+                    execute_blocking(frame, state, session).await;
+                }
+                state
+                    .replication
+                    .feed_raw(&data[offset..offset + used], session.db_index);
+                offset += used;
+                if getack {
+                    send_ack(state, writer).await?;
+                }
+            }
+            Parsed::Truncated => return Ok(offset),
+            Parsed::Invalid => return Err(invalid("protocol error from master".to_string())),
+        }
+    }
+}
+
+async fn send_ack(state: &ServerState, writer: &mut OwnedWriteHalf) -> io::Result<()> {
+    let offset = state.replication.offset().to_string();
+    send(writer, &["REPLCONF", "ACK", &offset]).await
+}
+
+async fn send(writer: &mut OwnedWriteHalf, args: &[&str]) -> io::Result<()> {
+    let args: Vec<Bytes> = args
+        .iter()
+        .map(|arg| Bytes::copy_from_slice(arg.as_bytes()))
+        .collect();
+    let mut out = Vec::new();
+    aof::encode(&mut out, &args);
+    writer.write_all(&out).await
+}
+
+async fn timeout<T>(future: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+    tokio::time::timeout(LINK_TIMEOUT, future)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "master timed out"))?
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use bytes::Bytes;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, copy_bidirectional};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::Notify;
+
+    use crate::client::{Client, ClientError};
+    use crate::replication::{Pending, Replica, ReplicaStream, read_payload};
+    use crate::resp_frame::RespFrame;
+    use crate::storage::RedisValue;
+    use crate::testing::TestServer;
+    use crate::{parser, serializer};
+
+    fn command(args: &[&str]) -> RespFrame {
+        RespFrame::Array(
+            args.iter()
+                .map(|arg| RespFrame::BulkString(Bytes::copy_from_slice(arg.as_bytes())))
+                .collect(),
+        )
+    }
+
+    async fn info(client: &Client) -> String {
+        match client.command(&["INFO", "replication"]).await.unwrap() {
+            RespFrame::BulkString(info) => String::from_utf8_lossy(&info).into_owned(),
+            other => panic!("unexpected INFO reply {}", other),
+        }
+    }
+
+    // replication is asynchronous, so replicas are polled for a while
+    async fn eventually<F: Future<Output = bool>>(mut check: impl FnMut() -> F) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !check().await {
+            assert!(
+                Instant::now() < deadline,
+                "timed out waiting for the replica"
+            );
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_replica_follows_its_primary() {
+        let primary = TestServer::start().await;
+        let replica = TestServer::start().await;
+        let on_primary = Client::connect(primary.addr()).await.unwrap();
+        let on_replica = Client::connect(replica.addr()).await.unwrap();
+        let port = primary.addr().port().to_string();
+
+        on_primary.set("k", "v").await.unwrap();
+        on_primary.expire("k", 100).await.unwrap();
+        on_primary.command(&["SELECT", "2"]).await.unwrap();
+        on_primary.set("in2", "x").await.unwrap();
+        on_replica.set("stale", "gone").await.unwrap();
+
+        // the full sync replaces the replica's data
+        assert_eq!(
+            on_replica
+                .command(&["REPLICAOF", "127.0.0.1", &port])
+                .await
+                .unwrap(),
+            RespFrame::simple("OK")
+        );
+        assert_eq!(
+            on_replica
+                .command(&["REPLICAOF", "127.0.0.1", &port])
+                .await
+                .unwrap(),
+            RespFrame::simple("OK Already connected to specified master")
+        );
+        eventually(|| async { on_replica.get("k").await.unwrap().is_some() }).await;
+        assert_eq!(on_replica.get("stale").await.unwrap(), None);
+        assert!(matches!(on_replica.ttl("k").await.unwrap(), 99 | 100));
+
+        // then the writes stream in, in the primary's database
+        on_primary.set("in2", "y").await.unwrap();
+        on_primary.command(&["MULTI"]).await.unwrap();
+        on_primary.command(&["INCR", "n"]).await.unwrap();
+        on_primary.command(&["INCR", "n"]).await.unwrap();
+        on_primary.command(&["EXEC"]).await.unwrap();
+        on_replica.command(&["SELECT", "2"]).await.unwrap();
+        eventually(|| async { on_replica.get("n").await.unwrap() == Some(Bytes::from("2")) }).await;
+        assert_eq!(on_replica.get("in2").await.unwrap(), Some(Bytes::from("y")));
+
+        assert!(matches!(
+            on_replica.set("k", "mine").await,
+            Err(ClientError::Server(message))
+                if message == "READONLY You can't write against a read only replica."
+        ));
+
+        eventually(|| async { info(&on_replica).await.contains("master_link_status:up") }).await;
+        let replica_info = info(&on_replica).await;
+        assert!(replica_info.contains("role:slave\r\n"));
+        assert!(replica_info.contains(&format!("master_port:{}\r\n", port)));
+        let offset = |info: &str| {
+            info.lines()
+                .find_map(|line| line.strip_prefix("master_repl_offset:"))
+                .map(str::to_string)
+        };
+        // the offset the replica acknowledged catches up with the primary's
+        eventually(|| async {
+            let primary_info = info(&on_primary).await;
+            let acked = format!(
+                "port={},state=online,offset={},",
+                replica.addr().port(),
+                offset(&primary_info).unwrap()
+            );
+            primary_info.contains("connected_slaves:1\r\n") && primary_info.contains(&acked)
+        })
+        .await;
+        assert_eq!(
+            offset(&info(&on_replica).await),
+            offset(&info(&on_primary).await)
+        );
+
+        // promoted, the replica takes writes and keeps the old history as its second
+        let primary_replid = info(&on_primary)
+            .await
+            .lines()
+            .find_map(|line| line.strip_prefix("master_replid:").map(str::to_string))
+            .unwrap();
+        on_replica
+            .command(&["REPLICAOF", "NO", "ONE"])
+            .await
+            .unwrap();
+        let promoted = info(&on_replica).await;
+        assert!(promoted.contains("role:master\r\n"));
+        assert!(promoted.contains(&format!("master_replid2:{}\r\n", primary_replid)));
+        on_replica.set("k", "mine").await.unwrap();
+        assert_eq!(
+            on_replica.get("k").await.unwrap(),
+            Some(Bytes::from("mine"))
+        );
+    }
+
+    // the primary decides when a key expires, whatever the replica's clock says
+    #[tokio::test]
+    async fn test_replica_waits_for_its_primary_to_expire_keys() {
+        let primary = TestServer::start().await;
+        let replica = TestServer::start().await;
+        let on_primary = Client::connect(primary.addr()).await.unwrap();
+        let on_replica = Client::connect(replica.addr()).await.unwrap();
+        let port = primary.addr().port().to_string();
+        on_primary.set("k", "v").await.unwrap();
+        on_primary.expire("k", 10).await.unwrap();
+        on_replica
+            .command(&["REPLICAOF", "127.0.0.1", &port])
+            .await
+            .unwrap();
+        eventually(|| async { on_replica.get("k").await.unwrap().is_some() }).await;
+
+        // expired on the replica, the key is hidden but kept
+        replica.advance(Duration::from_secs(11));
+        assert_eq!(on_replica.get("k").await.unwrap(), None);
+        assert_eq!(
+            on_replica.command(&["DBSIZE"]).await.unwrap(),
+            RespFrame::Integer(1)
+        );
+
+        // the primary expires it and sends the DEL
+        primary.advance(Duration::from_secs(11));
+        assert_eq!(on_primary.get("k").await.unwrap(), None);
+        eventually(|| async {
+            on_replica.command(&["DBSIZE"]).await.unwrap() == RespFrame::Integer(0)
+        })
+        .await;
+    }
+
+    // a replica whose link dropped gets what it missed from the backlog
+    #[tokio::test]
+    async fn test_psync_continues_from_the_backlog() {
+        let primary = TestServer::start().await;
+        let client = Client::connect(primary.addr()).await.unwrap();
+        client.set("a", "1").await.unwrap();
+
+        let mut link = BufReader::new(primary.connect().await);
+        serializer::serialize_frame(link.get_mut(), command(&["PSYNC", "?", "-1"]))
+            .await
+            .unwrap();
+        let RespFrame::SimpleString(reply) = parser::parse_frame(&mut link).await.unwrap() else {
+            panic!("PSYNC should reply with a status");
+        };
+        let words: Vec<&str> = reply.split(' ').collect();
+        assert_eq!(words[0], "FULLRESYNC");
+        assert_eq!(words[2], "0");
+        let replid = words[1].to_string();
+        let mut header = Vec::new();
+        link.read_until(b'\n', &mut header).await.unwrap();
+        let len: usize = std::str::from_utf8(&header[1..header.len() - 2])
+            .unwrap()
+            .parse()
+            .unwrap();
+        let mut rdb = vec![0; len];
+        link.read_exact(&mut rdb).await.unwrap();
+        assert!(rdb.starts_with(b"REDIS"));
+
+        client.set("b", "2").await.unwrap();
+        let mut received = Vec::new();
+        for expected in [command(&["SELECT", "0"]), command(&["SET", "b", "2"])] {
+            let frame = parser::parse_frame(&mut link).await.unwrap();
+            assert_eq!(frame, expected);
+            serializer::serialize_frame(&mut received, frame)
+                .await
+                .unwrap();
+        }
+        drop(link);
+
+        client.set("c", "3").await.unwrap();
+        let mut link = BufReader::new(primary.connect().await);
+        let next = (received.len() + 1).to_string();
+        serializer::serialize_frame(link.get_mut(), command(&["PSYNC", &replid, &next]))
+            .await
+            .unwrap();
+        assert_eq!(
+            parser::parse_frame(&mut link).await.unwrap(),
+            RespFrame::SimpleString(format!("CONTINUE {}", replid))
+        );
+        assert_eq!(
+            parser::parse_frame(&mut link).await.unwrap(),
+            command(&["SET", "c", "3"])
+        );
+
+        // another history, or an offset past the end, needs a full sync
+        for (replid, offset) in [("0123", next.as_str()), (replid.as_str(), "100000")] {
+            let mut link = BufReader::new(primary.connect().await);
+            serializer::serialize_frame(link.get_mut(), command(&["PSYNC", replid, offset]))
+                .await
+                .unwrap();
+            assert!(matches!(
+                parser::parse_frame(&mut link).await.unwrap(),
+                RespFrame::SimpleString(reply) if reply.starts_with("FULLRESYNC ")
+            ));
+        }
+    }
+
+    // the replica reaches its primary through a proxy whose connection the
+    // test can cut, after which it continues where it was instead of
+    // loading everything again
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_replica_resumes_after_a_dropped_link() {
+        let primary = TestServer::start().await;
+        let replica = TestServer::start().await;
+        let on_primary = Client::connect(primary.addr()).await.unwrap();
+        let on_replica = Client::connect(replica.addr()).await.unwrap();
+
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_port = proxy.local_addr().unwrap().port().to_string();
+        let cut = Arc::new(Notify::new());
+        let cut_link = cut.clone();
+        let primary_addr = primary.addr();
+        tokio::spawn(async move {
+            while let Ok((mut inbound, _)) = proxy.accept().await {
+                let mut outbound = TcpStream::connect(primary_addr).await.unwrap();
+                tokio::select! {
+                    _ = copy_bidirectional(&mut inbound, &mut outbound) => {}
+                    () = cut_link.notified() => {}
+                }
+            }
+        });
+
+        on_primary.set("a", "1").await.unwrap();
+        on_replica
+            .command(&["REPLICAOF", "127.0.0.1", &proxy_port])
+            .await
+            .unwrap();
+        eventually(|| async { on_replica.get("a").await.unwrap().is_some() }).await;
+
+        // a full sync would drop this key, which only the replica has
+        replica
+            .db()
+            .lock()
+            .unwrap()
+            .db(0)
+            .insert(Bytes::from("local"), RedisValue::new(Bytes::from("kept")));
+        cut.notify_one();
+        eventually(|| async { info(&on_replica).await.contains("master_link_status:down") }).await;
+        on_primary.set("b", "2").await.unwrap();
+
+        eventually(|| async { on_replica.get("b").await.unwrap().is_some() }).await;
+        assert_eq!(
+            on_replica.get("local").await.unwrap(),
+            Some(Bytes::from("kept"))
+        );
+        assert!(info(&on_replica).await.contains("master_link_status:up"));
+    }
+
+    #[tokio::test]
+    async fn test_replica_that_falls_behind_is_dropped() {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let pending = Arc::new(Pending::default());
+        let replica = Replica {
+            session_id: 1,
+            ip: "127.0.0.1".to_string(),
+            port: 0,
+            stream: sender,
+            pending: pending.clone(),
+            ack_offset: 0,
+            last_ack: Instant::now(),
+        };
+        let mut stream = ReplicaStream {
+            start: Some(Bytes::from("payload")),
+            stream: receiver,
+            pending,
+        };
+        let data = Bytes::from(vec![b'x'; 1000]);
+
+        // the payload a sync starts with does not count
+        assert!(replica.send(data.clone(), 1500));
+        assert_eq!(stream.recv().await, Some(Bytes::from("payload")));
+        assert_eq!(stream.recv().await, Some(data.clone()));
+        assert!(replica.send(data.clone(), 1500));
+        assert!(replica.send(data.clone(), 1500));
+        assert!(!replica.send(data, 1500));
+        // what is still queued is not sent
+        assert_eq!(stream.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_full_sync_payload_length_is_not_trusted() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut primary, _) = listener.accept().await.unwrap();
+            primary
+                .write_all(b"\n$4611686018427387904\r\nREDIS")
+                .await
+                .unwrap();
+        });
+
+        let (read_half, _write_half) = TcpStream::connect(addr).await.unwrap().into_split();
+        let error = read_payload(&mut BufReader::new(read_half))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
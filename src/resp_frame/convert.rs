@@ -0,0 +1,494 @@
+// Conversions between frames and Rust values. `From` covers the common reply
+// shapes, `ToResp`/`FromResp` let any type, including your own structs, go
+// to and from a frame; `Fields` reads a struct back out of a map reply.
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+use bytes::Bytes;
+
+use crate::resp_frame::RespFrame;
+
+impl RespFrame {
+    /// A status reply, like `+OK`.
+    pub fn simple(s: impl Into<String>) -> RespFrame {
+        RespFrame::SimpleString(s.into())
+    }
+
+    /// An error reply, the message starting with its code, like `ERR`.
+    pub fn error(message: impl Into<String>) -> RespFrame {
+        RespFrame::Error(message.into())
+    }
+}
+
+impl From<i64> for RespFrame {
+    fn from(n: i64) -> Self {
+        RespFrame::Integer(n)
+    }
+}
+
+impl From<usize> for RespFrame {
+    fn from(n: usize) -> Self {
+        RespFrame::Integer(n as i64)
+    }
+}
+
+impl From<&str> for RespFrame {
+    fn from(s: &str) -> Self {
+        RespFrame::BulkString(Bytes::copy_from_slice(s.as_bytes()))
+    }
+}
+
+impl From<String> for RespFrame {
+    fn from(s: String) -> Self {
+        RespFrame::BulkString(Bytes::from(s))
+    }
+}
+
+impl From<&[u8]> for RespFrame {
+    fn from(bytes: &[u8]) -> Self {
+        RespFrame::BulkString(Bytes::copy_from_slice(bytes))
+    }
+}
+
+impl From<Bytes> for RespFrame {
+    fn from(bytes: Bytes) -> Self {
+        RespFrame::BulkString(bytes)
+    }
+}
+
+impl<T: Into<RespFrame>> From<Option<T>> for RespFrame {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(RespFrame::Null, Into::into)
+    }
+}
+
+impl<T: Into<RespFrame>> From<Vec<T>> for RespFrame {
+    fn from(items: Vec<T>) -> Self {
+        RespFrame::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+// RESP2 clients need maps flattened, see `map_reply` in commands
+impl<K: Into<RespFrame>, V: Into<RespFrame>> From<HashMap<K, V>> for RespFrame {
+    fn from(map: HashMap<K, V>) -> Self {
+        RespFrame::Map(
+            map.into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect(),
+        )
+    }
+}
+
+/// A value that can be sent as a frame. For a struct, reply with a map of
+/// its fields:
+///
+/// ```
+/// use resprs::RespFrame;
+/// use resprs::resp_frame::ToResp;
+///
+/// struct User {
+///     name: String,
+///     age: i64,
+/// }
+///
+/// impl ToResp for User {
+///     fn to_resp(&self) -> RespFrame {
+///         RespFrame::Map(vec![
+///             ("name".into(), self.name.to_resp()),
+///             ("age".into(), self.age.to_resp()),
+///         ])
+///     }
+/// }
+/// ```
+pub trait ToResp {
+    fn to_resp(&self) -> RespFrame;
+}
+
+/// A value that can be read from a frame. For a struct, read its fields from
+/// a map reply with [`Fields`]:
+///
+/// ```
+/// use resprs::RespFrame;
+/// use resprs::resp_frame::{Fields, FromResp, FromRespError};
+///
+/// struct User {
+///     name: String,
+///     age: Option<i64>,
+/// }
+///
+/// impl FromResp for User {
+///     fn from_resp(frame: RespFrame) -> Result<Self, FromRespError> {
+///         let mut fields = Fields::from_resp(frame)?;
+///         Ok(User {
+///             name: fields.take("name")?,
+///             age: fields.take("age")?,
+///         })
+///     }
+/// }
+/// ```
+pub trait FromResp: Sized {
+    fn from_resp(frame: RespFrame) -> Result<Self, FromRespError>;
+}
+
+/// A frame of the wrong type for the value it was read into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FromRespError {
+    expected: &'static str,
+    frame: RespFrame,
+    // the struct field being read, when it came through `Fields`
+    field: Option<&'static str>,
+}
+
+impl FromRespError {
+    pub fn new(expected: &'static str, frame: RespFrame) -> FromRespError {
+        FromRespError {
+            expected,
+            frame,
+            field: None,
+        }
+    }
+
+    /// The frame that could not be converted.
+    pub fn frame(&self) -> &RespFrame {
+        &self.frame
+    }
+
+    pub fn into_frame(self) -> RespFrame {
+        self.frame
+    }
+}
+
+impl fmt::Display for FromRespError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(field) = self.field {
+            write!(f, "field '{}': ", field)?;
+        }
+        write!(f, "expected {}, got {:?}", self.expected, self.frame)
+    }
+}
+
+impl std::error::Error for FromRespError {}
+
+impl ToResp for RespFrame {
+    fn to_resp(&self) -> RespFrame {
+        self.clone()
+    }
+}
+
+impl ToResp for i64 {
+    fn to_resp(&self) -> RespFrame {
+        RespFrame::Integer(*self)
+    }
+}
+
+impl ToResp for usize {
+    fn to_resp(&self) -> RespFrame {
+        RespFrame::Integer(*self as i64)
+    }
+}
+
+// RESP2 has no booleans, redis replies 1 or 0
+impl ToResp for bool {
+    fn to_resp(&self) -> RespFrame {
+        RespFrame::Integer(*self as i64)
+    }
+}
+
+impl ToResp for str {
+    fn to_resp(&self) -> RespFrame {
+        self.into()
+    }
+}
+
+impl ToResp for String {
+    fn to_resp(&self) -> RespFrame {
+        self.as_str().into()
+    }
+}
+
+impl ToResp for Bytes {
+    fn to_resp(&self) -> RespFrame {
+        RespFrame::BulkString(self.clone())
+    }
+}
+
+impl<T: ToResp + ?Sized> ToResp for &T {
+    fn to_resp(&self) -> RespFrame {
+        (**self).to_resp()
+    }
+}
+
+impl<T: ToResp> ToResp for Option<T> {
+    fn to_resp(&self) -> RespFrame {
+        self.as_ref().map_or(RespFrame::Null, ToResp::to_resp)
+    }
+}
+
+impl<T: ToResp> ToResp for [T] {
+    fn to_resp(&self) -> RespFrame {
+        RespFrame::Array(self.iter().map(ToResp::to_resp).collect())
+    }
+}
+
+impl<T: ToResp> ToResp for Vec<T> {
+    fn to_resp(&self) -> RespFrame {
+        self.as_slice().to_resp()
+    }
+}
+
+impl<K: ToResp, V: ToResp> ToResp for HashMap<K, V> {
+    fn to_resp(&self) -> RespFrame {
+        RespFrame::Map(
+            self.iter()
+                .map(|(key, value)| (key.to_resp(), value.to_resp()))
+                .collect(),
+        )
+    }
+}
+
+impl FromResp for RespFrame {
+    fn from_resp(frame: RespFrame) -> Result<Self, FromRespError> {
+        Ok(frame)
+    }
+}
+
+// numbers also come as strings, like INCRBYFLOAT's or a GET of a counter
+impl FromResp for i64 {
+    fn from_resp(frame: RespFrame) -> Result<Self, FromRespError> {
+        let parsed = match &frame {
+            RespFrame::Integer(n) => Some(*n),
+            RespFrame::BulkString(bytes) => {
+                std::str::from_utf8(bytes).ok().and_then(|s| s.parse().ok())
+            }
+            RespFrame::SimpleString(s) => s.parse().ok(),
+            _ => None,
+        };
+        parsed.ok_or_else(|| FromRespError::new("an integer", frame))
+    }
+}
+
+impl FromResp for bool {
+    fn from_resp(frame: RespFrame) -> Result<Self, FromRespError> {
+        match frame {
+            RespFrame::Integer(n) => Ok(n != 0),
+            frame => Err(FromRespError::new("an integer", frame)),
+        }
+    }
+}
+
+impl FromResp for String {
+    fn from_resp(frame: RespFrame) -> Result<Self, FromRespError> {
+        match frame {
+            RespFrame::SimpleString(s) => Ok(s),
+            RespFrame::BulkString(bytes) => match std::str::from_utf8(&bytes) {
+                Ok(s) => Ok(s.to_string()),
+                Err(_) => Err(FromRespError::new(
+                    "a UTF-8 string",
+                    RespFrame::BulkString(bytes),
+                )),
+            },
+            frame => Err(FromRespError::new("a string", frame)),
+        }
+    }
+}
+
+impl FromResp for Bytes {
+    fn from_resp(frame: RespFrame) -> Result<Self, FromRespError> {
+        match frame {
+            RespFrame::BulkString(bytes) => Ok(bytes),
+            RespFrame::SimpleString(s) => Ok(Bytes::from(s)),
+            frame => Err(FromRespError::new("a string", frame)),
+        }
+    }
+}
+
+impl<T: FromResp> FromResp for Option<T> {
+    fn from_resp(frame: RespFrame) -> Result<Self, FromRespError> {
+        match frame {
+            RespFrame::Null => Ok(None),
+            frame => T::from_resp(frame).map(Some),
+        }
+    }
+}
+
+impl<T: FromResp> FromResp for Vec<T> {
+    fn from_resp(frame: RespFrame) -> Result<Self, FromRespError> {
+        match frame {
+            RespFrame::Array(items) | RespFrame::Push(items) => {
+                items.into_iter().map(T::from_resp).collect()
+            }
+            frame => Err(FromRespError::new("an array", frame)),
+        }
+    }
+}
+
+// a RESP3 map, or the flat key value array RESP2 sends instead
+impl<K, V> FromResp for HashMap<K, V>
+where
+    K: FromResp + Eq + Hash,
+    V: FromResp,
+{
+    fn from_resp(frame: RespFrame) -> Result<Self, FromRespError> {
+        pairs(frame)?
+            .into_iter()
+            .map(|(key, value)| Ok((K::from_resp(key)?, V::from_resp(value)?)))
+            .collect()
+    }
+}
+
+// a RESP3 map, or the flat key value array RESP2 sends instead
+pub(crate) fn pairs(frame: RespFrame) -> Result<Vec<(RespFrame, RespFrame)>, FromRespError> {
+    match frame {
+        RespFrame::Map(entries) => Ok(entries),
+        RespFrame::Array(items) if items.len().is_multiple_of(2) => {
+            let mut items = items.into_iter();
+            let mut entries = Vec::with_capacity(items.len() / 2);
+            while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                entries.push((key, value));
+            }
+            Ok(entries)
+        }
+        frame => Err(FromRespError::new("a map", frame)),
+    }
+}
+
+macro_rules! try_from_frame {
+    ($($ty:ty),*) => {
+        $(
+            impl TryFrom<RespFrame> for $ty {
+                type Error = FromRespError;
+
+                fn try_from(frame: RespFrame) -> Result<Self, Self::Error> {
+                    <$ty>::from_resp(frame)
+                }
+            }
+        )*
+    };
+}
+
+try_from_frame!(i64, bool, String, Bytes);
+
+/// The fields of a map reply, taken out one by one by name. A missing field
+/// reads as `Null`, so `Option` fields may be left out.
+pub struct Fields {
+    entries: HashMap<String, RespFrame>,
+}
+
+impl Fields {
+    pub fn take<T: FromResp>(&mut self, name: &'static str) -> Result<T, FromRespError> {
+        let frame = self.entries.remove(name).unwrap_or(RespFrame::Null);
+        T::from_resp(frame).map_err(|mut e| {
+            e.field = Some(name);
+            e
+        })
+    }
+}
+
+impl FromResp for Fields {
+    fn from_resp(frame: RespFrame) -> Result<Self, FromRespError> {
+        let entries = pairs(frame)?
+            .into_iter()
+            .map(|(key, value)| Ok((String::from_resp(key)?, value)))
+            .collect::<Result<_, FromRespError>>()?;
+        Ok(Fields { entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bytes::Bytes;
+
+    use crate::resp_frame::{Fields, FromResp, FromRespError, RespFrame, ToResp};
+
+    fn bulk(s: &str) -> RespFrame {
+        RespFrame::BulkString(Bytes::copy_from_slice(s.as_bytes()))
+    }
+
+    #[test]
+    fn test_from_native_values() {
+        assert_eq!(RespFrame::from(3i64), RespFrame::Integer(3));
+        assert_eq!(RespFrame::from("a"), bulk("a"));
+        assert_eq!(RespFrame::from(Bytes::from("b")), bulk("b"));
+        assert_eq!(RespFrame::from(None::<i64>), RespFrame::Null);
+        assert_eq!(
+            RespFrame::from(vec![Some("a"), None]),
+            RespFrame::Array(vec![bulk("a"), RespFrame::Null])
+        );
+        assert_eq!(
+            RespFrame::from(HashMap::from([("k", 1i64)])),
+            RespFrame::Map(vec![(bulk("k"), RespFrame::Integer(1))])
+        );
+        assert_eq!(
+            RespFrame::error("ERR nope"),
+            RespFrame::Error("ERR nope".to_string())
+        );
+    }
+
+    #[test]
+    fn test_try_from_frames() {
+        assert_eq!(i64::try_from(RespFrame::Integer(2)), Ok(2));
+        assert_eq!(i64::try_from(bulk("-7")), Ok(-7));
+        assert_eq!(
+            String::try_from(RespFrame::simple("OK")),
+            Ok("OK".to_string())
+        );
+        assert_eq!(
+            i64::try_from(bulk("x")),
+            Err(FromRespError::new("an integer", bulk("x")))
+        );
+        assert_eq!(
+            Vec::<Option<Bytes>>::from_resp(RespFrame::Array(vec![bulk("a"), RespFrame::Null])),
+            Ok(vec![Some(Bytes::from("a")), None])
+        );
+
+        // RESP2 sends maps as flat arrays
+        let flat = RespFrame::Array(vec![bulk("a"), RespFrame::Integer(1)]);
+        assert_eq!(
+            HashMap::<String, i64>::from_resp(flat),
+            Ok(HashMap::from([("a".to_string(), 1)]))
+        );
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct User {
+        name: String,
+        age: Option<i64>,
+    }
+
+    impl ToResp for User {
+        fn to_resp(&self) -> RespFrame {
+            RespFrame::Map(vec![
+                ("name".into(), self.name.to_resp()),
+                ("age".into(), self.age.to_resp()),
+            ])
+        }
+    }
+
+    impl FromResp for User {
+        fn from_resp(frame: RespFrame) -> Result<Self, FromRespError> {
+            let mut fields = Fields::from_resp(frame)?;
+            Ok(User {
+                name: fields.take("name")?,
+                age: fields.take("age")?,
+            })
+        }
+    }
+
+    #[test]
+    fn test_structs_round_trip_through_maps() {
+        let user = User {
+            name: "ann".to_string(),
+            age: Some(30),
+        };
+        assert_eq!(User::from_resp(user.to_resp()), Ok(user));
+
+        let nameless = RespFrame::Map(vec![(bulk("age"), RespFrame::Integer(1))]);
+        assert_eq!(
+            User::from_resp(nameless).unwrap_err().to_string(),
+            "field 'name': expected a string, got Null"
+        );
+    }
+}
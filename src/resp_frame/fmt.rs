@@ -0,0 +1,330 @@
+// Text renderings of a frame. `Display` shows a reply the way redis-cli
+// does on a terminal, `raw()` the way it does with --raw, `wire()` the exact
+// RESP encoding on one line with CR LF escaped, and `json()` a JSON value.
+use std::fmt::{self, Debug, Display, Formatter, Write};
+
+use crate::resp_frame::RespFrame;
+
+impl RespFrame {
+    /// Bare values one per line, like `redis-cli --raw`.
+    pub fn raw(&self) -> Raw<'_> {
+        Raw(self)
+    }
+
+    /// The RESP encoding with CR, LF and other unprintable bytes escaped,
+    /// e.g. `*1\r\n$4\r\nPING\r\n`.
+    pub fn wire(&self) -> Wire<'_> {
+        Wire(self)
+    }
+
+    /// A JSON value: errors become `{"error": ...}` and maps objects keyed by
+    /// their keys' text.
+    pub fn json(&self) -> Json<'_> {
+        Json(self)
+    }
+}
+
+pub struct Raw<'a>(&'a RespFrame);
+
+pub struct Wire<'a>(&'a RespFrame);
+
+pub struct Json<'a>(&'a RespFrame);
+
+impl Display for RespFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&tty(self, ""))
+    }
+}
+
+fn tty(frame: &RespFrame, indent: &str) -> String {
+    match frame {
+        RespFrame::SimpleString(s) => s.clone(),
+        RespFrame::Error(e) => format!("(error) {}", e),
+        RespFrame::Integer(n) => format!("(integer) {}", n),
+        RespFrame::BulkString(bytes) => quote(bytes),
+        RespFrame::Null => "(nil)".to_string(),
+        RespFrame::Array(items) | RespFrame::Push(items) if items.is_empty() => {
+            "(empty array)".to_string()
+        }
+        RespFrame::Array(items) | RespFrame::Push(items) => {
+            numbered(items.len(), indent, ")", |index, indent| {
+                tty(&items[index], indent)
+            })
+        }
+        RespFrame::Map(entries) if entries.is_empty() => "(empty hash)".to_string(),
+        RespFrame::Map(entries) => numbered(entries.len(), indent, "#", |index, indent| {
+            let (key, value) = &entries[index];
+            let key = tty(key, indent);
+            // a nested value lines up after the key it belongs to
+            let value_indent = format!("{}{}", indent, " ".repeat(key.len() + 4));
+            format!("{} => {}", key, tty(value, &value_indent))
+        }),
+    }
+}
+
+// "1) ..." lines, where items after the first line are indented by `indent`
+// plus the width of their own label, so nested replies line up
+fn numbered(
+    count: usize,
+    indent: &str,
+    marker: &str,
+    mut item: impl FnMut(usize, &str) -> String,
+) -> String {
+    let width = count.to_string().len();
+    let mut lines = Vec::with_capacity(count);
+    for index in 0..count {
+        let label = format!("{:>width$}{} ", index + 1, marker, width = width);
+        let nested_indent = format!("{}{}", indent, " ".repeat(label.len()));
+        let line = format!("{}{}", label, item(index, &nested_indent));
+        lines.push(if index == 0 {
+            line
+        } else {
+            format!("{}{}", indent, line)
+        });
+    }
+    lines.join("\n")
+}
+
+// double quoted with redis-cli's escapes
+fn quote(bytes: &[u8]) -> String {
+    let mut quoted = String::with_capacity(bytes.len() + 2);
+    quoted.push('"');
+    for &byte in bytes {
+        match byte {
+            b'\\' => quoted.push_str("\\\\"),
+            b'"' => quoted.push_str("\\\""),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            b'\t' => quoted.push_str("\\t"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            0x20..=0x7e => quoted.push(byte as char),
+            _ => quoted.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Display for Raw<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            RespFrame::SimpleString(s) | RespFrame::Error(s) => f.write_str(s),
+            RespFrame::Integer(n) => write!(f, "{}", n),
+            RespFrame::BulkString(bytes) => f.write_str(&String::from_utf8_lossy(bytes)),
+            RespFrame::Null => Ok(()),
+            RespFrame::Array(items) | RespFrame::Push(items) => {
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        f.write_char('\n')?;
+                    }
+                    write!(f, "{}", item.raw())?;
+                }
+                Ok(())
+            }
+            RespFrame::Map(entries) => {
+                for (index, (key, value)) in entries.iter().enumerate() {
+                    if index > 0 {
+                        f.write_char('\n')?;
+                    }
+                    write!(f, "{}\n{}", key.raw(), value.raw())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for Wire<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            RespFrame::SimpleString(s) => write!(f, "+{}\\r\\n", s.escape_default()),
+            RespFrame::Error(e) => write!(f, "-{}\\r\\n", e.escape_default()),
+            RespFrame::Integer(n) => write!(f, ":{}\\r\\n", n),
+            RespFrame::BulkString(bytes) => {
+                write!(f, "${}\\r\\n{}\\r\\n", bytes.len(), bytes.escape_ascii())
+            }
+            RespFrame::Null => f.write_str("$-1\\r\\n"),
+            RespFrame::Array(items) => write_items(f, '*', items),
+            RespFrame::Push(items) => write_items(f, '>', items),
+            RespFrame::Map(entries) => {
+                write!(f, "%{}\\r\\n", entries.len())?;
+                for (key, value) in entries {
+                    write!(f, "{}{}", key.wire(), value.wire())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn write_items(f: &mut Formatter<'_>, prefix: char, items: &[RespFrame]) -> fmt::Result {
+    write!(f, "{}{}\\r\\n", prefix, items.len())?;
+    for item in items {
+        write!(f, "{}", item.wire())?;
+    }
+    Ok(())
+}
+
+impl Display for Json<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            RespFrame::SimpleString(s) => json_string(f, s),
+            RespFrame::Error(e) => {
+                f.write_str("{\"error\":")?;
+                json_string(f, e)?;
+                f.write_char('}')
+            }
+            RespFrame::Integer(n) => write!(f, "{}", n),
+            RespFrame::BulkString(bytes) => json_string(f, &String::from_utf8_lossy(bytes)),
+            RespFrame::Null => f.write_str("null"),
+            RespFrame::Array(items) | RespFrame::Push(items) => {
+                f.write_char('[')?;
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", item.json())?;
+                }
+                f.write_char(']')
+            }
+            RespFrame::Map(entries) => {
+                f.write_char('{')?;
+                for (index, (key, value)) in entries.iter().enumerate() {
+                    if index > 0 {
+                        f.write_char(',')?;
+                    }
+                    // object keys must be strings, other keys use their raw text
+                    json_string(f, &key.raw().to_string())?;
+                    write!(f, ":{}", value.json())?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+fn json_string(f: &mut Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c < ' ' => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl Debug for RespFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RespFrame::SimpleString(s) => f.debug_tuple("SimpleString").field(s).finish(),
+            RespFrame::Error(e) => f.debug_tuple("Error").field(e).finish(),
+            RespFrame::Integer(n) => f.debug_tuple("Integer").field(n).finish(),
+            RespFrame::BulkString(bytes) => f
+                .debug_tuple("BulkString")
+                .field(&format_args!("{}", quote(bytes)))
+                .finish(),
+            RespFrame::Array(items) => f.debug_tuple("Array").field(items).finish(),
+            RespFrame::Null => f.write_str("Null"),
+            RespFrame::Map(entries) => f.debug_tuple("Map").field(entries).finish(),
+            RespFrame::Push(items) => f.debug_tuple("Push").field(items).finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::resp_frame::RespFrame;
+
+    fn bulk(s: &str) -> RespFrame {
+        RespFrame::BulkString(Bytes::copy_from_slice(s.as_bytes()))
+    }
+
+    #[test]
+    fn test_display_scalars() {
+        assert_eq!(RespFrame::SimpleString("OK".to_string()).to_string(), "OK");
+        assert_eq!(RespFrame::Integer(1).to_string(), "(integer) 1");
+        assert_eq!(RespFrame::Null.to_string(), "(nil)");
+        assert_eq!(
+            RespFrame::Error("ERR nope".to_string()).to_string(),
+            "(error) ERR nope"
+        );
+        assert_eq!(
+            bulk("say \"hi\"\n\u{1}").to_string(),
+            r#""say \"hi\"\n\x01""#
+        );
+    }
+
+    #[test]
+    fn test_display_nests_and_aligns() {
+        let reply = RespFrame::Array(vec![
+            RespFrame::Array(vec![bulk("a"), bulk("b")]),
+            RespFrame::Integer(2),
+            RespFrame::Array(vec![]),
+        ]);
+        assert_eq!(
+            reply.to_string(),
+            "1) 1) \"a\"\n   2) \"b\"\n2) (integer) 2\n3) (empty array)"
+        );
+
+        let long = RespFrame::Array((0..10).map(RespFrame::Integer).collect());
+        let formatted = long.to_string();
+        assert!(formatted.starts_with(" 1) (integer) 0\n"));
+        assert!(formatted.ends_with("\n10) (integer) 9"));
+
+        let map = RespFrame::Map(vec![(bulk("proto"), RespFrame::Integer(3))]);
+        assert_eq!(map.to_string(), "1# \"proto\" => (integer) 3");
+    }
+
+    #[test]
+    fn test_raw() {
+        let reply = RespFrame::Array(vec![bulk("a b"), RespFrame::Integer(1), RespFrame::Null]);
+        assert_eq!(reply.raw().to_string(), "a b\n1\n");
+        assert_eq!(
+            RespFrame::Error("ERR nope".to_string()).raw().to_string(),
+            "ERR nope"
+        );
+    }
+
+    #[test]
+    fn test_wire() {
+        let command = RespFrame::Array(vec![bulk("SET"), bulk("k"), bulk("a\r\nb")]);
+        assert_eq!(
+            command.wire().to_string(),
+            r"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$4\r\na\r\nb\r\n"
+        );
+        assert_eq!(RespFrame::Null.wire().to_string(), r"$-1\r\n");
+    }
+
+    #[test]
+    fn test_json() {
+        let reply = RespFrame::Map(vec![
+            (bulk("name"), bulk("say \"hi\"\n")),
+            (
+                RespFrame::Integer(1),
+                RespFrame::Array(vec![RespFrame::Null]),
+            ),
+            (bulk("err"), RespFrame::Error("ERR nope".to_string())),
+        ]);
+        assert_eq!(
+            reply.json().to_string(),
+            r#"{"name":"say \"hi\"\n","1":[null],"err":{"error":"ERR nope"}}"#
+        );
+    }
+
+    #[test]
+    fn test_debug_shows_bulk_strings_as_text() {
+        assert_eq!(
+            format!("{:?}", RespFrame::Array(vec![bulk("GET"), RespFrame::Null])),
+            r#"Array([BulkString("GET"), Null])"#
+        );
+    }
+}
@@ -0,0 +1,24 @@
+use bytes::Bytes;
+
+mod convert;
+mod fmt;
+#[cfg(feature = "serde")]
+pub mod serde;
+
+pub use convert::{Fields, FromResp, FromRespError, ToResp};
+pub use fmt::{Json, Raw, Wire};
+
+// Debug and Display are written by hand in fmt.rs, so bulk strings read as
+// text in logs and test failures
+#[derive(Clone, PartialEq, Eq)]
+pub enum RespFrame {
+    SimpleString(String),
+    Error(String),
+    Integer(i64),
+    BulkString(Bytes),
+    Array(Vec<RespFrame>),
+    Null,
+    // RESP3 only
+    Map(Vec<(RespFrame, RespFrame)>),
+    Push(Vec<RespFrame>),
+}
@@ -0,0 +1,937 @@
+// serde support, behind the `serde` feature. Structs and maps serialize to
+// RESP3 maps, or to flat key value arrays for RESP2 clients; numbers become
+// integers and floats bulk strings, the way redis replies with them.
+// Deserializing reads either map shape back, and parses numbers out of bulk
+// strings, so an HGETALL reply can be read straight into a struct.
+use std::fmt;
+
+use ::serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use ::serde::ser::{
+    self, Serialize, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant,
+    SerializeTuple, SerializeTupleStruct, SerializeTupleVariant,
+};
+use bytes::Bytes;
+
+use crate::resp_frame::{RespFrame, convert};
+
+/// Serializes `value` for a RESP3 client, structs and maps as `Map`.
+pub fn to_frame<T: Serialize + ?Sized>(value: &T) -> Result<RespFrame, SerdeError> {
+    value.serialize(FrameSerializer::resp3())
+}
+
+/// Serializes `value` for a RESP2 client, structs and maps as flat arrays.
+pub fn to_frame_resp2<T: Serialize + ?Sized>(value: &T) -> Result<RespFrame, SerdeError> {
+    value.serialize(FrameSerializer::resp2())
+}
+
+/// Reads a reply into a typed value.
+///
+/// ```
+/// use bytes::Bytes;
+/// use resprs::RespFrame;
+/// use resprs::resp_frame::serde::from_frame;
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct User {
+///     name: String,
+///     age: u32,
+/// }
+///
+/// // HGETALL user:1, in RESP2 shape
+/// let reply = RespFrame::Array(vec![
+///     RespFrame::BulkString(Bytes::from("name")),
+///     RespFrame::BulkString(Bytes::from("ann")),
+///     RespFrame::BulkString(Bytes::from("age")),
+///     RespFrame::BulkString(Bytes::from("30")),
+/// ]);
+/// let user: User = from_frame(reply).unwrap();
+/// assert_eq!((user.name.as_str(), user.age), ("ann", 30));
+/// ```
+pub fn from_frame<T: DeserializeOwned>(frame: RespFrame) -> Result<T, SerdeError> {
+    T::deserialize(FrameDeserializer::new(frame))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerdeError(String);
+
+impl fmt::Display for SerdeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for SerdeError {}
+
+impl ser::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerdeError(msg.to_string())
+    }
+}
+
+impl de::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerdeError(msg.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameSerializer {
+    resp3: bool,
+}
+
+impl FrameSerializer {
+    pub fn resp2() -> FrameSerializer {
+        FrameSerializer { resp3: false }
+    }
+
+    pub fn resp3() -> FrameSerializer {
+        FrameSerializer { resp3: true }
+    }
+
+    // RESP2 has no maps, they go out as flat key value arrays
+    fn map(self, entries: Vec<(RespFrame, RespFrame)>) -> RespFrame {
+        if self.resp3 {
+            RespFrame::Map(entries)
+        } else {
+            RespFrame::Array(
+                entries
+                    .into_iter()
+                    .flat_map(|(key, value)| [key, value])
+                    .collect(),
+            )
+        }
+    }
+
+    // enum variants with data are a one entry map from the variant name
+    fn variant(self, variant: &'static str, value: RespFrame) -> RespFrame {
+        self.map(vec![(variant.into(), value)])
+    }
+}
+
+fn text(s: impl ToString) -> RespFrame {
+    RespFrame::BulkString(Bytes::from(s.to_string()))
+}
+
+impl ser::Serializer for FrameSerializer {
+    type Ok = RespFrame;
+    type Error = SerdeError;
+    type SerializeSeq = SeqBuilder;
+    type SerializeTuple = SeqBuilder;
+    type SerializeTupleStruct = SeqBuilder;
+    type SerializeTupleVariant = SeqBuilder;
+    type SerializeMap = MapBuilder;
+    type SerializeStruct = MapBuilder;
+    type SerializeStructVariant = MapBuilder;
+
+    // RESP2 has no booleans, redis replies 1 or 0
+    fn serialize_bool(self, v: bool) -> Result<RespFrame, SerdeError> {
+        Ok(RespFrame::Integer(v as i64))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<RespFrame, SerdeError> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<RespFrame, SerdeError> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<RespFrame, SerdeError> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<RespFrame, SerdeError> {
+        Ok(RespFrame::Integer(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<RespFrame, SerdeError> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<RespFrame, SerdeError> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<RespFrame, SerdeError> {
+        self.serialize_i64(v.into())
+    }
+
+    // too big for a RESP integer, sent as its digits instead
+    fn serialize_u64(self, v: u64) -> Result<RespFrame, SerdeError> {
+        Ok(i64::try_from(v).map_or_else(|_| text(v), RespFrame::Integer))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<RespFrame, SerdeError> {
+        Ok(text(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<RespFrame, SerdeError> {
+        Ok(text(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<RespFrame, SerdeError> {
+        Ok(text(v))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<RespFrame, SerdeError> {
+        Ok(v.into())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<RespFrame, SerdeError> {
+        Ok(v.into())
+    }
+
+    fn serialize_none(self) -> Result<RespFrame, SerdeError> {
+        Ok(RespFrame::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<RespFrame, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<RespFrame, SerdeError> {
+        Ok(RespFrame::Null)
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<RespFrame, SerdeError> {
+        Ok(RespFrame::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> Result<RespFrame, SerdeError> {
+        Ok(variant.into())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<RespFrame, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<RespFrame, SerdeError> {
+        Ok(self.variant(variant, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqBuilder, SerdeError> {
+        Ok(SeqBuilder::new(self, len, None))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqBuilder, SerdeError> {
+        Ok(SeqBuilder::new(self, Some(len), None))
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, len: usize) -> Result<SeqBuilder, SerdeError> {
+        Ok(SeqBuilder::new(self, Some(len), None))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SeqBuilder, SerdeError> {
+        Ok(SeqBuilder::new(self, Some(len), Some(variant)))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<MapBuilder, SerdeError> {
+        Ok(MapBuilder::new(self, len, None))
+    }
+
+    fn serialize_struct(self, _: &'static str, len: usize) -> Result<MapBuilder, SerdeError> {
+        Ok(MapBuilder::new(self, Some(len), None))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<MapBuilder, SerdeError> {
+        Ok(MapBuilder::new(self, Some(len), Some(variant)))
+    }
+}
+
+pub struct SeqBuilder {
+    serializer: FrameSerializer,
+    items: Vec<RespFrame>,
+    variant: Option<&'static str>,
+}
+
+impl SeqBuilder {
+    fn new(
+        serializer: FrameSerializer,
+        len: Option<usize>,
+        variant: Option<&'static str>,
+    ) -> SeqBuilder {
+        SeqBuilder {
+            serializer,
+            items: Vec::with_capacity(len.unwrap_or(0)),
+            variant,
+        }
+    }
+
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.items.push(value.serialize(self.serializer)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<RespFrame, SerdeError> {
+        let array = RespFrame::Array(self.items);
+        Ok(match self.variant {
+            Some(variant) => self.serializer.variant(variant, array),
+            None => array,
+        })
+    }
+}
+
+impl SerializeSeq for SeqBuilder {
+    type Ok = RespFrame;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<RespFrame, SerdeError> {
+        self.finish()
+    }
+}
+
+impl SerializeTuple for SeqBuilder {
+    type Ok = RespFrame;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<RespFrame, SerdeError> {
+        self.finish()
+    }
+}
+
+impl SerializeTupleStruct for SeqBuilder {
+    type Ok = RespFrame;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<RespFrame, SerdeError> {
+        self.finish()
+    }
+}
+
+impl SerializeTupleVariant for SeqBuilder {
+    type Ok = RespFrame;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<RespFrame, SerdeError> {
+        self.finish()
+    }
+}
+
+pub struct MapBuilder {
+    serializer: FrameSerializer,
+    entries: Vec<(RespFrame, RespFrame)>,
+    // serialize_key comes before its serialize_value
+    key: Option<RespFrame>,
+    variant: Option<&'static str>,
+}
+
+impl MapBuilder {
+    fn new(
+        serializer: FrameSerializer,
+        len: Option<usize>,
+        variant: Option<&'static str>,
+    ) -> MapBuilder {
+        MapBuilder {
+            serializer,
+            entries: Vec::with_capacity(len.unwrap_or(0)),
+            key: None,
+            variant,
+        }
+    }
+
+    fn field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        let value = value.serialize(self.serializer)?;
+        self.entries.push((name.into(), value));
+        Ok(())
+    }
+
+    fn finish(self) -> Result<RespFrame, SerdeError> {
+        let map = self.serializer.map(self.entries);
+        Ok(match self.variant {
+            Some(variant) => self.serializer.variant(variant, map),
+            None => map,
+        })
+    }
+}
+
+impl SerializeMap for MapBuilder {
+    type Ok = RespFrame;
+    type Error = SerdeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerdeError> {
+        self.key = Some(key.serialize(self.serializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| SerdeError("map value without a key".to_string()))?;
+        let value = value.serialize(self.serializer)?;
+        self.entries.push((key, value));
+        Ok(())
+    }
+
+    fn end(self) -> Result<RespFrame, SerdeError> {
+        self.finish()
+    }
+}
+
+impl SerializeStruct for MapBuilder {
+    type Ok = RespFrame;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        self.field(name, value)
+    }
+
+    fn end(self) -> Result<RespFrame, SerdeError> {
+        self.finish()
+    }
+}
+
+impl SerializeStructVariant for MapBuilder {
+    type Ok = RespFrame;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        self.field(name, value)
+    }
+
+    fn end(self) -> Result<RespFrame, SerdeError> {
+        self.finish()
+    }
+}
+
+pub struct FrameDeserializer {
+    frame: RespFrame,
+}
+
+impl FrameDeserializer {
+    pub fn new(frame: RespFrame) -> FrameDeserializer {
+        FrameDeserializer { frame }
+    }
+
+    fn unexpected(&self, expected: &str) -> SerdeError {
+        SerdeError(format!("expected {}, got {:?}", expected, self.frame))
+    }
+
+    fn text(&self) -> Option<&str> {
+        match &self.frame {
+            RespFrame::SimpleString(s) => Some(s),
+            RespFrame::BulkString(bytes) => std::str::from_utf8(bytes).ok(),
+            _ => None,
+        }
+    }
+
+    // numbers come as integers, or as strings from HGETALL and friends
+    fn integer(&self) -> Result<i64, SerdeError> {
+        match &self.frame {
+            RespFrame::Integer(n) => Ok(*n),
+            _ => self
+                .text()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| self.unexpected("an integer")),
+        }
+    }
+
+    fn unsigned(&self) -> Result<u64, SerdeError> {
+        match &self.frame {
+            RespFrame::Integer(n) => {
+                u64::try_from(*n).map_err(|_| self.unexpected("an unsigned integer"))
+            }
+            _ => self
+                .text()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| self.unexpected("an unsigned integer")),
+        }
+    }
+
+    fn float(&self) -> Result<f64, SerdeError> {
+        match &self.frame {
+            RespFrame::Integer(n) => Ok(*n as f64),
+            _ => self
+                .text()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| self.unexpected("a float")),
+        }
+    }
+
+    fn into_entries(self) -> Result<Vec<(RespFrame, RespFrame)>, SerdeError> {
+        convert::pairs(self.frame)
+            .map_err(|e| FrameDeserializer::new(e.into_frame()).unexpected("a map"))
+    }
+}
+
+macro_rules! deserialize_number {
+    ($($method:ident => $visit:ident, $read:ident, $ty:ty;)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+                let n = self.$read()?;
+                let n = <$ty>::try_from(n).map_err(|_| self.unexpected(stringify!($ty)))?;
+                visitor.$visit(n)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for FrameDeserializer {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.frame {
+            RespFrame::SimpleString(s) => visitor.visit_string(s),
+            RespFrame::Error(message) => Err(SerdeError(message)),
+            RespFrame::Integer(n) => visitor.visit_i64(n),
+            RespFrame::BulkString(bytes) => match String::from_utf8(bytes.to_vec()) {
+                Ok(s) => visitor.visit_string(s),
+                Err(_) => visitor.visit_byte_buf(bytes.to_vec()),
+            },
+            RespFrame::Null => visitor.visit_unit(),
+            RespFrame::Array(items) | RespFrame::Push(items) => {
+                visitor.visit_seq(Items(items.into_iter()))
+            }
+            RespFrame::Map(entries) => visitor.visit_map(Entries {
+                entries: entries.into_iter(),
+                value: None,
+            }),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match (&self.frame, self.text()) {
+            (RespFrame::Integer(n), _) => visitor.visit_bool(*n != 0),
+            (_, Some("1" | "true")) => visitor.visit_bool(true),
+            (_, Some("0" | "false")) => visitor.visit_bool(false),
+            _ => Err(self.unexpected("a boolean")),
+        }
+    }
+
+    deserialize_number! {
+        deserialize_i8 => visit_i8, integer, i8;
+        deserialize_i16 => visit_i16, integer, i16;
+        deserialize_i32 => visit_i32, integer, i32;
+        deserialize_i64 => visit_i64, integer, i64;
+        deserialize_u8 => visit_u8, unsigned, u8;
+        deserialize_u16 => visit_u16, unsigned, u16;
+        deserialize_u32 => visit_u32, unsigned, u32;
+        deserialize_u64 => visit_u64, unsigned, u64;
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_f32(self.float()? as f32)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_f64(self.float()?)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.frame {
+            RespFrame::Integer(n) => visitor.visit_string(n.to_string()),
+            RespFrame::Error(message) => Err(SerdeError(message)),
+            _ => match self.text() {
+                Some(s) => visitor.visit_str(s),
+                None => Err(self.unexpected("a string")),
+            },
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.frame {
+            RespFrame::BulkString(bytes) => visitor.visit_byte_buf(bytes.to_vec()),
+            RespFrame::SimpleString(s) => visitor.visit_string(s),
+            frame => Err(FrameDeserializer::new(frame).unexpected("a string")),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.frame {
+            RespFrame::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.frame {
+            RespFrame::Error(message) => Err(SerdeError(message)),
+            _ => visitor.visit_unit(),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.frame {
+            RespFrame::Array(items) | RespFrame::Push(items) => {
+                visitor.visit_seq(Items(items.into_iter()))
+            }
+            frame => Err(FrameDeserializer::new(frame).unexpected("an array")),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _: usize,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: usize,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_map(Entries {
+            entries: self.into_entries()?.into_iter(),
+            value: None,
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        if let Some(variant) = self.text() {
+            return visitor.visit_enum(variant.to_string().into_deserializer());
+        }
+        let mut entries = self.into_entries()?;
+        if entries.len() != 1 {
+            return Err(SerdeError(
+                "expected an enum variant as a one entry map".to_string(),
+            ));
+        }
+        let (variant, value) = entries.remove(0);
+        visitor.visit_enum(Variant { variant, value })
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_unit()
+    }
+}
+
+impl<'de> IntoDeserializer<'de, SerdeError> for RespFrame {
+    type Deserializer = FrameDeserializer;
+
+    fn into_deserializer(self) -> FrameDeserializer {
+        FrameDeserializer::new(self)
+    }
+}
+
+struct Items(std::vec::IntoIter<RespFrame>);
+
+impl<'de> SeqAccess<'de> for Items {
+    type Error = SerdeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, SerdeError> {
+        self.0
+            .next()
+            .map(|item| seed.deserialize(FrameDeserializer::new(item)))
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct Entries {
+    entries: std::vec::IntoIter<(RespFrame, RespFrame)>,
+    // the value of the key handed out last
+    value: Option<RespFrame>,
+}
+
+impl<'de> MapAccess<'de> for Entries {
+    type Error = SerdeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, SerdeError> {
+        let Some((key, value)) = self.entries.next() else {
+            return Ok(None);
+        };
+        self.value = Some(value);
+        seed.deserialize(FrameDeserializer::new(key)).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, SerdeError> {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| SerdeError("map value without a key".to_string()))?;
+        seed.deserialize(FrameDeserializer::new(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+struct Variant {
+    variant: RespFrame,
+    value: RespFrame,
+}
+
+impl<'de> EnumAccess<'de> for Variant {
+    type Error = SerdeError;
+    type Variant = FrameDeserializer;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, FrameDeserializer), SerdeError> {
+        let variant = seed.deserialize(FrameDeserializer::new(self.variant))?;
+        Ok((variant, FrameDeserializer::new(self.value)))
+    }
+}
+
+impl<'de> VariantAccess<'de> for FrameDeserializer {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<(), SerdeError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, SerdeError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value, SerdeError> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bytes::Bytes;
+    use serde::{Deserialize, Serialize};
+
+    use crate::resp_frame::RespFrame;
+    use crate::resp_frame::serde::{from_frame, to_frame, to_frame_resp2};
+
+    fn bulk(s: &str) -> RespFrame {
+        RespFrame::BulkString(Bytes::copy_from_slice(s.as_bytes()))
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: u32,
+        admin: bool,
+        nick: Option<String>,
+        tags: Vec<String>,
+    }
+
+    fn ann() -> User {
+        User {
+            name: "ann".to_string(),
+            age: 30,
+            admin: false,
+            nick: None,
+            tags: vec!["a".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_structs_serialize_to_maps() {
+        let expected = vec![
+            (bulk("name"), bulk("ann")),
+            (bulk("age"), RespFrame::Integer(30)),
+            (bulk("admin"), RespFrame::Integer(0)),
+            (bulk("nick"), RespFrame::Null),
+            (bulk("tags"), RespFrame::Array(vec![bulk("a")])),
+        ];
+        assert_eq!(to_frame(&ann()), Ok(RespFrame::Map(expected.clone())));
+        assert_eq!(
+            to_frame_resp2(&ann()),
+            Ok(RespFrame::Array(
+                expected.into_iter().flat_map(|(k, v)| [k, v]).collect()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(from_frame::<User>(to_frame(&ann()).unwrap()), Ok(ann()));
+        assert_eq!(
+            from_frame::<User>(to_frame_resp2(&ann()).unwrap()),
+            Ok(ann())
+        );
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Shape {
+            Point,
+            Circle(f64),
+            Rect { w: i64, h: i64 },
+        }
+        for shape in [Shape::Point, Shape::Circle(1.5), Shape::Rect { w: 2, h: 3 }] {
+            assert_eq!(from_frame::<Shape>(to_frame(&shape).unwrap()), Ok(shape));
+        }
+    }
+
+    #[test]
+    fn test_hgetall_reply_into_a_struct() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Hash {
+            name: String,
+            age: u32,
+            admin: bool,
+            score: f64,
+        }
+        // every value of a hash comes back as a bulk string
+        let reply = RespFrame::Array(vec![
+            bulk("name"),
+            bulk("ann"),
+            bulk("age"),
+            bulk("30"),
+            bulk("admin"),
+            bulk("1"),
+            bulk("score"),
+            bulk("2.5"),
+        ]);
+        assert_eq!(
+            from_frame::<Hash>(reply),
+            Ok(Hash {
+                name: "ann".to_string(),
+                age: 30,
+                admin: true,
+                score: 2.5,
+            })
+        );
+
+        let counts = RespFrame::Map(vec![(bulk("a"), RespFrame::Integer(1))]);
+        assert_eq!(
+            from_frame::<BTreeMap<String, i64>>(counts),
+            Ok(BTreeMap::from([("a".to_string(), 1)]))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(from_frame::<u8>(RespFrame::Integer(300)).is_err());
+        assert!(from_frame::<i64>(bulk("x")).is_err());
+        assert_eq!(
+            from_frame::<String>(RespFrame::Error("ERR nope".to_string()))
+                .unwrap_err()
+                .to_string(),
+            "ERR nope"
+        );
+    }
+}
@@ -0,0 +1,1018 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use mlua::{
+    Function, HookTriggers, Lua, LuaOptions, MultiValue, RegistryKey, StdLib, Table, Value,
+};
+
+use crate::rdb;
+use crate::resp_frame::RespFrame;
+
+// once a script runs this long other clients get BUSY instead of waiting for it
+const BUSY_THRESHOLD: Duration = Duration::from_secs(5);
+
+// how often a running script checks whether SCRIPT KILL was called
+const KILL_CHECK_INSTRUCTIONS: u32 = 100_000;
+
+// commands redis.call refuses, they either nest scripts, change connection
+// state or snapshot the function libraries the running script locked
+const NOSCRIPT_COMMANDS: &[&str] = &[
+    "EVAL",
+    "EVALSHA",
+    "SCRIPT",
+    "FCALL",
+    "FCALL_RO",
+    "FUNCTION",
+    "MULTI",
+    "EXEC",
+    "DISCARD",
+    "WATCH",
+    "UNWATCH",
+    "SUBSCRIBE",
+    "PSUBSCRIBE",
+    "SSUBSCRIBE",
+    "UNSUBSCRIBE",
+    "PUNSUBSCRIBE",
+    "SUNSUBSCRIBE",
+    "HELLO",
+    "CLIENT",
+    "SAVE",
+    "BGSAVE",
+    "BGREWRITEAOF",
+];
+
+// Runs once when the Lua state is created. redis.call raises the error table
+// that redis.pcall returns, and globals can neither be created nor read when
+// missing, like in redis.
+const PRELUDE: &str = r#"
+redis.call = function(...)
+    local reply = redis.pcall(...)
+    if type(reply) == "table" and reply.err then
+        error(reply)
+    end
+    return reply
+end
+
+setmetatable(_G, {
+    __newindex = function(_, name)
+        error("Script attempted to create global variable '" .. tostring(name) .. "'", 2)
+    end,
+    __index = function(_, name)
+        error("Script attempted to access nonexistent global variable '" .. tostring(name) .. "'", 2)
+    end,
+})
+"#;
+
+const FUNCTION_FLAGS: &[&str] = &[
+    "no-writes",
+    "allow-oom",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+/// A library loaded with FUNCTION LOAD, as FUNCTION LIST reports it.
+#[derive(Debug, Clone)]
+pub struct LibraryInfo {
+    pub name: String,
+    pub code: Bytes,
+    pub functions: Vec<FunctionInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub flags: Vec<String>,
+}
+
+/// What FUNCTION RESTORE does with libraries that already exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestorePolicy {
+    Flush,
+    Append,
+    Replace,
+}
+
+struct LoadedFunction {
+    library: String,
+    callback: RegistryKey,
+    no_writes: bool,
+}
+
+pub fn sha1_hex(body: &[u8]) -> String {
+    sha1_smol::Sha1::from(body).digest().to_string()
+}
+
+struct Engine {
+    lua: Lua,
+    // sha1 -> compiled script
+    scripts: HashMap<String, RegistryKey>,
+    libraries: BTreeMap<String, LibraryInfo>,
+    // function name -> callback, function names are global across libraries
+    functions: HashMap<String, LoadedFunction>,
+}
+
+// The embedded Lua interpreter and its script cache. Scripts run while the
+// caller holds the storage lock, which is what makes them atomic.
+pub struct Scripting {
+    engine: Mutex<Engine>,
+    // when the running script started, read by other connections without the storage lock
+    running: Mutex<Option<Instant>>,
+    wrote: AtomicBool,
+    kill: Arc<AtomicBool>,
+}
+
+impl Scripting {
+    pub fn new() -> Self {
+        let kill = Arc::new(AtomicBool::new(false));
+        let lua = new_lua(kill.clone()).expect("failed to set up the Lua interpreter");
+
+        Scripting {
+            engine: Mutex::new(Engine {
+                lua,
+                scripts: HashMap::new(),
+                libraries: BTreeMap::new(),
+                functions: HashMap::new(),
+            }),
+            running: Mutex::new(None),
+            wrote: AtomicBool::new(false),
+            kill,
+        }
+    }
+
+    /// Compiles a script and adds it to the cache, returning its sha1.
+    pub fn load(&self, body: &[u8]) -> Result<String, RespFrame> {
+        let sha = sha1_hex(body);
+        let mut engine = self.engine.lock().unwrap();
+        if engine.scripts.contains_key(&sha) {
+            return Ok(sha);
+        }
+
+        let compiled = engine
+            .lua
+            .load(body)
+            .set_name("@user_script")
+            .into_function()
+            .and_then(|function| engine.lua.create_registry_value(function));
+        match compiled {
+            Ok(key) => {
+                engine.scripts.insert(sha.clone(), key);
+                Ok(sha)
+            }
+            Err(mlua::Error::SyntaxError { message, .. }) => Err(RespFrame::Error(format!(
+                "ERR Error compiling script (new function): {}",
+                message
+            ))),
+            Err(e) => Err(RespFrame::Error(format!("ERR {}", e))),
+        }
+    }
+
+    pub fn exists(&self, sha: &[u8]) -> bool {
+        let sha = String::from_utf8_lossy(sha).to_lowercase();
+        self.engine.lock().unwrap().scripts.contains_key(&sha)
+    }
+
+    pub fn flush(&self) {
+        let mut engine = self.engine.lock().unwrap();
+        engine.scripts.clear();
+        engine.lua.expire_registry_values();
+    }
+
+    /// Runs a cached script. `call` executes the commands the script issues
+    /// through redis.call and redis.pcall.
+    pub fn run(
+        &self,
+        sha: &[u8],
+        keys: Vec<Bytes>,
+        args: Vec<Bytes>,
+        call: &mut dyn FnMut(Vec<Bytes>) -> RespFrame,
+    ) -> RespFrame {
+        let sha = String::from_utf8_lossy(sha).to_lowercase();
+        let engine = self.engine.lock().unwrap();
+        let Some(script) = engine.scripts.get(&sha) else {
+            return RespFrame::Error("NOSCRIPT No matching script. Please use EVAL.".to_string());
+        };
+
+        self.guarded(&sha, || engine.run(script, false, keys, args, call))
+    }
+
+    /// Runs a function registered by a library, passing keys and arguments
+    /// as its two parameters instead of KEYS and ARGV.
+    pub fn fcall(
+        &self,
+        name: &[u8],
+        keys: Vec<Bytes>,
+        args: Vec<Bytes>,
+        call: &mut dyn FnMut(Vec<Bytes>) -> RespFrame,
+    ) -> RespFrame {
+        let name = String::from_utf8_lossy(name);
+        let engine = self.engine.lock().unwrap();
+        let Some(function) = engine.functions.get(name.as_ref()) else {
+            return RespFrame::Error("ERR Function not found".to_string());
+        };
+
+        self.guarded(&name, || {
+            engine.run(&function.callback, true, keys, args, call)
+        })
+    }
+
+    // tracks the running script for SCRIPT KILL and BUSY, and turns errors
+    // into replies that name the script
+    fn guarded(
+        &self,
+        name: &str,
+        run: impl FnOnce() -> mlua::Result<Result<RespFrame, String>>,
+    ) -> RespFrame {
+        self.wrote.store(false, Ordering::Relaxed);
+        self.kill.store(false, Ordering::Relaxed);
+        *self.running.lock().unwrap() = Some(Instant::now());
+
+        let result = run();
+
+        *self.running.lock().unwrap() = None;
+        if self.kill.swap(false, Ordering::Relaxed) {
+            return RespFrame::Error("ERR Script killed by user with SCRIPT KILL...".to_string());
+        }
+        match result {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(message)) | Err(mlua::Error::RuntimeError(message)) => {
+                RespFrame::Error(format!("ERR {} script: {}", message, name))
+            }
+            Err(e) => RespFrame::Error(format!("ERR {} script: {}", e, name)),
+        }
+    }
+
+    /// Loads a library from code starting with a `#!lua name=<library>`
+    /// line, returning the library name.
+    pub fn load_library(&self, code: &[u8], replace: bool) -> Result<String, RespFrame> {
+        self.engine
+            .lock()
+            .unwrap()
+            .load_library(code, replace)
+            .map_err(RespFrame::Error)
+    }
+
+    pub fn delete_library(&self, name: &[u8]) -> bool {
+        let mut engine = self.engine.lock().unwrap();
+        let deleted = engine.remove_library(&String::from_utf8_lossy(name));
+        engine.lua.expire_registry_values();
+        deleted
+    }
+
+    pub fn flush_functions(&self) {
+        let mut engine = self.engine.lock().unwrap();
+        engine.libraries.clear();
+        engine.functions.clear();
+        engine.lua.expire_registry_values();
+    }
+
+    pub fn libraries(&self) -> Vec<LibraryInfo> {
+        self.engine
+            .lock()
+            .unwrap()
+            .libraries
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Whether a function was registered with the no-writes flag, or `None`
+    /// if there is no such function.
+    pub fn function_is_read_only(&self, name: &[u8]) -> Option<bool> {
+        let engine = self.engine.lock().unwrap();
+        engine
+            .functions
+            .get(String::from_utf8_lossy(name).as_ref())
+            .map(|function| function.no_writes)
+    }
+
+    /// Serializes every library the way FUNCTION DUMP does: one FUNCTION2
+    /// opcode with the library code each, then the RDB footer.
+    pub fn dump_functions(&self) -> Vec<u8> {
+        let engine = self.engine.lock().unwrap();
+        let mut payload = Vec::new();
+        for library in engine.libraries.values() {
+            payload.push(rdb::OPCODE_FUNCTION2);
+            rdb::write_string(&mut payload, &library.code);
+        }
+        rdb::write_footer(&mut payload);
+        payload
+    }
+
+    pub fn restore_functions(
+        &self,
+        payload: &[u8],
+        policy: RestorePolicy,
+    ) -> Result<(), RespFrame> {
+        let Some(mut body) = rdb::verify_footer(payload) else {
+            return Err(RespFrame::Error(
+                "ERR payload version or checksum are wrong".to_string(),
+            ));
+        };
+
+        let mut codes = Vec::new();
+        while let Some((&opcode, rest)) = body.split_first() {
+            body = rest;
+            let code = match opcode {
+                rdb::OPCODE_FUNCTION2 => rdb::read_string(&mut body),
+                _ => None,
+            };
+            let Some(code) = code else {
+                return Err(RespFrame::Error(
+                    "ERR given type is not a function".to_string(),
+                ));
+            };
+            codes.push(code);
+        }
+
+        let mut engine = self.engine.lock().unwrap();
+        if policy == RestorePolicy::Flush {
+            engine.libraries.clear();
+            engine.functions.clear();
+        }
+        for code in codes {
+            engine
+                .load_library(&code, policy != RestorePolicy::Append)
+                .map_err(RespFrame::Error)?;
+        }
+        engine.lua.expire_registry_values();
+        Ok(())
+    }
+
+    /// Called by `run`'s callback whenever a command changed the dataset, after
+    /// which SCRIPT KILL is refused.
+    pub fn record_write(&self) {
+        self.wrote.store(true, Ordering::Relaxed);
+    }
+
+    pub fn kill(&self) -> RespFrame {
+        if self.running.lock().unwrap().is_none() {
+            return RespFrame::Error("NOTBUSY No scripts in execution right now.".to_string());
+        }
+        if self.wrote.load(Ordering::Relaxed) {
+            return RespFrame::Error("UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server in a hard way using the SHUTDOWN NOSAVE command.".to_string());
+        }
+        self.kill.store(true, Ordering::Relaxed);
+        RespFrame::SimpleString("OK".to_string())
+    }
+
+    /// Answers SCRIPT KILL, and BUSY for anything else while a script runs
+    /// past the threshold. Both have to be checked before taking the storage
+    /// lock, which the running script holds.
+    pub fn intercept(&self, frame: &RespFrame) -> Option<RespFrame> {
+        let RespFrame::Array(args) = frame else {
+            return None;
+        };
+        let is_kill = matches!(
+            args.as_slice(),
+            [RespFrame::BulkString(command), RespFrame::BulkString(subcommand)]
+                if (command.eq_ignore_ascii_case(b"SCRIPT") || command.eq_ignore_ascii_case(b"FUNCTION"))
+                    && subcommand.eq_ignore_ascii_case(b"KILL")
+        );
+        if is_kill {
+            return Some(self.kill());
+        }
+
+        let started = (*self.running.lock().unwrap())?;
+        (started.elapsed() >= BUSY_THRESHOLD).then(|| {
+            RespFrame::Error(
+                "BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSAVE."
+                    .to_string(),
+            )
+        })
+    }
+}
+
+impl Default for Scripting {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    // the outer error is a failure of the interpreter itself, the inner one a
+    // script error that redis reports with the script's sha
+    fn run(
+        &self,
+        script: &RegistryKey,
+        as_parameters: bool,
+        keys: Vec<Bytes>,
+        args: Vec<Bytes>,
+        call: &mut dyn FnMut(Vec<Bytes>) -> RespFrame,
+    ) -> mlua::Result<Result<RespFrame, String>> {
+        let lua = &self.lua;
+        let function: Function = lua.registry_value(script)?;
+        let globals = lua.globals();
+        let keys = string_table(lua, &keys)?;
+        let args = string_table(lua, &args)?;
+        // functions get their keys and arguments as parameters, EVAL scripts as globals
+        let parameters = if as_parameters {
+            (Value::Table(keys), Value::Table(args))
+        } else {
+            globals.raw_set("KEYS", keys)?;
+            globals.raw_set("ARGV", args)?;
+            (Value::Nil, Value::Nil)
+        };
+
+        let redis: Table = globals.raw_get("redis")?;
+        let pcall: Function = globals.raw_get("pcall")?;
+
+        let result = lua.scope(|scope| {
+            let redis_pcall = scope.create_function_mut(|lua, args: MultiValue| {
+                let reply = match command_args(args) {
+                    Ok(args) => {
+                        let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+                        if NOSCRIPT_COMMANDS.contains(&name.as_str()) {
+                            RespFrame::Error(
+                                "ERR This Redis command is not allowed from script".to_string(),
+                            )
+                        } else {
+                            call(args)
+                        }
+                    }
+                    Err(e) => RespFrame::Error(e.to_string()),
+                };
+                frame_to_lua(lua, reply)
+            })?;
+            redis.raw_set("pcall", redis_pcall)?;
+
+            let (ok, value): (bool, Value) = pcall.call((function, parameters.0, parameters.1))?;
+            Ok(match value {
+                _ if ok => Ok(lua_to_frame(value)),
+                // error tables, from redis.call or redis.error_reply, are sent as they are
+                Value::Table(_) => Ok(lua_to_frame(value)),
+                Value::Error(e) => Err(e.to_string()),
+                value => Err(lua
+                    .coerce_string(value)?
+                    .map(|message| message.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "unknown error".to_string())),
+            })
+        });
+
+        redis.raw_set("pcall", Value::Nil)?;
+        result
+    }
+
+    fn load_library(&mut self, code: &[u8], replace: bool) -> Result<String, String> {
+        let (name, body) = parse_library_metadata(code)?;
+        if !replace && self.libraries.contains_key(&name) {
+            return Err(format!("ERR Library '{}' already exists", name));
+        }
+
+        // only redis.register_function is available while the library code runs
+        let registered: RefCell<Vec<(FunctionInfo, RegistryKey)>> = RefCell::new(Vec::new());
+        let failure: RefCell<Option<String>> = RefCell::new(None);
+        let lua = &self.lua;
+        let redis: Table = lua.globals().raw_get("redis").map_err(|e| e.to_string())?;
+
+        let loaded = lua.scope(|scope| {
+            let register = scope.create_function(|lua, args: MultiValue| {
+                let result = parse_registration(lua, args).and_then(|(info, callback)| {
+                    if registered
+                        .borrow()
+                        .iter()
+                        .any(|(existing, _)| existing.name == info.name)
+                    {
+                        return Err("Function already exists in the library".to_string());
+                    }
+                    let key = lua
+                        .create_registry_value(callback)
+                        .map_err(|e| e.to_string())?;
+                    registered.borrow_mut().push((info, key));
+                    Ok(())
+                });
+                result.map_err(|message| {
+                    failure.borrow_mut().get_or_insert(message.clone());
+                    mlua::Error::runtime(message)
+                })
+            })?;
+            redis.raw_set("register_function", register)?;
+            lua.load(body).set_name("@user_function").exec()
+        });
+        let _ = redis.raw_set("register_function", Value::Nil);
+        drop(redis);
+
+        if let Err(e) = loaded {
+            return Err(match (failure.into_inner(), e) {
+                (Some(message), _) => format!("ERR {}", message),
+                (None, mlua::Error::SyntaxError { message, .. }) => {
+                    format!("ERR Error compiling function: {}", message)
+                }
+                (None, e) => format!("ERR Error registering functions: {}", e),
+            });
+        }
+
+        let registered = registered.into_inner();
+        if registered.is_empty() {
+            return Err("ERR No functions registered".to_string());
+        }
+        for (info, _) in &registered {
+            if self
+                .functions
+                .get(&info.name)
+                .is_some_and(|existing| existing.library != name)
+            {
+                return Err(format!("ERR Function {} already exists", info.name));
+            }
+        }
+
+        self.remove_library(&name);
+        let mut functions = Vec::with_capacity(registered.len());
+        for (info, callback) in registered {
+            self.functions.insert(
+                info.name.clone(),
+                LoadedFunction {
+                    library: name.clone(),
+                    callback,
+                    no_writes: info.flags.iter().any(|flag| flag == "no-writes"),
+                },
+            );
+            functions.push(info);
+        }
+        self.libraries.insert(
+            name.clone(),
+            LibraryInfo {
+                name: name.clone(),
+                code: Bytes::copy_from_slice(code),
+                functions,
+            },
+        );
+        Ok(name)
+    }
+
+    fn remove_library(&mut self, name: &str) -> bool {
+        let Some(library) = self.libraries.remove(name) else {
+            return false;
+        };
+        for function in library.functions {
+            self.functions.remove(&function.name);
+        }
+        true
+    }
+}
+
+// Splits `#!lua name=<library>` off the code. The line is blanked rather than
+// removed so error messages keep their line numbers.
+fn parse_library_metadata(code: &[u8]) -> Result<(String, Vec<u8>), String> {
+    let line_end = code
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .unwrap_or(code.len());
+    let Some(shebang) = code[..line_end].strip_prefix(b"#!") else {
+        return Err("ERR Missing library metadata".to_string());
+    };
+    let shebang = String::from_utf8_lossy(shebang);
+    let mut parts = shebang.split_whitespace();
+
+    let engine = parts.next().unwrap_or_default();
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(format!("ERR Engine '{}' not found", engine));
+    }
+
+    let mut name = None;
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(value) => name = Some(value.to_string()),
+            None => return Err(format!("ERR Invalid metadata value given: {}", part)),
+        }
+    }
+    let Some(name) = name else {
+        return Err("ERR Library name was not given".to_string());
+    };
+    if !is_valid_name(&name) {
+        return Err(
+            "ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long"
+                .to_string(),
+        );
+    }
+
+    Ok((name, code[line_end..].to_vec()))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// accepts both register_function(name, callback) and the table form with flags
+fn parse_registration<'lua>(
+    lua: &'lua Lua,
+    args: MultiValue<'lua>,
+) -> Result<(FunctionInfo, Function<'lua>), String> {
+    let args: Vec<Value> = args.into_iter().collect();
+    let (name, callback, flags, description) = match args.as_slice() {
+        [name, callback] => (name.clone(), callback.clone(), Value::Nil, Value::Nil),
+        [Value::Table(table)] => {
+            for pair in table.clone().pairs::<Value, Value>() {
+                let (key, _) = pair.map_err(|e| e.to_string())?;
+                let key = lua.coerce_string(key).ok().flatten();
+                let key = key.as_ref().map(|key| key.to_string_lossy());
+                if !matches!(
+                    key.as_deref(),
+                    Some("function_name" | "callback" | "flags" | "description")
+                ) {
+                    return Err("unknown argument given to redis.register_function".to_string());
+                }
+            }
+            let get = |field: &str| table.raw_get::<_, Value>(field).unwrap_or(Value::Nil);
+            (
+                get("function_name"),
+                get("callback"),
+                get("flags"),
+                get("description"),
+            )
+        }
+        _ => return Err("wrong number of arguments to redis.register_function".to_string()),
+    };
+
+    let Value::String(name) = name else {
+        return Err(
+            "function_name argument given to redis.register_function must be a string".to_string(),
+        );
+    };
+    let name = name.to_string_lossy().into_owned();
+    if !is_valid_name(&name) {
+        return Err("Function names can only contain letters, numbers, or underscores(_) and must be at least one character long".to_string());
+    }
+    let Value::Function(callback) = callback else {
+        return Err(
+            "callback argument given to redis.register_function must be a function".to_string(),
+        );
+    };
+
+    let flags = match flags {
+        Value::Nil => Vec::new(),
+        Value::Table(flags) => flags
+            .sequence_values::<mlua::String>()
+            .map(|flag| {
+                let flag = flag.map_err(|_| "unknown flag given".to_string())?;
+                let flag = flag.to_string_lossy().into_owned();
+                if FUNCTION_FLAGS.contains(&flag.as_str()) {
+                    Ok(flag)
+                } else {
+                    Err("unknown flag given".to_string())
+                }
+            })
+            .collect::<Result<_, _>>()?,
+        _ => return Err(
+            "flags argument to redis.register_function must be a table representing function flags"
+                .to_string(),
+        ),
+    };
+    let description = match description {
+        Value::Nil => None,
+        Value::String(description) => Some(description.to_string_lossy().into_owned()),
+        _ => {
+            return Err(
+                "description argument given to redis.register_function must be a string"
+                    .to_string(),
+            );
+        }
+    };
+
+    Ok((
+        FunctionInfo {
+            name,
+            description,
+            flags,
+        },
+        callback,
+    ))
+}
+
+fn new_lua(kill: Arc<AtomicBool>) -> mlua::Result<Lua> {
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+        LuaOptions::default(),
+    )?;
+    let globals = lua.globals();
+    for unsafe_function in ["dofile", "loadfile"] {
+        globals.raw_set(unsafe_function, Value::Nil)?;
+    }
+
+    let redis = lua.create_table()?;
+    redis.raw_set(
+        "sha1hex",
+        lua.create_function(|_, body: mlua::String| Ok(sha1_hex(body.as_bytes())))?,
+    )?;
+    redis.raw_set(
+        "error_reply",
+        lua.create_function(|lua, message: mlua::String| {
+            lua.create_table_from([("err", message)])
+        })?,
+    )?;
+    redis.raw_set(
+        "status_reply",
+        lua.create_function(|lua, message: mlua::String| lua.create_table_from([("ok", message)]))?,
+    )?;
+    globals.raw_set("redis", redis)?;
+    drop(globals);
+    lua.load(PRELUDE).set_name("=prelude").exec()?;
+
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(KILL_CHECK_INSTRUCTIONS),
+        move |_, _| {
+            if kill.load(Ordering::Relaxed) {
+                Err(mlua::Error::runtime(
+                    "Script killed by user with SCRIPT KILL...",
+                ))
+            } else {
+                Ok(())
+            }
+        },
+    );
+    Ok(lua)
+}
+
+fn string_table<'lua>(lua: &'lua Lua, items: &[Bytes]) -> mlua::Result<Table<'lua>> {
+    lua.create_sequence_from(
+        items
+            .iter()
+            .map(|item| lua.create_string(item))
+            .collect::<mlua::Result<Vec<_>>>()?,
+    )
+}
+
+// redis.call accepts strings and numbers only
+fn command_args(args: MultiValue) -> Result<Vec<Bytes>, &'static str> {
+    if args.is_empty() {
+        return Err("ERR Please specify at least one argument for this redis lib call");
+    }
+    args.into_iter()
+        .map(|arg| match arg {
+            Value::String(s) => Ok(Bytes::copy_from_slice(s.as_bytes())),
+            Value::Integer(n) => Ok(Bytes::from(n.to_string())),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e17 => {
+                Ok(Bytes::from((n as i64).to_string()))
+            }
+            Value::Number(n) => Ok(Bytes::from(n.to_string())),
+            _ => Err("ERR Lua redis lib command arguments must be strings or integers"),
+        })
+        .collect()
+}
+
+/// Converts a reply into the Lua value a script sees, following the RESP2
+/// conversion rules of redis.
+pub fn frame_to_lua(lua: &Lua, frame: RespFrame) -> mlua::Result<Value<'_>> {
+    Ok(match frame {
+        RespFrame::Integer(n) => Value::Integer(n),
+        RespFrame::BulkString(bytes) => Value::String(lua.create_string(&bytes)?),
+        RespFrame::Null => Value::Boolean(false),
+        RespFrame::SimpleString(s) => Value::Table(lua.create_table_from([("ok", s)])?),
+        RespFrame::Error(e) => Value::Table(lua.create_table_from([("err", e)])?),
+        RespFrame::Array(items) | RespFrame::Push(items) => {
+            let table = lua.create_table_with_capacity(items.len(), 0)?;
+            for item in items {
+                table.raw_push(frame_to_lua(lua, item)?)?;
+            }
+            Value::Table(table)
+        }
+        // scripts talk RESP2, so maps arrive flattened
+        RespFrame::Map(pairs) => {
+            let table = lua.create_table_with_capacity(pairs.len() * 2, 0)?;
+            for (key, value) in pairs {
+                table.raw_push(frame_to_lua(lua, key)?)?;
+                table.raw_push(frame_to_lua(lua, value)?)?;
+            }
+            Value::Table(table)
+        }
+    })
+}
+
+/// Converts a script's return value into a reply: numbers are truncated to
+/// integers, true becomes 1, false and nil become null, and tables become
+/// arrays up to their first nil unless they carry an `ok` or `err` field.
+pub fn lua_to_frame(value: Value) -> RespFrame {
+    match value {
+        Value::Nil | Value::Boolean(false) => RespFrame::Null,
+        Value::Boolean(true) => RespFrame::Integer(1),
+        Value::Integer(n) => RespFrame::Integer(n),
+        Value::Number(n) => RespFrame::Integer(n as i64),
+        Value::String(s) => RespFrame::BulkString(Bytes::copy_from_slice(s.as_bytes())),
+        Value::Table(table) => {
+            if let Ok(Value::String(err)) = table.raw_get("err") {
+                return RespFrame::Error(err.to_string_lossy().into_owned());
+            }
+            if let Ok(Value::String(ok)) = table.raw_get("ok") {
+                return RespFrame::SimpleString(ok.to_string_lossy().into_owned());
+            }
+
+            let mut items = Vec::new();
+            for index in 1.. {
+                match table.raw_get(index) {
+                    Ok(Value::Nil) | Err(_) => break,
+                    Ok(item) => items.push(lua_to_frame(item)),
+                }
+            }
+            RespFrame::Array(items)
+        }
+        _ => RespFrame::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::resp_frame::RespFrame;
+    use crate::scripting::{RestorePolicy, Scripting, sha1_hex};
+
+    fn bulk(s: &str) -> RespFrame {
+        RespFrame::BulkString(Bytes::copy_from_slice(s.as_bytes()))
+    }
+
+    fn eval(
+        scripting: &Scripting,
+        body: &str,
+        call: &mut dyn FnMut(Vec<Bytes>) -> RespFrame,
+    ) -> RespFrame {
+        let sha = scripting.load(body.as_bytes()).unwrap();
+        scripting.run(
+            sha.as_bytes(),
+            vec![Bytes::from("k")],
+            vec![Bytes::from("a")],
+            call,
+        )
+    }
+
+    #[test]
+    fn test_lua_values_convert_like_redis() {
+        let scripting = Scripting::new();
+        let mut call = |_: Vec<Bytes>| RespFrame::Null;
+
+        assert_eq!(
+            eval(
+                &scripting,
+                "return {1, 2.9, 'x', true, false, nil, 3}",
+                &mut call
+            ),
+            RespFrame::Array(vec![
+                RespFrame::Integer(1),
+                RespFrame::Integer(2),
+                bulk("x"),
+                RespFrame::Integer(1),
+                RespFrame::Null
+            ])
+        );
+        assert_eq!(
+            eval(&scripting, "return redis.status_reply('FINE')", &mut call),
+            RespFrame::SimpleString("FINE".to_string())
+        );
+        assert_eq!(
+            eval(
+                &scripting,
+                "return redis.error_reply('MY error')",
+                &mut call
+            ),
+            RespFrame::Error("MY error".to_string())
+        );
+        assert_eq!(
+            eval(&scripting, "return {KEYS[1], ARGV[1]}", &mut call),
+            RespFrame::Array(vec![bulk("k"), bulk("a")])
+        );
+    }
+
+    #[test]
+    fn test_call_raises_and_pcall_returns_errors() {
+        let scripting = Scripting::new();
+        let mut call = |args: Vec<Bytes>| match args[0].as_ref() {
+            b"GET" => bulk("value"),
+            b"NIL" => RespFrame::Null,
+            _ => RespFrame::Error("ERR boom".to_string()),
+        };
+
+        assert_eq!(
+            eval(&scripting, "return redis.call('GET', 'k')", &mut call),
+            bulk("value")
+        );
+        assert_eq!(
+            eval(&scripting, "return redis.call('NIL') == false", &mut call),
+            RespFrame::Integer(1)
+        );
+        assert_eq!(
+            eval(&scripting, "redis.call('FAIL') return 1", &mut call),
+            RespFrame::Error("ERR boom".to_string())
+        );
+        assert_eq!(
+            eval(&scripting, "return redis.pcall('FAIL').err", &mut call),
+            bulk("ERR boom")
+        );
+        assert_eq!(
+            eval(
+                &scripting,
+                "return redis.pcall('EVAL', 'return 1', 0)",
+                &mut call
+            ),
+            RespFrame::Error("ERR This Redis command is not allowed from script".to_string())
+        );
+    }
+
+    #[test]
+    fn test_globals_are_protected() {
+        let scripting = Scripting::new();
+        let mut call = |_: Vec<Bytes>| RespFrame::Null;
+
+        let RespFrame::Error(e) = eval(&scripting, "leak = 1", &mut call) else {
+            panic!("creating a global should fail");
+        };
+        assert!(e.contains("Script attempted to create global variable 'leak'"));
+    }
+
+    #[test]
+    fn test_script_cache() {
+        let scripting = Scripting::new();
+        let sha = scripting.load(b"return 1").unwrap();
+        assert_eq!(sha, sha1_hex(b"return 1"));
+        assert!(scripting.exists(sha.to_uppercase().as_bytes()));
+
+        assert!(matches!(
+            scripting.load(b"return ("),
+            Err(RespFrame::Error(e)) if e.starts_with("ERR Error compiling script")
+        ));
+
+        scripting.flush();
+        assert!(!scripting.exists(sha.as_bytes()));
+        assert_eq!(
+            scripting.kill(),
+            RespFrame::Error("NOTBUSY No scripts in execution right now.".to_string())
+        );
+    }
+
+    const LIBRARY: &str = "#!lua name=counters\n\
+        redis.register_function('bump', function(keys, args) return redis.call('INCR', keys[1]) end)\n\
+        redis.register_function{function_name='peek', callback=function(keys) return keys[1] end, flags={'no-writes'}}";
+
+    #[test]
+    fn test_libraries_register_functions() {
+        let scripting = Scripting::new();
+        assert_eq!(
+            scripting.load_library(LIBRARY.as_bytes(), false),
+            Ok("counters".to_string())
+        );
+        assert_eq!(scripting.function_is_read_only(b"peek"), Some(true));
+        assert_eq!(scripting.function_is_read_only(b"bump"), Some(false));
+
+        let mut call = |args: Vec<Bytes>| {
+            assert_eq!(args, vec![Bytes::from("INCR"), Bytes::from("hits")]);
+            RespFrame::Integer(7)
+        };
+        assert_eq!(
+            scripting.fcall(b"bump", vec![Bytes::from("hits")], vec![], &mut call),
+            RespFrame::Integer(7)
+        );
+
+        assert_eq!(
+            scripting.load_library(LIBRARY.as_bytes(), false),
+            Err(RespFrame::Error(
+                "ERR Library 'counters' already exists".to_string()
+            ))
+        );
+        assert_eq!(
+            scripting.load_library(
+                b"#!lua name=other\nredis.register_function('bump', function() end)",
+                false
+            ),
+            Err(RespFrame::Error(
+                "ERR Function bump already exists".to_string()
+            ))
+        );
+        assert_eq!(
+            scripting.load_library(b"return 1", false),
+            Err(RespFrame::Error("ERR Missing library metadata".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_dump_and_restore_functions() {
+        let source = Scripting::new();
+        source.load_library(LIBRARY.as_bytes(), false).unwrap();
+        let payload = source.dump_functions();
+
+        let target = Scripting::new();
+        target
+            .restore_functions(&payload, RestorePolicy::Append)
+            .unwrap();
+        assert_eq!(target.libraries()[0].name, "counters");
+        assert!(
+            target
+                .restore_functions(&payload, RestorePolicy::Append)
+                .is_err()
+        );
+        target
+            .restore_functions(&payload, RestorePolicy::Replace)
+            .unwrap();
+
+        let mut corrupt = payload.clone();
+        corrupt[3] ^= 1;
+        assert_eq!(
+            target.restore_functions(&corrupt, RestorePolicy::Flush),
+            Err(RespFrame::Error(
+                "ERR payload version or checksum are wrong".to_string()
+            ))
+        );
+    }
+}
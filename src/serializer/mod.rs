@@ -46,6 +46,29 @@ where
         RespFrame::Null => {
             stream.write_all(b"$-1\r\n").await?;
         }
+        RespFrame::Map(entries) => {
+            stream.write_all(b"%").await?;
+            stream
+                .write_all(entries.len().to_string().as_bytes())
+                .await?;
+            stream.write_all(b"\r\n").await?;
+
+            for (key, value) in entries {
+                Box::pin(serialize_frame(stream, key)).await?;
+                Box::pin(serialize_frame(stream, value)).await?;
+            }
+        }
+        RespFrame::Push(resp_frames) => {
+            stream.write_all(b">").await?;
+            stream
+                .write_all(resp_frames.len().to_string().as_bytes())
+                .await?;
+            stream.write_all(b"\r\n").await?;
+
+            for frame in resp_frames {
+                Box::pin(serialize_frame(stream, frame)).await?;
+            }
+        }
     }
     Ok(())
 }
@@ -82,4 +105,30 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(buf, b"$-1\r\n");
     }
+
+    #[tokio::test]
+    async fn test_serialize_push() {
+        let frame = RespFrame::Push(vec![
+            RespFrame::BulkString(Bytes::from("message")),
+            RespFrame::Integer(1),
+        ]);
+        let mut buf = Vec::new();
+        let result = serialize_frame(&mut buf, frame).await;
+
+        assert!(result.is_ok());
+        assert_eq!(buf, b">2\r\n$7\r\nmessage\r\n:1\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_serialize_map() {
+        let frame = RespFrame::Map(vec![(
+            RespFrame::SimpleString("proto".to_string()),
+            RespFrame::Integer(3),
+        )]);
+        let mut buf = Vec::new();
+        let result = serialize_frame(&mut buf, frame).await;
+
+        assert!(result.is_ok());
+        assert_eq!(buf, b"%1\r\n+proto\r\n:3\r\n");
+    }
 }
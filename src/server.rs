@@ -0,0 +1,522 @@
+// The network side of resprs: accepting clients, reading their commands and
+// writing replies, plus the background expiry, snapshot, AOF and replication
+// tasks.
+// Embedders build a `Server`, keep its `ShutdownHandle` and drive `run` on
+// their own runtime.
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter, ReadHalf, WriteHalf};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+
+use crate::aof::{self, Rewrite};
+use crate::config::Config;
+use crate::executor::Executor;
+use crate::outbox::{self, Inbox};
+use crate::replication::{self, ReplicaStream};
+use crate::session::Session;
+use crate::{
+    ServerState, close_session, execute_blocking, open_session, parser, propagate_expired,
+    publish_keyspace_events, run_blocking, serializer, track_keys, with_session_blocking,
+};
+
+// how often the background task looks for expired keys and how many it evicts per run
+const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+const ACTIVE_EXPIRE_MAX_KEYS: usize = 200;
+// how often save points are checked
+const SAVE_CRON_INTERVAL: Duration = Duration::from_secs(1);
+const AOF_FSYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Configures a `Server` before it binds.
+///
+/// ```no_run
+/// # async fn example() -> std::io::Result<()> {
+/// let server = resprs::Server::builder().bind("127.0.0.1:0").build().await?;
+/// let shutdown = server.shutdown_handle();
+/// tokio::spawn(server.run());
+/// shutdown.shutdown();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct ServerBuilder {
+    config: Config,
+    bind_addr: Option<String>,
+}
+
+impl ServerBuilder {
+    pub fn config(mut self, config: Config) -> ServerBuilder {
+        self.config = config;
+        self
+    }
+
+    /// Listens on `addr` instead of the bind and port of the config. Port 0
+    /// picks a free port, see `Server::local_addr`.
+    pub fn bind(mut self, addr: impl Into<String>) -> ServerBuilder {
+        self.bind_addr = Some(addr.into());
+        self
+    }
+
+    pub async fn build(self) -> io::Result<Server> {
+        let bind_addr = self.bind_addr.unwrap_or_else(|| self.config.bind_addr());
+        let listener = TcpListener::bind(&bind_addr).await?;
+        let (shutdown, _) = watch::channel(false);
+
+        let state = ServerState::new(self.config);
+        state
+            .replication
+            .set_listening_port(listener.local_addr()?.port());
+        load_data(&state)?;
+
+        Ok(Server {
+            listener,
+            state: Arc::new(state),
+            shutdown: ShutdownHandle {
+                sender: Arc::new(shutdown),
+            },
+        })
+    }
+}
+
+/// A bound resprs server. Nothing is accepted until `run` is awaited.
+pub struct Server {
+    listener: TcpListener,
+    state: Arc<ServerState>,
+    shutdown: ShutdownHandle,
+}
+
+/// Stops a running `Server`. Clones all stop the same server.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl ShutdownHandle {
+    /// Stops accepting clients and closes the open connections. `run`
+    /// returns once every connection is gone.
+    pub fn shutdown(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<bool> {
+        self.sender.subscribe()
+    }
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.state.config
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// A client that runs commands against this server without a socket.
+    pub fn executor(&self) -> Executor {
+        Executor::with_state(self.state.clone())
+    }
+
+    pub(crate) fn state(&self) -> Arc<ServerState> {
+        self.state.clone()
+    }
+
+    /// Serves clients until the shutdown handle fires.
+    pub async fn run(self) -> io::Result<()> {
+        let Server {
+            listener,
+            state,
+            shutdown,
+        } = self;
+        let mut stopped = shutdown.subscribe();
+        let expire = tokio::spawn(active_expire(state.clone()));
+        let save_cron = tokio::spawn(save_cron(state.clone()));
+        let aof_fsync = tokio::spawn(aof_fsync(state.clone()));
+        let replication = tokio::spawn(replication::run(state.clone()));
+        let mut connections = JoinSet::new();
+
+        let result = loop {
+            if *stopped.borrow_and_update() {
+                break Ok(());
+            }
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, peer_addr)) => {
+                        connections.spawn(handle_connection(
+                            stream,
+                            format!("{:?}", peer_addr),
+                            state.clone(),
+                            shutdown.subscribe(),
+                        ));
+                    }
+                    Err(e) => break Err(e),
+                },
+                // the value is checked at the top of the loop
+                _ = stopped.changed() => {}
+                // reap finished connections so the set does not grow forever
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            }
+        };
+
+        // connections see the same signal, unless accepting failed
+        shutdown.shutdown();
+        while connections.join_next().await.is_some() {}
+        expire.abort();
+        save_cron.abort();
+        aof_fsync.abort();
+        replication.abort();
+        state.aof.sync();
+        final_save(&state).await;
+        result
+    }
+}
+
+// serves one client over any byte stream, a TCP socket or an in-memory pipe
+pub(crate) async fn handle_connection<S>(
+    stream: S,
+    peer_addr: String,
+    state: Arc<ServerState>,
+    mut stopped: watch::Receiver<bool>,
+) where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    // splitting into read half and write half.
+    // serialise frame needs a writer
+    // bufreader needs a reader
+    let (read_half, write_half) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_half);
+
+    println!("Client connected {}", peer_addr);
+
+    // pub/sub messages can arrive at any time, so a separate task owns the
+    // write half and everything is sent to it through the session outbox
+    let (outbox, inbox) = outbox::channel();
+    let writer = tokio::spawn(write_frames(write_half, inbox));
+    let mut session = open_session(outbox, &state);
+    session.peer_addr = Some(peer_addr.clone());
+    let mut replica_stream = None;
+    let mut overflowed = false;
+
+    while !*stopped.borrow_and_update() {
+        let frame_result = tokio::select! {
+            frame_result = parser::parse_frame(&mut reader) => frame_result,
+            _ = stopped.changed() => break,
+            () = session.outbox.overflowed() => {
+                println!("Client {} closed for overcoming of output buffer limits", peer_addr);
+                overflowed = true;
+                break;
+            }
+        };
+
+        match frame_result {
+            Ok(frame) => {
+                println!("Received : {}", frame.wire());
+
+                // Process the command and get response
+                let response = execute_blocking(frame, &state, &mut session).await;
+
+                if session.outbox.send(response).is_err() {
+                    break;
+                }
+                // after PSYNC the connection carries the replication stream
+                if let Some(stream) = session.replica_stream.take() {
+                    replica_stream = Some(stream);
+                    break;
+                }
+            }
+
+            Err(e) => {
+                println!("Error parsing frame or connection closed: {}", e);
+                break;
+            }
+        }
+    }
+
+    let closing = state.clone();
+    with_session_blocking(&mut session, move |session| {
+        close_session(session, &closing)
+    })
+    .await;
+    match replica_stream {
+        Some(stream) => {
+            // the writer hands back the write half once the PSYNC reply is out
+            session.outbox = outbox::channel().0;
+            if let Ok(Some(write_half)) = writer.await {
+                serve_replica(reader, write_half, stream, &state, &mut session, stopped).await;
+            }
+            state.replication.remove_replica(session.id);
+        }
+        // what a subscriber that fell behind still has queued is not sent
+        None if overflowed => writer.abort(),
+        None => {
+            // dropping the last sender lets the writer finish what is queued and exit
+            drop(session);
+            let _ = writer.await;
+        }
+    }
+
+    println!("Client disconnected: {}", peer_addr);
+}
+
+// returns the writer once every sender is gone, None if writing failed
+async fn write_frames<S>(
+    write_half: WriteHalf<S>,
+    mut inbox: Inbox,
+) -> Option<BufWriter<WriteHalf<S>>>
+where
+    S: AsyncWrite,
+{
+    let mut writer = BufWriter::new(write_half);
+
+    while let Some(frame) = inbox.recv().await {
+        if let Err(e) = serializer::serialize_frame(&mut writer, frame).await {
+            println!("Error writing to client : {}", e);
+            return None;
+        }
+        // only flush once the queue is drained so pipelined replies share a write
+        if inbox.is_empty()
+            && let Err(e) = writer.flush().await
+        {
+            println!("Error writing to client : {}", e);
+            return None;
+        }
+    }
+    Some(writer)
+}
+
+// a replica's connection after PSYNC: the stream goes out from a task of its
+// own, the replica only sends REPLCONF ACKs, which get no reply
+async fn serve_replica<S>(
+    mut reader: BufReader<ReadHalf<S>>,
+    writer: BufWriter<WriteHalf<S>>,
+    stream: ReplicaStream,
+    state: &Arc<ServerState>,
+    session: &mut Session,
+    mut stopped: watch::Receiver<bool>,
+) where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let mut sending = tokio::spawn(send_stream(writer, stream));
+    while !*stopped.borrow_and_update() {
+        tokio::select! {
+            frame = parser::parse_frame(&mut reader) => match frame {
+                Ok(frame) => {
+                    execute_blocking(frame, state, session).await;
+                }
+                Err(_) => break,
+            },
+            _ = &mut sending => break,
+            _ = stopped.changed() => break,
+        }
+    }
+    sending.abort();
+}
+
+// ends when the primary drops the replica or the connection fails
+async fn send_stream<S>(mut writer: BufWriter<WriteHalf<S>>, mut stream: ReplicaStream)
+where
+    S: AsyncWrite,
+{
+    while let Some(data) = stream.recv().await {
+        if writer.write_all(&data).await.is_err() {
+            return;
+        }
+        if stream.is_empty() && writer.flush().await.is_err() {
+            return;
+        }
+    }
+}
+
+// evicts expired keys nobody asks for, lookups only catch the ones that are accessed
+async fn active_expire(state: Arc<ServerState>) {
+    let mut interval = tokio::time::interval(ACTIVE_EXPIRE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let state = state.clone();
+        run_blocking(move || {
+            let mut storage = state.db.lock().unwrap();
+            for keyspace in storage.databases_mut() {
+                keyspace.active_expire_cycle(ACTIVE_EXPIRE_MAX_KEYS);
+            }
+            propagate_expired(&mut storage, &state);
+            state.aof.flush();
+            if storage.key_tracking() {
+                track_keys(storage.take_tracked_keys(), &state, None, None);
+            }
+            publish_keyspace_events(&mut storage, &state);
+        })
+        .await;
+    }
+}
+
+// with appendonly on the AOF has the newest data, so like redis the
+// snapshot is only loaded when there is no AOF yet, which is then made from it
+pub(crate) fn load_data(state: &ServerState) -> io::Result<()> {
+    if state.config.appendonly && aof::load(state)? {
+        println!("DB loaded from append only file");
+        return Ok(());
+    }
+    let storage = &mut state.db.lock().unwrap();
+    if state.persistence.load(storage, &state.scripting)? {
+        println!("DB loaded from disk");
+    }
+    if state.config.appendonly
+        && state.aof.start(storage, &state.scripting, false) != Rewrite::Started
+    {
+        return Err(io::Error::other("could not create the append only file"));
+    }
+    Ok(())
+}
+
+// syncs the AOF once a second for appendfsync everysec
+async fn aof_fsync(state: Arc<ServerState>) {
+    let mut interval = tokio::time::interval(AOF_FSYNC_INTERVAL);
+    loop {
+        interval.tick().await;
+        let state = state.clone();
+        let _ = tokio::task::spawn_blocking(move || state.aof.fsync_if_needed()).await;
+    }
+}
+
+// starts a BGSAVE whenever a save point is reached
+async fn save_cron(state: Arc<ServerState>) {
+    let mut interval = tokio::time::interval(SAVE_CRON_INTERVAL);
+    loop {
+        interval.tick().await;
+        let state = state.clone();
+        run_blocking(move || {
+            let storage = state.db.lock().unwrap();
+            state.persistence.cron(&storage, &state.scripting);
+        })
+        .await;
+    }
+}
+
+// like redis-server, a server with save points snapshots on the way out
+async fn final_save(state: &Arc<ServerState>) {
+    if state.persistence.save_points().is_empty() {
+        return;
+    }
+    // a background save finishing later would rename its older data over ours
+    while state.persistence.in_progress() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let state = state.clone();
+    run_blocking(move || {
+        let storage = state.db.lock().unwrap();
+        match state.persistence.save(&storage, &state.scripting) {
+            Ok(()) => println!("DB saved on disk"),
+            Err(e) => println!("Error saving DB on disk: {}", e),
+        }
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use tokio::io::BufReader;
+    use tokio::net::TcpStream;
+
+    use crate::client::Client;
+    use crate::resp_frame::RespFrame;
+    use crate::server::Server;
+    use crate::testing::TestServer;
+    use crate::{parser, serializer};
+
+    fn command(args: &[&str]) -> RespFrame {
+        RespFrame::Array(
+            args.iter()
+                .map(|arg| RespFrame::BulkString(Bytes::copy_from_slice(arg.as_bytes())))
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_serves_clients_until_shutdown() {
+        let server = Server::builder().bind("127.0.0.1:0").build().await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let mut executor = server.executor();
+        let running = tokio::spawn(server.run());
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (read_half, mut write_half) = stream.into_split();
+        let mut reader = BufReader::new(read_half);
+
+        serializer::serialize_frame(&mut write_half, command(&["SET", "k", "v"]))
+            .await
+            .unwrap();
+        assert_eq!(
+            parser::parse_frame(&mut reader).await.unwrap(),
+            RespFrame::SimpleString("OK".to_string())
+        );
+        // the executor shares the keyspace with network clients
+        assert_eq!(
+            executor.execute(command(&["GET", "k"])),
+            RespFrame::BulkString(Bytes::from("v"))
+        );
+
+        shutdown.shutdown();
+        running.await.unwrap().unwrap();
+        assert!(parser::parse_frame(&mut reader).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_that_does_not_read_is_disconnected() {
+        let server = TestServer::start().await;
+        let publisher = Client::connect(server.addr()).await.unwrap();
+        let stream = TcpStream::connect(server.addr()).await.unwrap();
+        let (read_half, mut write_half) = stream.into_split();
+        let mut reader = BufReader::new(read_half);
+        serializer::serialize_frame(&mut write_half, command(&["SUBSCRIBE", "ch"]))
+            .await
+            .unwrap();
+        parser::parse_frame(&mut reader).await.unwrap();
+
+        // nothing is read from here on, so the messages pile up on the server
+        let message = vec![b'x'; 1024 * 1024];
+        let mut published = 0;
+        while publisher.publish("ch", &message).await.unwrap() == 1 {
+            published += 1;
+            assert!(published < 200, "the subscriber was never disconnected");
+        }
+        assert!(published > 32);
+        // the connection closes on its own time, then its subscriptions go
+        let numsub = || async {
+            publisher
+                .command(&["PUBSUB", "NUMSUB", "ch"])
+                .await
+                .unwrap()
+        };
+        let unsubscribed = RespFrame::Array(vec![
+            RespFrame::BulkString(Bytes::from("ch")),
+            RespFrame::Integer(0),
+        ]);
+        for _ in 0..100 {
+            if numsub().await == unsubscribed {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("the subscriber is still subscribed");
+    }
+}
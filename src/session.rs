@@ -0,0 +1,142 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::Bytes;
+
+use crate::commands::PendingMigrate;
+use crate::outbox::{self, Outbox};
+use crate::replication::ReplicaStream;
+use crate::resp_frame::RespFrame;
+use crate::tracking::TrackingOptions;
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+// Per connection state that outlives a single command.
+#[derive(Debug)]
+pub struct Session {
+    pub id: u64,
+    // everything written to the client goes through here, replies and pub/sub messages alike
+    pub outbox: Outbox,
+    pub resp3: bool,
+    pub name: Option<Bytes>,
+    pub db_index: usize,
+    // set between MULTI and EXEC/DISCARD
+    pub transaction: Option<Transaction>,
+    pub watched_keys: Vec<WatchedKey>,
+    pub channels: HashSet<Bytes>,
+    pub patterns: HashSet<Bytes>,
+    pub shard_channels: HashSet<Bytes>,
+    // set by CLIENT TRACKING ON, a copy of what the tracking table holds
+    pub tracking: Option<TrackingOptions>,
+    // CLIENT CACHING YES|NO, only applies to the next command
+    pub caching: Option<bool>,
+    // host:port the client connected from, None for in-process clients
+    pub peer_addr: Option<String>,
+    // on a replica, the link to its primary, which may write although
+    // clients can't
+    pub is_master: bool,
+    // the port a replica said it listens on, with REPLCONF listening-port
+    pub replica_port: Option<u16>,
+    // set by PSYNC: the replication stream the connection turns into once
+    // the reply is sent
+    pub replica_stream: Option<ReplicaStream>,
+    // set by MIGRATE: the keys to send once the storage lock is released
+    pub migration: Option<PendingMigrate>,
+}
+
+impl Session {
+    pub fn new(outbox: Outbox) -> Self {
+        Session::with_id(NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed), outbox)
+    }
+
+    // what is left behind while a session is lent to a blocking task, it
+    // takes no client id and is never used
+    pub fn placeholder() -> Self {
+        Session::with_id(0, outbox::channel().0)
+    }
+
+    fn with_id(id: u64, outbox: Outbox) -> Self {
+        Session {
+            id,
+            outbox,
+            resp3: false,
+            name: None,
+            db_index: 0,
+            transaction: None,
+            watched_keys: Vec::new(),
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            shard_channels: HashSet::new(),
+            tracking: None,
+            caching: None,
+            peer_addr: None,
+            is_master: false,
+            replica_port: None,
+            replica_stream: None,
+            migration: None,
+        }
+    }
+
+    // what SUBSCRIBE and PSUBSCRIBE replies report, shard channels are counted apart
+    pub fn subscription_count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    // RESP2 connections with subscriptions can only run pub/sub commands
+    pub fn in_subscriber_mode(&self) -> bool {
+        !self.resp3 && (self.subscription_count() > 0 || !self.shard_channels.is_empty())
+    }
+
+    /// Whether the keys read by a command should be remembered for
+    /// invalidation, given the CLIENT CACHING answer that preceded it.
+    pub fn tracks_reads(&self, caching: Option<bool>) -> bool {
+        match &self.tracking {
+            Some(options) if options.bcast => false,
+            Some(options) if options.optin => caching == Some(true),
+            Some(options) if options.optout => caching != Some(false),
+            Some(_) => true,
+            None => false,
+        }
+    }
+}
+
+// The ids of the clients connected to a server, for the commands that name
+// another client
+#[derive(Debug, Default)]
+pub struct Clients {
+    ids: Mutex<HashSet<u64>>,
+}
+
+impl Clients {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, client_id: u64) {
+        self.ids.lock().unwrap().insert(client_id);
+    }
+
+    pub fn unregister(&self, client_id: u64) {
+        self.ids.lock().unwrap().remove(&client_id);
+    }
+
+    pub fn contains(&self, client_id: u64) -> bool {
+        self.ids.lock().unwrap().contains(&client_id)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Transaction {
+    pub queued: Vec<RespFrame>,
+    // a command was rejected while queueing, so EXEC must fail
+    pub aborted: bool,
+}
+
+// a key registered with `Keyspace::watch`, and the stamp it had then
+#[derive(Debug)]
+pub struct WatchedKey {
+    pub db_index: usize,
+    pub key: Bytes,
+    pub stamp: u64,
+}
@@ -0,0 +1,87 @@
+// Cluster hash slots, computed the same way as redis cluster so clients agree
+// on where a key or shard channel lives.
+use crate::config::SlotRange;
+
+pub const SLOT_COUNT: u16 = 16384;
+
+/// The node serving `slot`, None when it is this one.
+pub fn slot_node(ranges: &[SlotRange], slot: u16) -> Option<&str> {
+    ranges
+        .iter()
+        .find(|range| (range.start..=range.end).contains(&slot))
+        .map(|range| range.node.as_str())
+}
+
+/// CRC16 (XMODEM) of the key, or of its {hash tag} when it has a non empty
+/// one, modulo 16384.
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    let hashed = match key.iter().position(|byte| *byte == b'{') {
+        Some(open) => match key[open + 1..].iter().position(|byte| *byte == b'}') {
+            Some(len) if len > 0 => &key[open + 1..open + 1 + len],
+            _ => key,
+        },
+        None => key,
+    };
+    crc16(hashed) % SLOT_COUNT
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::SlotRange;
+    use crate::slot::{crc16, key_hash_slot, slot_node};
+
+    #[test]
+    fn test_crc16_reference_value() {
+        // the check value from the redis cluster spec
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+    }
+
+    #[test]
+    fn test_key_hash_slot() {
+        assert_eq!(key_hash_slot(b"foo"), 12182);
+        assert_eq!(
+            key_hash_slot(b"{user1000}.following"),
+            key_hash_slot(b"user1000")
+        );
+        // an empty tag hashes the whole key
+        assert_eq!(key_hash_slot(b"foo{}{bar}"), crc16(b"foo{}{bar}") % 16384);
+    }
+
+    #[test]
+    fn test_slot_ranges() {
+        let ranges = SlotRange::parse_list("0-99 10.0.0.2:7001, 16383 [::1]:7002").unwrap();
+        assert_eq!(
+            ranges[0],
+            SlotRange {
+                start: 0,
+                end: 99,
+                node: "10.0.0.2:7001".to_string()
+            }
+        );
+        assert_eq!(ranges[1].to_string(), "16383-16383 [::1]:7002");
+        assert_eq!(slot_node(&ranges, 99), Some("10.0.0.2:7001"));
+        assert_eq!(slot_node(&ranges, 100), None);
+        assert_eq!(slot_node(&ranges, 16383), Some("[::1]:7002"));
+
+        assert_eq!(SlotRange::parse_list(""), Some(vec![]));
+        assert!(SlotRange::parse_list("0-16384 host:1").is_none());
+        assert!(SlotRange::parse_list("10-5 host:1").is_none());
+        assert!(SlotRange::parse_list("0-5 host").is_none());
+        assert!(SlotRange::parse_list("0-5").is_none());
+    }
+}
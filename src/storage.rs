@@ -0,0 +1,815 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+
+use crate::clock::Clock;
+use crate::notify::{self, KeyspaceEvent, NotifyFlags};
+use crate::random_index;
+
+pub struct RedisValue {
+    pub data: Bytes,
+    pub expires_at: Option<Instant>,
+    // used by OBJECT IDLETIME / OBJECT FREQ
+    pub last_accessed: Instant,
+    // redis' LFU counter as of `last_accessed`, see `frequency`
+    pub lfu_counter: u8,
+}
+
+// stamps of watched keys are unique across all databases, so a key that
+// comes back from another database with MOVE or SWAPDB never looks untouched
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(1);
+
+fn next_version() -> u64 {
+    NEXT_VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
+// redis' defaults for new keys, lfu-log-factor and lfu-decay-time
+const LFU_INIT_VAL: u8 = 5;
+const LFU_LOG_FACTOR: f64 = 10.0;
+const LFU_DECAY_PERIOD: Duration = Duration::from_secs(60);
+
+// a number in [0, 1) for the LFU counter's coin flips
+fn random_fraction() -> f64 {
+    random_index(1 << 24) as f64 / (1 << 24) as f64
+}
+
+impl RedisValue {
+    pub fn new(data: Bytes) -> Self {
+        RedisValue {
+            data,
+            expires_at: None,
+            last_accessed: Instant::now(),
+            lfu_counter: LFU_INIT_VAL,
+        }
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        match self.expires_at {
+            Some(instant) => instant < now,
+            _ => false,
+        }
+    }
+
+    /// The access frequency OBJECT FREQ reports. Like redis' LFU counter it
+    /// grows logarithmically with the number of accesses and loses one for
+    /// every minute the key is not used.
+    pub fn frequency(&self, now: Instant) -> u8 {
+        let idle = now.saturating_duration_since(self.last_accessed);
+        let periods = idle.as_secs() / LFU_DECAY_PERIOD.as_secs();
+        self.lfu_counter
+            .saturating_sub(u8::try_from(periods).unwrap_or(u8::MAX))
+    }
+
+    fn touch(&mut self, now: Instant) {
+        let mut counter = self.frequency(now);
+        // the higher the counter, the less likely an access increments it
+        let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+        if counter < u8::MAX && random_fraction() < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+            counter += 1;
+        }
+        self.lfu_counter = counter;
+        self.last_accessed = now;
+    }
+
+    // mirrors the encodings redis reports for strings
+    pub fn encoding(&self) -> &'static str {
+        let is_int = self.data.len() <= 20
+            && std::str::from_utf8(&self.data).is_ok_and(|s| s.parse::<i64>().is_ok());
+
+        if is_int {
+            "int"
+        } else if self.data.len() <= 44 {
+            "embstr"
+        } else {
+            "raw"
+        }
+    }
+}
+
+// Keys read and modified by a command, collected only while some client has
+// CLIENT TRACKING enabled.
+#[derive(Default)]
+pub struct TrackedKeys {
+    pub read: Vec<Bytes>,
+    pub modified: Vec<Bytes>,
+    // FLUSHDB and FLUSHALL invalidate everything at once
+    pub flushed: bool,
+}
+
+// A key some clients WATCH. The stamp changes whenever the key is written,
+// created, deleted, expired or flushed, even if it ends up as it was.
+struct Watch {
+    watchers: usize,
+    touched: u64,
+}
+
+// All key access goes through the lookup functions below so that lazy expiry,
+// access stats and keyspace events are applied the same way for every command.
+#[derive(Default)]
+pub struct Keyspace {
+    entries: HashMap<Bytes, RedisValue>,
+    watched: HashMap<Bytes, Watch>,
+    notify_flags: NotifyFlags,
+    // keyspace events waiting to be published once the command finishes
+    events: Vec<KeyspaceEvent>,
+    key_tracking: bool,
+    tracked: TrackedKeys,
+    // number of changes ever made, like redis' server.dirty
+    dirty: u64,
+    // keys evicted on expiry, deleted on the AOF and the replicas next
+    expired: Vec<Bytes>,
+    // on a replica expired keys are only hidden from reads, the primary
+    // deletes them when its clock says so
+    keep_expired: bool,
+    clock: Clock,
+}
+
+impl Keyspace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The current time as far as expiry is concerned.
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Looks up a key for reading, evicting it first if it has expired.
+    pub fn lookup_read(&mut self, key: &Bytes) -> Option<&RedisValue> {
+        let expired = self.expire_if_needed(key);
+        self.track_read(key);
+        if expired || !self.entries.contains_key(key) {
+            self.notify(notify::KEY_MISS, "keymiss", key);
+            return None;
+        }
+        let value = self.entries.get_mut(key)?;
+        value.touch(self.clock.now());
+        Some(value)
+    }
+
+    /// Looks up a key for modification, evicting it first if it has expired.
+    /// The key only counts as changed once the caller reports the write with
+    /// `mark_modified`, so a command that fails half way changes nothing.
+    pub fn lookup_write(&mut self, key: &Bytes) -> Option<&mut RedisValue> {
+        self.expire_if_needed(key);
+        if !self.entries.contains_key(key) {
+            return None;
+        }
+        let value = self.entries.get_mut(key)?;
+        value.touch(self.clock.now());
+        Some(value)
+    }
+
+    /// Like `lookup_read` but without counting as an access, for
+    /// introspection commands such as TTL, TYPE and OBJECT.
+    pub fn peek(&mut self, key: &Bytes) -> Option<&RedisValue> {
+        let expired = self.expire_if_needed(key);
+        self.track_read(key);
+        if expired {
+            return None;
+        }
+        self.entries.get(key)
+    }
+
+    /// Looks up a key for modification, inserting `default()` if the key is
+    /// missing or has expired. Like `lookup_write`, the caller reports the
+    /// write with `mark_modified`.
+    pub fn lookup_write_or_insert(
+        &mut self,
+        key: &Bytes,
+        default: impl FnOnce() -> RedisValue,
+    ) -> &mut RedisValue {
+        self.expire_if_needed(key);
+        if !self.entries.contains_key(key) {
+            self.notify(notify::NEW, "new", key);
+        }
+        let now = self.clock.now();
+        match self.entries.entry(key.clone()) {
+            Entry::Occupied(entry) => {
+                let value = entry.into_mut();
+                value.touch(now);
+                value
+            }
+            // a new key starts out with the initial frequency
+            Entry::Vacant(entry) => {
+                let mut value = default();
+                value.last_accessed = now;
+                entry.insert(value)
+            }
+        }
+    }
+
+    /// Starts watching a key for a transaction, returning the stamp EXEC
+    /// compares with `watch_stamp`. Every `watch` needs an `unwatch`.
+    pub fn watch(&mut self, key: &Bytes) -> u64 {
+        // a key that expired before WATCH was not changed after it
+        self.expire_if_needed(key);
+        let watch = self.watched.entry(key.clone()).or_insert_with(|| Watch {
+            watchers: 0,
+            touched: next_version(),
+        });
+        watch.watchers += 1;
+        watch.touched
+    }
+
+    pub fn unwatch(&mut self, key: &Bytes) {
+        if let Some(watch) = self.watched.get_mut(key) {
+            watch.watchers -= 1;
+            if watch.watchers == 0 {
+                self.watched.remove(key);
+            }
+        }
+    }
+
+    /// The current stamp of a watched key, which changes when the key does.
+    pub fn watch_stamp(&mut self, key: &Bytes) -> Option<u64> {
+        self.expire_if_needed(key);
+        self.watched.get(key).map(|watch| watch.touched)
+    }
+
+    // marks the watched keys that exist here or in `others` as changed, for
+    // commands that replace the whole keyspace
+    fn touch_all_watched(&mut self, others: &HashMap<Bytes, RedisValue>) {
+        for (key, watch) in &mut self.watched {
+            if self.entries.contains_key(key) || others.contains_key(key) {
+                watch.touched = next_version();
+            }
+        }
+    }
+
+    pub fn contains(&mut self, key: &Bytes) -> bool {
+        self.peek(key).is_some()
+    }
+
+    /// Stores a value, returning the previous one if it was still alive.
+    pub fn insert(&mut self, key: Bytes, mut value: RedisValue) -> Option<RedisValue> {
+        value.last_accessed = self.now();
+        self.restore(key, value)
+    }
+
+    /// Like `insert`, but keeps the access time and frequency of the value,
+    /// which RESTORE can set.
+    pub fn restore(&mut self, key: Bytes, value: RedisValue) -> Option<RedisValue> {
+        self.expire_if_needed(&key);
+        if !self.entries.contains_key(&key) {
+            self.notify(notify::NEW, "new", &key);
+        }
+        self.mark_modified(&key);
+        self.entries.insert(key, value)
+    }
+
+    /// Removes a key, returning its value only if it had not expired.
+    pub fn remove(&mut self, key: &Bytes) -> Option<RedisValue> {
+        self.expire_if_needed(key);
+        let value = self.entries.remove(key)?;
+        self.mark_modified(key);
+        Some(value)
+    }
+
+    /// Returns every live key, evicting the expired ones along the way.
+    pub fn keys(&mut self) -> Vec<Bytes> {
+        let now = self.now();
+        let expired: Vec<Bytes> = self
+            .entries
+            .iter()
+            .filter(|(_, value)| value.is_expired(now))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in &expired {
+            self.expire_if_needed(key);
+        }
+
+        self.entries
+            .iter()
+            .filter(|(_, value)| !value.is_expired(now))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Every key that has not expired, without evicting anything or counting
+    /// as an access. Snapshots are built from this.
+    pub fn live_entries(&self) -> impl Iterator<Item = (&Bytes, &RedisValue)> {
+        let now = self.now();
+        self.entries
+            .iter()
+            .filter(move |(_, value)| !value.is_expired(now))
+    }
+
+    // like redis, DBSIZE counts keys that have expired but were not evicted yet
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of keys with a ttl and their average remaining ttl in milliseconds,
+    /// as reported by INFO keyspace.
+    pub fn expiry_stats(&self) -> (usize, u128) {
+        let now = self.now();
+        let ttls: Vec<u128> = self
+            .entries
+            .values()
+            .filter_map(|value| value.expires_at)
+            .map(|instant| instant.saturating_duration_since(now).as_millis())
+            .collect();
+
+        if ttls.is_empty() {
+            return (0, 0);
+        }
+        (ttls.len(), ttls.iter().sum::<u128>() / ttls.len() as u128)
+    }
+
+    /// Empties the keyspace, handing back the old contents so the caller
+    /// decides where they get dropped.
+    pub fn flush(&mut self) -> Keyspace {
+        if self.key_tracking {
+            self.tracked.flushed = true;
+        }
+        self.dirty += self.entries.len() as u64;
+        self.touch_all_watched(&HashMap::new());
+        Keyspace {
+            entries: std::mem::take(&mut self.entries),
+            ..Keyspace::default()
+        }
+    }
+
+    /// Records a keyspace event if its class is enabled. With notifications
+    /// off this is a single flag check.
+    pub fn notify(&mut self, class: u16, event: &'static str, key: &Bytes) {
+        if self.notify_flags.wants(class) {
+            self.events.push(KeyspaceEvent {
+                event,
+                key: key.clone(),
+            });
+        }
+    }
+
+    pub fn take_events(&mut self) -> Vec<KeyspaceEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    fn track_read(&mut self, key: &Bytes) {
+        if self.key_tracking {
+            self.tracked.read.push(key.clone());
+        }
+    }
+
+    /// Records that a key was written: it counts towards `dirty`, breaks the
+    /// WATCH of transactions and invalidates the clients tracking it.
+    pub fn mark_modified(&mut self, key: &Bytes) {
+        self.dirty += 1;
+        if let Some(watch) = self.watched.get_mut(key) {
+            watch.touched = next_version();
+        }
+        if self.key_tracking {
+            self.tracked.modified.push(key.clone());
+        }
+    }
+
+    /// Evicts a batch of expired keys, returning how many were removed.
+    pub fn active_expire_cycle(&mut self, max_keys: usize) -> usize {
+        if self.keep_expired {
+            return 0;
+        }
+        let now = self.now();
+        let expired: Vec<Bytes> = self
+            .entries
+            .iter()
+            .filter(|(_, value)| value.is_expired(now))
+            .take(max_keys)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in &expired {
+            self.expire_if_needed(key);
+        }
+        expired.len()
+    }
+
+    // the single place where expired keys get evicted, returns whether the
+    // key has expired. A replica keeps the key for the writes of its
+    // primary, which sees it as live until it sends the DEL.
+    fn expire_if_needed(&mut self, key: &Bytes) -> bool {
+        let now = self.now();
+        if !self
+            .entries
+            .get(key)
+            .is_some_and(|value| value.is_expired(now))
+        {
+            return false;
+        }
+        if self.keep_expired {
+            return true;
+        }
+        self.entries.remove(key);
+        self.notify(notify::EXPIRED, "expired", key);
+        self.mark_modified(key);
+        self.expired.push(key.clone());
+        true
+    }
+}
+
+// The numbered databases selectable with SELECT.
+pub struct Storage {
+    databases: Vec<Keyspace>,
+    clock: Clock,
+}
+
+impl Storage {
+    pub fn new(database_count: usize) -> Self {
+        let clock = Clock::default();
+        Storage {
+            databases: (0..database_count)
+                .map(|_| Keyspace {
+                    clock: clock.clone(),
+                    ..Keyspace::default()
+                })
+                .collect(),
+            clock,
+        }
+    }
+
+    /// The clock every database expires keys by.
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Returns the database at `index`, which callers validate with
+    /// `database_index` first.
+    pub fn db(&mut self, index: usize) -> &mut Keyspace {
+        &mut self.databases[index]
+    }
+
+    /// Parses a client supplied database index, checking it is in range.
+    pub fn database_index(&self, index: i64) -> Option<usize> {
+        usize::try_from(index)
+            .ok()
+            .filter(|index| *index < self.databases.len())
+    }
+
+    pub fn databases(&self) -> impl Iterator<Item = (usize, &Keyspace)> {
+        self.databases.iter().enumerate()
+    }
+
+    pub fn databases_mut(&mut self) -> impl Iterator<Item = &mut Keyspace> {
+        self.databases.iter_mut()
+    }
+
+    pub fn notify_flags(&self) -> NotifyFlags {
+        self.databases[0].notify_flags
+    }
+
+    pub fn set_notify_flags(&mut self, flags: NotifyFlags) {
+        for keyspace in &mut self.databases {
+            keyspace.notify_flags = flags;
+        }
+    }
+
+    /// Total number of key changes since startup, across all databases.
+    pub fn dirty(&self) -> u64 {
+        self.databases.iter().map(|keyspace| keyspace.dirty).sum()
+    }
+
+    pub fn key_tracking(&self) -> bool {
+        self.databases[0].key_tracking
+    }
+
+    /// Turns recording of read and modified keys on or off, which is only
+    /// needed while a client uses CLIENT TRACKING.
+    pub fn set_key_tracking(&mut self, enabled: bool) {
+        for keyspace in &mut self.databases {
+            keyspace.key_tracking = enabled;
+            keyspace.tracked = TrackedKeys::default();
+        }
+    }
+
+    /// Drains the keys recorded for client side caching in every database.
+    /// The tracking table is not per database, so neither is the result.
+    pub fn take_tracked_keys(&mut self) -> TrackedKeys {
+        let mut all = TrackedKeys::default();
+        for keyspace in &mut self.databases {
+            let tracked = std::mem::take(&mut keyspace.tracked);
+            all.read.extend(tracked.read);
+            all.modified.extend(tracked.modified);
+            all.flushed |= tracked.flushed;
+        }
+        all
+    }
+
+    /// Stops deleting expired keys on a replica, or starts again once it
+    /// becomes a primary.
+    pub fn set_keep_expired(&mut self, keep: bool) {
+        for keyspace in &mut self.databases {
+            keyspace.keep_expired = keep;
+        }
+    }
+
+    /// Drains the keys evicted on expiry in every database, for the DELs
+    /// that tell the AOF and the replicas.
+    pub fn take_expired(&mut self) -> Vec<(usize, Vec<Bytes>)> {
+        self.databases
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, keyspace)| !keyspace.expired.is_empty())
+            .map(|(index, keyspace)| (index, std::mem::take(&mut keyspace.expired)))
+            .collect()
+    }
+
+    /// Drains the recorded keyspace events of every database.
+    pub fn take_events(&mut self) -> Vec<(usize, Vec<KeyspaceEvent>)> {
+        self.databases
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, keyspace)| !keyspace.events.is_empty())
+            .map(|(index, keyspace)| (index, keyspace.take_events()))
+            .collect()
+    }
+
+    pub fn swap(&mut self, first: usize, second: usize) {
+        if first == second {
+            return;
+        }
+        self.databases.swap(first, second);
+        let (low, high) = self.databases.split_at_mut(first.max(second));
+        let (one, other) = (&mut low[first.min(second)], &mut high[0]);
+        // clients watch a key in a numbered database, whatever data it holds
+        std::mem::swap(&mut one.watched, &mut other.watched);
+        one.touch_all_watched(&other.entries);
+        other.touch_all_watched(&one.entries);
+    }
+
+    /// Moves a key between two databases, keeping its ttl. Fails if the key
+    /// is missing from `from` or already present in `to`.
+    pub fn move_key(&mut self, key: &Bytes, from: usize, to: usize) -> bool {
+        if self.databases[to].contains(key) {
+            return false;
+        }
+        let Some(value) = self.databases[from].remove(key) else {
+            return false;
+        };
+        self.databases[to].insert(key.clone(), value);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bytes::Bytes;
+
+    use crate::notify::NotifyFlags;
+    use crate::storage::{Keyspace, RedisValue, Storage};
+
+    fn expired_value(data: &'static str) -> RedisValue {
+        let mut value = RedisValue::new(Bytes::from(data));
+        value.expires_at = Some(Instant::now() - Duration::from_secs(1));
+        value
+    }
+
+    #[test]
+    fn test_lookup_evicts_expired_key() {
+        let mut keyspace = Keyspace::new();
+        let key = Bytes::from("key");
+        keyspace.insert(key.clone(), expired_value("value"));
+
+        assert!(keyspace.lookup_read(&key).is_none());
+        assert_eq!(keyspace.len(), 0);
+    }
+
+    #[test]
+    fn test_lookup_updates_access_stats() {
+        let mut keyspace = Keyspace::new();
+        let key = Bytes::from("key");
+        keyspace.insert(key.clone(), RedisValue::new(Bytes::from("value")));
+
+        let now = keyspace.now();
+        assert_eq!(keyspace.peek(&key).unwrap().frequency(now), 5);
+        // the first access past the initial value always counts
+        keyspace.lookup_read(&key);
+        assert_eq!(keyspace.peek(&key).unwrap().frequency(now), 6);
+        for _ in 0..1000 {
+            keyspace.lookup_write(&key);
+        }
+        let frequency = keyspace.peek(&key).unwrap().frequency(now);
+        assert!((10..40).contains(&frequency), "{}", frequency);
+
+        keyspace.clock().advance(Duration::from_secs(180));
+        let now = keyspace.now();
+        let value = keyspace.peek(&key).unwrap();
+        assert_eq!(value.frequency(now), frequency - 3);
+        assert!(now - value.last_accessed >= Duration::from_secs(180));
+    }
+
+    #[test]
+    fn test_remove_ignores_expired_key() {
+        let mut keyspace = Keyspace::new();
+        let key = Bytes::from("key");
+        keyspace.insert(key.clone(), expired_value("value"));
+
+        assert!(keyspace.remove(&key).is_none());
+    }
+
+    #[test]
+    fn test_lookup_write_or_insert_replaces_expired_value() {
+        let mut keyspace = Keyspace::new();
+        let key = Bytes::from("key");
+        keyspace.insert(key.clone(), expired_value("value"));
+
+        let value = keyspace.lookup_write_or_insert(&key, || RedisValue::new(Bytes::new()));
+        assert!(value.data.is_empty());
+        assert!(value.expires_at.is_none());
+    }
+
+    #[test]
+    fn test_active_expire_cycle() {
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(Bytes::from("a"), expired_value("1"));
+        keyspace.insert(Bytes::from("b"), expired_value("2"));
+        keyspace.insert(Bytes::from("c"), RedisValue::new(Bytes::from("3")));
+
+        assert_eq!(keyspace.active_expire_cycle(10), 2);
+        assert_eq!(keyspace.keys(), vec![Bytes::from("c")]);
+    }
+
+    #[test]
+    fn test_expired_keys_are_recorded_for_propagation() {
+        let mut storage = Storage::new(2);
+        storage.db(1).insert(Bytes::from("a"), expired_value("1"));
+        storage.db(1).insert(Bytes::from("b"), expired_value("2"));
+        storage.db(1).lookup_read(&Bytes::from("a"));
+        assert_eq!(storage.take_expired(), vec![(1, vec![Bytes::from("a")])]);
+        storage.db(1).active_expire_cycle(10);
+        assert_eq!(storage.take_expired(), vec![(1, vec![Bytes::from("b")])]);
+        assert!(storage.take_expired().is_empty());
+    }
+
+    #[test]
+    fn test_replica_keeps_expired_keys() {
+        let mut storage = Storage::new(1);
+        storage.set_keep_expired(true);
+        let key = Bytes::from("key");
+        storage.db(0).insert(key.clone(), expired_value("value"));
+
+        assert!(storage.db(0).lookup_read(&key).is_none());
+        assert!(!storage.db(0).contains(&key));
+        assert!(storage.db(0).keys().is_empty());
+        assert_eq!(storage.db(0).active_expire_cycle(10), 0);
+        assert_eq!(storage.db(0).len(), 1);
+        assert!(storage.take_expired().is_empty());
+        // the primary's writes still see the key
+        assert!(storage.db(0).lookup_write(&key).is_some());
+        assert!(storage.db(0).remove(&key).is_some());
+    }
+
+    #[test]
+    fn test_move_key_keeps_ttl() {
+        let mut storage = Storage::new(2);
+        let key = Bytes::from("key");
+        let mut value = RedisValue::new(Bytes::from("value"));
+        value.expires_at = Some(Instant::now() + Duration::from_secs(60));
+        storage.db(0).insert(key.clone(), value);
+
+        assert!(storage.move_key(&key, 0, 1));
+        assert!(!storage.db(0).contains(&key));
+        assert!(storage.db(1).peek(&key).unwrap().expires_at.is_some());
+    }
+
+    #[test]
+    fn test_move_key_refuses_existing_destination() {
+        let mut storage = Storage::new(2);
+        let key = Bytes::from("key");
+        storage
+            .db(0)
+            .insert(key.clone(), RedisValue::new(Bytes::from("a")));
+        storage
+            .db(1)
+            .insert(key.clone(), RedisValue::new(Bytes::from("b")));
+
+        assert!(!storage.move_key(&key, 0, 1));
+        assert!(storage.db(0).contains(&key));
+    }
+
+    #[test]
+    fn test_watch_stamp_changes_on_write_only() {
+        let mut keyspace = Keyspace::new();
+        let key = Bytes::from("key");
+        keyspace.insert(key.clone(), RedisValue::new(Bytes::from("value")));
+        let stamp = keyspace.watch(&key);
+
+        keyspace.lookup_read(&key);
+        assert_eq!(keyspace.watch_stamp(&key), Some(stamp));
+
+        keyspace.lookup_write(&key);
+        assert_eq!(keyspace.watch_stamp(&key), Some(stamp));
+        keyspace.mark_modified(&key);
+        let written = keyspace.watch_stamp(&key).unwrap();
+        assert_ne!(written, stamp);
+
+        keyspace.remove(&key);
+        assert_ne!(keyspace.watch_stamp(&key), Some(written));
+
+        keyspace.unwatch(&key);
+        assert_eq!(keyspace.watch_stamp(&key), None);
+    }
+
+    #[test]
+    fn test_watch_sees_a_key_come_and_go() {
+        let mut storage = Storage::new(2);
+        let key = Bytes::from("key");
+        let stamp = storage.db(0).watch(&key);
+        let other = storage.db(0).watch(&key);
+        assert_eq!(stamp, other);
+
+        storage
+            .db(0)
+            .insert(key.clone(), RedisValue::new(Bytes::from("value")));
+        storage.db(0).remove(&key);
+        let removed = storage.db(0).watch_stamp(&key).unwrap();
+        assert_ne!(removed, stamp);
+
+        // swapping in a database that has the key counts, one without does not
+        storage.swap(0, 1);
+        assert_eq!(storage.db(0).watch_stamp(&key), Some(removed));
+        storage
+            .db(1)
+            .insert(key.clone(), RedisValue::new(Bytes::from("value")));
+        storage.swap(0, 1);
+        assert_ne!(storage.db(0).watch_stamp(&key), Some(removed));
+        assert_eq!(storage.db(1).watch_stamp(&key), None);
+
+        // the second watcher keeps the key watched
+        storage.db(0).unwatch(&key);
+        assert!(storage.db(0).watch_stamp(&key).is_some());
+        storage.db(0).unwatch(&key);
+        assert_eq!(storage.db(0).watch_stamp(&key), None);
+    }
+
+    #[test]
+    fn test_events_are_only_recorded_when_enabled() {
+        let mut storage = Storage::new(1);
+        let key = Bytes::from("key");
+        storage.db(0).insert(key.clone(), expired_value("value"));
+        storage.db(0).lookup_read(&key);
+        assert!(storage.take_events().is_empty());
+
+        storage.set_notify_flags(NotifyFlags::parse("Exn").unwrap());
+        storage.db(0).insert(key.clone(), expired_value("value"));
+        storage.db(0).lookup_read(&key);
+
+        let events: Vec<&str> = storage.take_events()[0]
+            .1
+            .iter()
+            .map(|event| event.event)
+            .collect();
+        assert_eq!(events, vec!["new", "expired"]);
+    }
+
+    #[test]
+    fn test_tracked_keys_are_only_recorded_when_enabled() {
+        let mut storage = Storage::new(2);
+        let key = Bytes::from("key");
+        storage
+            .db(0)
+            .insert(key.clone(), RedisValue::new(Bytes::from("value")));
+        assert!(storage.take_tracked_keys().modified.is_empty());
+
+        storage.set_key_tracking(true);
+        storage.db(0).lookup_read(&key);
+        storage.db(1).insert(key.clone(), expired_value("value"));
+        storage.db(1).remove(&key);
+        storage.db(1).flush();
+
+        let tracked = storage.take_tracked_keys();
+        assert_eq!(tracked.read, vec![key.clone()]);
+        // removing the already expired key records nothing more
+        assert_eq!(tracked.modified, vec![key.clone(), key]);
+        assert!(tracked.flushed);
+    }
+
+    #[test]
+    fn test_dirty_counts_changes_only() {
+        let mut storage = Storage::new(1);
+        let key = Bytes::from("key");
+        storage
+            .db(0)
+            .insert(key.clone(), RedisValue::new(Bytes::from("value")));
+        storage.db(0).lookup_read(&key);
+        // looking a key up to write it is not a change yet
+        storage.db(0).lookup_write(&key);
+        storage.db(0).lookup_write(&Bytes::from("missing"));
+        assert_eq!(storage.dirty(), 1);
+        storage.db(0).mark_modified(&key);
+        assert_eq!(storage.dirty(), 2);
+    }
+}
@@ -0,0 +1,132 @@
+// Helpers for the unit tests of the command modules: in-process clients of
+// one server state, and servers that keep their files in a directory of
+// their own.
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+
+use crate::config::{AppendFsync, Config};
+use crate::outbox::{self, Inbox};
+use crate::resp_frame::RespFrame;
+use crate::session::Session;
+use crate::storage::RedisValue;
+use crate::{ServerState, execute, open_session};
+
+pub(crate) struct TestDb {
+    pub state: Arc<ServerState>,
+    pub session: Session,
+    // frames the server pushed outside of regular replies
+    inbox: Inbox,
+}
+
+impl TestDb {
+    // another connection to the same server
+    pub(crate) fn connect(&self) -> TestDb {
+        let (outbox, inbox) = outbox::channel();
+        TestDb {
+            state: self.state.clone(),
+            session: open_session(outbox, &self.state),
+            inbox,
+        }
+    }
+
+    pub(crate) fn pushed(&mut self) -> Vec<RespFrame> {
+        let mut frames = Vec::new();
+        while let Ok(frame) = self.inbox.try_recv() {
+            frames.push(frame);
+        }
+        frames
+    }
+}
+
+// "gone" has expired but has not been evicted yet, "live" has no ttl
+pub(crate) fn setup_db() -> TestDb {
+    let state = ServerState::new(Config::default());
+    {
+        let mut storage = state.db.lock().unwrap();
+        let keyspace = storage.db(0);
+
+        let mut expired = RedisValue::new(Bytes::from("10"));
+        expired.expires_at = Some(Instant::now() - Duration::from_secs(1));
+        keyspace.insert(Bytes::from("gone"), expired);
+        keyspace.insert(Bytes::from("live"), RedisValue::new(Bytes::from("1")));
+    }
+
+    let (outbox, inbox) = outbox::channel();
+    TestDb {
+        session: open_session(outbox, &state),
+        state: Arc::new(state),
+        inbox,
+    }
+}
+
+// a server whose snapshots go to a fresh directory, removed on drop
+pub(crate) struct TempDir(pub std::path::PathBuf);
+
+impl TempDir {
+    pub(crate) fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("resprs-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        TempDir(dir)
+    }
+
+    pub(crate) fn server(&self) -> TestDb {
+        self.server_with(Config::default()).unwrap()
+    }
+
+    pub(crate) fn aof_server(&self) -> TestDb {
+        self.server_with(Config {
+            appendonly: true,
+            appendfsync: AppendFsync::Always,
+            ..Config::default()
+        })
+        .unwrap()
+    }
+
+    // loads what is on disk like a server starting up would
+    pub(crate) fn server_with(&self, config: Config) -> std::io::Result<TestDb> {
+        let state = ServerState::new(Config {
+            dir: self.0.clone(),
+            ..config
+        });
+        crate::server::load_data(&state)?;
+        let (outbox, inbox) = outbox::channel();
+        Ok(TestDb {
+            session: open_session(outbox, &state),
+            state: Arc::new(state),
+            inbox,
+        })
+    }
+
+    pub(crate) fn aof_file(&self, name: &str) -> std::path::PathBuf {
+        self.0.join("appendonlydir").join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+pub(crate) fn run(db: &mut TestDb, args: &[&str]) -> RespFrame {
+    let frame = RespFrame::Array(
+        args.iter()
+            .map(|arg| RespFrame::BulkString(Bytes::copy_from_slice(arg.as_bytes())))
+            .collect(),
+    );
+    execute(frame, &db.state, &mut db.session)
+}
+
+pub(crate) fn ok() -> RespFrame {
+    RespFrame::SimpleString("OK".to_string())
+}
+
+pub(crate) fn bulk(s: &str) -> RespFrame {
+    RespFrame::BulkString(Bytes::copy_from_slice(s.as_bytes()))
+}
+
+pub(crate) fn push(items: Vec<RespFrame>) -> RespFrame {
+    RespFrame::Push(items)
+}
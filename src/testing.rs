@@ -0,0 +1,236 @@
+// A real resprs server for integration tests, running inside the test's own
+// tokio runtime. It listens on an ephemeral port, can also be reached over
+// in-memory pipes, and stops when dropped.
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use tokio::io::DuplexStream;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+
+use crate::config::Config;
+use crate::executor::Executor;
+use crate::server::{Server, ShutdownHandle, handle_connection};
+use crate::{Db, ServerState};
+
+// room for pipelined commands and replies in each direction of a duplex pipe
+const DUPLEX_BUFFER_SIZE: usize = 64 * 1024;
+
+static NEXT_DIR: AtomicU64 = AtomicU64::new(0);
+
+/// A server on `127.0.0.1:0`.
+///
+/// ```
+/// # #[tokio::main]
+/// # async fn main() {
+/// use std::time::Duration;
+///
+/// use bytes::Bytes;
+/// use resprs::storage::RedisValue;
+/// use resprs::testing::TestServer;
+///
+/// let server = TestServer::start().await;
+/// let mut value = RedisValue::new(Bytes::from("v"));
+/// value.expires_at = Some(server.now() + Duration::from_secs(10));
+/// server.db().lock().unwrap().db(0).insert(Bytes::from("k"), value);
+///
+/// server.advance(Duration::from_secs(11));
+/// assert!(!server.db().lock().unwrap().db(0).contains(&Bytes::from("k")));
+/// # }
+/// ```
+pub struct TestServer {
+    addr: SocketAddr,
+    state: Arc<ServerState>,
+    shutdown: ShutdownHandle,
+    running: Option<JoinHandle<std::io::Result<()>>>,
+    // the snapshot directory `start` made up, removed on drop
+    temp_dir: Option<PathBuf>,
+}
+
+impl TestServer {
+    /// Starts a server with the default config and an empty directory of its
+    /// own for snapshots. Panics if it can't listen, which in a test is the
+    /// most useful thing to do.
+    pub async fn start() -> TestServer {
+        let dir = std::env::temp_dir().join(format!(
+            "resprs-test-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut server = TestServer::with_config(Config {
+            dir: dir.clone(),
+            ..Config::default()
+        })
+        .await;
+        server.temp_dir = Some(dir);
+        server
+    }
+
+    /// Starts a server with `config` as given, including its `dir`, so it
+    /// loads whatever snapshot is there.
+    pub async fn with_config(config: Config) -> TestServer {
+        let server = Server::builder()
+            .config(config)
+            .bind("127.0.0.1:0")
+            .build()
+            .await
+            .expect("test server could not listen on 127.0.0.1:0");
+
+        TestServer {
+            addr: server.local_addr().expect("listener has no local address"),
+            state: server.state(),
+            shutdown: server.shutdown_handle(),
+            running: Some(tokio::spawn(server.run())),
+            temp_dir: None,
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The keyspace itself, for seeding data and checking what commands did.
+    /// Don't hold the lock across an await, clients can't run meanwhile.
+    pub fn db(&self) -> &Db {
+        &self.state.db
+    }
+
+    /// A client that runs commands in process, without a socket.
+    pub fn executor(&self) -> Executor {
+        Executor::with_state(self.state.clone())
+    }
+
+    pub async fn connect(&self) -> TcpStream {
+        TcpStream::connect(self.addr)
+            .await
+            .expect("could not connect to the test server")
+    }
+
+    /// A connection over an in-memory pipe; the server holds the other end.
+    pub fn connect_duplex(&self) -> DuplexStream {
+        let (client, server) = tokio::io::duplex(DUPLEX_BUFFER_SIZE);
+        tokio::spawn(handle_connection(
+            server,
+            "duplex".to_string(),
+            self.state.clone(),
+            self.shutdown.subscribe(),
+        ));
+        client
+    }
+
+    /// The time keys expire by, which `advance` moves forward.
+    pub fn now(&self) -> std::time::Instant {
+        self.state.db.lock().unwrap().clock().now()
+    }
+
+    /// Moves the server's clock forward, so TTLs run out without sleeping.
+    pub fn advance(&self, by: Duration) {
+        self.state.db.lock().unwrap().clock().advance(by);
+    }
+
+    /// Stops the server and waits until every connection is closed.
+    pub async fn shutdown(mut self) {
+        self.shutdown.shutdown();
+        if let Some(running) = self.running.take() {
+            let _ = running.await;
+        }
+    }
+}
+
+impl Drop for TestServer {
+    // the server task notices the signal on its own, drop can't wait for it
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+        if let Some(dir) = &self.temp_dir {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use tokio::io::{AsyncRead, AsyncWrite, BufReader};
+
+    use crate::resp_frame::RespFrame;
+    use crate::storage::RedisValue;
+    use crate::testing::TestServer;
+    use crate::{parser, serializer};
+
+    async fn roundtrip<S>(stream: &mut BufReader<S>, args: &[&str]) -> RespFrame
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let frame = RespFrame::Array(
+            args.iter()
+                .map(|arg| RespFrame::BulkString(Bytes::copy_from_slice(arg.as_bytes())))
+                .collect(),
+        );
+        serializer::serialize_frame(stream.get_mut(), frame)
+            .await
+            .unwrap();
+        parser::parse_frame(stream).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_seeded_keys_expire_when_time_advances() {
+        let server = TestServer::start().await;
+        let mut value = RedisValue::new(Bytes::from("v"));
+        value.expires_at = Some(server.now() + Duration::from_secs(10));
+        server
+            .db()
+            .lock()
+            .unwrap()
+            .db(0)
+            .insert(Bytes::from("k"), value);
+
+        let mut client = BufReader::new(server.connect().await);
+        assert_eq!(
+            roundtrip(&mut client, &["TTL", "k"]).await,
+            RespFrame::Integer(9)
+        );
+        server.advance(Duration::from_secs(5));
+        assert_eq!(
+            roundtrip(&mut client, &["TTL", "k"]).await,
+            RespFrame::Integer(4)
+        );
+        server.advance(Duration::from_secs(6));
+        assert_eq!(roundtrip(&mut client, &["GET", "k"]).await, RespFrame::Null);
+    }
+
+    #[tokio::test]
+    async fn test_duplex_clients_share_the_keyspace() {
+        let server = TestServer::start().await;
+        let mut first = BufReader::new(server.connect_duplex());
+        let mut second = BufReader::new(server.connect_duplex());
+
+        assert_eq!(
+            roundtrip(&mut first, &["SET", "k", "v"]).await,
+            RespFrame::SimpleString("OK".to_string())
+        );
+        assert_eq!(
+            roundtrip(&mut second, &["GET", "k"]).await,
+            RespFrame::BulkString(Bytes::from("v"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dropping_closes_connections() {
+        let server = TestServer::start().await;
+        let mut client = BufReader::new(server.connect().await);
+        let mut pipe = BufReader::new(server.connect_duplex());
+        assert_eq!(
+            roundtrip(&mut client, &["PING"]).await,
+            RespFrame::SimpleString("PONG".to_string())
+        );
+
+        drop(server);
+        assert!(parser::parse_frame(&mut client).await.is_err());
+        assert!(parser::parse_frame(&mut pipe).await.is_err());
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use bytes::Bytes;
+
+use crate::outbox::Outbox;
+use crate::pubsub::PubSub;
+use crate::resp_frame::RespFrame;
+
+// the channel RESP2 clients subscribe to when they receive invalidations through REDIRECT
+pub const INVALIDATE_CHANNEL: &str = "__redis__:invalidate";
+
+// what CLIENT TRACKING ON was given
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackingOptions {
+    pub redirect: Option<u64>,
+    pub bcast: bool,
+    pub prefixes: Vec<Bytes>,
+    pub optin: bool,
+    pub optout: bool,
+    pub noloop: bool,
+}
+
+struct TrackingClient {
+    outbox: Outbox,
+    resp3: bool,
+    options: TrackingOptions,
+}
+
+#[derive(Default)]
+struct TrackingTable {
+    clients: HashMap<u64, TrackingClient>,
+    // key -> clients that read it since its last invalidation
+    keys: HashMap<Bytes, HashSet<u64>>,
+    // BCAST prefix -> clients, the empty prefix matches every key
+    prefixes: HashMap<Bytes, HashSet<u64>>,
+}
+
+// The server side of client side caching. Like the PubSub registry it has its
+// own lock and never calls back into the keyspace.
+#[derive(Default)]
+pub struct Tracking {
+    table: Mutex<TrackingTable>,
+}
+
+impl Tracking {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn enable(&self, client_id: u64, outbox: Outbox, resp3: bool, options: TrackingOptions) {
+        let mut table = self.table.lock().unwrap();
+        table.forget_client(client_id);
+
+        if options.bcast {
+            if options.prefixes.is_empty() {
+                table
+                    .prefixes
+                    .entry(Bytes::new())
+                    .or_default()
+                    .insert(client_id);
+            }
+            for prefix in &options.prefixes {
+                table
+                    .prefixes
+                    .entry(prefix.clone())
+                    .or_default()
+                    .insert(client_id);
+            }
+        }
+        table.clients.insert(
+            client_id,
+            TrackingClient {
+                outbox,
+                resp3,
+                options,
+            },
+        );
+    }
+
+    pub fn disable(&self, client_id: u64) {
+        self.table.lock().unwrap().forget_client(client_id);
+    }
+
+    /// Whether any client has tracking on, so the keyspace has to record keys.
+    pub fn is_active(&self) -> bool {
+        !self.table.lock().unwrap().clients.is_empty()
+    }
+
+    pub fn set_protocol(&self, client_id: u64, resp3: bool) {
+        if let Some(client) = self.table.lock().unwrap().clients.get_mut(&client_id) {
+            client.resp3 = resp3;
+        }
+    }
+
+    /// Records keys a client read, so it hears about their next change.
+    pub fn remember(&self, client_id: u64, keys: Vec<Bytes>) {
+        let mut table = self.table.lock().unwrap();
+        for key in keys {
+            table.keys.entry(key).or_default().insert(client_id);
+        }
+    }
+
+    /// Sends an invalidation to every client that read one of the modified
+    /// keys or tracks a matching prefix. `origin` is the client that made the
+    /// change, which NOLOOP clients are not told about.
+    pub fn invalidate(&self, pubsub: &PubSub, modified: &[Bytes], origin: Option<u64>) {
+        let mut table = self.table.lock().unwrap();
+        let mut pending: HashMap<u64, Vec<Bytes>> = HashMap::new();
+
+        for key in modified {
+            let readers = table.keys.remove(key).unwrap_or_default();
+            let watchers = table
+                .prefixes
+                .iter()
+                .filter(|(prefix, _)| key.starts_with(prefix))
+                .flat_map(|(_, clients)| clients.iter().copied());
+
+            for client_id in readers.into_iter().chain(watchers) {
+                let keys = pending.entry(client_id).or_default();
+                if !keys.contains(key) {
+                    keys.push(key.clone());
+                }
+            }
+        }
+
+        for (client_id, keys) in pending {
+            let Some(client) = table.clients.get(&client_id) else {
+                continue;
+            };
+            if client.options.noloop && origin == Some(client_id) {
+                continue;
+            }
+            let keys = RespFrame::Array(keys.into_iter().map(RespFrame::BulkString).collect());
+            client.send(client_id, pubsub, keys);
+        }
+    }
+
+    /// After FLUSHDB or FLUSHALL every client drops its whole cache, which
+    /// redis signals with a null key list.
+    pub fn invalidate_all(&self, pubsub: &PubSub) {
+        let mut table = self.table.lock().unwrap();
+        table.keys.clear();
+        for (client_id, client) in &table.clients {
+            client.send(*client_id, pubsub, RespFrame::Null);
+        }
+    }
+}
+
+impl TrackingTable {
+    fn forget_client(&mut self, client_id: u64) {
+        if self.clients.remove(&client_id).is_none() {
+            return;
+        }
+        for clients in self.keys.values_mut() {
+            clients.remove(&client_id);
+        }
+        self.keys.retain(|_, clients| !clients.is_empty());
+        for clients in self.prefixes.values_mut() {
+            clients.remove(&client_id);
+        }
+        self.prefixes.retain(|_, clients| !clients.is_empty());
+    }
+}
+
+impl TrackingClient {
+    // RESP3 clients get an invalidate push on their own connection. Everything
+    // else goes to the __redis__:invalidate subscription of the redirect
+    // target, or of the client itself when RESP2 is used without REDIRECT.
+    fn send(&self, client_id: u64, pubsub: &PubSub, keys: RespFrame) {
+        match self.options.redirect {
+            None if self.resp3 => {
+                let _ = self.outbox.send(RespFrame::Push(vec![
+                    RespFrame::BulkString(Bytes::from_static(b"invalidate")),
+                    keys,
+                ]));
+            }
+            None => pubsub.invalidate(client_id, keys),
+            Some(target) => pubsub.invalidate(target, keys),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::outbox::channel;
+    use bytes::Bytes;
+
+    use crate::pubsub::PubSub;
+    use crate::resp_frame::RespFrame;
+    use crate::test_util::{ok, push, run, setup_db};
+    use crate::tracking::{INVALIDATE_CHANNEL, Tracking, TrackingOptions};
+
+    fn bulk(s: &str) -> RespFrame {
+        RespFrame::BulkString(Bytes::copy_from_slice(s.as_bytes()))
+    }
+
+    #[test]
+    fn test_keys_are_invalidated_once() {
+        let pubsub = PubSub::new();
+        let tracking = Tracking::new();
+        let (tx, mut rx) = channel();
+        let key = Bytes::from("key");
+
+        tracking.enable(1, tx, true, TrackingOptions::default());
+        tracking.remember(1, vec![key.clone()]);
+
+        tracking.invalidate(&pubsub, &[key.clone(), key.clone()], None);
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            RespFrame::Push(vec![
+                bulk("invalidate"),
+                RespFrame::Array(vec![bulk("key")])
+            ])
+        );
+
+        // the client has to read the key again to hear about it
+        tracking.invalidate(&pubsub, &[key], None);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_bcast_prefixes_and_noloop() {
+        let pubsub = PubSub::new();
+        let tracking = Tracking::new();
+        let (tx, mut rx) = channel();
+        let options = TrackingOptions {
+            bcast: true,
+            prefixes: vec![Bytes::from("user:")],
+            noloop: true,
+            ..TrackingOptions::default()
+        };
+        tracking.enable(1, tx, true, options);
+
+        tracking.invalidate(&pubsub, &[Bytes::from("user:1")], Some(1));
+        tracking.invalidate(&pubsub, &[Bytes::from("order:1")], Some(2));
+        assert!(rx.try_recv().is_err());
+
+        tracking.invalidate(&pubsub, &[Bytes::from("user:1")], Some(2));
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            RespFrame::Push(vec![
+                bulk("invalidate"),
+                RespFrame::Array(vec![bulk("user:1")])
+            ])
+        );
+    }
+
+    #[test]
+    fn test_redirect_goes_through_the_invalidate_channel() {
+        let pubsub = PubSub::new();
+        let tracking = Tracking::new();
+        let (tx, mut rx) = channel();
+        let (own_tx, mut own) = channel();
+
+        pubsub.subscribe(7, Bytes::from(INVALIDATE_CHANNEL), tx, false);
+        let options = TrackingOptions {
+            redirect: Some(7),
+            ..TrackingOptions::default()
+        };
+        tracking.enable(1, own_tx, false, options);
+
+        tracking.invalidate_all(&pubsub);
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            RespFrame::Array(vec![
+                bulk("message"),
+                bulk(INVALIDATE_CHANNEL),
+                RespFrame::Null
+            ])
+        );
+        assert!(own.try_recv().is_err());
+    }
+
+    fn invalidate(keys: &[&str]) -> RespFrame {
+        push(vec![
+            bulk("invalidate"),
+            RespFrame::Array(keys.iter().map(|key| bulk(key)).collect()),
+        ])
+    }
+
+    #[test]
+    fn test_tracking_invalidates_keys_that_were_read() {
+        let mut reader = setup_db();
+        let mut writer = reader.connect();
+
+        run(&mut reader, &["HELLO", "3"]);
+        assert_eq!(run(&mut reader, &["CLIENT", "TRACKING", "ON"]), ok());
+        run(&mut reader, &["GET", "live"]);
+
+        // keys nobody read are not reported
+        run(&mut writer, &["SET", "other", "x"]);
+        assert!(reader.pushed().is_empty());
+
+        run(&mut writer, &["SET", "live", "2"]);
+        assert_eq!(reader.pushed(), vec![invalidate(&["live"])]);
+
+        // the key has to be read again before the next invalidation
+        run(&mut writer, &["DEL", "live"]);
+        assert!(reader.pushed().is_empty());
+    }
+
+    #[test]
+    fn test_tracking_reports_expired_and_flushed_keys() {
+        let mut reader = setup_db();
+        let mut writer = reader.connect();
+
+        run(&mut reader, &["HELLO", "3"]);
+        run(&mut reader, &["CLIENT", "TRACKING", "ON"]);
+        run(&mut reader, &["GET", "live"]);
+        run(&mut reader, &["GET", "gone"]);
+        // the read evicted "gone" before it was remembered, so that is not reported
+        assert!(reader.pushed().is_empty());
+
+        run(&mut writer, &["SET", "gone", "back"]);
+        assert_eq!(reader.pushed(), vec![invalidate(&["gone"])]);
+
+        run(&mut writer, &["FLUSHALL"]);
+        assert_eq!(
+            reader.pushed(),
+            vec![push(vec![bulk("invalidate"), RespFrame::Null])]
+        );
+    }
+
+    #[test]
+    fn test_tracking_optin_bcast_and_noloop() {
+        let mut reader = setup_db();
+        let mut writer = reader.connect();
+        run(&mut reader, &["HELLO", "3"]);
+
+        run(&mut reader, &["CLIENT", "TRACKING", "ON", "OPTIN"]);
+        run(&mut reader, &["GET", "live"]);
+        run(&mut writer, &["SET", "live", "2"]);
+        assert!(reader.pushed().is_empty());
+
+        assert_eq!(run(&mut reader, &["CLIENT", "CACHING", "YES"]), ok());
+        run(&mut reader, &["GET", "live"]);
+        run(&mut writer, &["SET", "live", "3"]);
+        assert_eq!(reader.pushed(), vec![invalidate(&["live"])]);
+
+        run(&mut reader, &["CLIENT", "TRACKING", "OFF"]);
+        run(
+            &mut reader,
+            &[
+                "CLIENT", "TRACKING", "ON", "BCAST", "PREFIX", "user:", "NOLOOP",
+            ],
+        );
+        run(&mut reader, &["SET", "user:1", "mine"]);
+        run(&mut writer, &["SET", "order:1", "x"]);
+        assert!(reader.pushed().is_empty());
+
+        run(&mut writer, &["SET", "user:2", "x"]);
+        assert_eq!(reader.pushed(), vec![invalidate(&["user:2"])]);
+    }
+
+    #[test]
+    fn test_tracking_redirects_to_invalidate_subscriber() {
+        let mut reader = setup_db();
+        let mut writer = reader.connect();
+        let mut listener = reader.connect();
+
+        run(&mut listener, &["SUBSCRIBE", "__redis__:invalidate"]);
+        listener.pushed();
+        let id = listener.session.id.to_string();
+        run(&mut reader, &["CLIENT", "TRACKING", "ON", "REDIRECT", &id]);
+        assert_eq!(
+            run(&mut reader, &["CLIENT", "GETREDIR"]),
+            RespFrame::Integer(listener.session.id as i64)
+        );
+
+        run(&mut reader, &["GET", "live"]);
+        run(&mut writer, &["INCR", "live"]);
+        assert_eq!(
+            listener.pushed(),
+            vec![RespFrame::Array(vec![
+                bulk("message"),
+                bulk("__redis__:invalidate"),
+                RespFrame::Array(vec![bulk("live")])
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_client_tracking_rejects_bad_options() {
+        let mut db = setup_db();
+        assert_eq!(
+            run(&mut db, &["CLIENT", "TRACKING", "ON", "REDIRECT", "999999"]),
+            RespFrame::Error("ERR The client ID you want redirect to does not exist".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["CLIENT", "GETREDIR"]),
+            RespFrame::Integer(-1)
+        );
+        assert_eq!(
+            run(&mut db, &["CLIENT", "TRACKING", "ON", "PREFIX", "a"]),
+            RespFrame::Error("ERR PREFIX option requires BCAST mode to be enabled".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["CLIENT", "TRACKING", "ON", "BCAST", "OPTIN"]),
+            RespFrame::Error("ERR OPTIN and OPTOUT are not compatible with BCAST".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["CLIENT", "CACHING", "YES"]),
+            RespFrame::Error("ERR CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled".to_string())
+        );
+        assert_eq!(
+            run(&mut db, &["CLIENT", "GETREDIR"]),
+            RespFrame::Integer(-1)
+        );
+    }
+}